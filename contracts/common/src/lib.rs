@@ -0,0 +1,116 @@
+#![no_std]
+//! Definitions shared by the Fundable contracts: basis-point fee math, the
+//! fee cap, error code ranges, TTL defaults and a get-or-default storage
+//! helper. Nothing here is exported in a contract's spec, so depending on
+//! it doesn't change any contract's interface.
+use soroban_sdk::storage::{Instance, Persistent, Temporary};
+use soroban_sdk::{contracttype, Address, Env, IntoVal, TryFromVal, Val};
+
+/// Basis points in a whole: a rate of 10000 bps is 100%
+pub const BPS_DENOMINATOR: i128 = 10_000;
+/// Highest protocol fee either contract accepts, 5% in basis points
+pub const MAX_FEE_BPS: u32 = 500;
+
+/// Each contract numbers its own error enums from 1 up to this code
+pub const CONTRACT_ERROR_MAX: u32 = 99;
+/// First code reserved for errors defined once for every contract
+pub const SHARED_ERROR_BASE: u32 = 100;
+
+pub const LEDGER_THRESHOLD: u32 = 518400; // ~30 days at 5s/ledger
+pub const LEDGER_BUMP: u32 = 535680; // ~31 days
+pub const MIN_LEDGER_THRESHOLD: u32 = 17280; // ~1 day
+
+/// `amount * bps / 10000`, rounded down. Nothing for a zero rate or a
+/// non-positive amount.
+pub fn bps_of(amount: i128, bps: u32) -> i128 {
+    bps_of_with_carry(amount, bps, 0).0
+}
+
+/// `bps_of` with a remainder carried between calls. `carry` is the
+/// fraction (in 1/10000ths) a previous call rounded away; it's added before
+/// rounding down, and the new remainder is returned alongside the share.
+/// Summing the shares of several calls this way gives the same total as
+/// one call on the summed amount.
+pub fn bps_of_with_carry(amount: i128, bps: u32, carry: i128) -> (i128, i128) {
+    if bps == 0 || amount <= 0 {
+        return (0, carry);
+    }
+
+    // Split calculation to avoid overflow while preserving precision
+    let rate = bps as i128;
+    let fraction = (amount % BPS_DENOMINATOR) * rate + carry;
+    (
+        (amount / BPS_DENOMINATOR) * rate + fraction / BPS_DENOMINATOR,
+        fraction % BPS_DENOMINATOR,
+    )
+}
+
+/// A protocol fee rate and who collects it
+#[contracttype(export = false)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeConfig {
+    pub rate_bps: u32,
+    pub collector: Option<Address>,
+}
+
+/// Why a `FeeConfig` was rejected; each contract maps these to its own error
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FeeConfigError {
+    /// The rate is above `MAX_FEE_BPS`
+    RateTooHigh,
+    /// A nonzero rate has no collector to pay
+    MissingCollector,
+}
+
+impl FeeConfig {
+    pub fn check(&self) -> Result<(), FeeConfigError> {
+        if self.rate_bps > MAX_FEE_BPS {
+            return Err(FeeConfigError::RateTooHigh);
+        }
+        if self.rate_bps > 0 && self.collector.is_none() {
+            return Err(FeeConfigError::MissingCollector);
+        }
+        Ok(())
+    }
+
+    /// Fee on `amount` at this rate, rounded down
+    pub fn fee_on(&self, amount: i128) -> i128 {
+        bps_of(amount, self.rate_bps)
+    }
+}
+
+/// Reads that fall back to a value when the entry is missing, for counters
+/// and settings that start out unset
+pub trait StorageExt {
+    fn get_or<K, V>(&self, key: &K, default: V) -> V
+    where
+        K: IntoVal<Env, Val>,
+        V: TryFromVal<Env, Val>;
+
+    fn get_or_default<K, V>(&self, key: &K) -> V
+    where
+        K: IntoVal<Env, Val>,
+        V: TryFromVal<Env, Val> + Default,
+    {
+        self.get_or(key, V::default())
+    }
+}
+
+macro_rules! impl_storage_ext {
+    ($($storage:ty),*) => {$(
+        impl StorageExt for $storage {
+            fn get_or<K, V>(&self, key: &K, default: V) -> V
+            where
+                K: IntoVal<Env, Val>,
+                V: TryFromVal<Env, Val>,
+            {
+                self.get(key).unwrap_or(default)
+            }
+        }
+    )*};
+}
+
+impl_storage_ext!(Instance, Persistent, Temporary);
+
+#[cfg(test)]
+mod test;
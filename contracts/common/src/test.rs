@@ -0,0 +1,65 @@
+#![cfg(test)]
+
+use super::*;
+use soroban_sdk::testutils::Address as _;
+use soroban_sdk::{contract, contractimpl, symbol_short};
+
+#[contract]
+struct StorageContract;
+
+#[contractimpl]
+impl StorageContract {}
+
+#[test]
+fn test_bps_of_rounds_down() {
+    assert_eq!(bps_of(10_000, 250), 250);
+    assert_eq!(bps_of(1001, 250), 25);
+    assert_eq!(bps_of(39, 250), 0);
+    assert_eq!(bps_of(1000, 0), 0);
+    assert_eq!(bps_of(0, 250), 0);
+    assert_eq!(bps_of(-1000, 250), 0);
+    // No overflow near the top of the range
+    assert_eq!(bps_of(i128::MAX, 10_000), i128::MAX);
+    assert_eq!(bps_of(i128::MAX / 3, MAX_FEE_BPS), (i128::MAX / 3) / 10_000 * 500 + ((i128::MAX / 3) % 10_000) * 500 / 10_000);
+}
+
+#[test]
+fn test_carry_adds_up_to_a_single_call() {
+    let mut carry = 0;
+    let mut total = 0;
+    for _ in 0..100 {
+        let (share, next) = bps_of_with_carry(39, 250, carry);
+        total += share;
+        carry = next;
+    }
+    assert_eq!(total, bps_of(3900, 250));
+    assert_eq!(carry, (3900 * 250) % 10_000);
+    assert_eq!(bps_of_with_carry(1000, 0, 7), (0, 7));
+}
+
+#[test]
+fn test_fee_config_check() {
+    let env = Env::default();
+    let collector = Some(Address::generate(&env));
+
+    assert_eq!(FeeConfig { rate_bps: 0, collector: None }.check(), Ok(()));
+    assert_eq!(FeeConfig { rate_bps: MAX_FEE_BPS, collector: collector.clone() }.check(), Ok(()));
+    assert_eq!(FeeConfig { rate_bps: 250, collector: None }.check(), Err(FeeConfigError::MissingCollector));
+    assert_eq!(FeeConfig { rate_bps: MAX_FEE_BPS + 1, collector: collector.clone() }.check(), Err(FeeConfigError::RateTooHigh));
+    assert_eq!(FeeConfig { rate_bps: 250, collector }.fee_on(1001), 25);
+}
+
+#[test]
+fn test_storage_get_or_default() {
+    let env = Env::default();
+    let contract_id = env.register(StorageContract, ());
+    env.as_contract(&contract_id, || {
+        let key = symbol_short!("count");
+        assert_eq!(env.storage().instance().get_or_default::<_, u32>(&key), 0);
+        assert_eq!(env.storage().persistent().get_or(&key, 7u32), 7);
+
+        env.storage().persistent().set(&key, &3u32);
+        assert_eq!(env.storage().persistent().get_or(&key, 7u32), 3);
+        assert_eq!(env.storage().temporary().get_or_default::<_, u32>(&key), 0);
+    });
+}
@@ -0,0 +1,203 @@
+//! Resource benchmarks for the distribution entrypoints.
+//!
+//! Runs `distribute_equal`, `distribute_weighted` and `distribute_payouts` at
+//! increasing recipient counts, measuring CPU instructions, memory and ledger
+//! entry counts from the test host. Results are compared against the
+//! committed `bench_baseline.csv`; a metric more than `TOLERANCE_PCT` above its
+//! baseline fails the test. Set `UPDATE_BENCH_BASELINE=1` to rewrite the
+//! baseline after an intentional change.
+//!
+//! Numbers come from the native test host, so they exclude Wasm VM costs and
+//! are only meaningful relative to each other and to the baseline.
+extern crate std;
+
+use std::{format, string::String, vec::Vec as StdVec};
+
+use soroban_sdk::{
+    testutils::{Address as _, EnvTestConfig},
+    token::StellarAssetClient,
+    Address, Env, Vec,
+};
+
+use crate::{DistributorContract, DistributorContractClient, Payout};
+
+const RECIPIENT_COUNTS: [u32; 4] = [1, 10, 50, 100];
+const TOLERANCE_PCT: u64 = 10;
+const BASELINE_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/bench_baseline.csv");
+const HEADER: &str = "variant,recipients,cpu_insns,mem_bytes,read_entries,write_entries";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variant {
+    Equal,
+    Weighted,
+    Payouts,
+}
+
+impl Variant {
+    fn name(self) -> &'static str {
+        match self {
+            Variant::Equal => "equal",
+            Variant::Weighted => "weighted",
+            Variant::Payouts => "payouts",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Measurement {
+    variant: String,
+    recipients: u32,
+    cpu_insns: u64,
+    mem_bytes: u64,
+    read_entries: u32,
+    write_entries: u32,
+}
+
+impl Measurement {
+    fn to_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.variant, self.recipients, self.cpu_insns, self.mem_bytes, self.read_entries, self.write_entries
+        )
+    }
+
+    fn from_line(line: &str) -> Self {
+        let fields: StdVec<&str> = line.split(',').collect();
+        assert_eq!(fields.len(), 6, "malformed baseline line: {line}");
+        Measurement {
+            variant: String::from(fields[0]),
+            recipients: fields[1].parse().unwrap(),
+            cpu_insns: fields[2].parse().unwrap(),
+            mem_bytes: fields[3].parse().unwrap(),
+            read_entries: fields[4].parse().unwrap(),
+            write_entries: fields[5].parse().unwrap(),
+        }
+    }
+}
+
+fn measure(variant: Variant, recipient_count: u32) -> Measurement {
+    // Snapshots of 100-recipient runs are large and add nothing here.
+    let env = Env::new_with_config(EnvTestConfig { capture_snapshot_at_drop: false });
+    env.mock_all_auths();
+
+    let token_admin = Address::generate(&env);
+    let token = env.register_stellar_asset_contract_v2(token_admin).address();
+
+    let contract_id = env.register(DistributorContract, (Address::generate(&env), 250_u32, Address::generate(&env)));
+    let client = DistributorContractClient::new(&env, &contract_id);
+
+    let sender = Address::generate(&env);
+    StellarAssetClient::new(&env, &token).mint(&sender, &1_000_000_000);
+
+    let mut recipients = Vec::new(&env);
+    let mut amounts = Vec::new(&env);
+    let mut payouts = Vec::new(&env);
+    for i in 0..recipient_count {
+        let recipient = Address::generate(&env);
+        let amount = 1_000 + i as i128;
+        recipients.push_back(recipient.clone());
+        amounts.push_back(amount);
+        payouts.push_back(Payout { recipient, amount });
+    }
+
+    env.cost_estimate().budget().reset_unlimited();
+    match variant {
+        Variant::Equal => client.distribute_equal(&sender, &token, &(1_000 * recipient_count as i128), &recipients),
+        Variant::Weighted => client.distribute_weighted(&sender, &token, &recipients, &amounts),
+        Variant::Payouts => client.distribute_payouts(&sender, &token, &payouts),
+    }
+
+    let budget = env.cost_estimate().budget();
+    let resources = env.cost_estimate().resources();
+    Measurement {
+        variant: String::from(variant.name()),
+        recipients: recipient_count,
+        cpu_insns: budget.cpu_instruction_cost(),
+        mem_bytes: budget.memory_bytes_cost(),
+        read_entries: resources.read_entries,
+        write_entries: resources.write_entries,
+    }
+}
+
+/// Marginal per-recipient cost between the 10 and 50 recipient runs should
+/// match the marginal cost between 50 and 100 within a factor of two.
+fn assert_roughly_linear(rows: &[Measurement]) {
+    let at = |n: u32| rows.iter().find(|m| m.recipients == n).unwrap();
+    let (small, mid, large) = (at(10), at(50), at(100));
+
+    let slope_cpu_low = (mid.cpu_insns - small.cpu_insns) / 40;
+    let slope_cpu_high = (large.cpu_insns - mid.cpu_insns) / 50;
+    assert!(
+        slope_cpu_high <= slope_cpu_low * 2 && slope_cpu_low <= slope_cpu_high * 2,
+        "{} cpu does not scale linearly: {slope_cpu_low} vs {slope_cpu_high} per recipient",
+        small.variant
+    );
+
+    let slope_mem_low = (mid.mem_bytes - small.mem_bytes) / 40;
+    let slope_mem_high = (large.mem_bytes - mid.mem_bytes) / 50;
+    assert!(
+        slope_mem_high <= slope_mem_low * 2 && slope_mem_low <= slope_mem_high * 2,
+        "{} memory does not scale linearly: {slope_mem_low} vs {slope_mem_high} per recipient",
+        small.variant
+    );
+}
+
+fn within_tolerance(current: u64, baseline: u64) -> bool {
+    current * 100 <= baseline * (100 + TOLERANCE_PCT)
+}
+
+#[test]
+fn bench_distribution_variants() {
+    let mut results = StdVec::new();
+    for variant in [Variant::Equal, Variant::Weighted, Variant::Payouts] {
+        let rows: StdVec<Measurement> = RECIPIENT_COUNTS.iter().map(|n| measure(variant, *n)).collect();
+        assert_roughly_linear(&rows);
+        results.extend(rows);
+    }
+
+    let mut report = String::from(HEADER);
+    for row in &results {
+        report.push('\n');
+        report.push_str(&row.to_line());
+    }
+    report.push('\n');
+
+    let update = std::env::var("UPDATE_BENCH_BASELINE").is_ok();
+    let baseline = match std::fs::read_to_string(BASELINE_FILE) {
+        Ok(contents) if !update => contents,
+        _ => {
+            std::fs::write(BASELINE_FILE, &report).unwrap();
+            return;
+        }
+    };
+
+    let baseline: StdVec<Measurement> = baseline
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(Measurement::from_line)
+        .collect();
+
+    for row in &results {
+        let base = baseline
+            .iter()
+            .find(|b| b.variant == row.variant && b.recipients == row.recipients)
+            .unwrap_or_else(|| panic!("no baseline for {} x{}", row.variant, row.recipients));
+
+        assert!(
+            within_tolerance(row.cpu_insns, base.cpu_insns),
+            "cpu regression for {} x{}: {} > {} (+{}%)",
+            row.variant, row.recipients, row.cpu_insns, base.cpu_insns, TOLERANCE_PCT
+        );
+        assert!(
+            within_tolerance(row.mem_bytes, base.mem_bytes),
+            "memory regression for {} x{}: {} > {} (+{}%)",
+            row.variant, row.recipients, row.mem_bytes, base.mem_bytes, TOLERANCE_PCT
+        );
+        assert!(
+            row.read_entries <= base.read_entries && row.write_entries <= base.write_entries,
+            "ledger entry regression for {} x{}: {}r/{}w > {}r/{}w",
+            row.variant, row.recipients, row.read_entries, row.write_entries, base.read_entries, base.write_entries
+        );
+    }
+}
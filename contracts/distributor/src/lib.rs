@@ -1,8 +1,24 @@
 #![no_std]
+use common::{bps_of, StorageExt, LEDGER_BUMP, LEDGER_THRESHOLD};
 use soroban_sdk::{
-    contract, contractimpl, contracttype, token, Address, Env, Map, Symbol, Vec,
+    contract, contracterror, contractimpl, contracttype, panic_with_error, token, Address, BytesN,
+    Env, IntoVal, Map, Symbol, Vec,
 };
 
+const CONTRACT_VERSION: u32 = 1;
+const CONFIG_VERSION: u32 = 2; // Bump when a field is appended to ContractConfig
+const SECONDS_PER_DAY: u64 = 86400;
+const MAX_FEE_QUERY_DAYS: u64 = 366;
+const MAX_FEE: u32 = common::MAX_FEE_BPS; // 5% in basis points, same cap as payment-stream
+const MAX_MULTI_TOKEN_RECIPIENTS: u32 = 100;
+const MAX_PROPORTIONAL_HOLDERS: u32 = 100;
+const MAX_SCHEDULE_RECIPIENTS: u32 = 100;
+const MAX_STREAM_RECIPIENTS: u32 = 25;
+const MAX_LIST_RECIPIENTS: u32 = 150;
+const MAX_ADMIN_ACTION_PAGE: u32 = 50;
+const MAX_HISTORY_PAGE: u32 = 50;
+const LEADERBOARD_SIZE: u32 = 20;
+
 #[contract]
 pub struct DistributorContract;
 
@@ -22,7 +38,7 @@ pub struct UserStats {
 }
 
 #[contracttype]
-#[derive(Clone)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct DistributionHistory {
     pub sender: Address,
     pub token: Address,
@@ -31,21 +47,317 @@ pub struct DistributionHistory {
     pub timestamp: u64,
 }
 
+/// One entry of the contract's append-only log of admin calls. `detail`
+/// carries the call's main numeric argument, or 0 when there isn't one.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminAction {
+    pub action: Symbol,
+    pub actor: Address,
+    pub timestamp: u64,
+    pub detail: i128,
+}
+
+/// Every instance-level setting in one read. Fields are only ever
+/// appended; `config_version` counts the fields a client can expect.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractConfig {
+    pub config_version: u32,
+    pub admin: Option<Address>,
+    pub fee_address: Option<Address>,
+    pub protocol_fee_percent: u32,
+    pub allowlist_enabled: bool,
+    pub sender_daily_cap: i128,
+    pub version: u32,
+    pub fee_registry: Option<Address>,
+}
+
+/// What pruned history entries for one token added up to
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ArchivedTotals {
+    pub count: u64,
+    pub total_amount: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DistributionHistoryV2 {
+    pub sender: Address,
+    pub token: Address,
+    pub amount: i128,
+    pub recipients_count: u32,
+    pub timestamp: u64,
+    pub references: Vec<Symbol>,
+}
+
+/// History record of a `distribute_as_streams` call. `stream_ids` line up
+/// with the recipients, in order.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamDistributionHistory {
+    pub sender: Address,
+    pub token: Address,
+    pub stream_contract: Address,
+    pub amount: i128,
+    pub recipients_count: u32,
+    pub timestamp: u64,
+    pub stream_ids: Vec<u64>,
+}
+
+/// History record of a `distribute_proportional` call, noting the token
+/// whose balances set the shares
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProportionalHistory {
+    pub sender: Address,
+    pub token: Address,
+    pub weight_token: Address,
+    pub amount: i128,
+    pub recipients_count: u32,
+    pub timestamp: u64,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DistributionPaidEvent {
+    pub recipient: Address,
+    pub amount: i128,
+    pub reference: Symbol,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientPaidEvent {
+    pub index: u32,
+    pub recipient: Address,
+    pub amount: i128,
+}
+
+/// A recipient `distribute_weighted_v3` couldn't pay under
+/// `FailurePolicy::SkipAndReport`; its amount stayed with the sender
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientSkippedEvent {
+    pub index: u32,
+    pub recipient: Address,
+    pub amount: i128,
+}
+
+/// What a weighted distribution paid; `distribution_id` is its history index
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DistributionReceipt {
+    pub distribution_id: u64,
+    pub total: i128,
+    pub recipients_count: u32,
+    pub fee: i128,
+}
+
+/// Outcome of `validate_distribution`. `error` is the `Error` code of the
+/// first failed check; `first_error_index` points at the offending entry
+/// when the problem is a single row.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationReport {
+    pub ok: bool,
+    pub total: i128,
+    pub fee: i128,
+    pub first_error_index: Option<u32>,
+    pub error: Option<u32>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DistributionSession {
+    pub sender: Address,
+    pub token: Address,
+    pub expected_total: i128,
+    pub escrowed: i128,
+    pub distributed: i128,
+    pub recipients_count: u32,
+    pub finalized: bool,
+}
+
+/// A recurring weighted distribution paid from escrow. `fee_per_run` is
+/// fixed at creation so later fee changes can't overdraw the escrow.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DistributionSchedule {
+    pub sender: Address,
+    pub token: Address,
+    pub recipients: Vec<Address>,
+    pub amounts: Vec<i128>,
+    pub interval: u64,
+    pub remaining: u32,
+    pub last_executed: u64,
+    pub fee_per_run: i128,
+}
+
+/// A saved set of recipients and integer weights, reusable through
+/// `distribute_from_list` by the owner that saved it
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientList {
+    pub name: Symbol,
+    pub recipients: Vec<Address>,
+    pub weights: Vec<u32>,
+}
+
+/// Published for every distribution, under the id its history entry got.
+/// `kind` names the entrypoint family: `equal`, `weighted`, `multi_token`,
+/// `approved`, `proportional`, `streams`, `payouts`, `session`,
+/// `schedule` or `list`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DistributionExecutedEvent {
+    pub distribution_id: u64,
+    pub sender: Address,
+    pub token: Address,
+    pub total_amount: i128,
+    pub fee: i128,
+    pub recipients_count: u32,
+    pub kind: Symbol,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProtocolFeeChangedEvent {
+    pub old_fee: u32,
+    pub new_fee: u32,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeRegistryChangedEvent {
+    pub old_registry: Option<Address>,
+    pub new_registry: Option<Address>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScheduleExecutedEvent {
+    pub schedule_id: u64,
+    pub distribution_id: u64,
+    pub remaining: u32,
+}
+
+/// Where `distribute_equal_v2` sends the remainder of a total that doesn't
+/// split evenly across recipients
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RemainderPolicy {
+    KeepWithSender,
+    ToFirstRecipient,
+    ToFeeAddress,
+}
+
+/// What the `_dedup` distributions do when an address appears more than
+/// once in the recipient list
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DuplicatePolicy {
+    Reject,
+    Merge,
+}
+
+/// What `distribute_weighted_v3` does when a transfer fails
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailurePolicy {
+    RevertAll,
+    SkipAndReport,
+}
+
+/// One token's share of a `distribute_multi_token` call
+#[contracttype]
+#[derive(Clone)]
+pub struct TokenDistribution {
+    pub token: Address,
+    pub recipients: Vec<Address>,
+    pub amounts: Vec<i128>,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Payout {
+    pub recipient: Address,
+    pub amount: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractUpgradedEvent {
+    pub new_wasm_hash: BytesN<32>,
+    pub version: u32,
+}
+
+// Codes stay at or below `common::CONTRACT_ERROR_MAX`; codes from
+// `common::SHARED_ERROR_BASE` up are reserved for shared errors
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    SessionNotFound = 1,
+    SessionFinalized = 2,
+    SessionTotalExceeded = 3,
+    InvalidAmount = 4,
+    InvalidTimeRange = 5,
+    FeeQueryRangeTooLarge = 6,
+    ReferencesMismatch = 7,
+    EmptyReference = 8,
+    TokenNotAllowed = 9,
+    DailyCapExceeded = 10,
+    TooManyRecipients = 11,
+    NoEligibleRecipients = 12,
+    ScheduleNotFound = 13,
+    ScheduleNotDue = 14,
+    ScheduleFinished = 15,
+    LengthMismatch = 16,
+    NoRecipients = 17,
+    InsufficientBalance = 18,
+    FeeTooHigh = 19,
+    InvalidRecipient = 20,
+    AlreadyInitialized = 21,
+    DuplicateRecipient = 22,
+    InvalidLimit = 23,
+    NothingToDistribute = 24,
+    /// A transfer moved a different amount in or out of the contract's
+    /// escrow than requested, e.g. a token that takes a transfer fee
+    NonStandardTokenBehavior = 25,
+    ListNotFound = 26,
+}
+
 #[contractimpl]
 impl DistributorContract {
+    /// Configure the contract as part of deployment, leaving no window
+    /// in which someone else could `initialize` it
+    pub fn __constructor(env: Env, admin: Address, protocol_fee_percent: u32, fee_address: Address) {
+        Self::configure(&env, admin, protocol_fee_percent, fee_address);
+    }
+
+    /// Kept for existing tooling; fails with `AlreadyInitialized` on
+    /// contracts set up by the constructor
     pub fn initialize(env: Env, admin: Address, protocol_fee_percent: u32, fee_address: Address) {
         if env.storage().instance().has(&Symbol::new(&env, "admin")) {
-            panic!("Contract already initialized");
+            panic_with_error!(&env, Error::AlreadyInitialized);
         }
         admin.require_auth();
+        Self::configure(&env, admin, protocol_fee_percent, fee_address);
+    }
+
+    fn configure(env: &Env, admin: Address, protocol_fee_percent: u32, fee_address: Address) {
+        Self::assert_fee_within_cap(env, protocol_fee_percent);
         
         let storage = env.storage().instance();
-        storage.set(&Symbol::new(&env, "admin"), &admin);
-        storage.set(&Symbol::new(&env, "fee_pct"), &protocol_fee_percent);
-        storage.set(&Symbol::new(&env, "fee_addr"), &fee_address);
-        storage.set(&Symbol::new(&env, "tot_dist"), &0u64);
-        storage.set(&Symbol::new(&env, "tot_amt"), &0i128);
-        storage.set(&Symbol::new(&env, "hist_cnt"), &0u64);
+        storage.set(&Symbol::new(env, "admin"), &admin);
+        storage.set(&Symbol::new(env, "fee_pct"), &protocol_fee_percent);
+        storage.set(&Symbol::new(env, "fee_addr"), &fee_address);
+        storage.set(&Symbol::new(env, "tot_dist"), &0u64);
+        storage.set(&Symbol::new(env, "tot_amt"), &0i128);
+        storage.set(&Symbol::new(env, "hist_cnt"), &0u64);
+        storage.set(&Symbol::new(env, "version"), &CONTRACT_VERSION);
     }
 
     
@@ -57,6 +369,7 @@ impl DistributorContract {
         recipients: Vec<Address>,
     ) {
         sender.require_auth();
+        Self::assert_token_allowed(&env, &token);
         
         let recipient_count = recipients.len() as i128;
         assert!(recipient_count > 0, "No recipients provided");
@@ -64,495 +377,3437 @@ impl DistributorContract {
         
         let amount_per_recipient = total_amount / recipient_count;
         assert!(amount_per_recipient > 0, "Amount too small to distribute");
+        Self::consume_daily_cap(&env, &sender, total_amount);
         
         let token_client = token::Client::new(&env, &token);
         
        
-        let protocol_fee = Self::calculate_fee(&env, total_amount);
-        let total_with_fee = total_amount + protocol_fee;
+        let protocol_fee = Self::calculate_fee(&env, &sender, total_amount);
         
         if protocol_fee > 0 {
             let fee_address: Address = env.storage().instance()
                 .get(&Symbol::new(&env, "fee_addr"))
                 .unwrap();
-            token_client.transfer(&sender, &fee_address, &protocol_fee);
+            Self::transfer_if_needed(&token_client, &sender, &fee_address, protocol_fee);
+            Self::record_fee_paid(&env, &sender, protocol_fee);
         }
         
         
         for recipient in recipients.iter() {
-            token_client.transfer(&sender, &recipient, &amount_per_recipient);
+            Self::assert_valid_recipient(&env, &sender, &token, &recipient);
+            Self::transfer_if_needed(&token_client, &sender, &recipient, amount_per_recipient);
         }
         
         
         Self::update_global_stats(&env, total_amount);
         Self::update_token_stats(&env, &token, total_amount, recipients.len());
         Self::update_user_stats(&env, &sender, total_amount);
-        Self::record_history(&env, sender, token, total_amount, recipients.len());
+        Self::record_history(&env, sender, token, total_amount, recipients.len(), protocol_fee, "equal");
     }
 
-  
-    pub fn distribute_weighted(
+    /// Equal distribution with explicit remainder handling. Stats and
+    /// history record what recipients actually received, not the requested
+    /// total. The fee is charged on `total_amount`, as in `distribute_equal`.
+    pub fn distribute_equal_v2(
         env: Env,
         sender: Address,
         token: Address,
+        total_amount: i128,
         recipients: Vec<Address>,
-        amounts: Vec<i128>,
+        remainder_policy: RemainderPolicy,
     ) {
         sender.require_auth();
-        
-        assert!(recipients.len() == amounts.len(), "Recipients and amounts must match");
-        assert!(recipients.len() > 0, "No recipients provided");
-        
+        Self::assert_token_allowed(&env, &token);
+
+        let recipient_count = recipients.len() as i128;
+        assert!(recipient_count > 0, "No recipients provided");
+        assert!(total_amount > 0, "Amount must be positive");
+
+        let amount_per_recipient = total_amount / recipient_count;
+        assert!(amount_per_recipient > 0, "Amount too small to distribute");
+        let remainder = total_amount - amount_per_recipient * recipient_count;
+
+        let distributed = match remainder_policy {
+            RemainderPolicy::ToFirstRecipient => total_amount,
+            _ => total_amount - remainder,
+        };
+        let leaving_sender = match remainder_policy {
+            RemainderPolicy::KeepWithSender => distributed,
+            _ => total_amount,
+        };
+        Self::consume_daily_cap(&env, &sender, leaving_sender);
+
         let token_client = token::Client::new(&env, &token);
-        
-        let mut total_amount: i128 = 0;
-        for amount in amounts.iter() {
-            assert!(amount > 0, "All amounts must be positive");
-            total_amount += amount;
-        }
-        
-       
-        let protocol_fee = Self::calculate_fee(&env, total_amount);
-        
-       
+        let fee_address: Address = env.storage().instance()
+            .get(&Symbol::new(&env, "fee_addr"))
+            .unwrap();
+
+        let protocol_fee = Self::calculate_fee(&env, &sender, total_amount);
         if protocol_fee > 0 {
-            let fee_address: Address = env.storage().instance()
-                .get(&Symbol::new(&env, "fee_addr"))
-                .unwrap();
-            token_client.transfer(&sender, &fee_address, &protocol_fee);
+            Self::transfer_if_needed(&token_client, &sender, &fee_address, protocol_fee);
+            Self::record_fee_paid(&env, &sender, protocol_fee);
         }
-        
-        
-        for i in 0..recipients.len() {
-            let recipient = recipients.get(i).unwrap();
-            let amount = amounts.get(i).unwrap();
-            token_client.transfer(&sender, &recipient, &amount);
+        if remainder > 0 && remainder_policy == RemainderPolicy::ToFeeAddress {
+            Self::transfer_if_needed(&token_client, &sender, &fee_address, remainder);
         }
-        
-        
-        Self::update_global_stats(&env, total_amount);
-        Self::update_token_stats(&env, &token, total_amount, recipients.len());
-        Self::update_user_stats(&env, &sender, total_amount);
-        Self::record_history(&env, sender, token, total_amount, recipients.len());
-    }
-
-   
-    fn update_global_stats(env: &Env, amount: i128) {
-        let storage = env.storage().instance();
-        let mut total_dist: u64 = storage.get(&Symbol::new(&env, "tot_dist")).unwrap_or(0);
-        let mut total_amt: i128 = storage.get(&Symbol::new(&env, "tot_amt")).unwrap_or(0);
-        
-        total_dist += 1;
-        total_amt += amount;
-        
-        storage.set(&Symbol::new(&env, "tot_dist"), &total_dist);
-        storage.set(&Symbol::new(&env, "tot_amt"), &total_amt);
-    }
 
-    fn update_token_stats(env: &Env, token: &Address, amount: i128, recipient_count: u32) {
-        let storage = env.storage().persistent();
-        let key = (Symbol::new(&env, "tok_stats"), token);
-        
-        let mut stats: TokenStats = storage.get(&key).unwrap_or(TokenStats {
-            total_amount: 0,
-            distribution_count: 0,
-            last_time: 0,
-        });
-        
-        stats.total_amount += amount;
-        stats.distribution_count += 1;
-    
-        let ts = env.ledger().timestamp();
-        stats.last_time = if ts == 0 { 1 } else { ts };
-        
-        storage.set(&key, &stats);
-    }
+        for (i, recipient) in recipients.iter().enumerate() {
+            let amount = if i == 0 && remainder_policy == RemainderPolicy::ToFirstRecipient {
+                amount_per_recipient + remainder
+            } else {
+                amount_per_recipient
+            };
+            Self::assert_valid_recipient(&env, &sender, &token, &recipient);
+            Self::transfer_if_needed(&token_client, &sender, &recipient, amount);
+        }
 
-    fn update_user_stats(env: &Env, user: &Address, amount: i128) {
-        let storage = env.storage().persistent();
-        let key = (Symbol::new(&env, "usr_stats"), user);
-        
-        let mut stats: UserStats = storage.get(&key).unwrap_or(UserStats {
-            distributions_initiated: 0,
-            total_amount: 0,
-        });
-        
-        stats.distributions_initiated += 1;
-        stats.total_amount += amount;
-        
-        storage.set(&key, &stats);
+        Self::update_global_stats(&env, distributed);
+        Self::update_token_stats(&env, &token, distributed, recipients.len());
+        Self::update_user_stats(&env, &sender, distributed);
+        Self::record_history(&env, sender, token, distributed, recipients.len(), protocol_fee, "equal");
     }
 
-    fn record_history(env: &Env, sender: Address, token: Address, amount: i128, recipient_count: u32) {
-        let storage = env.storage().persistent();
-        let mut count: u64 = env.storage().instance()
-            .get(&Symbol::new(&env, "hist_cnt"))
-            .unwrap_or(0);
-        
-        let history = DistributionHistory {
-            sender,
-            token,
-            amount,
-            recipients_count: recipient_count,
-            timestamp: env.ledger().timestamp(),
-        };
-        
-        storage.set(&(Symbol::new(&env, "history"), count), &history);
-        count += 1;
-        env.storage().instance().set(&Symbol::new(&env, "hist_cnt"), &count);
-    }
+    /// `distribute_equal` with explicit handling of repeated addresses.
+    /// Every row is one share; with `Merge` an address listed twice gets
+    /// both shares in a single transfer, with `Reject` the call fails with
+    /// `DuplicateRecipient` (`find_duplicate_recipient` gives the row). The
+    /// remainder of an uneven split stays with the sender and the fee is
+    /// charged on what's distributed.
+    pub fn distribute_equal_dedup(
+        env: Env,
+        sender: Address,
+        token: Address,
+        total_amount: i128,
+        recipients: Vec<Address>,
+        duplicate_policy: DuplicatePolicy,
+    ) -> DistributionReceipt {
+        sender.require_auth();
+        if recipients.is_empty() {
+            panic_with_error!(&env, Error::NoRecipients);
+        }
 
-    fn calculate_fee(env: &Env, amount: i128) -> i128 {
-        let fee_percent: u32 = env.storage().instance()
-            .get(&Symbol::new(&env, "fee_pct"))
-            .unwrap_or(0);
-        (amount * fee_percent as i128) / 10000
-    }
+        let amount_per_recipient = total_amount / recipients.len() as i128;
+        if amount_per_recipient <= 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        let mut amounts = Vec::new(&env);
+        for _ in 0..recipients.len() {
+            amounts.push_back(amount_per_recipient);
+        }
 
-  
-    pub fn get_total_distributions(env: Env) -> u64 {
-        env.storage().instance().get(&Symbol::new(&env, "tot_dist")).unwrap_or(0)
+        let (recipients, amounts) = Self::apply_duplicate_policy(&env, &recipients, &amounts, duplicate_policy);
+        Self::execute_weighted(&env, sender, token, &recipients, &amounts, "equal")
     }
 
-    pub fn get_total_distributed_amount(env: Env) -> i128 {
-        env.storage().instance().get(&Symbol::new(&env, "tot_amt")).unwrap_or(0)
-    }
+    /// `distribute_weighted_v2` with explicit handling of repeated
+    /// addresses: `Merge` sums an address's amounts into one transfer,
+    /// `Reject` fails with `DuplicateRecipient`
+    pub fn distribute_weighted_dedup(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+        duplicate_policy: DuplicatePolicy,
+    ) -> DistributionReceipt {
+        sender.require_auth();
+        if recipients.len() != amounts.len() {
+            panic_with_error!(&env, Error::LengthMismatch);
+        }
 
-    pub fn get_token_stats(env: Env, token: Address) -> Option<TokenStats> {
-        env.storage().persistent().get(&(Symbol::new(&env, "tok_stats"), token))
+        let (recipients, amounts) = Self::apply_duplicate_policy(&env, &recipients, &amounts, duplicate_policy);
+        Self::execute_weighted(&env, sender, token, &recipients, &amounts, "weighted")
     }
 
-    pub fn get_user_stats(env: Env, user: Address) -> Option<UserStats> {
-        env.storage().persistent().get(&(Symbol::new(&env, "usr_stats"), user))
+    /// Index of the first recipient whose address already appeared earlier
+    /// in the list, if any
+    pub fn find_duplicate_recipient(env: Env, recipients: Vec<Address>) -> Option<u32> {
+        let mut seen: Map<Address, bool> = Map::new(&env);
+        for (i, recipient) in recipients.iter().enumerate() {
+            if seen.contains_key(recipient.clone()) {
+                return Some(i as u32);
+            }
+            seen.set(recipient, true);
+        }
+        None
     }
 
-    pub fn get_distribution_history(env: Env, start_id: u64, limit: u64) -> Vec<DistributionHistory> {
-        let mut history = Vec::new(&env);
-        let storage = env.storage().persistent();
-        
-        for i in start_id..(start_id + limit) {
-            if let Some(record) = storage.get::<_, DistributionHistory>(&(Symbol::new(&env, "history"), i)) {
-                history.push_back(record);
+    /// Reject or merge repeated recipients. Merged rows keep the position
+    /// of the address's first appearance.
+    fn apply_duplicate_policy(
+        env: &Env,
+        recipients: &Vec<Address>,
+        amounts: &Vec<i128>,
+        policy: DuplicatePolicy,
+    ) -> (Vec<Address>, Vec<i128>) {
+        if policy == DuplicatePolicy::Reject {
+            if Self::find_duplicate_recipient(env.clone(), recipients.clone()).is_some() {
+                panic_with_error!(env, Error::DuplicateRecipient);
             }
+            return (recipients.clone(), amounts.clone());
         }
-        
-        history
-    }
 
-    pub fn get_admin(env: Env) -> Option<Address> {
-        env.storage().instance().get(&Symbol::new(&env, "admin"))
+        let mut totals: Map<Address, i128> = Map::new(env);
+        let mut unique = Vec::new(env);
+        for (recipient, amount) in recipients.iter().zip(amounts.iter()) {
+            match totals.get(recipient.clone()) {
+                Some(total) => totals.set(recipient, total + amount),
+                None => {
+                    unique.push_back(recipient.clone());
+                    totals.set(recipient, amount);
+                }
+            }
+        }
+        let mut merged = Vec::new(env);
+        for recipient in unique.iter() {
+            merged.push_back(totals.get(recipient).unwrap());
+        }
+        (unique, merged)
     }
 
-    pub fn set_protocol_fee(env: Env, admin: Address, new_fee_percent: u32) {
-        admin.require_auth();
-        let stored_admin: Address = env.storage().instance()
-            .get(&Symbol::new(&env, "admin"))
+  
+    pub fn distribute_weighted(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+    ) {
+        sender.require_auth();
+        Self::execute_weighted(&env, sender, token, &recipients, &amounts, "weighted");
+    }
+
+    /// `distribute_weighted` with a per-recipient `RecipientPaid` event and
+    /// a receipt whose id can be passed to `get_distribution_history`
+    pub fn distribute_weighted_v2(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+    ) -> DistributionReceipt {
+        sender.require_auth();
+        let receipt = Self::execute_weighted(&env, sender, token, &recipients, &amounts, "weighted");
+
+        for i in 0..recipients.len() {
+            env.events().publish(
+                ("RecipientPaid", receipt.distribution_id),
+                RecipientPaidEvent {
+                    index: i,
+                    recipient: recipients.get(i).unwrap(),
+                    amount: amounts.get(i).unwrap(),
+                },
+            );
+        }
+        receipt
+    }
+
+    /// Weighted distribution with a choice of what a failed transfer does.
+    /// `RevertAll` behaves like `distribute_weighted`. `SkipAndReport`
+    /// attempts each transfer on its own: a failure (e.g. a recipient the
+    /// issuer has frozen) leaves that amount with the sender and publishes
+    /// `RecipientSkipped`. Fee, stats, daily cap and history only count
+    /// what was paid. Returns the indexes of the skipped recipients; fails
+    /// with `NothingToDistribute` if every transfer failed.
+    pub fn distribute_weighted_v3(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+        policy: FailurePolicy,
+    ) -> Vec<u32> {
+        sender.require_auth();
+        match policy {
+            FailurePolicy::RevertAll => {
+                Self::execute_weighted(&env, sender, token, &recipients, &amounts, "weighted");
+                Vec::new(&env)
+            }
+            FailurePolicy::SkipAndReport => Self::execute_weighted_skipping(&env, sender, token, &recipients, &amounts),
+        }
+    }
+
+    /// `execute_weighted` that skips recipients whose transfer fails
+    fn execute_weighted_skipping(
+        env: &Env,
+        sender: Address,
+        token: Address,
+        recipients: &Vec<Address>,
+        amounts: &Vec<i128>,
+    ) -> Vec<u32> {
+        Self::assert_token_allowed(env, &token);
+        assert!(recipients.len() == amounts.len(), "Recipients and amounts must match");
+        assert!(!recipients.is_empty(), "No recipients provided");
+        assert!(Self::first_invalid_amount(amounts).is_none(), "All amounts must be positive");
+
+        let token_client = token::Client::new(env, &token);
+        let mut failed = Vec::new(env);
+        let mut distributed: i128 = 0;
+        for i in 0..recipients.len() {
+            let recipient = recipients.get(i).unwrap();
+            let amount = amounts.get(i).unwrap();
+            Self::assert_valid_recipient(env, &sender, &token, &recipient);
+            if matches!(token_client.try_transfer(&sender, &recipient, &amount), Ok(Ok(()))) {
+                distributed += amount;
+            } else {
+                failed.push_back(i);
+            }
+        }
+        if distributed == 0 {
+            panic_with_error!(env, Error::NothingToDistribute);
+        }
+        Self::consume_daily_cap(env, &sender, distributed);
+
+        let protocol_fee = Self::calculate_fee(env, &sender, distributed);
+        if protocol_fee > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(env, "fee_addr"))
+                .unwrap();
+            Self::transfer_if_needed(&token_client, &sender, &fee_address, protocol_fee);
+            Self::record_fee_paid(env, &sender, protocol_fee);
+        }
+
+        let paid_count = recipients.len() - failed.len();
+        Self::update_global_stats(env, distributed);
+        Self::update_token_stats(env, &token, distributed, paid_count);
+        Self::update_user_stats(env, &sender, distributed);
+        let distribution_id = Self::record_history(env, sender, token, distributed, paid_count, protocol_fee, "weighted");
+
+        for i in failed.iter() {
+            env.events().publish(
+                ("RecipientSkipped", distribution_id),
+                RecipientSkippedEvent {
+                    index: i,
+                    recipient: recipients.get(i).unwrap(),
+                    amount: amounts.get(i).unwrap(),
+                },
+            );
+        }
+        failed
+    }
+
+    /// Distribute everything `sender` has approved this contract to spend
+    /// in `token`, split by integer `weights`. The allowance is pulled into
+    /// the contract, the fee is taken out of it, and the rest is paid out
+    /// pro rata, with the rounding remainder going to the heaviest
+    /// recipient (the first one, on a tie). At most 100 recipients.
+    pub fn distribute_all_approved(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        weights: Vec<u32>,
+    ) -> DistributionReceipt {
+        sender.require_auth();
+        Self::assert_token_allowed(&env, &token);
+        if recipients.len() != weights.len() {
+            panic_with_error!(&env, Error::LengthMismatch);
+        }
+        if recipients.is_empty() {
+            panic_with_error!(&env, Error::NoRecipients);
+        }
+        if recipients.len() > MAX_MULTI_TOKEN_RECIPIENTS {
+            panic_with_error!(&env, Error::TooManyRecipients);
+        }
+        if weights.iter().any(|weight| weight == 0) {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+
+        let token_client = token::Client::new(&env, &token);
+        let contract = env.current_contract_address();
+        let approved = token_client.allowance(&sender, &contract);
+        if approved <= 0 {
+            panic_with_error!(&env, Error::NothingToDistribute);
+        }
+        let held = token_client.balance(&contract);
+        token_client.transfer_from(&contract, &sender, &contract, &approved);
+
+        let protocol_fee = Self::calculate_fee(&env, &sender, approved);
+        let total_amount = approved - protocol_fee;
+        Self::consume_daily_cap(&env, &sender, total_amount);
+        if protocol_fee > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(&env, "fee_addr"))
+                .unwrap();
+            token_client.transfer(&contract, &fee_address, &protocol_fee);
+            Self::record_fee_paid(&env, &sender, protocol_fee);
+        }
+
+        let shares = Self::weighted_shares(&env, total_amount, &weights);
+        for i in 0..recipients.len() {
+            let recipient = recipients.get(i).unwrap();
+            Self::assert_valid_recipient(&env, &sender, &token, &recipient);
+            Self::transfer_if_needed(&token_client, &contract, &recipient, shares.get(i).unwrap());
+        }
+        // Everything pulled in was paid straight back out
+        Self::assert_escrow_moved(&env, &token_client, held, 0);
+
+        Self::update_global_stats(&env, total_amount);
+        Self::update_token_stats(&env, &token, total_amount, recipients.len());
+        Self::update_user_stats(&env, &sender, total_amount);
+        let distribution_id = Self::record_history(&env, sender, token, total_amount, recipients.len(), protocol_fee, "approved");
+
+        DistributionReceipt {
+            distribution_id,
+            total: total_amount,
+            recipients_count: recipients.len(),
+            fee: protocol_fee,
+        }
+    }
+
+    /// Split `total_amount` pro rata by `weights`, with the rounding
+    /// remainder going to the heaviest weight (the first one, on a tie)
+    fn weighted_shares(env: &Env, total_amount: i128, weights: &Vec<u32>) -> Vec<i128> {
+        let total_weight: i128 = weights.iter().map(|weight| weight as i128).sum();
+        let mut heaviest = 0;
+        let mut shares = Vec::new(env);
+        for (i, weight) in weights.iter().enumerate() {
+            if weight > weights.get(heaviest).unwrap() {
+                heaviest = i as u32;
+            }
+            shares.push_back(total_amount * weight as i128 / total_weight);
+        }
+        let remainder = total_amount - shares.iter().sum::<i128>();
+        shares.set(heaviest, shares.get(heaviest).unwrap() + remainder);
+        shares
+    }
+
+    /// Weighted distributions in several tokens at once, each charged its
+    /// own fee and recorded as its own history entry. Returns a group id
+    /// whose history ids `get_distribution_group` lists. At most 100
+    /// recipients across all tokens.
+    pub fn distribute_multi_token(env: Env, sender: Address, distributions: Vec<TokenDistribution>) -> u64 {
+        sender.require_auth();
+        assert!(!distributions.is_empty(), "No distributions provided");
+
+        let total_recipients: u32 = distributions.iter().map(|d| d.recipients.len()).sum();
+        if total_recipients > MAX_MULTI_TOKEN_RECIPIENTS {
+            panic_with_error!(&env, Error::TooManyRecipients);
+        }
+
+        let group_id: u64 = env.storage().instance()
+            .get(&Symbol::new(&env, "group_cnt"))
+            .unwrap_or(0);
+        env.storage().instance().set(&Symbol::new(&env, "group_cnt"), &(group_id + 1));
+
+        let mut history_ids = Vec::new(&env);
+        for distribution in distributions.iter() {
+            let receipt = Self::execute_weighted(
+                &env,
+                sender.clone(),
+                distribution.token,
+                &distribution.recipients,
+                &distribution.amounts,
+                "multi_token",
+            );
+            history_ids.push_back(receipt.distribution_id);
+        }
+        env.storage().persistent().set(&(Symbol::new(&env, "dist_group"), group_id), &history_ids);
+
+        group_id
+    }
+
+    /// History ids written by one `distribute_multi_token` call
+    pub fn get_distribution_group(env: Env, group_id: u64) -> Vec<u64> {
+        env.storage().persistent()
+            .get(&(Symbol::new(&env, "dist_group"), group_id))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Weighted distribution where each payment carries an accounting
+    /// reference (e.g. an invoice id), emitted per recipient and kept in
+    /// the extended history record
+    pub fn distribute_weighted_with_refs(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+        refs: Vec<Symbol>,
+    ) {
+        sender.require_auth();
+
+        if refs.len() != recipients.len() {
+            panic_with_error!(&env, Error::ReferencesMismatch);
+        }
+        let empty = Symbol::new(&env, "");
+        if refs.iter().any(|reference| reference == empty) {
+            panic_with_error!(&env, Error::EmptyReference);
+        }
+
+        let history_id = Self::execute_weighted(&env, sender, token, &recipients, &amounts, "weighted").distribution_id;
+
+        for i in 0..recipients.len() {
+            env.events().publish(
+                ("DistributionPaid",),
+                DistributionPaidEvent {
+                    recipient: recipients.get(i).unwrap(),
+                    amount: amounts.get(i).unwrap(),
+                    reference: refs.get(i).unwrap(),
+                },
+            );
+        }
+
+        let history: DistributionHistory = env.storage().persistent()
+            .get(&(Symbol::new(&env, "history"), history_id))
             .unwrap();
-        assert!(admin == stored_admin, "Unauthorized");
+        let history_v2 = DistributionHistoryV2 {
+            sender: history.sender,
+            token: history.token,
+            amount: history.amount,
+            recipients_count: history.recipients_count,
+            timestamp: history.timestamp,
+            references: refs,
+        };
+        env.storage().persistent().set(&(Symbol::new(&env, "history_v2"), history_id), &history_v2);
+    }
+
+    /// Split `total_amount` of `reward_token` across `holders` in
+    /// proportion to their current `weight_token` balances. Holders with no
+    /// balance are skipped; the rounding remainder goes to the largest
+    /// holder. At most 100 holders.
+    pub fn distribute_proportional(
+        env: Env,
+        sender: Address,
+        reward_token: Address,
+        weight_token: Address,
+        holders: Vec<Address>,
+        total_amount: i128,
+    ) -> DistributionReceipt {
+        sender.require_auth();
+        assert!(!holders.is_empty(), "No recipients provided");
+        assert!(total_amount > 0, "Amount must be positive");
+        if holders.len() > MAX_PROPORTIONAL_HOLDERS {
+            panic_with_error!(&env, Error::TooManyRecipients);
+        }
+
+        let weight_client = token::Client::new(&env, &weight_token);
+        let mut weights = Vec::new(&env);
+        let mut total_weight: i128 = 0;
+        for holder in holders.iter() {
+            let balance = weight_client.balance(&holder).max(0);
+            weights.push_back(balance);
+            total_weight += balance;
+        }
+        if total_weight == 0 {
+            panic_with_error!(&env, Error::NoEligibleRecipients);
+        }
+
+        let mut recipients = Vec::new(&env);
+        let mut amounts = Vec::new(&env);
+        let mut largest = 0;
+        let mut largest_weight = 0;
+        let mut allocated = 0;
+        for (holder, weight) in holders.iter().zip(weights.iter()) {
+            if weight == 0 {
+                continue;
+            }
+            if weight > largest_weight {
+                largest = recipients.len();
+                largest_weight = weight;
+            }
+            let share = total_amount * weight / total_weight;
+            recipients.push_back(holder);
+            amounts.push_back(share);
+            allocated += share;
+        }
+        amounts.set(largest, amounts.get(largest).unwrap() + total_amount - allocated);
+
+        // Shares rounded down to zero aren't worth a transfer
+        let mut payees = Vec::new(&env);
+        let mut payouts = Vec::new(&env);
+        for (recipient, amount) in recipients.iter().zip(amounts.iter()) {
+            if amount > 0 {
+                payees.push_back(recipient);
+                payouts.push_back(amount);
+            }
+        }
+
+        let receipt = Self::execute_weighted(&env, sender.clone(), reward_token.clone(), &payees, &payouts, "proportional");
+
+        let history = ProportionalHistory {
+            sender,
+            token: reward_token,
+            weight_token,
+            amount: receipt.total,
+            recipients_count: receipt.recipients_count,
+            timestamp: env.ledger().timestamp(),
+        };
+        env.storage().persistent().set(&(Symbol::new(&env, "history_prop"), receipt.distribution_id), &history);
+
+        receipt
+    }
+
+    /// Open a fully funded payment stream from `sender` to each recipient on
+    /// `stream_contract`, vesting between `start_time` and `end_time`. The
+    /// protocol fee is charged here on the total. The sender's authorization
+    /// must cover the nested `create_stream` calls; any failed creation
+    /// reverts the whole call. At most 25 recipients.
+    #[allow(clippy::too_many_arguments)]
+    pub fn distribute_as_streams(
+        env: Env,
+        sender: Address,
+        token: Address,
+        stream_contract: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+        start_time: u64,
+        end_time: u64,
+    ) -> Vec<u64> {
+        sender.require_auth();
+        Self::assert_token_allowed(&env, &token);
+        if recipients.len() != amounts.len() {
+            panic_with_error!(&env, Error::LengthMismatch);
+        }
+        if recipients.is_empty() {
+            panic_with_error!(&env, Error::NoRecipients);
+        }
+        if recipients.len() > MAX_STREAM_RECIPIENTS {
+            panic_with_error!(&env, Error::TooManyRecipients);
+        }
+        if Self::first_invalid_amount(&amounts).is_some() {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        let total_amount: i128 = amounts.iter().sum();
+        Self::consume_daily_cap(&env, &sender, total_amount);
+
+        let protocol_fee = Self::calculate_fee(&env, &sender, total_amount);
+        if protocol_fee > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(&env, "fee_addr"))
+                .unwrap();
+            Self::transfer_if_needed(&token::Client::new(&env, &token), &sender, &fee_address, protocol_fee);
+            Self::record_fee_paid(&env, &sender, protocol_fee);
+        }
+
+        let create_stream = Symbol::new(&env, "create_stream");
+        let mut stream_ids = Vec::new(&env);
+        for (recipient, amount) in recipients.iter().zip(amounts.iter()) {
+            Self::assert_valid_recipient(&env, &sender, &token, &recipient);
+            let args = (sender.clone(), recipient, token.clone(), amount, amount, start_time, end_time).into_val(&env);
+            let stream_id: u64 = env.invoke_contract(&stream_contract, &create_stream, args);
+            stream_ids.push_back(stream_id);
+        }
+
+        Self::update_global_stats(&env, total_amount);
+        Self::update_token_stats(&env, &token, total_amount, recipients.len());
+        Self::update_user_stats(&env, &sender, total_amount);
+        let history_id = Self::record_history(&env, sender.clone(), token.clone(), total_amount, recipients.len(), protocol_fee, "streams");
+
+        let history = StreamDistributionHistory {
+            sender,
+            token,
+            stream_contract,
+            amount: total_amount,
+            recipients_count: recipients.len(),
+            timestamp: env.ledger().timestamp(),
+            stream_ids: stream_ids.clone(),
+        };
+        env.storage().persistent().set(&(Symbol::new(&env, "history_strm"), history_id), &history);
+
+        stream_ids
+    }
+
+    /// Extended history record for distributions paid as streams
+    pub fn get_stream_distribution_history(env: Env, history_id: u64) -> Option<StreamDistributionHistory> {
+        env.storage().persistent().get(&(Symbol::new(&env, "history_strm"), history_id))
+    }
+
+    /// Extended history record for proportional distributions
+    pub fn get_proportional_history(env: Env, history_id: u64) -> Option<ProportionalHistory> {
+        env.storage().persistent().get(&(Symbol::new(&env, "history_prop"), history_id))
+    }
+
+    /// Extended history record for distributions made with references
+    pub fn get_distribution_history_v2(env: Env, history_id: u64) -> Option<DistributionHistoryV2> {
+        env.storage().persistent().get(&(Symbol::new(&env, "history_v2"), history_id))
+    }
+
+    /// Body of `distribute_weighted` without the auth check
+    fn execute_weighted(
+        env: &Env,
+        sender: Address,
+        token: Address,
+        recipients: &Vec<Address>,
+        amounts: &Vec<i128>,
+        kind: &str,
+    ) -> DistributionReceipt {
+        Self::assert_token_allowed(env, &token);
+        assert!(recipients.len() == amounts.len(), "Recipients and amounts must match");
+        assert!(!recipients.is_empty(), "No recipients provided");
         
-        env.storage().instance().set(&Symbol::new(&env, "fee_pct"), &new_fee_percent);
+        let token_client = token::Client::new(env, &token);
+        
+        assert!(Self::first_invalid_amount(amounts).is_none(), "All amounts must be positive");
+        let total_amount: i128 = amounts.iter().sum();
+        Self::consume_daily_cap(env, &sender, total_amount);
+        
+       
+        let protocol_fee = Self::calculate_fee(env, &sender, total_amount);
+        
+       
+        if protocol_fee > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(env, "fee_addr"))
+                .unwrap();
+            Self::transfer_if_needed(&token_client, &sender, &fee_address, protocol_fee);
+            Self::record_fee_paid(env, &sender, protocol_fee);
+        }
+        
+        
+        for i in 0..recipients.len() {
+            let recipient = recipients.get(i).unwrap();
+            let amount = amounts.get(i).unwrap();
+            Self::assert_valid_recipient(env, &sender, &token, &recipient);
+            Self::transfer_if_needed(&token_client, &sender, &recipient, amount);
+        }
+        
+        
+        Self::update_global_stats(env, total_amount);
+        Self::update_token_stats(env, &token, total_amount, recipients.len());
+        Self::update_user_stats(env, &sender, total_amount);
+        let distribution_id = Self::record_history(env, sender, token, total_amount, recipients.len(), protocol_fee, kind);
+
+        DistributionReceipt {
+            distribution_id,
+            total: total_amount,
+            recipients_count: recipients.len(),
+            fee: protocol_fee,
+        }
+    }
+
+    /// Index of the first amount that isn't positive
+    fn first_invalid_amount(amounts: &Vec<i128>) -> Option<u32> {
+        amounts.iter().position(|amount| amount <= 0).map(|i| i as u32)
+    }
+
+    /// Run the checks `distribute_weighted` makes (token allowed, lengths,
+    /// positive amounts, valid recipients, daily cap, sender balance
+    /// covering total plus fee)
+    /// without moving funds. Meant to be simulated before submitting a
+    /// large payload.
+    pub fn validate_distribution(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+    ) -> ValidationReport {
+        let total: i128 = amounts.iter().filter(|amount| *amount > 0).sum();
+        let fee = Self::calculate_fee(&env, &sender, total);
+        let failure = |index: Option<u32>, error: Error| ValidationReport {
+            ok: false,
+            total,
+            fee,
+            first_error_index: index,
+            error: Some(error as u32),
+        };
+
+        if !Self::is_token_allowed(env.clone(), token.clone()) {
+            return failure(None, Error::TokenNotAllowed);
+        }
+        if recipients.len() != amounts.len() {
+            return failure(None, Error::LengthMismatch);
+        }
+        if recipients.is_empty() {
+            return failure(None, Error::NoRecipients);
+        }
+        if let Some(index) = Self::first_invalid_amount(&amounts) {
+            return failure(Some(index), Error::InvalidAmount);
+        }
+        if let Some(index) = recipients.iter().position(|recipient| !Self::is_valid_recipient(&env, &sender, &token, &recipient)) {
+            return failure(Some(index as u32), Error::InvalidRecipient);
+        }
+        if total > Self::get_sender_remaining_cap(env.clone(), sender.clone()) {
+            return failure(None, Error::DailyCapExceeded);
+        }
+        if token::Client::new(&env, &token).balance(&sender) < total + fee {
+            return failure(None, Error::InsufficientBalance);
+        }
+
+        ValidationReport { ok: true, total, fee, first_error_index: None, error: None }
+    }
+
+    pub fn distribute_payouts(
+        env: Env,
+        sender: Address,
+        token: Address,
+        payouts: Vec<Payout>,
+    ) {
+        sender.require_auth();
+        Self::assert_token_allowed(&env, &token);
+
+        assert!(!payouts.is_empty(), "No recipients provided");
+
+        let token_client = token::Client::new(&env, &token);
+
+        let mut total_amount: i128 = 0;
+        for payout in payouts.iter() {
+            assert!(payout.amount > 0, "All amounts must be positive");
+            total_amount += payout.amount;
+        }
+        Self::consume_daily_cap(&env, &sender, total_amount);
+
+        let protocol_fee = Self::calculate_fee(&env, &sender, total_amount);
+
+        if protocol_fee > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(&env, "fee_addr"))
+                .unwrap();
+            Self::transfer_if_needed(&token_client, &sender, &fee_address, protocol_fee);
+            Self::record_fee_paid(&env, &sender, protocol_fee);
+        }
+
+        for payout in payouts.iter() {
+            Self::assert_valid_recipient(&env, &sender, &token, &payout.recipient);
+            Self::transfer_if_needed(&token_client, &sender, &payout.recipient, payout.amount);
+        }
+
+        Self::update_global_stats(&env, total_amount);
+        Self::update_token_stats(&env, &token, total_amount, payouts.len());
+        Self::update_user_stats(&env, &sender, total_amount);
+        Self::record_history(&env, sender, token, total_amount, payouts.len(), protocol_fee, "payouts");
     }
 
-    
-}
+   
+    /// Open a session for a distribution too large for one transaction.
+    /// Escrows `expected_total` plus the protocol fee on it; chunks are then
+    /// paid out of escrow and `finalize_session` refunds whatever is unused.
+    pub fn start_distribution_session(
+        env: Env,
+        sender: Address,
+        token: Address,
+        expected_total: i128,
+    ) -> u64 {
+        sender.require_auth();
+        Self::assert_token_allowed(&env, &token);
+
+        if expected_total <= 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        Self::consume_daily_cap(&env, &sender, expected_total);
+
+        let escrowed = expected_total + Self::calculate_fee(&env, &sender, expected_total);
+        let token_client = token::Client::new(&env, &token);
+        let held = token_client.balance(&env.current_contract_address());
+        token_client.transfer(&sender, &env.current_contract_address(), &escrowed);
+        Self::assert_escrow_moved(&env, &token_client, held, escrowed);
+
+        let session_id: u64 = env.storage().instance()
+            .get(&Symbol::new(&env, "sess_cnt"))
+            .unwrap_or(0);
+        env.storage().instance().set(&Symbol::new(&env, "sess_cnt"), &(session_id + 1));
+
+        let session = DistributionSession {
+            sender,
+            token,
+            expected_total,
+            escrowed,
+            distributed: 0,
+            recipients_count: 0,
+            finalized: false,
+        };
+        env.storage().persistent().set(&(Symbol::new(&env, "session"), session_id), &session);
+
+        session_id
+    }
+
+    /// Pay one chunk of recipients out of a session's escrow
+    pub fn add_distribution_chunk(
+        env: Env,
+        session_id: u64,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+    ) {
+        let mut session = Self::load_open_session(&env, session_id);
+        session.sender.require_auth();
+
+        assert!(recipients.len() == amounts.len(), "Recipients and amounts must match");
+        assert!(!recipients.is_empty(), "No recipients provided");
+
+        let mut chunk_total: i128 = 0;
+        for amount in amounts.iter() {
+            assert!(amount > 0, "All amounts must be positive");
+            chunk_total += amount;
+        }
+        if session.distributed + chunk_total > session.expected_total {
+            panic_with_error!(&env, Error::SessionTotalExceeded);
+        }
+
+        session.distributed += chunk_total;
+        session.recipients_count += recipients.len();
+        env.storage().persistent().set(&(Symbol::new(&env, "session"), session_id), &session);
+
+        let token_client = token::Client::new(&env, &session.token);
+        let held = token_client.balance(&env.current_contract_address());
+        for i in 0..recipients.len() {
+            let recipient = recipients.get(i).unwrap();
+            let amount = amounts.get(i).unwrap();
+            Self::assert_valid_recipient(&env, &session.sender, &session.token, &recipient);
+            token_client.transfer(&env.current_contract_address(), &recipient, &amount);
+        }
+        Self::assert_escrow_moved(&env, &token_client, held, -chunk_total);
+    }
+
+    /// Close a session: charge the fee on what was actually distributed,
+    /// refund the rest of the escrow and record a single history entry
+    pub fn finalize_session(env: Env, session_id: u64) {
+        let mut session = Self::load_open_session(&env, session_id);
+        session.sender.require_auth();
+
+        session.finalized = true;
+        env.storage().persistent().set(&(Symbol::new(&env, "session"), session_id), &session);
+
+        let fee_reserved = session.escrowed - session.expected_total;
+        let protocol_fee = Self::calculate_fee(&env, &session.sender, session.distributed).min(fee_reserved);
+        let refund = session.escrowed - session.distributed - protocol_fee;
+
+        let token_client = token::Client::new(&env, &session.token);
+        let held = token_client.balance(&env.current_contract_address());
+        if protocol_fee > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(&env, "fee_addr"))
+                .unwrap();
+            token_client.transfer(&env.current_contract_address(), &fee_address, &protocol_fee);
+            Self::record_fee_paid(&env, &session.sender, protocol_fee);
+        }
+        if refund > 0 {
+            token_client.transfer(&env.current_contract_address(), &session.sender, &refund);
+        }
+        Self::assert_escrow_moved(&env, &token_client, held, -(protocol_fee + refund.max(0)));
+
+        if session.distributed > 0 {
+            Self::update_global_stats(&env, session.distributed);
+            Self::update_token_stats(&env, &session.token, session.distributed, session.recipients_count);
+            Self::update_user_stats(&env, &session.sender, session.distributed);
+            Self::record_history(
+                &env,
+                session.sender,
+                session.token,
+                session.distributed,
+                session.recipients_count,
+                protocol_fee,
+                "session",
+            );
+        }
+    }
+
+    pub fn get_distribution_session(env: Env, session_id: u64) -> Option<DistributionSession> {
+        env.storage().persistent().get(&(Symbol::new(&env, "session"), session_id))
+    }
+
+    fn load_open_session(env: &Env, session_id: u64) -> DistributionSession {
+        let session: DistributionSession = env.storage().persistent()
+            .get(&(Symbol::new(env, "session"), session_id))
+            .unwrap_or_else(|| panic_with_error!(env, Error::SessionNotFound));
+        if session.finalized {
+            panic_with_error!(env, Error::SessionFinalized);
+        }
+        session
+    }
+
+    /// Set up a weighted distribution repeated `occurrences` times, one
+    /// `interval_seconds` apart. Escrows every occurrence plus its fee now;
+    /// anyone can then trigger each run with `execute_schedule`.
+    pub fn create_schedule(
+        env: Env,
+        sender: Address,
+        token: Address,
+        recipients: Vec<Address>,
+        amounts: Vec<i128>,
+        interval_seconds: u64,
+        occurrences: u32,
+    ) -> u64 {
+        sender.require_auth();
+        Self::assert_token_allowed(&env, &token);
+        assert!(recipients.len() == amounts.len(), "Recipients and amounts must match");
+        assert!(!recipients.is_empty(), "No recipients provided");
+        if recipients.len() > MAX_SCHEDULE_RECIPIENTS {
+            panic_with_error!(&env, Error::TooManyRecipients);
+        }
+        if interval_seconds == 0 {
+            panic_with_error!(&env, Error::InvalidTimeRange);
+        }
+        if occurrences == 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        for recipient in recipients.iter() {
+            Self::assert_valid_recipient(&env, &sender, &token, &recipient);
+        }
+
+        let mut per_run: i128 = 0;
+        for amount in amounts.iter() {
+            assert!(amount > 0, "All amounts must be positive");
+            per_run = per_run.checked_add(amount)
+                .unwrap_or_else(|| panic_with_error!(&env, Error::InvalidAmount));
+        }
+        let fee_per_run = Self::calculate_fee(&env, &sender, per_run);
+        let total = per_run.checked_mul(occurrences as i128)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::InvalidAmount));
+        Self::consume_daily_cap(&env, &sender, total);
+
+        let escrowed = fee_per_run.checked_mul(occurrences as i128)
+            .and_then(|fees| fees.checked_add(total))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::InvalidAmount));
+        let token_client = token::Client::new(&env, &token);
+        let held = token_client.balance(&env.current_contract_address());
+        token_client.transfer(&sender, &env.current_contract_address(), &escrowed);
+        Self::assert_escrow_moved(&env, &token_client, held, escrowed);
+
+        let schedule_id: u64 = env.storage().instance()
+            .get(&Symbol::new(&env, "sched_cnt"))
+            .unwrap_or(0);
+        env.storage().instance().set(&Symbol::new(&env, "sched_cnt"), &(schedule_id + 1));
+
+        let schedule = DistributionSchedule {
+            sender,
+            token,
+            recipients,
+            amounts,
+            interval: interval_seconds,
+            remaining: occurrences,
+            last_executed: env.ledger().timestamp(),
+            fee_per_run,
+        };
+        Self::store_schedule(&env, schedule_id, &schedule);
+
+        schedule_id
+    }
+
+    /// Run the next occurrence of a schedule once its interval has passed
+    /// since the previous run. Permissionless, for keeper bots.
+    pub fn execute_schedule(env: Env, schedule_id: u64) -> u64 {
+        let mut schedule = Self::load_active_schedule(&env, schedule_id);
+
+        let now = env.ledger().timestamp();
+        if now < schedule.last_executed.saturating_add(schedule.interval) {
+            panic_with_error!(&env, Error::ScheduleNotDue);
+        }
+
+        // Measured from the actual run, so a late keeper can't fire twice
+        // in one interval to catch up
+        schedule.last_executed = now;
+        schedule.remaining -= 1;
+        Self::store_schedule(&env, schedule_id, &schedule);
+
+        let token_client = token::Client::new(&env, &schedule.token);
+        let held = token_client.balance(&env.current_contract_address());
+        if schedule.fee_per_run > 0 {
+            let fee_address: Address = env.storage().instance()
+                .get(&Symbol::new(&env, "fee_addr"))
+                .unwrap();
+            token_client.transfer(&env.current_contract_address(), &fee_address, &schedule.fee_per_run);
+            Self::record_fee_paid(&env, &schedule.sender, schedule.fee_per_run);
+        }
+        let mut total: i128 = 0;
+        for i in 0..schedule.recipients.len() {
+            let amount = schedule.amounts.get(i).unwrap();
+            token_client.transfer(&env.current_contract_address(), &schedule.recipients.get(i).unwrap(), &amount);
+            total += amount;
+        }
+        Self::assert_escrow_moved(&env, &token_client, held, -(schedule.fee_per_run + total));
+
+        let recipients_count = schedule.recipients.len();
+        Self::update_global_stats(&env, total);
+        Self::update_token_stats(&env, &schedule.token, total, recipients_count);
+        Self::update_user_stats(&env, &schedule.sender, total);
+        let distribution_id = Self::record_history(
+            &env,
+            schedule.sender,
+            schedule.token,
+            total,
+            recipients_count,
+            schedule.fee_per_run,
+            "schedule",
+        );
+
+        env.events().publish(
+            ("ScheduleExecuted", schedule_id),
+            ScheduleExecutedEvent { schedule_id, distribution_id, remaining: schedule.remaining },
+        );
+        distribution_id
+    }
+
+    /// Stop a schedule and refund the escrow of its unexecuted occurrences
+    /// (sender only). Returns the amount refunded.
+    pub fn cancel_schedule(env: Env, schedule_id: u64) -> i128 {
+        let mut schedule = Self::load_active_schedule(&env, schedule_id);
+        schedule.sender.require_auth();
+
+        let per_run: i128 = schedule.amounts.iter().sum();
+        let refund = (per_run + schedule.fee_per_run) * schedule.remaining as i128;
+        schedule.remaining = 0;
+        Self::store_schedule(&env, schedule_id, &schedule);
+
+        let token_client = token::Client::new(&env, &schedule.token);
+        let held = token_client.balance(&env.current_contract_address());
+        token_client.transfer(&env.current_contract_address(), &schedule.sender, &refund);
+        Self::assert_escrow_moved(&env, &token_client, held, -refund);
+        env.events().publish(("ScheduleCanceled", schedule_id), refund);
+        refund
+    }
+
+    pub fn get_schedule(env: Env, schedule_id: u64) -> Option<DistributionSchedule> {
+        env.storage().persistent().get(&(Symbol::new(&env, "schedule"), schedule_id))
+    }
+
+    /// Schedules hold escrow across many intervals, so every write keeps
+    /// the entry alive
+    fn store_schedule(env: &Env, schedule_id: u64, schedule: &DistributionSchedule) {
+        let key = (Symbol::new(env, "schedule"), schedule_id);
+        env.storage().persistent().set(&key, schedule);
+        env.storage().persistent().extend_ttl(&key, LEDGER_THRESHOLD, LEDGER_BUMP);
+    }
+
+    fn load_active_schedule(env: &Env, schedule_id: u64) -> DistributionSchedule {
+        let schedule: DistributionSchedule = env.storage().persistent()
+            .get(&(Symbol::new(env, "schedule"), schedule_id))
+            .unwrap_or_else(|| panic_with_error!(env, Error::ScheduleNotFound));
+        if schedule.remaining == 0 {
+            panic_with_error!(env, Error::ScheduleFinished);
+        }
+        schedule
+    }
+
+    /// Save a recipient list with integer weights for `distribute_from_list`.
+    /// Ids are numbered per owner. At most 150 recipients, no duplicates
+    /// and no zero weights.
+    pub fn save_recipient_list(
+        env: Env,
+        owner: Address,
+        name: Symbol,
+        recipients: Vec<Address>,
+        weights: Vec<u32>,
+    ) -> u32 {
+        owner.require_auth();
+        Self::validate_recipient_list(&env, &recipients, &weights);
+
+        let count_key = (Symbol::new(&env, "rlist_cnt"), owner.clone());
+        let list_id: u32 = env.storage().persistent().get_or(&count_key, 0);
+        env.storage().persistent().set(&count_key, &(list_id + 1));
+        env.storage().persistent().extend_ttl(&count_key, LEDGER_THRESHOLD, LEDGER_BUMP);
+
+        Self::store_recipient_list(&env, &owner, list_id, &RecipientList { name, recipients, weights });
+        list_id
+    }
+
+    /// Replace the recipients and weights of a saved list (owner only). The
+    /// name stays.
+    pub fn update_recipient_list(env: Env, owner: Address, list_id: u32, recipients: Vec<Address>, weights: Vec<u32>) {
+        owner.require_auth();
+        let mut list = Self::load_recipient_list(&env, &owner, list_id);
+        Self::validate_recipient_list(&env, &recipients, &weights);
+
+        list.recipients = recipients;
+        list.weights = weights;
+        Self::store_recipient_list(&env, &owner, list_id, &list);
+    }
+
+    /// Delete a saved list (owner only). Its id is not reused.
+    pub fn delete_recipient_list(env: Env, owner: Address, list_id: u32) {
+        owner.require_auth();
+        Self::load_recipient_list(&env, &owner, list_id);
+        env.storage().persistent().remove(&(Symbol::new(&env, "rlist"), owner, list_id));
+    }
+
+    pub fn get_recipient_list(env: Env, owner: Address, list_id: u32) -> Option<RecipientList> {
+        env.storage().persistent().get(&(Symbol::new(&env, "rlist"), owner, list_id))
+    }
+
+    /// Weighted distribution of `total_amount` over one of the sender's
+    /// saved lists, split by its weights with the rounding remainder going
+    /// to the heaviest recipient. The fee is charged on top, as with
+    /// `distribute_weighted`. Only the sender's own lists can be used.
+    pub fn distribute_from_list(
+        env: Env,
+        sender: Address,
+        token: Address,
+        list_id: u32,
+        total_amount: i128,
+    ) -> DistributionReceipt {
+        sender.require_auth();
+        let list = Self::load_recipient_list(&env, &sender, list_id);
+        if total_amount <= 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+
+        let amounts = Self::weighted_shares(&env, total_amount, &list.weights);
+        // A total too small for every weight would leave someone with nothing
+        if Self::first_invalid_amount(&amounts).is_some() {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        Self::execute_weighted(&env, sender, token, &list.recipients, &amounts, "list")
+    }
+
+    fn validate_recipient_list(env: &Env, recipients: &Vec<Address>, weights: &Vec<u32>) {
+        if recipients.len() != weights.len() {
+            panic_with_error!(env, Error::LengthMismatch);
+        }
+        if recipients.is_empty() {
+            panic_with_error!(env, Error::NoRecipients);
+        }
+        if recipients.len() > MAX_LIST_RECIPIENTS {
+            panic_with_error!(env, Error::TooManyRecipients);
+        }
+        if weights.iter().any(|weight| weight == 0) {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        if Self::find_duplicate_recipient(env.clone(), recipients.clone()).is_some() {
+            panic_with_error!(env, Error::DuplicateRecipient);
+        }
+    }
+
+    fn load_recipient_list(env: &Env, owner: &Address, list_id: u32) -> RecipientList {
+        let key = (Symbol::new(env, "rlist"), owner.clone(), list_id);
+        let list: RecipientList = env.storage().persistent()
+            .get(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::ListNotFound));
+        env.storage().persistent().extend_ttl(&key, LEDGER_THRESHOLD, LEDGER_BUMP);
+        list
+    }
+
+    fn store_recipient_list(env: &Env, owner: &Address, list_id: u32, list: &RecipientList) {
+        let key = (Symbol::new(env, "rlist"), owner.clone(), list_id);
+        env.storage().persistent().set(&key, list);
+        env.storage().persistent().extend_ttl(&key, LEDGER_THRESHOLD, LEDGER_BUMP);
+    }
+
+    /// Opt an address into per-day fee tracking for tax reporting. Costs one
+    /// persistent entry per address per day on which a fee was paid.
+    pub fn enable_fee_tracking(env: Env, address: Address) {
+        address.require_auth();
+        env.storage().persistent().set(&(Symbol::new(&env, "fee_track"), address), &true);
+    }
+
+    pub fn is_fee_tracking_enabled(env: Env, address: Address) -> bool {
+        env.storage().persistent()
+            .get(&(Symbol::new(&env, "fee_track"), address))
+            .unwrap_or(false)
+    }
+
+    /// Total fees paid by an address over the days covering `[from_ts, to_ts]`
+    /// (at most 366 days)
+    pub fn get_fees_paid(env: Env, address: Address, from_ts: u64, to_ts: u64) -> i128 {
+        if to_ts < from_ts {
+            panic_with_error!(&env, Error::InvalidTimeRange);
+        }
+        let from_day = from_ts / SECONDS_PER_DAY;
+        let to_day = to_ts / SECONDS_PER_DAY;
+        if to_day - from_day >= MAX_FEE_QUERY_DAYS {
+            panic_with_error!(&env, Error::FeeQueryRangeTooLarge);
+        }
+
+        let mut total: i128 = 0;
+        for day in from_day..=to_day {
+            let paid: i128 = env.storage().persistent()
+                .get(&(Symbol::new(&env, "fees_day"), address.clone(), day))
+                .unwrap_or(0);
+            total += paid;
+        }
+        total
+    }
+
+    fn record_fee_paid(env: &Env, payer: &Address, fee: i128) {
+        if !Self::is_fee_tracking_enabled(env.clone(), payer.clone()) {
+            return;
+        }
+        let day = env.ledger().timestamp() / SECONDS_PER_DAY;
+        let key = (Symbol::new(env, "fees_day"), payer.clone(), day);
+        let paid: i128 = env.storage().persistent().get_or(&key, 0);
+        env.storage().persistent().set(&key, &(paid + fee));
+    }
+
+    /// A recipient can't be the sender, this contract or the token itself;
+    /// payments to the latter two would be locked for good.
+    fn is_valid_recipient(env: &Env, sender: &Address, token: &Address, recipient: &Address) -> bool {
+        recipient != sender && *recipient != env.current_contract_address() && recipient != token
+    }
+
+    fn assert_valid_recipient(env: &Env, sender: &Address, token: &Address, recipient: &Address) {
+        if !Self::is_valid_recipient(env, sender, token, recipient) {
+            panic_with_error!(env, Error::InvalidRecipient);
+        }
+    }
+
+    /// Fail with `NonStandardTokenBehavior` unless the contract's balance
+    /// changed by exactly `expected` since it was `held`. Escrow is booked
+    /// at face value, so a token that takes a transfer fee would leave later
+    /// payouts from it short.
+    fn assert_escrow_moved(env: &Env, token_client: &token::Client, held: i128, expected: i128) {
+        if token_client.balance(&env.current_contract_address()) - held != expected {
+            panic_with_error!(env, Error::NonStandardTokenBehavior);
+        }
+    }
+
+    /// Transfer unless it would be a no-op: paying yourself (a sender that
+    /// is also the fee address) is skipped.
+    fn transfer_if_needed(token_client: &token::Client, from: &Address, to: &Address, amount: i128) {
+        if from != to && amount > 0 {
+            token_client.transfer(from, to, &amount);
+        }
+    }
+
+    fn update_global_stats(env: &Env, amount: i128) {
+        let storage = env.storage().instance();
+        let mut total_dist: u64 = storage.get_or(&Symbol::new(env, "tot_dist"), 0);
+        let mut total_amt: i128 = storage.get_or(&Symbol::new(env, "tot_amt"), 0);
+        
+        total_dist += 1;
+        total_amt += amount;
+        
+        storage.set(&Symbol::new(env, "tot_dist"), &total_dist);
+        storage.set(&Symbol::new(env, "tot_amt"), &total_amt);
+    }
+
+    fn update_token_stats(env: &Env, token: &Address, amount: i128, _recipient_count: u32) {
+        let storage = env.storage().persistent();
+        let key = (Symbol::new(env, "tok_stats"), token);
+        
+        let mut stats: TokenStats = storage.get(&key).unwrap_or(TokenStats {
+            total_amount: 0,
+            distribution_count: 0,
+            last_time: 0,
+        });
+        
+        stats.total_amount += amount;
+        stats.distribution_count += 1;
+    
+        let ts = env.ledger().timestamp();
+        stats.last_time = if ts == 0 { 1 } else { ts };
+        
+        storage.set(&key, &stats);
+        Self::update_leaderboard(env, "top_tokens", token, stats.total_amount);
+    }
+
+    fn update_user_stats(env: &Env, user: &Address, amount: i128) {
+        let storage = env.storage().persistent();
+        let key = (Symbol::new(env, "usr_stats"), user);
+        
+        let mut stats: UserStats = storage.get(&key).unwrap_or(UserStats {
+            distributions_initiated: 0,
+            total_amount: 0,
+        });
+        
+        stats.distributions_initiated += 1;
+        stats.total_amount += amount;
+        
+        storage.set(&key, &stats);
+        Self::update_leaderboard(env, "top_senders", user, stats.total_amount);
+    }
+
+    /// Move `who` to its place for `total` on the leaderboard under `key`,
+    /// keeping at most `LEADERBOARD_SIZE` entries, largest first. Totals
+    /// only grow, so anyone off the board is below its last entry and
+    /// enters the moment its total passes it.
+    fn update_leaderboard(env: &Env, key: &str, who: &Address, total: i128) {
+        let storage = env.storage().instance();
+        let key = Symbol::new(env, key);
+        let mut board: Vec<(Address, i128)> = storage.get(&key).unwrap_or_else(|| Vec::new(env));
+
+        if let Some(i) = board.iter().position(|(address, _)| address == *who) {
+            board.remove(i as u32);
+        }
+        // Ties keep their earlier place
+        let position = board.iter().position(|(_, entry)| entry < total).unwrap_or(board.len() as usize) as u32;
+        if position >= LEADERBOARD_SIZE {
+            return;
+        }
+        board.insert(position, (who.clone(), total));
+        if board.len() > LEADERBOARD_SIZE {
+            board.pop_back();
+        }
+        storage.set(&key, &board);
+    }
+
+    /// Tokens with the largest total distributed, largest first, at most 20
+    pub fn get_top_tokens(env: Env) -> Vec<(Address, i128)> {
+        env.storage().instance().get(&Symbol::new(&env, "top_tokens")).unwrap_or_else(|| Vec::new(&env))
+    }
+
+    /// Senders with the largest total distributed, largest first, at most 20
+    pub fn get_top_senders(env: Env) -> Vec<(Address, i128)> {
+        env.storage().instance().get(&Symbol::new(&env, "top_senders")).unwrap_or_else(|| Vec::new(&env))
+    }
+
+    /// Append a history entry and publish `DistributionExecuted` for it.
+    /// Returns the entry's id.
+    fn record_history(
+        env: &Env,
+        sender: Address,
+        token: Address,
+        amount: i128,
+        recipient_count: u32,
+        fee: i128,
+        kind: &str,
+    ) -> u64 {
+        let storage = env.storage().persistent();
+        let count: u64 = env.storage().instance()
+            .get(&Symbol::new(env, "hist_cnt"))
+            .unwrap_or(0);
+        
+        let history = DistributionHistory {
+            sender,
+            token,
+            amount,
+            recipients_count: recipient_count,
+            timestamp: env.ledger().timestamp(),
+        };
+        
+        storage.set(&(Symbol::new(env, "history"), count), &history);
+        env.storage().instance().set(&Symbol::new(env, "hist_cnt"), &(count + 1));
+
+        env.events().publish(
+            ("DistributionExecuted", count),
+            DistributionExecutedEvent {
+                distribution_id: count,
+                sender: history.sender,
+                token: history.token,
+                total_amount: amount,
+                fee,
+                recipients_count: recipient_count,
+                kind: Symbol::new(env, kind),
+            },
+        );
+        count
+    }
+
+    /// Protocol fee on `amount` distributed by `sender`; nothing if the fee
+    /// registry exempts the sender. Panics with `FeeTooHigh` if the fee
+    /// would swallow the whole amount, which the cap should already rule out.
+    fn calculate_fee(env: &Env, sender: &Address, amount: i128) -> i128 {
+        if Self::is_fee_exempt(env, sender) {
+            return 0;
+        }
+        let fee_percent: u32 = env.storage().instance()
+            .get(&Symbol::new(env, "fee_pct"))
+            .unwrap_or(0);
+        let fee = bps_of(amount, fee_percent);
+        if amount > 0 && fee >= amount {
+            panic_with_error!(env, Error::FeeTooHigh);
+        }
+        fee
+    }
+
+    /// Whether the fee registry, if one is set, exempts `address`. A
+    /// registry call that fails counts as not exempt.
+    fn is_fee_exempt(env: &Env, address: &Address) -> bool {
+        let Some(registry) = Self::get_fee_registry(env.clone()) else {
+            return false;
+        };
+        let result = env.try_invoke_contract::<bool, soroban_sdk::Error>(
+            &registry,
+            &Symbol::new(env, "is_fee_exempt"),
+            (address.clone(),).into_val(env),
+        );
+        matches!(result, Ok(Ok(true)))
+    }
+
+    fn assert_fee_within_cap(env: &Env, fee_percent: u32) {
+        if fee_percent > MAX_FEE {
+            panic_with_error!(env, Error::FeeTooHigh);
+        }
+    }
+
+  
+    pub fn get_total_distributions(env: Env) -> u64 {
+        env.storage().instance().get_or(&Symbol::new(&env, "tot_dist"), 0)
+    }
+
+    pub fn get_total_distributed_amount(env: Env) -> i128 {
+        env.storage().instance().get_or(&Symbol::new(&env, "tot_amt"), 0)
+    }
+
+    pub fn get_token_stats(env: Env, token: Address) -> Option<TokenStats> {
+        env.storage().persistent().get(&(Symbol::new(&env, "tok_stats"), token))
+    }
+
+    pub fn get_user_stats(env: Env, user: Address) -> Option<UserStats> {
+        env.storage().persistent().get(&(Symbol::new(&env, "usr_stats"), user))
+    }
+
+    /// History entries with ids in `start_id..start_id + limit`. Pruned
+    /// ids are skipped, so fewer than `limit` records may come back.
+    pub fn get_distribution_history(env: Env, start_id: u64, limit: u64) -> Vec<DistributionHistory> {
+        let mut history = Vec::new(&env);
+        let storage = env.storage().persistent();
+        
+        let end = start_id.saturating_add(limit);
+        for i in start_id.max(Self::get_history_watermark(env.clone()))..end {
+            if let Some(record) = storage.get::<_, DistributionHistory>(&(Symbol::new(&env, "history"), i)) {
+                history.push_back(record);
+            }
+        }
+        
+        history
+    }
+
+    /// Up to `limit` (at most 50) history entries from `start_id` upwards,
+    /// with the id to pass as `start_id` for the next page, or `None` once
+    /// the newest entry has been returned. Pruned ids are skipped.
+    pub fn get_distribution_history_page(env: Env, start_id: u64, limit: u32) -> (Vec<DistributionHistory>, Option<u64>) {
+        let limit = Self::history_page_limit(&env, limit);
+        let count: u64 = env.storage().instance().get_or(&Symbol::new(&env, "hist_cnt"), 0);
+
+        let start = start_id.max(Self::get_history_watermark(env.clone()));
+        let end = start.saturating_add(limit).min(count);
+        let mut history = Vec::new(&env);
+        for i in start..end {
+            if let Some(record) = env.storage().persistent().get(&(Symbol::new(&env, "history"), i)) {
+                history.push_back(record);
+            }
+        }
+
+        (history, (end < count).then_some(end))
+    }
+
+    /// Up to `limit` (at most 50) history entries newest first, starting
+    /// below `before_id` (or at the newest entry when `None`). The returned
+    /// cursor is the `before_id` for the next page, `None` once the oldest
+    /// unpruned entry has been returned.
+    pub fn get_distribution_history_desc(env: Env, before_id: Option<u64>, limit: u32) -> (Vec<DistributionHistory>, Option<u64>) {
+        let limit = Self::history_page_limit(&env, limit);
+        let count: u64 = env.storage().instance().get_or(&Symbol::new(&env, "hist_cnt"), 0);
+        let watermark = Self::get_history_watermark(env.clone());
+
+        let end = before_id.unwrap_or(count).min(count);
+        let start = end.saturating_sub(limit).max(watermark);
+        let mut history = Vec::new(&env);
+        for i in (start..end).rev() {
+            if let Some(record) = env.storage().persistent().get(&(Symbol::new(&env, "history"), i)) {
+                history.push_back(record);
+            }
+        }
+
+        (history, (start > watermark).then_some(start))
+    }
+
+    /// Reject an empty page and clamp to `MAX_HISTORY_PAGE`
+    fn history_page_limit(env: &Env, limit: u32) -> u64 {
+        if limit == 0 {
+            panic_with_error!(env, Error::InvalidLimit);
+        }
+        limit.min(MAX_HISTORY_PAGE) as u64
+    }
+
+    /// Delete up to `max_entries` of the oldest history entries recorded
+    /// before `before_timestamp` (admin only), folding their amounts into
+    /// per-token archival totals. Returns how many entries were pruned.
+    pub fn prune_history(env: Env, before_timestamp: u64, max_entries: u32) -> u32 {
+        Self::require_admin(&env, "prune_history", before_timestamp as i128);
+
+        let storage = env.storage().persistent();
+        let count: u64 = env.storage().instance().get_or(&Symbol::new(&env, "hist_cnt"), 0);
+        let mut next = Self::get_history_watermark(env.clone());
+        let mut pruned = 0;
+
+        // Ids are handed out in time order, so the walk stops at the first
+        // entry at or after the cutoff
+        while next < count && pruned < max_entries {
+            let key = (Symbol::new(&env, "history"), next);
+            if let Some(record) = storage.get::<_, DistributionHistory>(&key) {
+                if record.timestamp >= before_timestamp {
+                    break;
+                }
+                let totals_key = (Symbol::new(&env, "archived"), record.token.clone());
+                let mut totals = Self::get_archived_totals(env.clone(), record.token);
+                totals.count += 1;
+                totals.total_amount += record.amount;
+                storage.set(&totals_key, &totals);
+
+                storage.remove(&key);
+                storage.remove(&(Symbol::new(&env, "history_v2"), next));
+                storage.remove(&(Symbol::new(&env, "history_prop"), next));
+                storage.remove(&(Symbol::new(&env, "history_strm"), next));
+                pruned += 1;
+            }
+            next += 1;
+        }
+        env.storage().instance().set(&Symbol::new(&env, "hist_pruned"), &next);
+
+        pruned
+    }
+
+    /// Lowest history id that hasn't been pruned
+    pub fn get_history_watermark(env: Env) -> u64 {
+        env.storage().instance().get_or(&Symbol::new(&env, "hist_pruned"), 0)
+    }
+
+    /// Count and amount of the pruned history entries in `token`
+    pub fn get_archived_totals(env: Env, token: Address) -> ArchivedTotals {
+        env.storage().persistent()
+            .get(&(Symbol::new(&env, "archived"), token))
+            .unwrap_or(ArchivedTotals { count: 0, total_amount: 0 })
+    }
+
+    pub fn get_admin(env: Env) -> Option<Address> {
+        env.storage().instance().get(&Symbol::new(&env, "admin"))
+    }
+
+    pub fn set_protocol_fee(env: Env, admin: Address, new_fee_percent: u32) {
+        admin.require_auth();
+        let stored_admin: Address = env.storage().instance()
+            .get(&Symbol::new(&env, "admin"))
+            .unwrap();
+        assert!(admin == stored_admin, "Unauthorized");
+        Self::assert_fee_within_cap(&env, new_fee_percent);
+        Self::record_admin_action(&env, "set_fee", &admin, new_fee_percent as i128);
+        
+        let old_fee: u32 = env.storage().instance().get_or(&Symbol::new(&env, "fee_pct"), 0);
+        env.storage().instance().set(&Symbol::new(&env, "fee_pct"), &new_fee_percent);
+        env.events().publish(
+            ("ProtocolFeeChanged",),
+            ProtocolFeeChangedEvent { old_fee, new_fee: new_fee_percent },
+        );
+    }
+
+    /// Point fee calculation at a shared fee registry contract exposing
+    /// `is_fee_exempt(address) -> bool`; exempt senders pay no protocol
+    /// fee (admin only)
+    pub fn set_fee_registry(env: Env, registry: Address) {
+        Self::require_admin(&env, "set_fee_registry", 0);
+        Self::store_fee_registry(&env, Some(registry));
+    }
+
+    /// Stop consulting the fee registry (admin only)
+    pub fn remove_fee_registry(env: Env) {
+        Self::require_admin(&env, "remove_fee_registry", 0);
+        Self::store_fee_registry(&env, None);
+    }
+
+    pub fn get_fee_registry(env: Env) -> Option<Address> {
+        env.storage().instance().get(&Symbol::new(&env, "fee_registry"))
+    }
+
+    fn store_fee_registry(env: &Env, new_registry: Option<Address>) {
+        let old_registry = Self::get_fee_registry(env.clone());
+        let key = Symbol::new(env, "fee_registry");
+        match &new_registry {
+            Some(registry) => env.storage().instance().set(&key, registry),
+            None => env.storage().instance().remove(&key),
+        }
+        env.events().publish(
+            ("FeeRegistryChanged",),
+            FeeRegistryChangedEvent { old_registry, new_registry },
+        );
+    }
+
+    /// Turn allowlist enforcement on or off. While on, only tokens passed
+    /// to `allow_token` can be distributed.
+    pub fn set_token_allowlist_enabled(env: Env, enabled: bool) {
+        Self::require_admin(&env, "set_allowlist", enabled as i128);
+        env.storage().instance().set(&Symbol::new(&env, "allowlist_on"), &enabled);
+    }
+
+    pub fn allow_token(env: Env, token: Address) {
+        Self::require_admin(&env, "allow_token", 0);
+        env.storage().persistent().set(&(Symbol::new(&env, "tok_list"), token), &true);
+    }
+
+    /// Block a token outright, whether or not the allowlist is enforced
+    pub fn deny_token(env: Env, token: Address) {
+        Self::require_admin(&env, "deny_token", 0);
+        env.storage().persistent().set(&(Symbol::new(&env, "tok_list"), token), &false);
+    }
+
+    pub fn is_token_allowed(env: Env, token: Address) -> bool {
+        let listed: Option<bool> = env.storage().persistent().get(&(Symbol::new(&env, "tok_list"), token));
+        match listed {
+            Some(allowed) => allowed,
+            None => !env.storage().instance().get_or(&Symbol::new(&env, "allowlist_on"), false),
+        }
+    }
+
+    fn assert_token_allowed(env: &Env, token: &Address) {
+        if !Self::is_token_allowed(env.clone(), token.clone()) {
+            panic_with_error!(env, Error::TokenNotAllowed);
+        }
+    }
+
+    /// Cap on how much one sender can distribute per rolling 24h window,
+    /// excluding fees. 0 disables the cap.
+    pub fn set_sender_daily_cap(env: Env, cap: i128) {
+        Self::require_admin(&env, "set_daily_cap", cap);
+        if cap < 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        env.storage().instance().set(&Symbol::new(&env, "daily_cap"), &cap);
+    }
+
+    pub fn get_sender_daily_cap(env: Env) -> i128 {
+        env.storage().instance().get_or(&Symbol::new(&env, "daily_cap"), 0)
+    }
+
+    /// What `sender` can still distribute in its current window, or
+    /// `i128::MAX` when no cap is set
+    pub fn get_sender_remaining_cap(env: Env, sender: Address) -> i128 {
+        let cap = Self::get_sender_daily_cap(env.clone());
+        if cap == 0 {
+            return i128::MAX;
+        }
+        let (_, used) = Self::current_window(&env, &sender);
+        (cap - used).max(0)
+    }
+
+    /// Sender's `(window_start, amount_in_window)`, rolled forward if the
+    /// stored window is a day or more old
+    fn current_window(env: &Env, sender: &Address) -> (u64, i128) {
+        let now = env.ledger().timestamp();
+        let window: Option<(u64, i128)> = env.storage().persistent()
+            .get(&(Symbol::new(env, "daily_used"), sender.clone()));
+        match window {
+            Some((start, used)) if now < start + SECONDS_PER_DAY => (start, used),
+            _ => (now, 0),
+        }
+    }
+
+    /// Count `amount` against the sender's window. Nothing is tracked while
+    /// the cap is off.
+    fn consume_daily_cap(env: &Env, sender: &Address, amount: i128) {
+        let cap = Self::get_sender_daily_cap(env.clone());
+        if cap == 0 {
+            return;
+        }
+        let (start, used) = Self::current_window(env, sender);
+        if used + amount > cap {
+            panic_with_error!(env, Error::DailyCapExceeded);
+        }
+        env.storage().persistent()
+            .set(&(Symbol::new(env, "daily_used"), sender.clone()), &(start, used + amount));
+    }
+
+    /// Require the admin's auth and log `action` to the admin audit trail
+    fn require_admin(env: &Env, action: &str, detail: i128) -> Address {
+        let admin: Address = env.storage().instance()
+            .get(&Symbol::new(env, "admin"))
+            .expect("Contract not initialized");
+        admin.require_auth();
+        Self::record_admin_action(env, action, &admin, detail);
+        admin
+    }
+
+    /// Append to the admin audit trail. Admin entrypoints reach this
+    /// through `require_admin`.
+    fn record_admin_action(env: &Env, action: &str, actor: &Address, detail: i128) {
+        let index: u64 = env.storage().instance().get_or(&Symbol::new(env, "admin_cnt"), 0);
+        env.storage().instance().set(&Symbol::new(env, "admin_cnt"), &(index + 1));
+
+        let entry = AdminAction {
+            action: Symbol::new(env, action),
+            actor: actor.clone(),
+            timestamp: env.ledger().timestamp(),
+            detail,
+        };
+        env.storage().persistent().set(&(Symbol::new(env, "admin_act"), index), &entry);
+    }
+
+    /// Admin audit trail, newest first: `offset` 0 is the latest entry.
+    /// At most 50 entries per call.
+    pub fn get_admin_actions(env: Env, offset: u64, limit: u32) -> Vec<AdminAction> {
+        let count: u64 = env.storage().instance().get_or(&Symbol::new(&env, "admin_cnt"), 0);
+        let end = count.saturating_sub(offset);
+        let start = end.saturating_sub(limit.min(MAX_ADMIN_ACTION_PAGE) as u64);
+
+        let mut actions = Vec::new(&env);
+        for index in (start..end).rev() {
+            if let Some(entry) = env.storage().persistent().get(&(Symbol::new(&env, "admin_act"), index)) {
+                actions.push_back(entry);
+            }
+        }
+        actions
+    }
+
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) {
+        let version = Self::version(env.clone()) + 1;
+        Self::require_admin(&env, "upgrade", version as i128);
+        env.storage().instance().set(&Symbol::new(&env, "version"), &version);
+
+        env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+
+        env.events().publish(
+            ("ContractUpgraded",),
+            ContractUpgradedEvent { new_wasm_hash, version },
+        );
+    }
+
+    pub fn version(env: Env) -> u32 {
+        env.storage().instance().get(&Symbol::new(&env, "version")).unwrap_or(CONTRACT_VERSION)
+    }
+
+    /// All instance-level configuration in one call
+    pub fn get_config(env: Env) -> ContractConfig {
+        let storage = env.storage().instance();
+        ContractConfig {
+            config_version: CONFIG_VERSION,
+            admin: storage.get(&Symbol::new(&env, "admin")),
+            fee_address: storage.get(&Symbol::new(&env, "fee_addr")),
+            protocol_fee_percent: storage.get_or(&Symbol::new(&env, "fee_pct"), 0),
+            allowlist_enabled: storage.get_or(&Symbol::new(&env, "allowlist_on"), false),
+            sender_daily_cap: Self::get_sender_daily_cap(env.clone()),
+            version: Self::version(env.clone()),
+            fee_registry: Self::get_fee_registry(env.clone()),
+        }
+    }
+
+    
+}
+
+#[cfg(test)]
+mod bench;
+
+#[cfg(test)]
+mod test {
+  use super::*;
+    use soroban_sdk::{
+        testutils::{Address as _, Events, Ledger, LedgerInfo},
+        token::{Client as TokenClient, StellarAssetClient},
+        Address, Bytes, Env, IntoVal,
+    };
+
+    /// Minimal contract wasm (only the `contractenvmetav0` section) used as
+    /// an upgrade target.
+    const UPGRADE_TARGET_WASM: [u8; 40] = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x1e, 0x11,
+        b'c', b'o', b'n', b't', b'r', b'a', b'c', b't', b'e', b'n', b'v', b'm', b'e', b't', b'a', b'v', b'0',
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+
+    fn create_token_contract<'a>(
+        env: &Env,
+        admin: &Address,
+    ) -> (Address, TokenClient<'a>, StellarAssetClient<'a>) {
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        let token_client = TokenClient::new(env, &token_address);
+        let token_admin_client = StellarAssetClient::new(env, &token_address);
+        (token_address, token_client, token_admin_client)
+    }
+
+    /// Token whose issuer can freeze holders with `set_authorized`
+    fn create_revocable_token_contract<'a>(
+        env: &Env,
+        admin: &Address,
+    ) -> (Address, TokenClient<'a>, StellarAssetClient<'a>) {
+        let sac = env.register_stellar_asset_contract_v2(admin.clone());
+        sac.issuer().set_flag(soroban_sdk::testutils::IssuerFlags::RevocableFlag);
+        let token_address = sac.address();
+        (token_address.clone(), TokenClient::new(env, &token_address), StellarAssetClient::new(env, &token_address))
+    }
+
+     
+    fn setup_distributor(env: &Env) -> (Address, DistributorContractClient<'_>, Address, Address) {
+        let admin = Address::generate(env);
+        let fee_address = Address::generate(env);
+        
+        let contract_id = env.register(DistributorContract, (&admin, 250_u32, &fee_address));
+        let client = DistributorContractClient::new(env, &contract_id);
+        
+        (contract_id, client, admin, fee_address)
+    }
+
+
+    #[test]
+    fn test_initialize() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let fee_address = Address::generate(&env);
+
+        let contract_id = env.register(DistributorContract, (&admin, 250_u32, &fee_address));
+        let client = DistributorContractClient::new(&env, &contract_id);
+
+        let stored_admin = client.get_admin();
+        assert_eq!(stored_admin, Some(admin));
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #21)")]
+    fn test_re_initialize_fails() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let fee_address = Address::generate(&env);
+
+        let contract_id = env.register(DistributorContract, (&admin, 250_u32, &fee_address));
+        let client = DistributorContractClient::new(&env, &contract_id);
+        // This should panic
+        client.initialize(&admin, &250, &fee_address);
+    }
+
+    #[test]
+    fn test_distribute_equal() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+        let recipient2 = Address::generate(&env);
+        let recipient3 = Address::generate(&env);
+
+       
+        token_admin.mint(&sender, &10000);
+
+       
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+        recipients.push_back(recipient2.clone());
+        recipients.push_back(recipient3.clone());
+
+        
+        let total_amount = 900i128;
+        
+        distributor_client.distribute_equal(&sender, &token_address, &total_amount, &recipients);
+
+        
+        assert_eq!(token_client.balance(&recipient1), 300);
+        assert_eq!(token_client.balance(&recipient2), 300);
+        assert_eq!(token_client.balance(&recipient3), 300);
+
+        assert_eq!(distributor_client.get_total_distributions(), 1);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 900);
+    }
+
+    #[test]
+    fn test_distribute_weighted() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+        let recipient2 = Address::generate(&env);
+        let recipient3 = Address::generate(&env);
+
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+        recipients.push_back(recipient2.clone());
+        recipients.push_back(recipient3.clone());
+
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(100);
+        amounts.push_back(200);
+        amounts.push_back(300);
+
+        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+
+        
+        assert_eq!(token_client.balance(&recipient1), 100);
+        assert_eq!(token_client.balance(&recipient2), 200);
+        assert_eq!(token_client.balance(&recipient3), 300);
+
+       
+        assert_eq!(distributor_client.get_total_distributions(), 1);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 600);
+    }
+
+#[test]
+    fn test_distribute_equal_with_protocol_fee() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+        let recipient2 = Address::generate(&env);
+
+        
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+        recipients.push_back(recipient2.clone());
+
+       
+        let total_amount = 1000i128;
+        
+        distributor_client.distribute_equal(&sender, &token_address, &total_amount, &recipients);
+
+        assert_eq!(token_client.balance(&recipient1), 500);
+        assert_eq!(token_client.balance(&recipient2), 500);
+        
+        
+        assert_eq!(token_client.balance(&fee_address), 25);
+        
+        
+        assert_eq!(token_client.balance(&sender), 8975);
+    }
+
+    
+
+     #[test]
+    fn test_distribute_weighted_with_protocol_fee() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+        let recipient2 = Address::generate(&env);
+
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+        recipients.push_back(recipient2.clone());
+
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(400);
+        amounts.push_back(600);
+
+        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+
+        assert_eq!(token_client.balance(&recipient1), 400);
+        assert_eq!(token_client.balance(&recipient2), 600);
+        
+       
+        assert_eq!(token_client.balance(&fee_address), 25);
+    }
+
+    
+    #[test]
+    fn test_update_global_stats() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &100000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+
+        assert_eq!(distributor_client.get_total_distributions(), 0);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 0);
+
+      
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        
+        
+        assert_eq!(distributor_client.get_total_distributions(), 1);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 1000);
+
+       
+        distributor_client.distribute_equal(&sender, &token_address, &2500, &recipients);
+        
+       
+        assert_eq!(distributor_client.get_total_distributions(), 2);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 3500);
+
+       
+        distributor_client.distribute_equal(&sender, &token_address, &500, &recipients);
+        
+       
+        assert_eq!(distributor_client.get_total_distributions(), 3);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 4000);
+
+        
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(300);
+        
+        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+        
+        
+        assert_eq!(distributor_client.get_total_distributions(), 4);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 4300);
+    }
+
+     #[test]
+    fn test_update_token_statistics() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+
+        token_admin.mint(&sender, &100000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+
+     
+        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
+
+       
+        let token_stats = distributor_client.get_token_stats(&token_address);
+        assert!(token_stats.is_some());
+        
+        let stats = token_stats.unwrap();
+        assert_eq!(stats.total_amount, 3000);
+        assert_eq!(stats.distribution_count, 2);
+        assert!(stats.last_time > 0);
+    }
+
+    #[test]
+    fn test_update_user_statistics() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+
+        token_admin.mint(&sender, &100000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+
+       
+        distributor_client.distribute_equal(&sender, &token_address, &500, &recipients);
+        distributor_client.distribute_equal(&sender, &token_address, &1500, &recipients);
+        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
+
+ 
+        let user_stats = distributor_client.get_user_stats(&sender);
+        assert!(user_stats.is_some());
+        
+        let stats = user_stats.unwrap();
+        assert_eq!(stats.distributions_initiated, 3);
+        assert_eq!(stats.total_amount, 4000);
+    }
+
+
+
+#[test]
+    fn test_record_history() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+       
+        env.ledger().set(LedgerInfo {
+            timestamp: 12345,
+            protocol_version: env.ledger().protocol_version(),
+            sequence_number: 10,
+            network_id: Default::default(),
+            base_reserve: 10,
+            min_temp_entry_ttl: 16,
+            min_persistent_entry_ttl: 16,
+            max_entry_ttl: 6312000,
+        });
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+        let recipient2 = Address::generate(&env);
+
+        token_admin.mint(&sender, &100000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(recipient1.clone());
+        recipients.push_back(recipient2.clone());
+
+       
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
+
+       
+        let history = distributor_client.get_distribution_history(&0, &2);
+        assert_eq!(history.len(), 2);
+
+        let record1 = history.get(0).unwrap();
+        assert_eq!(record1.sender, sender);
+        assert_eq!(record1.token, token_address);
+        assert_eq!(record1.amount, 1000);
+        assert_eq!(record1.recipients_count, 2);
+        assert_eq!(record1.timestamp, 12345);
+
+    
+        let record2 = history.get(1).unwrap();
+        assert_eq!(record2.amount, 2000);
+    }
+
+
+
+    #[test]
+    fn test_set_protocol_fee() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let fee_address = Address::generate(&env);
+
+        let contract_id = env.register(DistributorContract, (&admin, 250_u32, &fee_address));
+        let client = DistributorContractClient::new(&env, &contract_id);
+
+        // Change fee to 5% (500 basis points)
+        client.set_protocol_fee(&admin, &500);
+
+        // Test with new fee
+        let sender = Address::generate(&env);
+        let token_admin_addr = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &token_admin_addr);
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+
+        // 1000 tokens with 5% fee = 50 fee
+        client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        assert_eq!(token_client.balance(&fee_address), 50);
+    }
+
+    #[test]
+    fn test_initialize_fee_at_cap() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let fee_address = Address::generate(&env);
+        let contract_id = env.register(DistributorContract, (Address::generate(&env), MAX_FEE, &fee_address));
+        let client = DistributorContractClient::new(&env, &contract_id);
+
+        let sender = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10000);
+        let recipients = Vec::from_array(&env, [Address::generate(&env)]);
+        client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        assert_eq!(token_client.balance(&fee_address), 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #19)")]
+    fn test_initialize_fee_above_cap() {
+        let env = Env::default();
+        env.register(DistributorContract, (Address::generate(&env), MAX_FEE + 1, Address::generate(&env)));
+    }
+
+    #[test]
+    fn test_set_protocol_fee_to_full_amount_rejected() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let (_contract_id, client, admin, fee_address) = setup_distributor(&env);
+        let result = client.try_set_protocol_fee(&admin, &10000);
+        assert_eq!(result, Err(Ok(Error::FeeTooHigh.into())));
+
+        // The previous 2.5% fee still applies
+        let sender = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10000);
+        let recipients = Vec::from_array(&env, [Address::generate(&env)]);
+        client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        assert_eq!(token_client.balance(&fee_address), 25);
+    }
+
+
+
+#[test]
+    fn test_zero_protocol_fee() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let fee_address = Address::generate(&env);
+
+        // Initialize with 0% fee
+        let contract_id = env.register(DistributorContract, (&admin, 0_u32, &fee_address));
+        let client = DistributorContractClient::new(&env, &contract_id);
+
+        let sender = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+
+        client.distribute_equal(&sender, &token_address, &1000, &recipients);
+
+        // Fee address should have 0 balance
+        assert_eq!(token_client.balance(&fee_address), 0);
+    }
+
+
+    #[test]
+    #[should_panic(expected = "All amounts must be positive")]
+    fn test_distribute_weighted_zero_amount() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+        recipients.push_back(Address::generate(&env));
+
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(100);
+        amounts.push_back(0); // Invalid: zero amount
+
+        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+    }
+
+     #[test]
+    #[should_panic(expected = "Amount too small to distribute")]
+    fn test_distribute_equal_amount_too_small() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        // Create many recipients so amount per recipient becomes 0
+        let mut recipients = Vec::new(&env);
+        for _ in 0..1000 {
+            recipients.push_back(Address::generate(&env));
+        }
+
+        distributor_client.distribute_equal(&sender, &token_address, &10, &recipients);
+    }
+
+    #[test]
+    #[should_panic(expected = "No recipients provided")]
+    fn test_distribute_equal_empty_recipients() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let recipients = Vec::new(&env);
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+    }
+
+    #[test]
+    fn test_upgrade_preserves_storage() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        assert_eq!(distributor_client.version(), 1);
+
+        let new_wasm_hash = env.deployer().upload_contract_wasm(Bytes::from_array(&env, &UPGRADE_TARGET_WASM));
+        distributor_client.upgrade(&new_wasm_hash);
+
+        let events = env.events().all();
+        let (_, topics, data) = events.last().unwrap();
+        assert_eq!(topics, ("ContractUpgraded",).into_val(&env));
+        let payload: ContractUpgradedEvent = data.into_val(&env);
+        assert_eq!(payload, ContractUpgradedEvent { new_wasm_hash, version: 2 });
+
+        env.as_contract(&contract_id, || {
+            let total: i128 = env.storage().instance().get(&Symbol::new(&env, "tot_amt")).unwrap();
+            assert_eq!(total, 1000);
+            let record: DistributionHistory = env.storage().persistent()
+                .get(&(Symbol::new(&env, "history"), 0u64))
+                .unwrap();
+            assert_eq!(record.sender, sender);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_upgrade_requires_admin_auth() {
+        let env = Env::default();
+
+        let admin = Address::generate(&env);
+        let fee_address = Address::generate(&env);
+        env.mock_all_auths();
+        let contract_id = env.register(DistributorContract, (&admin, 250_u32, &fee_address));
+        let client = DistributorContractClient::new(&env, &contract_id);
+        env.set_auths(&[]);
+
+        let new_wasm_hash = env.deployer().upload_contract_wasm(Bytes::from_array(&env, &UPGRADE_TARGET_WASM));
+        client.upgrade(&new_wasm_hash);
+    }
+
+    #[test]
+    fn test_distribute_payouts() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        let recipient1 = Address::generate(&env);
+        let recipient2 = Address::generate(&env);
+
+        token_admin.mint(&sender, &10000);
+
+        let mut payouts = Vec::new(&env);
+        payouts.push_back(Payout { recipient: recipient1.clone(), amount: 400 });
+        payouts.push_back(Payout { recipient: recipient2.clone(), amount: 600 });
+
+        distributor_client.distribute_payouts(&sender, &token_address, &payouts);
+
+        assert_eq!(token_client.balance(&recipient1), 400);
+        assert_eq!(token_client.balance(&recipient2), 600);
+        assert_eq!(token_client.balance(&fee_address), 25);
+        assert_eq!(distributor_client.get_total_distributed_amount(), 1000);
+    }
+
+    fn session_chunk(env: &Env, count: u32) -> (Vec<Address>, Vec<i128>) {
+        let mut recipients = Vec::new(env);
+        let mut amounts = Vec::new(env);
+        for _ in 0..count {
+            recipients.push_back(Address::generate(env));
+            amounts.push_back(100);
+        }
+        (recipients, amounts)
+    }
+
+    #[test]
+    fn test_distribution_session_in_chunks() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        // 1000 expected + 25 fee escrowed
+        let session_id = distributor_client.start_distribution_session(&sender, &token_address, &1000);
+        assert_eq!(token_client.balance(&contract_id), 1025);
+
+        let mut all_recipients = Vec::new(&env);
+        for _ in 0..3 {
+            let (recipients, amounts) = session_chunk(&env, 3);
+            distributor_client.add_distribution_chunk(&session_id, &recipients, &amounts);
+            all_recipients.append(&recipients);
+        }
+
+        distributor_client.finalize_session(&session_id);
+
+        for recipient in all_recipients.iter() {
+            assert_eq!(token_client.balance(&recipient), 100);
+        }
+        // 900 distributed, fee charged on 900 only, remainder refunded
+        assert_eq!(token_client.balance(&fee_address), 22);
+        assert_eq!(token_client.balance(&sender), 10000 - 900 - 22);
+        assert_eq!(token_client.balance(&contract_id), 0);
+
+        let history = distributor_client.get_distribution_history(&0, &1);
+        assert_eq!(history.len(), 1);
+        let record = history.get(0).unwrap();
+        assert_eq!(record.amount, 900);
+        assert_eq!(record.recipients_count, 9);
+        assert_eq!(distributor_client.get_total_distributions(), 1);
+
+        let session = distributor_client.get_distribution_session(&session_id).unwrap();
+        assert!(session.finalized);
+        assert_eq!(session.distributed, 900);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #3)")]
+    fn test_distribution_session_chunk_exceeds_total() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let session_id = distributor_client.start_distribution_session(&sender, &token_address, &500);
+        let (recipients, amounts) = session_chunk(&env, 6);
+        distributor_client.add_distribution_chunk(&session_id, &recipients, &amounts);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #2)")]
+    fn test_distribution_session_finalize_twice() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let session_id = distributor_client.start_distribution_session(&sender, &token_address, &500);
+        distributor_client.finalize_session(&session_id);
+        distributor_client.finalize_session(&session_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #2)")]
+    fn test_distribution_session_chunk_after_finalize() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let session_id = distributor_client.start_distribution_session(&sender, &token_address, &500);
+        distributor_client.finalize_session(&session_id);
+
+        let (recipients, amounts) = session_chunk(&env, 2);
+        distributor_client.add_distribution_chunk(&session_id, &recipients, &amounts);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_distribution_session_unauthorized_chunk() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+
+        let session_id = distributor_client.start_distribution_session(&sender, &token_address, &500);
+
+        env.set_auths(&[]);
+        let (recipients, amounts) = session_chunk(&env, 2);
+        distributor_client.add_distribution_chunk(&session_id, &recipients, &amounts);
+    }
+
+    #[test]
+    fn test_fee_tracking_by_day() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &100000);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+
+        distributor_client.enable_fee_tracking(&sender);
+
+        // Day 0: 2.5% of 1000 and of 2000
+        env.ledger().set_timestamp(3600);
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
+
+        // Day 1: 2.5% of 4000
+        env.ledger().set_timestamp(86400 + 3600);
+        distributor_client.distribute_equal(&sender, &token_address, &4000, &recipients);
+
+        assert_eq!(distributor_client.get_fees_paid(&sender, &0, &86399), 75);
+        assert_eq!(distributor_client.get_fees_paid(&sender, &86400, &172799), 100);
+        assert_eq!(distributor_client.get_fees_paid(&sender, &0, &172799), 175);
+    }
+
+    #[test]
+    fn test_single_address_plays_every_role() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let operator = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &operator);
+
+        let contract_id = env.register(DistributorContract, (&operator, 250_u32, &operator));
+        let client = DistributorContractClient::new(&env, &contract_id);
+
+        token_admin.mint(&operator, &10000);
+        let other = Address::generate(&env);
+
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(other.clone());
+        client.distribute_equal(&operator, &token_address, &1000, &recipients);
+
+        // The fee the operator pays to itself never leaves its balance
+        assert_eq!(token_client.balance(&other), 1000);
+        assert_eq!(token_client.balance(&operator), 9000);
+
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(500);
+        client.distribute_weighted(&operator, &token_address, &recipients, &amounts);
+        assert_eq!(token_client.balance(&other), 1500);
+        assert_eq!(token_client.balance(&operator), 8500);
+
+        // Paying itself as a recipient is rejected outright
+        recipients.push_back(operator.clone());
+        let result = client.try_distribute_equal(&operator, &token_address, &1000, &recipients);
+        assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+
+        client.set_protocol_fee(&operator, &100);
+        assert_eq!(client.get_total_distributions(), 2);
+        assert_eq!(client.get_total_distributed_amount(), 1500);
+        assert_eq!(client.get_user_stats(&operator).unwrap().total_amount, 1500);
+    }
+
+    fn refs_fixture(env: &Env) -> (Vec<Address>, Vec<i128>, Vec<Symbol>) {
+        let mut recipients = Vec::new(env);
+        let mut amounts = Vec::new(env);
+        let mut refs = Vec::new(env);
+        for (amount, reference) in [(100, "INV_001"), (200, "INV_002"), (300, "INV_003")] {
+            recipients.push_back(Address::generate(env));
+            amounts.push_back(amount);
+            refs.push_back(Symbol::new(env, reference));
+        }
+        (recipients, amounts, refs)
+    }
+
+    #[test]
+    fn test_distribute_weighted_with_refs() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+        let (recipients, amounts, refs) = refs_fixture(&env);
+
+        distributor_client.distribute_weighted_with_refs(&sender, &token_address, &recipients, &amounts, &refs);
+
+        let mut i = 0;
+        for (emitter, topics, data) in env.events().all().iter() {
+            if emitter != contract_id || topics != ("DistributionPaid",).into_val(&env) {
+                continue;
+            }
+            let event: DistributionPaidEvent = data.into_val(&env);
+            assert_eq!(event.recipient, recipients.get(i).unwrap());
+            assert_eq!(event.amount, amounts.get(i).unwrap());
+            assert_eq!(event.reference, refs.get(i).unwrap());
+            assert_eq!(token_client.balance(&event.recipient), event.amount);
+            i += 1;
+        }
+        assert_eq!(i, 3);
+
+        let history = distributor_client.get_distribution_history_v2(&0).unwrap();
+        assert_eq!(history.amount, 600);
+        assert_eq!(history.recipients_count, 3);
+        assert_eq!(history.references, refs);
+        assert_eq!(distributor_client.get_distribution_history(&0, &1).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #7)")]
+    fn test_distribute_weighted_with_refs_length_mismatch() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+        let (recipients, amounts, mut refs) = refs_fixture(&env);
+        refs.pop_back();
+
+        distributor_client.distribute_weighted_with_refs(&sender, &token_address, &recipients, &amounts, &refs);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #8)")]
+    fn test_distribute_weighted_with_refs_empty_reference() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+        let (recipients, amounts, mut refs) = refs_fixture(&env);
+        refs.set(1, Symbol::new(&env, ""));
+
+        distributor_client.distribute_weighted_with_refs(&sender, &token_address, &recipients, &amounts, &refs);
+    }
+
+    #[test]
+    fn test_token_allowlist_enforced_only_when_enabled() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (listed_token, _, listed_admin) = create_token_contract(&env, &admin);
+        let (unlisted_token, unlisted_client, unlisted_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        listed_admin.mint(&sender, &10000);
+        unlisted_admin.mint(&sender, &10000);
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+
+        distributor_client.allow_token(&listed_token);
+        assert!(distributor_client.is_token_allowed(&unlisted_token));
+        distributor_client.distribute_equal(&sender, &unlisted_token, &100, &recipients);
+
+        distributor_client.set_token_allowlist_enabled(&true);
+        assert!(distributor_client.is_token_allowed(&listed_token));
+        assert!(!distributor_client.is_token_allowed(&unlisted_token));
+        distributor_client.distribute_equal(&sender, &listed_token, &100, &recipients);
+
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(100);
+        let result = distributor_client.try_distribute_weighted(&sender, &unlisted_token, &recipients, &amounts);
+        assert_eq!(result, Err(Ok(Error::TokenNotAllowed.into())));
+        let result = distributor_client.try_distribute_equal(&sender, &unlisted_token, &100, &recipients);
+        assert_eq!(result, Err(Ok(Error::TokenNotAllowed.into())));
+
+        distributor_client.set_token_allowlist_enabled(&false);
+        distributor_client.distribute_weighted(&sender, &unlisted_token, &recipients, &amounts);
+        assert_eq!(unlisted_client.balance(&recipients.get(0).unwrap()), 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #9)")]
+    fn test_denied_token_blocked_without_allowlist() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+
+        distributor_client.deny_token(&token_address);
+        distributor_client.distribute_equal(&sender, &token_address, &100, &recipients);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_allow_token_requires_admin() {
+        let env = Env::default();
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, _token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = {
+            env.mock_all_auths();
+            setup_distributor(&env)
+        };
+        env.set_auths(&[]);
+        distributor_client.allow_token(&token_address);
+    }
+
+    #[test]
+    fn test_sender_daily_cap_resets_after_window() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+        let mut recipients = Vec::new(&env);
+        recipients.push_back(Address::generate(&env));
+        let mut amounts = Vec::new(&env);
+        amounts.push_back(300);
+
+        assert_eq!(distributor_client.get_sender_remaining_cap(&sender), i128::MAX);
+        distributor_client.set_sender_daily_cap(&1000);
+
+        env.ledger().set_timestamp(1_000);
+        distributor_client.distribute_equal(&sender, &token_address, &500, &recipients);
+        env.ledger().set_timestamp(1_000 + SECONDS_PER_DAY - 1);
+        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+        assert_eq!(distributor_client.get_sender_remaining_cap(&sender), 200);
 
-#[cfg(test)]
-mod test {
-  use super::*;
-    use soroban_sdk::{
-        testutils::{Address as _, Ledger, LedgerInfo},
-        token::{Client as TokenClient, StellarAssetClient},
-        Address, Env,
-    };
+        let result = distributor_client.try_distribute_weighted(&sender, &token_address, &recipients, &amounts);
+        assert_eq!(result, Err(Ok(Error::DailyCapExceeded.into())));
+        let result = distributor_client.try_distribute_equal(&sender, &token_address, &300, &recipients);
+        assert_eq!(result, Err(Ok(Error::DailyCapExceeded.into())));
 
+        // Other senders have their own window
+        assert_eq!(distributor_client.get_sender_remaining_cap(&Address::generate(&env)), 1000);
 
-    fn create_token_contract<'a>(
-        env: &Env,
-        admin: &Address,
-    ) -> (Address, TokenClient<'a>, StellarAssetClient<'a>) {
-        let token_address = env.register_stellar_asset_contract(admin.clone());
-        let token_client = TokenClient::new(env, &token_address);
-        let token_admin_client = StellarAssetClient::new(env, &token_address);
-        (token_address, token_client, token_admin_client)
+        env.ledger().set_timestamp(1_000 + SECONDS_PER_DAY);
+        assert_eq!(distributor_client.get_sender_remaining_cap(&sender), 1000);
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        assert_eq!(distributor_client.get_sender_remaining_cap(&sender), 0);
     }
 
-     
-    fn setup_distributor(env: &Env) -> (Address, DistributorContractClient, Address, Address) {
-        let contract_id = env.register(DistributorContract, ());
-        let client = DistributorContractClient::new(&env, &contract_id);
-        
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_set_sender_daily_cap_requires_admin() {
+        let env = Env::default();
+        let (_contract_id, distributor_client, _admin, _fee_address) = {
+            env.mock_all_auths();
+            setup_distributor(&env)
+        };
+        env.set_auths(&[]);
+        distributor_client.set_sender_daily_cap(&1000);
+    }
+
+    fn run_equal_v2(policy: RemainderPolicy) -> (Vec<i128>, i128, i128, i128) {
+        let env = Env::default();
+        env.mock_all_auths();
+
         let admin = Address::generate(&env);
-        let fee_address = Address::generate(&env);
-        
-        client.initialize(&admin, &250, &fee_address); 
-        
-        (contract_id, client, admin, fee_address)
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &10000);
+        let mut recipients = Vec::new(&env);
+        for _ in 0..7 {
+            recipients.push_back(Address::generate(&env));
+        }
+
+        distributor_client.distribute_equal_v2(&sender, &token_address, &900, &recipients, &policy);
+
+        let mut balances = Vec::new(&env);
+        for recipient in recipients.iter() {
+            balances.push_back(token_client.balance(&recipient));
+        }
+        let history = distributor_client.get_distribution_history(&0, &1).get(0).unwrap();
+        assert_eq!(distributor_client.get_total_distributed_amount(), history.amount);
+        (balances, token_client.balance(&sender), token_client.balance(&fee_address), history.amount)
     }
 
+    #[test]
+    fn test_distribute_equal_v2_remainder_policies() {
+        // 900 / 7 = 128 each with 4 left over; 2.5% fee on 900 = 22
+        let (balances, sender, fee, recorded) = run_equal_v2(RemainderPolicy::KeepWithSender);
+        assert!(balances.iter().all(|b| b == 128));
+        assert_eq!((sender, fee, recorded), (10000 - 896 - 22, 22, 896));
+
+        let (balances, sender, fee, recorded) = run_equal_v2(RemainderPolicy::ToFirstRecipient);
+        assert_eq!(balances.get(0).unwrap(), 132);
+        assert!(balances.iter().skip(1).all(|b| b == 128));
+        assert_eq!((sender, fee, recorded), (10000 - 900 - 22, 22, 900));
+
+        let (balances, sender, fee, recorded) = run_equal_v2(RemainderPolicy::ToFeeAddress);
+        assert!(balances.iter().all(|b| b == 128));
+        assert_eq!((sender, fee, recorded), (10000 - 900 - 22, 26, 896));
+    }
 
     #[test]
-    fn test_initialize() {
+    fn test_distribute_weighted_v2_receipt_and_events() {
         let env = Env::default();
         env.mock_all_auths();
 
-        let contract_id = env.register(DistributorContract, ());
-        let client = DistributorContractClient::new(&env, &contract_id);
-
         let admin = Address::generate(&env);
-        let fee_address = Address::generate(&env);
-
-        client.initialize(&admin, &250, &fee_address);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
-        let stored_admin = client.get_admin();
-        assert_eq!(stored_admin, Some(admin));
+        let sender = Address::generate(&env);
+        token_admin.mint(&sender, &100000);
+        let (first_recipients, _) = session_chunk(&env, 2);
+        distributor_client.distribute_equal(&sender, &token_address, &1000, &first_recipients);
+
+        let (recipients, amounts) = session_chunk(&env, 5);
+        let receipt = distributor_client.distribute_weighted_v2(&sender, &token_address, &recipients, &amounts);
+
+        let paid_topics: Vec<soroban_sdk::Val> = ("RecipientPaid", receipt.distribution_id).into_val(&env);
+        let paid_events = env.events().all().iter()
+            .filter(|(emitter, topics, _)| *emitter == contract_id && *topics == paid_topics)
+            .count();
+        assert_eq!(paid_events, recipients.len() as usize);
+
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, ("RecipientPaid", receipt.distribution_id).into_val(&env));
+        let last: RecipientPaidEvent = data.into_val(&env);
+        assert_eq!(
+            last,
+            RecipientPaidEvent { index: 4, recipient: recipients.get(4).unwrap(), amount: amounts.get(4).unwrap() }
+        );
+
+        let total: i128 = amounts.iter().sum();
+        assert_eq!(receipt.distribution_id, 1);
+        assert_eq!(receipt.total, total);
+        assert_eq!(receipt.recipients_count, 5);
+        assert_eq!(receipt.fee, total * 250 / 10000);
+
+        let history = distributor_client.get_distribution_history(&receipt.distribution_id, &1);
+        let record = history.get(0).unwrap();
+        assert_eq!((record.sender, record.amount, record.recipients_count), (sender, total, 5));
     }
 
     #[test]
-    #[should_panic(expected = "Contract already initialized")]
-    fn test_re_initialize_fails() {
+    fn test_distribute_multi_token() {
         let env = Env::default();
         env.mock_all_auths();
 
-        let contract_id = env.register(DistributorContract, ());
-        let client = DistributorContractClient::new(&env, &contract_id);
-
         let admin = Address::generate(&env);
-        let fee_address = Address::generate(&env);
+        let (usdc, usdc_client, usdc_admin) = create_token_contract(&env, &admin);
+        let (gov, gov_client, gov_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
 
-        client.initialize(&admin, &250, &fee_address);
-        // This should panic
-        client.initialize(&admin, &250, &fee_address);
+        let sender = Address::generate(&env);
+        usdc_admin.mint(&sender, &10000);
+        gov_admin.mint(&sender, &10000);
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+        let carol = Address::generate(&env);
+
+        let mut distributions = Vec::new(&env);
+        distributions.push_back(TokenDistribution {
+            token: usdc.clone(),
+            recipients: soroban_sdk::vec![&env, alice.clone(), bob.clone()],
+            amounts: soroban_sdk::vec![&env, 1000, 3000],
+        });
+        distributions.push_back(TokenDistribution {
+            token: gov.clone(),
+            recipients: soroban_sdk::vec![&env, bob.clone(), carol.clone()],
+            amounts: soroban_sdk::vec![&env, 200, 200],
+        });
+        let group_id = distributor_client.distribute_multi_token(&sender, &distributions);
+
+        assert_eq!(usdc_client.balance(&alice), 1000);
+        assert_eq!(usdc_client.balance(&bob), 3000);
+        assert_eq!(gov_client.balance(&bob), 200);
+        assert_eq!(gov_client.balance(&carol), 200);
+        // 2.5% per token
+        assert_eq!(usdc_client.balance(&fee_address), 100);
+        assert_eq!(gov_client.balance(&fee_address), 10);
+        assert_eq!(usdc_client.balance(&sender), 10000 - 4000 - 100);
+        assert_eq!(gov_client.balance(&sender), 10000 - 400 - 10);
+
+        let history_ids = distributor_client.get_distribution_group(&group_id);
+        assert_eq!(history_ids.len(), 2);
+        let usdc_record = distributor_client.get_distribution_history(&history_ids.get(0).unwrap(), &1).get(0).unwrap();
+        let gov_record = distributor_client.get_distribution_history(&history_ids.get(1).unwrap(), &1).get(0).unwrap();
+        assert_eq!((usdc_record.token, usdc_record.amount, usdc_record.recipients_count), (usdc, 4000, 2));
+        assert_eq!((gov_record.token, gov_record.amount, gov_record.recipients_count), (gov, 400, 2));
+
+        let second_group = distributor_client.distribute_multi_token(&sender, &distributions);
+        assert_ne!(second_group, group_id);
+        assert_eq!(distributor_client.get_distribution_group(&second_group).len(), 2);
     }
 
     #[test]
-    fn test_distribute_equal() {
+    fn test_distribute_multi_token_recipient_cap() {
         let env = Env::default();
         env.mock_all_auths();
 
         let admin = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (usdc, _usdc_client, _usdc_admin) = create_token_contract(&env, &admin);
+        let (gov, _gov_client, _gov_admin) = create_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
-        let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
-        let recipient2 = Address::generate(&env);
-        let recipient3 = Address::generate(&env);
-
-       
-        token_admin.mint(&sender, &10000);
+        let (recipients, amounts) = session_chunk(&env, 60);
+        let mut distributions = Vec::new(&env);
+        for token in [usdc, gov] {
+            distributions.push_back(TokenDistribution { token, recipients: recipients.clone(), amounts: amounts.clone() });
+        }
 
-       
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
-        recipients.push_back(recipient2.clone());
-        recipients.push_back(recipient3.clone());
+        let result = distributor_client.try_distribute_multi_token(&Address::generate(&env), &distributions);
+        assert_eq!(result, Err(Ok(Error::TooManyRecipients.into())));
+    }
 
-        
-        let total_amount = 900i128;
-        
-        distributor_client.distribute_equal(&sender, &token_address, &total_amount, &recipients);
+    #[test]
+    fn test_distribute_proportional_to_weight_balances() {
+        let env = Env::default();
+        env.mock_all_auths();
 
-        
-        assert_eq!(token_client.balance(&recipient1), 300);
-        assert_eq!(token_client.balance(&recipient2), 300);
-        assert_eq!(token_client.balance(&recipient3), 300);
+        let admin = Address::generate(&env);
+        let (reward_token, reward_client, reward_admin) = create_token_contract(&env, &admin);
+        let (weight_token, _weight_client, weight_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
 
-        assert_eq!(distributor_client.get_total_distributions(), 1);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 900);
+        let sender = Address::generate(&env);
+        reward_admin.mint(&sender, &10000);
+
+        let small = Address::generate(&env);
+        let medium = Address::generate(&env);
+        let large = Address::generate(&env);
+        let empty = Address::generate(&env);
+        weight_admin.mint(&small, &100);
+        weight_admin.mint(&medium, &300);
+        weight_admin.mint(&large, &600);
+
+        let holders = soroban_sdk::vec![&env, small.clone(), empty.clone(), medium.clone(), large.clone()];
+        let receipt = distributor_client.distribute_proportional(&sender, &reward_token, &weight_token, &holders, &1001);
+
+        // 100.1 / 300.3 / 600.6 round down; the leftover 1 goes to the largest holder
+        assert_eq!(reward_client.balance(&small), 100);
+        assert_eq!(reward_client.balance(&medium), 300);
+        assert_eq!(reward_client.balance(&large), 601);
+        assert_eq!(reward_client.balance(&empty), 0);
+        assert_eq!(reward_client.balance(&fee_address), 1001 * 250 / 10000);
+        assert_eq!((receipt.total, receipt.recipients_count), (1001, 3));
+
+        let history = distributor_client.get_proportional_history(&receipt.distribution_id).unwrap();
+        assert_eq!(history.weight_token, weight_token);
+        assert_eq!(history.token, reward_token);
+        assert_eq!((history.amount, history.recipients_count), (1001, 3));
     }
 
     #[test]
-    fn test_distribute_weighted() {
+    fn test_distribute_proportional_requires_weight() {
         let env = Env::default();
         env.mock_all_auths();
 
         let admin = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
+        let (reward_token, _reward_client, reward_admin) = create_token_contract(&env, &admin);
+        let (weight_token, _weight_client, _weight_admin) = create_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
         let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
-        let recipient2 = Address::generate(&env);
-        let recipient3 = Address::generate(&env);
+        reward_admin.mint(&sender, &10000);
+        let holders = soroban_sdk::vec![&env, Address::generate(&env), Address::generate(&env)];
+
+        let result = distributor_client.try_distribute_proportional(&sender, &reward_token, &weight_token, &holders, &1000);
+        assert_eq!(result, Err(Ok(Error::NoEligibleRecipients.into())));
+    }
 
+    const MONTH: u64 = 30 * SECONDS_PER_DAY;
+
+    fn schedule_fixture(env: &Env) -> (DistributorContractClient<'_>, Address, TokenClient<'_>, Address, Vec<Address>, u64) {
+        let admin = Address::generate(env);
+        let (token_address, token_client, token_admin) = create_token_contract(env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(env);
+
+        let sender = Address::generate(env);
         token_admin.mint(&sender, &10000);
+        let recipients = soroban_sdk::vec![env, Address::generate(env), Address::generate(env)];
+        let amounts = soroban_sdk::vec![env, 100i128, 200];
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
-        recipients.push_back(recipient2.clone());
-        recipients.push_back(recipient3.clone());
+        env.ledger().set_timestamp(1_000);
+        let schedule_id = distributor_client.create_schedule(&sender, &token_address, &recipients, &amounts, &MONTH, &3);
+        (distributor_client, sender, token_client, token_address, recipients, schedule_id)
+    }
 
-        let mut amounts = Vec::new(&env);
-        amounts.push_back(100);
-        amounts.push_back(200);
-        amounts.push_back(300);
+    #[test]
+    fn test_schedule_runs_once_per_interval() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (distributor_client, sender, token_client, _token, recipients, schedule_id) = schedule_fixture(&env);
 
-        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+        // 3 runs of 300 plus a 7 fee each
+        assert_eq!(token_client.balance(&sender), 10000 - 921);
+        assert_eq!(token_client.balance(&distributor_client.address), 921);
 
-        
-        assert_eq!(token_client.balance(&recipient1), 100);
-        assert_eq!(token_client.balance(&recipient2), 200);
-        assert_eq!(token_client.balance(&recipient3), 300);
+        let result = distributor_client.try_execute_schedule(&schedule_id);
+        assert_eq!(result, Err(Ok(Error::ScheduleNotDue.into())));
 
-       
-        assert_eq!(distributor_client.get_total_distributions(), 1);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 600);
+        for run in 1..=3u64 {
+            env.ledger().set_timestamp(1_000 + run * MONTH);
+            distributor_client.execute_schedule(&schedule_id);
+
+            let (_, topics, data) = env.events().all().last().unwrap();
+            assert_eq!(topics, ("ScheduleExecuted", schedule_id).into_val(&env));
+            let event: ScheduleExecutedEvent = data.into_val(&env);
+            assert_eq!(event.remaining, 3 - run as u32);
+
+            // A second call in the same interval is rejected
+            if run < 3 {
+                env.ledger().set_timestamp(1_000 + run * MONTH + MONTH - 1);
+                let result = distributor_client.try_execute_schedule(&schedule_id);
+                assert_eq!(result, Err(Ok(Error::ScheduleNotDue.into())));
+            }
+
+            assert_eq!(token_client.balance(&recipients.get(0).unwrap()), 100 * run as i128);
+            assert_eq!(token_client.balance(&recipients.get(1).unwrap()), 200 * run as i128);
+        }
+
+        env.ledger().set_timestamp(1_000 + 10 * MONTH);
+        let result = distributor_client.try_execute_schedule(&schedule_id);
+        assert_eq!(result, Err(Ok(Error::ScheduleFinished.into())));
+        assert_eq!(token_client.balance(&distributor_client.address), 0);
+        assert_eq!(distributor_client.get_total_distributions(), 3);
     }
 
-#[test]
-    fn test_distribute_equal_with_protocol_fee() {
+    #[test]
+    fn test_cancel_schedule_refunds_unexecuted_runs() {
         let env = Env::default();
         env.mock_all_auths();
+        let (distributor_client, sender, token_client, _token, _recipients, schedule_id) = schedule_fixture(&env);
 
-        let admin = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
-        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
-
-        let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
-        let recipient2 = Address::generate(&env);
+        env.ledger().set_timestamp(1_000 + MONTH);
+        distributor_client.execute_schedule(&schedule_id);
 
-        
-        token_admin.mint(&sender, &10000);
+        assert_eq!(distributor_client.cancel_schedule(&schedule_id), 2 * 307);
+        assert_eq!(token_client.balance(&sender), 10000 - 307);
+        assert_eq!(token_client.balance(&distributor_client.address), 0);
+        assert_eq!(distributor_client.get_schedule(&schedule_id).unwrap().remaining, 0);
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
-        recipients.push_back(recipient2.clone());
+        env.ledger().set_timestamp(1_000 + 2 * MONTH);
+        let result = distributor_client.try_execute_schedule(&schedule_id);
+        assert_eq!(result, Err(Ok(Error::ScheduleFinished.into())));
+    }
 
-       
-        let total_amount = 1000i128;
-        
-        distributor_client.distribute_equal(&sender, &token_address, &total_amount, &recipients);
+    #[test]
+    fn test_schedule_entry_ttl_extended_on_create_and_run() {
+        use soroban_sdk::testutils::storage::Persistent as _;
 
-        assert_eq!(token_client.balance(&recipient1), 500);
-        assert_eq!(token_client.balance(&recipient2), 500);
-        
-        
-        assert_eq!(token_client.balance(&fee_address), 25);
-        
-        
-        assert_eq!(token_client.balance(&sender), 8975);
+        let env = Env::default();
+        env.mock_all_auths();
+        let (distributor_client, _sender, _token_client, _token, _recipients, schedule_id) = schedule_fixture(&env);
+        let key = (Symbol::new(&env, "schedule"), schedule_id);
+        let ttl = || env.as_contract(&distributor_client.address, || env.storage().persistent().get_ttl(&key));
+        assert_eq!(ttl(), LEDGER_BUMP);
+
+        // Each run re-extends what time has eaten into; the instance is
+        // kept alive separately so only the schedule entry ages
+        env.as_contract(&distributor_client.address, || {
+            env.storage().instance().extend_ttl(LEDGER_BUMP, LEDGER_BUMP)
+        });
+        env.ledger().with_mut(|info| {
+            info.timestamp = 1_000 + MONTH;
+            info.sequence_number += LEDGER_BUMP - LEDGER_THRESHOLD + 1;
+        });
+        assert!(ttl() < LEDGER_THRESHOLD);
+        distributor_client.execute_schedule(&schedule_id);
+        assert_eq!(ttl(), LEDGER_BUMP);
     }
 
-    
+    #[test]
+    fn test_schedule_escrow_overflow_rejected() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let admin = Address::generate(&env);
+        let (token_address, _token_client, _token_admin) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let sender = Address::generate(&env);
+        let recipients = soroban_sdk::vec![&env, Address::generate(&env)];
 
-     #[test]
-    fn test_distribute_weighted_with_protocol_fee() {
+        // The runs themselves fit in an i128; adding their fees doesn't
+        let amounts = soroban_sdk::vec![&env, i128::MAX / 3];
+        let result = distributor_client.try_create_schedule(&sender, &token_address, &recipients, &amounts, &MONTH, &3);
+        assert_eq!(result, Err(Ok(Error::InvalidAmount.into())));
+    }
+
+    #[test]
+    fn test_prune_history_archives_old_entries() {
         let env = Env::default();
         env.mock_all_auths();
 
         let admin = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
-        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+        let (token_a, _client_a, admin_a) = create_token_contract(&env, &admin);
+        let (token_b, _client_b, admin_b) = create_token_contract(&env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
         let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
-        let recipient2 = Address::generate(&env);
-
-        token_admin.mint(&sender, &10000);
+        admin_a.mint(&sender, &100000);
+        admin_b.mint(&sender, &100000);
+        let recipients = soroban_sdk::vec![&env, Address::generate(&env), Address::generate(&env)];
+
+        for (i, (token, amount)) in [(&token_a, 100i128), (&token_b, 200), (&token_a, 300), (&token_a, 400), (&token_b, 500)]
+            .into_iter()
+            .enumerate()
+        {
+            env.ledger().set_timestamp(100 * (i as u64 + 1));
+            distributor_client.distribute_equal(&sender, token, &amount, &recipients);
+        }
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
-        recipients.push_back(recipient2.clone());
+        env.ledger().set_timestamp(1_000);
+        assert_eq!(distributor_client.prune_history(&250, &10), 2);
+        assert_eq!(distributor_client.get_history_watermark(), 2);
 
-        let mut amounts = Vec::new(&env);
-        amounts.push_back(400);
-        amounts.push_back(600);
+        let history = distributor_client.get_distribution_history(&0, &5);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get(0).unwrap().amount, 300);
+        assert_eq!(distributor_client.get_distribution_history(&0, &2).len(), 0);
 
-        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+        assert_eq!(distributor_client.get_archived_totals(&token_a), ArchivedTotals { count: 1, total_amount: 100 });
+        assert_eq!(distributor_client.get_archived_totals(&token_b), ArchivedTotals { count: 1, total_amount: 200 });
 
-        assert_eq!(token_client.balance(&recipient1), 400);
-        assert_eq!(token_client.balance(&recipient2), 600);
-        
-       
-        assert_eq!(token_client.balance(&fee_address), 25);
+        // Nothing else is old enough; a later cutoff is capped by max_entries
+        assert_eq!(distributor_client.prune_history(&250, &10), 0);
+        assert_eq!(distributor_client.prune_history(&10_000, &1), 1);
+        assert_eq!(distributor_client.get_history_watermark(), 3);
+        assert_eq!(distributor_client.get_archived_totals(&token_a), ArchivedTotals { count: 2, total_amount: 400 });
+        assert_eq!(distributor_client.get_total_distributions(), 5);
     }
 
-    
     #[test]
-    fn test_update_global_stats() {
+    #[should_panic(expected = "Unauthorized")]
+    fn test_prune_history_requires_admin() {
         let env = Env::default();
         env.mock_all_auths();
-
-        let admin = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
+        env.set_auths(&[]);
+        distributor_client.prune_history(&1_000, &10);
+    }
+
+    #[test]
+    fn test_validate_distribution_reports_first_bad_row() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (contract_id, distributor_client, admin, _fee_address) = setup_distributor(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
         let sender = Address::generate(&env);
-        token_admin.mint(&sender, &100000);
+        token_admin.mint(&sender, &10_000);
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(Address::generate(&env));
+        let (recipients, mut amounts) = session_chunk(&env, 5);
+        amounts.set(3, 0);
 
+        let report = distributor_client.validate_distribution(&sender, &token_address, &recipients, &amounts);
+        assert!(!report.ok);
+        assert_eq!(report.first_error_index, Some(3));
+        assert_eq!(report.error, Some(Error::InvalidAmount as u32));
+
+        // Nothing moved
+        assert_eq!(token_client.balance(&sender), 10_000);
+        assert_eq!(token_client.balance(&contract_id), 0);
         assert_eq!(distributor_client.get_total_distributions(), 0);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 0);
+    }
 
-      
-        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
-        
-        
-        assert_eq!(distributor_client.get_total_distributions(), 1);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 1000);
+    #[test]
+    fn test_validate_distribution_matches_real_path() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (_contract_id, distributor_client, admin, _fee_address) = setup_distributor(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let sender = Address::generate(&env);
+        let (recipients, amounts) = session_chunk(&env, 4);
+        let total: i128 = amounts.iter().sum();
+        let fee = total * 250 / 10_000;
+
+        // Balance must cover the fee as well
+        token_admin.mint(&sender, &(total + fee - 1));
+        let report = distributor_client.validate_distribution(&sender, &token_address, &recipients, &amounts);
+        assert_eq!(
+            report,
+            ValidationReport { ok: false, total, fee, first_error_index: None, error: Some(Error::InsufficientBalance as u32) }
+        );
+
+        token_admin.mint(&sender, &1);
+        let report = distributor_client.validate_distribution(&sender, &token_address, &recipients, &amounts);
+        assert_eq!(report, ValidationReport { ok: true, total, fee, first_error_index: None, error: None });
+        let receipt = distributor_client.distribute_weighted_v2(&sender, &token_address, &recipients, &amounts);
+        assert_eq!((receipt.total, receipt.fee), (total, fee));
+
+        let mut short = amounts.clone();
+        short.pop_back();
+        let report = distributor_client.validate_distribution(&sender, &token_address, &recipients, &short);
+        assert_eq!(report.error, Some(Error::LengthMismatch as u32));
+
+        distributor_client.deny_token(&token_address);
+        let report = distributor_client.validate_distribution(&sender, &token_address, &recipients, &amounts);
+        assert_eq!(report.error, Some(Error::TokenNotAllowed as u32));
+    }
 
-       
-        distributor_client.distribute_equal(&sender, &token_address, &2500, &recipients);
-        
-       
-        assert_eq!(distributor_client.get_total_distributions(), 2);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 3500);
+    fn setup_payment_stream(env: &Env) -> (Address, payment_stream::PaymentStreamContractClient<'_>, Address) {
+        let admin = Address::generate(env);
+        let contract_id = env.register(payment_stream::PaymentStreamContract, (&admin, None::<Address>, 0_u32));
+        let client = payment_stream::PaymentStreamContractClient::new(env, &contract_id);
+        (contract_id, client, admin)
+    }
 
-       
-        distributor_client.distribute_equal(&sender, &token_address, &500, &recipients);
-        
-       
-        assert_eq!(distributor_client.get_total_distributions(), 3);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 4000);
+    #[test]
+    fn test_distribute_as_streams_opens_funded_streams() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let (_contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
+        let (stream_contract, stream_client, _stream_admin) = setup_payment_stream(&env);
+        let sender = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10_000);
+
+        let recipients = Vec::from_array(&env, [Address::generate(&env), Address::generate(&env)]);
+        let amounts = Vec::from_array(&env, [1000_i128, 600]);
+        let stream_ids = distributor_client.distribute_as_streams(
+            &sender, &token_address, &stream_contract, &recipients, &amounts, &0, &100,
+        );
+
+        assert_eq!(stream_ids.len(), 2);
+        for i in 0..2 {
+            let stream = stream_client.get_stream(&stream_ids.get(i).unwrap());
+            assert_eq!(stream.sender, sender);
+            assert_eq!(stream.recipient, recipients.get(i).unwrap());
+            assert_eq!(stream.total_amount, amounts.get(i).unwrap());
+            assert_eq!(stream.balance, amounts.get(i).unwrap());
+            assert_eq!((stream.start_time, stream.end_time), (0, 100));
+        }
+        // 2.5% of 1600 goes to the fee address, the rest into escrow
+        assert_eq!(token_client.balance(&stream_contract), 1600);
+        assert_eq!(token_client.balance(&fee_address), 40);
+        assert_eq!(token_client.balance(&sender), 10_000 - 1640);
+
+        let history = distributor_client.get_stream_distribution_history(&0).unwrap();
+        assert_eq!(history.stream_ids, stream_ids);
+        assert_eq!(history.stream_contract, stream_contract);
+        assert_eq!((history.amount, history.recipients_count), (1600, 2));
+        assert_eq!(distributor_client.get_total_distributed_amount(), 1600);
+    }
+
+    #[test]
+    fn test_prune_history_drops_stream_distribution_records() {
+        let env = Env::default();
+        env.mock_all_auths();
 
-        
-        let mut amounts = Vec::new(&env);
-        amounts.push_back(300);
-        
-        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
-        
-        
-        assert_eq!(distributor_client.get_total_distributions(), 4);
-        assert_eq!(distributor_client.get_total_distributed_amount(), 4300);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let (stream_contract, _stream_client, _stream_admin) = setup_payment_stream(&env);
+        let sender = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10_000);
+
+        let recipients = Vec::from_array(&env, [Address::generate(&env)]);
+        let amounts = Vec::from_array(&env, [1000_i128]);
+        env.ledger().set_timestamp(100);
+        distributor_client.distribute_as_streams(&sender, &token_address, &stream_contract, &recipients, &amounts, &100, &200);
+        assert!(distributor_client.get_stream_distribution_history(&0).is_some());
+
+        env.ledger().set_timestamp(1_000);
+        assert_eq!(distributor_client.prune_history(&500, &10), 1);
+        assert_eq!(distributor_client.get_stream_distribution_history(&0), None);
     }
 
-     #[test]
-    fn test_update_token_statistics() {
+    #[test]
+    fn test_distribute_as_streams_reverts_on_failed_creation() {
         let env = Env::default();
         env.mock_all_auths();
 
-        let admin = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let (stream_contract, stream_client, stream_admin) = setup_payment_stream(&env);
+        stream_client.set_stream_limits(
+            &stream_admin,
+            &payment_stream::StreamLimits { min_stream_amount: 800, min_duration: 0, min_withdrawal_amount: 0 },
+        );
+        let sender = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10_000);
+
+        // The second stream is below the stream contract's minimum
+        let recipients = Vec::from_array(&env, [Address::generate(&env), Address::generate(&env)]);
+        let amounts = Vec::from_array(&env, [1000_i128, 600]);
+        let result = distributor_client.try_distribute_as_streams(
+            &sender, &token_address, &stream_contract, &recipients, &amounts, &0, &100,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(token_client.balance(&sender), 10_000);
+        assert_eq!(token_client.balance(&stream_contract), 0);
+        assert!(stream_client.find_stream(&1).is_none());
+        assert_eq!(distributor_client.get_total_distributions(), 0);
+    }
+
+    #[test]
+    fn test_distributions_reject_invalid_recipients() {
+        let env = Env::default();
+        env.mock_all_auths();
 
+        let (contract_id, client, _admin, _fee_address) = setup_distributor(&env);
         let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10_000);
+
+        for invalid in [&sender, &contract_id, &token_address] {
+            let recipients = Vec::from_array(&env, [Address::generate(&env), invalid.clone()]);
+            let amounts = Vec::from_array(&env, [100_i128, 100]);
+
+            let result = client.try_distribute_equal(&sender, &token_address, &200, &recipients);
+            assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+            let result = client.try_distribute_weighted(&sender, &token_address, &recipients, &amounts);
+            assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+            let result = client.try_create_schedule(&sender, &token_address, &recipients, &amounts, &3600, &2);
+            assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+
+            let report = client.validate_distribution(&sender, &token_address, &recipients, &amounts);
+            assert_eq!(report.error, Some(Error::InvalidRecipient as u32));
+            assert_eq!(report.first_error_index, Some(1));
+        }
+        assert_eq!(token_client.balance(&sender), 10_000);
+    }
 
-        token_admin.mint(&sender, &100000);
+    #[test]
+    fn test_initialize_after_constructor_cannot_take_over() {
+        let env = Env::default();
+        env.mock_all_auths();
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
+        let (_contract_id, client, admin, _fee_address) = setup_distributor(&env);
+        let stranger = Address::generate(&env);
 
-        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        let result = client.try_initialize(&stranger, &0, &stranger);
+        assert_eq!(result, Err(Ok(Error::AlreadyInitialized.into())));
+        assert_eq!(client.get_admin(), Some(admin));
+    }
 
-     
-        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
+    #[test]
+    fn test_admin_actions_logged_newest_first() {
+        let env = Env::default();
+        env.mock_all_auths();
 
-       
-        let token_stats = distributor_client.get_token_stats(&token_address);
-        assert!(token_stats.is_some());
-        
-        let stats = token_stats.unwrap();
-        assert_eq!(stats.total_amount, 3000);
-        assert_eq!(stats.distribution_count, 2);
-        assert!(stats.last_time > 0);
+        let (_contract_id, client, admin, _fee_address) = setup_distributor(&env);
+        let token = Address::generate(&env);
+
+        env.ledger().set_timestamp(10);
+        client.set_protocol_fee(&admin, &100);
+        env.ledger().set_timestamp(20);
+        client.allow_token(&token);
+        env.ledger().set_timestamp(30);
+        client.set_sender_daily_cap(&5_000);
+
+        let actions = client.get_admin_actions(&0, &10);
+        assert_eq!(actions.len(), 3);
+        assert_eq!(
+            actions.get(0).unwrap(),
+            AdminAction { action: Symbol::new(&env, "set_daily_cap"), actor: admin.clone(), timestamp: 30, detail: 5_000 }
+        );
+        assert_eq!(
+            actions.get(1).unwrap(),
+            AdminAction { action: Symbol::new(&env, "allow_token"), actor: admin.clone(), timestamp: 20, detail: 0 }
+        );
+        assert_eq!(
+            actions.get(2).unwrap(),
+            AdminAction { action: Symbol::new(&env, "set_fee"), actor: admin, timestamp: 10, detail: 100 }
+        );
+        assert_eq!(client.get_admin_actions(&2, &10).len(), 1);
     }
 
     #[test]
-    fn test_update_user_statistics() {
+    fn test_distribute_dedup_rejects_duplicate_recipient() {
         let env = Env::default();
         env.mock_all_auths();
 
@@ -561,208 +3816,643 @@ mod test {
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
         let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
-
-        token_admin.mint(&sender, &100000);
-
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
-
-       
-        distributor_client.distribute_equal(&sender, &token_address, &500, &recipients);
-        distributor_client.distribute_equal(&sender, &token_address, &1500, &recipients);
-        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
-
- 
-        let user_stats = distributor_client.get_user_stats(&sender);
-        assert!(user_stats.is_some());
-        
-        let stats = user_stats.unwrap();
-        assert_eq!(stats.distributions_initiated, 3);
-        assert_eq!(stats.total_amount, 4000);
+        token_admin.mint(&sender, &10_000);
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+        let recipients = soroban_sdk::vec![&env, alice.clone(), bob.clone(), alice.clone()];
+        let amounts = soroban_sdk::vec![&env, 100_i128, 200, 50];
+
+        assert_eq!(distributor_client.find_duplicate_recipient(&recipients), Some(2));
+        let result = distributor_client.try_distribute_weighted_dedup(
+            &sender, &token_address, &recipients, &amounts, &DuplicatePolicy::Reject,
+        );
+        assert_eq!(result, Err(Ok(Error::DuplicateRecipient.into())));
+        let result = distributor_client.try_distribute_equal_dedup(
+            &sender, &token_address, &300, &recipients, &DuplicatePolicy::Reject,
+        );
+        assert_eq!(result, Err(Ok(Error::DuplicateRecipient.into())));
+
+        assert_eq!(token_client.balance(&sender), 10_000);
+        assert_eq!(token_client.balance(&alice), 0);
+        assert_eq!(distributor_client.find_duplicate_recipient(&soroban_sdk::vec![&env, alice, bob]), None);
     }
 
-
-
-#[test]
-    fn test_record_history() {
+    #[test]
+    fn test_distribute_dedup_merges_duplicate_recipient() {
         let env = Env::default();
         env.mock_all_auths();
 
-       
-        env.ledger().set(LedgerInfo {
-            timestamp: 12345,
-            protocol_version: env.ledger().protocol_version(),
-            sequence_number: 10,
-            network_id: Default::default(),
-            base_reserve: 10,
-            min_temp_entry_ttl: 16,
-            min_persistent_entry_ttl: 16,
-            max_entry_ttl: 6312000,
-        });
-
         let admin = Address::generate(&env);
         let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
-        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let (contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
         let sender = Address::generate(&env);
-        let recipient1 = Address::generate(&env);
-        let recipient2 = Address::generate(&env);
+        token_admin.mint(&sender, &10_000);
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+        let recipients = soroban_sdk::vec![&env, alice.clone(), bob.clone(), alice.clone()];
+
+        let receipt = distributor_client.distribute_weighted_dedup(
+            &sender, &token_address, &recipients, &soroban_sdk::vec![&env, 100_i128, 200, 50], &DuplicatePolicy::Merge,
+        );
+        assert_eq!(token_client.balance(&alice), 150);
+        assert_eq!(token_client.balance(&bob), 200);
+        assert_eq!((receipt.total, receipt.recipients_count), (350, 2));
+
+        distributor_client.distribute_equal_dedup(&sender, &token_address, &300, &recipients, &DuplicatePolicy::Merge);
+        assert_eq!(token_client.balance(&alice), 150 + 200);
+        assert_eq!(token_client.balance(&bob), 200 + 100);
+        assert_eq!(token_client.balance(&contract_id), 0);
+    }
 
-        token_admin.mint(&sender, &100000);
+    /// Ten distributions of 100, 200, ..., 1000 with one per 100 seconds
+    fn ten_history_entries(env: &Env) -> DistributorContractClient<'_> {
+        let admin = Address::generate(env);
+        let (token, _token_client, token_admin) = create_token_contract(env, &admin);
+        let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(env);
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(recipient1.clone());
-        recipients.push_back(recipient2.clone());
+        let sender = Address::generate(env);
+        token_admin.mint(&sender, &100000);
+        let recipients = soroban_sdk::vec![env, Address::generate(env), Address::generate(env)];
+        for i in 1..=10_i128 {
+            env.ledger().set_timestamp(100 * i as u64);
+            distributor_client.distribute_equal(&sender, &token, &(100 * i), &recipients);
+        }
+        distributor_client
+    }
 
-       
-        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
-        distributor_client.distribute_equal(&sender, &token_address, &2000, &recipients);
+    fn history_amounts(env: &Env, history: &Vec<DistributionHistory>) -> Vec<i128> {
+        let mut amounts = Vec::new(env);
+        for record in history.iter() {
+            amounts.push_back(record.amount);
+        }
+        amounts
+    }
 
-       
-        let history = distributor_client.get_distribution_history(&0, &2);
-        assert_eq!(history.len(), 2);
+    #[test]
+    fn test_distribution_history_desc_pages_backwards() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let distributor_client = ten_history_entries(&env);
+
+        let (page, cursor) = distributor_client.get_distribution_history_desc(&None, &3);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 1000, 900, 800]);
+        assert_eq!(cursor, Some(7));
+
+        let (page, cursor) = distributor_client.get_distribution_history_desc(&cursor, &3);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 700, 600, 500]);
+        assert_eq!(cursor, Some(4));
+
+        let (page, cursor) = distributor_client.get_distribution_history_desc(&cursor, &3);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 400, 300, 200]);
+        assert_eq!(cursor, Some(1));
+
+        let (page, cursor) = distributor_client.get_distribution_history_desc(&cursor, &3);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 100]);
+        assert_eq!(cursor, None);
+
+        // Pruned entries end the walk early
+        env.ledger().set_timestamp(10_000);
+        distributor_client.prune_history(&450, &10);
+        let (page, cursor) = distributor_client.get_distribution_history_desc(&Some(6), &3);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 600, 500]);
+        assert_eq!(cursor, None);
+    }
 
-        let record1 = history.get(0).unwrap();
-        assert_eq!(record1.sender, sender);
-        assert_eq!(record1.token, token_address);
-        assert_eq!(record1.amount, 1000);
-        assert_eq!(record1.recipients_count, 2);
-        assert_eq!(record1.timestamp, 12345);
+    #[test]
+    fn test_distribution_history_page_reports_next_id() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let distributor_client = ten_history_entries(&env);
+
+        let (page, next) = distributor_client.get_distribution_history_page(&0, &4);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 100, 200, 300, 400]);
+        assert_eq!(next, Some(4));
+
+        let (page, next) = distributor_client.get_distribution_history_page(&8, &4);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 900, 1000]);
+        assert_eq!(next, None);
+
+        // A page starting inside the pruned range begins at the watermark
+        env.ledger().set_timestamp(10_000);
+        distributor_client.prune_history(&250, &10);
+        let (page, next) = distributor_client.get_distribution_history_page(&0, &3);
+        assert_eq!(history_amounts(&env, &page), soroban_sdk::vec![&env, 300, 400, 500]);
+        assert_eq!(next, Some(5));
+
+        let result = distributor_client.try_get_distribution_history_page(&0, &0);
+        assert_eq!(result, Err(Ok(Error::InvalidLimit.into())));
+        let result = distributor_client.try_get_distribution_history_desc(&None, &0);
+        assert_eq!(result, Err(Ok(Error::InvalidLimit.into())));
+    }
 
-    
-        let record2 = history.get(1).unwrap();
-        assert_eq!(record2.amount, 2000);
+    #[test]
+    fn test_get_config_matches_getters() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (_contract_id, client, admin, fee_address) = setup_distributor(&env);
+
+        client.set_protocol_fee(&admin, &100);
+        client.set_token_allowlist_enabled(&true);
+        client.set_sender_daily_cap(&5_000);
+
+        let config = client.get_config();
+        assert_eq!(config.config_version, 2);
+        assert_eq!(config.admin, client.get_admin());
+        assert_eq!(config.fee_address, Some(fee_address));
+        assert_eq!(config.protocol_fee_percent, 100);
+        assert!(config.allowlist_enabled);
+        assert_eq!(config.sender_daily_cap, client.get_sender_daily_cap());
+        assert_eq!(config.version, client.version());
+        assert_eq!(config.fee_registry, client.get_fee_registry());
+
+        // The allowlist flag agrees with how an unlisted token is treated
+        let token = Address::generate(&env);
+        assert_eq!(config.allowlist_enabled, !client.is_token_allowed(&token));
     }
 
+    #[test]
+    fn test_distribute_all_approved_splits_allowance_by_weight() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (contract_id, client, _admin, fee_address) = setup_distributor(&env);
+        let sender = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &20_000);
+        token_client.approve(&sender, &contract_id, &10_003, &1000);
+
+        let recipients = soroban_sdk::vec![
+            &env,
+            Address::generate(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+            Address::generate(&env),
+        ];
+        let weights = soroban_sdk::vec![&env, 1_u32, 2, 2, 1, 1];
+        let receipt = client.distribute_all_approved(&sender, &token_address, &recipients, &weights);
+
+        // 2.5% of 10_003 is 250, leaving 9_753 for 7 shares of 1_393 with
+        // 2 left over for the first of the heaviest recipients
+        assert_eq!(receipt.fee, 250);
+        assert_eq!(receipt.total, 9_753);
+        assert_eq!(token_client.balance(&fee_address), 250);
+        let mut paid = Vec::new(&env);
+        for recipient in recipients.iter() {
+            paid.push_back(token_client.balance(&recipient));
+        }
+        assert_eq!(paid, soroban_sdk::vec![&env, 1_393, 2_788, 2_786, 1_393, 1_393]);
+        assert_eq!(token_client.balance(&sender), 20_000 - 10_003);
+        assert_eq!(token_client.balance(&contract_id), 0);
+        assert_eq!(token_client.allowance(&sender, &contract_id), 0);
+
+        let result = client.try_distribute_all_approved(&sender, &token_address, &recipients, &weights);
+        assert_eq!(result, Err(Ok(Error::NothingToDistribute.into())));
+    }
 
+    fn distribution_event(env: &Env, distribution_id: u64) -> DistributionExecutedEvent {
+        let (_, _, data) = env.events().all().iter()
+            .find(|(_, topics, _)| *topics == ("DistributionExecuted", distribution_id).into_val(env))
+            .unwrap();
+        data.into_val(env)
+    }
 
     #[test]
-    fn test_set_protocol_fee() {
+    fn test_distributions_publish_executed_event() {
         let env = Env::default();
         env.mock_all_auths();
+        let (_contract_id, client, _admin, _fee_address) = setup_distributor(&env);
+        let sender = Address::generate(&env);
+        let (token_address, _token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&sender, &10_000);
+        let recipients = soroban_sdk::vec![&env, Address::generate(&env), Address::generate(&env)];
 
-        let contract_id = env.register(DistributorContract, ());
-        let client = DistributorContractClient::new(&env, &contract_id);
+        client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        assert_eq!(
+            distribution_event(&env, 0),
+            DistributionExecutedEvent {
+                distribution_id: 0,
+                sender: sender.clone(),
+                token: token_address.clone(),
+                total_amount: 1000,
+                fee: 25,
+                recipients_count: 2,
+                kind: Symbol::new(&env, "equal"),
+            }
+        );
 
-        let admin = Address::generate(&env);
-        let fee_address = Address::generate(&env);
+        client.distribute_weighted(&sender, &token_address, &recipients, &soroban_sdk::vec![&env, 300, 100]);
+        let event = distribution_event(&env, 1);
+        assert_eq!((event.total_amount, event.fee, event.recipients_count), (400, 10, 2));
+        assert_eq!(event.kind, Symbol::new(&env, "weighted"));
+        assert_eq!(event.sender, sender);
+    }
 
-        client.initialize(&admin, &250, &fee_address);
+    #[test]
+    fn test_set_protocol_fee_publishes_change() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (_contract_id, client, admin, _fee_address) = setup_distributor(&env);
 
-        // Change fee to 5% (500 basis points)
-        client.set_protocol_fee(&admin, &500);
+        client.set_protocol_fee(&admin, &100);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, ("ProtocolFeeChanged",).into_val(&env));
+        let payload: ProtocolFeeChangedEvent = data.into_val(&env);
+        assert_eq!(payload, ProtocolFeeChangedEvent { old_fee: 250, new_fee: 100 });
+    }
 
-        // Test with new fee
-        let sender = Address::generate(&env);
-        let token_admin_addr = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &token_admin_addr);
-        token_admin.mint(&sender, &10000);
+    #[test]
+    fn test_fee_registry_exempts_sender_on_both_contracts() {
+        let env = Env::default();
+        env.mock_all_auths();
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(Address::generate(&env));
+        let (_contract_id, distributor_client, admin, fee_address) = setup_distributor(&env);
+        let stream_fee_collector = Address::generate(&env);
+        let stream_contract = env.register(
+            payment_stream::PaymentStreamContract,
+            (&admin, Some(stream_fee_collector.clone()), 100_u32),
+        );
+        let stream_client = payment_stream::PaymentStreamContractClient::new(&env, &stream_contract);
+        let registry = env.register(fee_registry::FeeRegistryContract, (&admin,));
+        let registry_client = fee_registry::FeeRegistryContractClient::new(&env, &registry);
+
+        let partner = Address::generate(&env);
+        let regular = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&partner, &10_000);
+        token_admin.mint(&regular, &10_000);
+
+        registry_client.add_exempt(&partner);
+        distributor_client.set_fee_registry(&registry);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, ("FeeRegistryChanged",).into_val(&env));
+        let payload: FeeRegistryChangedEvent = data.into_val(&env);
+        assert_eq!(payload, FeeRegistryChangedEvent { old_registry: None, new_registry: Some(registry.clone()) });
+        stream_client.set_fee_registry(&registry);
+        assert_eq!(distributor_client.get_config().fee_registry, Some(registry.clone()));
+        assert_eq!(stream_client.get_config().fee_registry, Some(registry.clone()));
+
+        // Distributor fee: 2.5% for the regular sender, nothing for the partner
+        let recipients = Vec::from_array(&env, [Address::generate(&env), Address::generate(&env)]);
+        distributor_client.distribute_equal(&partner, &token_address, &1000, &recipients);
+        assert_eq!(token_client.balance(&fee_address), 0);
+        assert_eq!(token_client.balance(&partner), 9_000);
+        distributor_client.distribute_equal(&regular, &token_address, &1000, &recipients);
+        assert_eq!(token_client.balance(&fee_address), 25);
 
-        // 1000 tokens with 5% fee = 50 fee
-        client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        // Streams opened through the distributor: the partner's skip both
+        // the distribution fee and the 1% withdrawal fee
+        let stream_recipients = Vec::from_array(&env, [Address::generate(&env)]);
+        let amounts = Vec::from_array(&env, [1000_i128]);
+        let partner_stream = distributor_client
+            .distribute_as_streams(&partner, &token_address, &stream_contract, &stream_recipients, &amounts, &0, &100)
+            .get(0)
+            .unwrap();
+        assert_eq!(token_client.balance(&fee_address), 25);
+        let regular_recipients = Vec::from_array(&env, [Address::generate(&env)]);
+        let regular_stream = distributor_client
+            .distribute_as_streams(&regular, &token_address, &stream_contract, &regular_recipients, &amounts, &0, &100)
+            .get(0)
+            .unwrap();
         assert_eq!(token_client.balance(&fee_address), 50);
-    }
-
 
+        env.ledger().with_mut(|li| li.timestamp = 100);
+        stream_client.withdraw(&partner_stream, &1000);
+        stream_client.withdraw(&regular_stream, &1000);
+        assert_eq!(token_client.balance(&stream_recipients.get(0).unwrap()), 1000);
+        assert_eq!(token_client.balance(&regular_recipients.get(0).unwrap()), 990);
+        assert_eq!(stream_client.get_accrued_fees(&token_address), 10);
+
+        // Once the exemption is lifted the partner pays like everyone else
+        registry_client.remove_exempt(&partner);
+        distributor_client.distribute_equal(&partner, &token_address, &1000, &recipients);
+        assert_eq!(token_client.balance(&fee_address), 75);
+    }
 
-#[test]
-    fn test_zero_protocol_fee() {
+    #[test]
+    fn test_distribute_weighted_v3_skips_frozen_recipient() {
         let env = Env::default();
         env.mock_all_auths();
 
-        let contract_id = env.register(DistributorContract, ());
-        let client = DistributorContractClient::new(&env, &contract_id);
-
         let admin = Address::generate(&env);
-        let fee_address = Address::generate(&env);
-
-        // Initialize with 0% fee
-        client.initialize(&admin, &0, &fee_address);
+        let (token_address, token_client, token_admin) = create_revocable_token_contract(&env, &admin);
+        let (contract_id, distributor_client, _admin, fee_address) = setup_distributor(&env);
 
         let sender = Address::generate(&env);
-        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
         token_admin.mint(&sender, &10000);
-
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(Address::generate(&env));
-
-        client.distribute_equal(&sender, &token_address, &1000, &recipients);
-
-        // Fee address should have 0 balance
-        assert_eq!(token_client.balance(&fee_address), 0);
+        let (recipients, amounts) = session_chunk(&env, 3);
+        let frozen = recipients.get(1).unwrap();
+        token_admin.set_authorized(&frozen, &false);
+
+        let failed = distributor_client.distribute_weighted_v3(
+            &sender, &token_address, &recipients, &amounts, &FailurePolicy::SkipAndReport,
+        );
+        assert_eq!(failed, soroban_sdk::vec![&env, 1_u32]);
+
+        let skipped_event = env.events().all().last().unwrap();
+        assert_eq!(skipped_event.0, contract_id);
+        assert_eq!(skipped_event.1, ("RecipientSkipped", 0_u64).into_val(&env));
+        let skipped: RecipientSkippedEvent = skipped_event.2.into_val(&env);
+        assert_eq!(skipped, RecipientSkippedEvent { index: 1, recipient: frozen.clone(), amount: amounts.get(1).unwrap() });
+
+        // The frozen recipient's amount never left the sender, and the fee
+        // is charged on what was paid
+        let paid = amounts.get(0).unwrap() + amounts.get(2).unwrap();
+        let fee = paid * 250 / 10000;
+        assert_eq!(token_client.balance(&frozen), 0);
+        assert_eq!(token_client.balance(&recipients.get(0).unwrap()), amounts.get(0).unwrap());
+        assert_eq!(token_client.balance(&recipients.get(2).unwrap()), amounts.get(2).unwrap());
+        assert_eq!(token_client.balance(&sender), 10000 - paid - fee);
+        assert_eq!(token_client.balance(&fee_address), fee);
+
+        let record = distributor_client.get_distribution_history(&0, &1).get(0).unwrap();
+        assert_eq!((record.amount, record.recipients_count), (paid, 2));
+        assert_eq!(distributor_client.get_total_distributed_amount(), paid);
+        assert_eq!(distributor_client.get_token_stats(&token_address).unwrap().total_amount, paid);
+        assert_eq!(distributor_client.get_user_stats(&sender).unwrap().total_amount, paid);
     }
 
-
     #[test]
-    #[should_panic(expected = "All amounts must be positive")]
-    fn test_distribute_weighted_zero_amount() {
+    fn test_distribute_weighted_v3_revert_all() {
         let env = Env::default();
         env.mock_all_auths();
 
         let admin = Address::generate(&env);
-        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (token_address, token_client, token_admin) = create_revocable_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
 
         let sender = Address::generate(&env);
         token_admin.mint(&sender, &10000);
+        let (recipients, amounts) = session_chunk(&env, 3);
+
+        let failed = distributor_client.distribute_weighted_v3(
+            &sender, &token_address, &recipients, &amounts, &FailurePolicy::RevertAll,
+        );
+        assert!(failed.is_empty());
+
+        token_admin.set_authorized(&recipients.get(1).unwrap(), &false);
+        let before = token_client.balance(&sender);
+        let result = distributor_client.try_distribute_weighted_v3(
+            &sender, &token_address, &recipients, &amounts, &FailurePolicy::RevertAll,
+        );
+        assert!(result.is_err());
+        assert_eq!(token_client.balance(&sender), before);
+        assert_eq!(distributor_client.get_total_distributions(), 1);
 
-        let mut recipients = Vec::new(&env);
-        recipients.push_back(Address::generate(&env));
-        recipients.push_back(Address::generate(&env));
-
-        let mut amounts = Vec::new(&env);
-        amounts.push_back(100);
-        amounts.push_back(0); // Invalid: zero amount
-
-        distributor_client.distribute_weighted(&sender, &token_address, &recipients, &amounts);
+        // Skipping every recipient leaves nothing to record
+        for recipient in recipients.iter() {
+            token_admin.set_authorized(&recipient, &false);
+        }
+        let result = distributor_client.try_distribute_weighted_v3(
+            &sender, &token_address, &recipients, &amounts, &FailurePolicy::SkipAndReport,
+        );
+        assert_eq!(result, Err(Ok(Error::NothingToDistribute.into())));
     }
 
-     #[test]
-    #[should_panic(expected = "Amount too small to distribute")]
-    fn test_distribute_equal_amount_too_small() {
+    #[test]
+    fn test_top_tokens_and_senders_leaderboards() {
         let env = Env::default();
         env.mock_all_auths();
-
-        let admin = Address::generate(&env);
-        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let issuer = Address::generate(&env);
+        let recipient = Address::generate(&env);
+
+        // Token and sender i distribute (i + 1) * 100, in a shuffled order
+        let mut tokens = Vec::new(&env);
+        let mut senders = Vec::new(&env);
+        for _ in 0..25 {
+            let (token_address, _, token_admin) = create_token_contract(&env, &issuer);
+            let sender = Address::generate(&env);
+            token_admin.mint(&sender, &100_000);
+            tokens.push_back(token_address);
+            senders.push_back(sender);
+        }
+        for step in 0..25_u32 {
+            let i = (step * 7) % 25;
+            let amount = (i as i128 + 1) * 100;
+            distributor_client.distribute_weighted(
+                &senders.get(i).unwrap(), &tokens.get(i).unwrap(), &soroban_sdk::vec![&env, recipient.clone()], &soroban_sdk::vec![&env, amount],
+            );
+        }
 
-        let sender = Address::generate(&env);
-        token_admin.mint(&sender, &10000);
-
-        // Create many recipients so amount per recipient becomes 0
-        let mut recipients = Vec::new(&env);
-        for _ in 0..1000 {
-            recipients.push_back(Address::generate(&env));
+        let top_tokens = distributor_client.get_top_tokens();
+        let top_senders = distributor_client.get_top_senders();
+        assert_eq!(top_tokens.len(), 20);
+        assert_eq!(top_senders.len(), 20);
+        for rank in 0..20 {
+            let i = 24 - rank;
+            let amount = (i as i128 + 1) * 100;
+            assert_eq!(top_tokens.get(rank).unwrap(), (tokens.get(i).unwrap(), amount));
+            assert_eq!(top_senders.get(rank).unwrap(), (senders.get(i).unwrap(), amount));
         }
 
-        distributor_client.distribute_equal(&sender, &token_address, &10, &recipients);
+        // The smallest token climbs from off the board to its middle, pushing
+        // the last entry off
+        distributor_client.distribute_weighted(
+            &senders.get(0).unwrap(), &tokens.get(0).unwrap(), &soroban_sdk::vec![&env, recipient.clone()], &soroban_sdk::vec![&env, 1_950],
+        );
+        let top_tokens = distributor_client.get_top_tokens();
+        assert_eq!(top_tokens.len(), 20);
+        assert_eq!(top_tokens.get(4).unwrap(), (tokens.get(20).unwrap(), 2_100));
+        assert_eq!(top_tokens.get(5).unwrap(), (tokens.get(0).unwrap(), 2_050));
+        assert_eq!(top_tokens.get(6).unwrap(), (tokens.get(19).unwrap(), 2_000));
+        assert!(top_tokens.iter().all(|(token, _)| token != tokens.get(5).unwrap()));
+        assert_eq!(top_tokens.last().unwrap(), (tokens.get(6).unwrap(), 700));
+        assert_eq!(distributor_client.get_top_senders().get(5).unwrap(), (senders.get(0).unwrap(), 2_050));
     }
 
     #[test]
-    #[should_panic(expected = "No recipients provided")]
-    fn test_distribute_equal_empty_recipients() {
+    fn test_fee_matches_payment_stream_withdrawal_fee() {
         let env = Env::default();
         env.mock_all_auths();
 
         let admin = Address::generate(&env);
-        let (token_address, _token_client, token_admin) = create_token_contract(&env, &admin);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &admin);
         let (_contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let stream_contract = env.register(
+            payment_stream::PaymentStreamContract,
+            (&admin, Some(Address::generate(&env)), 250_u32),
+        );
+        let stream_client = payment_stream::PaymentStreamContractClient::new(&env, &stream_contract);
 
         let sender = Address::generate(&env);
-        token_admin.mint(&sender, &10000);
-
-        let recipients = Vec::new(&env);
-        distributor_client.distribute_equal(&sender, &token_address, &1000, &recipients);
+        token_admin.mint(&sender, &100_000_000);
+        env.ledger().set_timestamp(10);
+        for amount in [1_i128, 39, 40, 399, 1001, 123_457, 10_000_000] {
+            let recipients = soroban_sdk::vec![&env, Address::generate(&env)];
+            let amounts = soroban_sdk::vec![&env, amount];
+            let receipt = distributor_client.distribute_weighted_v2(&sender, &token_address, &recipients, &amounts);
+
+            // A fresh stream has no fee carry, so one full withdrawal pays
+            // the plain rounded-down fee
+            let recipient = Address::generate(&env);
+            let stream_id = stream_client.create_stream(&sender, &recipient, &token_address, &amount, &amount, &0, &1);
+            stream_client.withdraw(&stream_id, &amount);
+            let stream_fee = amount - token_client.balance(&recipient);
+
+            assert_eq!(receipt.fee, common::bps_of(amount, 250));
+            assert_eq!(stream_fee, receipt.fee);
+        }
     }
 
-}
+    /// SEP-41 subset whose transfers take a `fee_bps` fee, out of the amount
+    /// received or, with `charge_payer`, on top of it from the payer
+    mod fee_token {
+        use soroban_sdk::{contract, contractimpl, symbol_short, Address, Env};
 
-    
+        #[contract]
+        pub struct FeeOnTransferToken;
+
+        #[contractimpl]
+        impl FeeOnTransferToken {
+            pub fn set_fee(env: Env, fee_bps: i128, charge_payer: bool) {
+                env.storage().instance().set(&symbol_short!("fee"), &(fee_bps, charge_payer));
+            }
+
+            pub fn mint(env: Env, to: Address, amount: i128) {
+                env.storage().persistent().set(&to, &(Self::balance(env.clone(), to.clone()) + amount));
+            }
+
+            pub fn balance(env: Env, id: Address) -> i128 {
+                env.storage().persistent().get(&id).unwrap_or(0)
+            }
+
+            pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+                from.require_auth();
+                let (fee_bps, charge_payer): (i128, bool) =
+                    env.storage().instance().get(&symbol_short!("fee")).unwrap_or((0, false));
+                let fee = amount * fee_bps / 10_000;
+                let (debit, credit) = if charge_payer { (amount + fee, amount) } else { (amount, amount - fee) };
+                let from_balance = Self::balance(env.clone(), from.clone());
+                if from_balance < debit {
+                    panic!("insufficient balance");
+                }
+                env.storage().persistent().set(&from, &(from_balance - debit));
+                env.storage().persistent().set(&to, &(Self::balance(env.clone(), to.clone()) + credit));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fee_on_transfer_token_rejected_from_escrow() {
+        let env = Env::default();
+        env.mock_all_auths();
 
+        let (contract_id, distributor_client, _admin, _fee_address) = setup_distributor(&env);
+        let token = env.register(fee_token::FeeOnTransferToken, ());
+        let token_client = fee_token::FeeOnTransferTokenClient::new(&env, &token);
+        let sender = Address::generate(&env);
+        token_client.mint(&sender, &100_000);
+
+        // 1% kept out of what the escrow receives
+        token_client.set_fee(&100, &false);
+        let result = distributor_client.try_start_distribution_session(&sender, &token, &10_000);
+        assert_eq!(result, Err(Ok(Error::NonStandardTokenBehavior.into())));
+
+        // 1% charged on top of what the escrow pays out
+        token_client.set_fee(&0, &false);
+        let (recipients, amounts) = session_chunk(&env, 3);
+        let schedule_id = distributor_client.create_schedule(&sender, &token, &recipients, &amounts, &60, &2);
+        let escrowed = token_client.balance(&contract_id);
+        token_client.set_fee(&100, &true);
+        env.ledger().set_timestamp(env.ledger().timestamp() + 60);
+        let result = distributor_client.try_execute_schedule(&schedule_id);
+        assert_eq!(result, Err(Ok(Error::NonStandardTokenBehavior.into())));
+        assert_eq!(token_client.balance(&contract_id), escrowed);
+    }
 
+    #[test]
+    fn test_distribute_from_saved_list() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (_contract_id, client, _admin, fee_address) = setup_distributor(&env);
+        let owner = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&owner, &100_000);
+
+        let recipients = soroban_sdk::vec![&env, Address::generate(&env), Address::generate(&env), Address::generate(&env)];
+        let weights = soroban_sdk::vec![&env, 1_u32, 2, 1];
+        let name = Symbol::new(&env, "contributors");
+        let list_id = client.save_recipient_list(&owner, &name, &recipients, &weights);
+        assert_eq!(
+            client.get_recipient_list(&owner, &list_id),
+            Some(RecipientList { name: name.clone(), recipients: recipients.clone(), weights: weights.clone() })
+        );
+
+        // Shares of 1_000, 2_000 and 1_000, then 2_500, 5_001 and 2_500,
+        // the remainder going to the heaviest weight
+        let receipt = client.distribute_from_list(&owner, &token_address, &list_id, &4_000);
+        assert_eq!((receipt.total, receipt.recipients_count, receipt.fee), (4_000, 3, 100));
+        client.distribute_from_list(&owner, &token_address, &list_id, &10_001);
+        let mut paid = Vec::new(&env);
+        for recipient in recipients.iter() {
+            paid.push_back(token_client.balance(&recipient));
+        }
+        assert_eq!(paid, soroban_sdk::vec![&env, 3_500, 7_001, 3_500]);
+        assert_eq!(token_client.balance(&fee_address), 100 + 250);
+
+        // New weights apply to the next run; the name is kept
+        let new_weights = soroban_sdk::vec![&env, 1_u32, 0, 3];
+        assert_eq!(
+            client.try_update_recipient_list(&owner, &list_id, &recipients, &new_weights),
+            Err(Ok(Error::InvalidAmount.into()))
+        );
+        client.update_recipient_list(&owner, &list_id, &recipients, &soroban_sdk::vec![&env, 1_u32, 1, 2]);
+        assert_eq!(client.get_recipient_list(&owner, &list_id).unwrap().name, name);
+        client.distribute_from_list(&owner, &token_address, &list_id, &400);
+        assert_eq!(token_client.balance(&recipients.get(2).unwrap()), 3_700);
+
+        // Too small a total would leave a recipient with nothing
+        assert_eq!(
+            client.try_distribute_from_list(&owner, &token_address, &list_id, &3),
+            Err(Ok(Error::InvalidAmount.into()))
+        );
+
+        client.delete_recipient_list(&owner, &list_id);
+        assert_eq!(client.get_recipient_list(&owner, &list_id), None);
+        assert_eq!(
+            client.try_distribute_from_list(&owner, &token_address, &list_id, &400),
+            Err(Ok(Error::ListNotFound.into()))
+        );
+        // Ids are not reused
+        assert_eq!(client.save_recipient_list(&owner, &name, &recipients, &weights), list_id + 1);
+    }
 
+    #[test]
+    fn test_recipient_lists_are_scoped_to_their_owner() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (_contract_id, client, _admin, _fee_address) = setup_distributor(&env);
+        let owner = Address::generate(&env);
+        let other = Address::generate(&env);
+        let (token_address, token_client, token_admin) = create_token_contract(&env, &Address::generate(&env));
+        token_admin.mint(&other, &10_000);
+
+        let recipients = soroban_sdk::vec![&env, Address::generate(&env), Address::generate(&env)];
+        let weights = soroban_sdk::vec![&env, 1_u32, 1];
+        let list_id = client.save_recipient_list(&owner, &Symbol::new(&env, "payroll"), &recipients, &weights);
+
+        // The id means nothing under another owner
+        assert_eq!(client.get_recipient_list(&other, &list_id), None);
+        assert_eq!(
+            client.try_distribute_from_list(&other, &token_address, &list_id, &1_000),
+            Err(Ok(Error::ListNotFound.into()))
+        );
+        assert_eq!(
+            client.try_update_recipient_list(&other, &list_id, &recipients, &weights),
+            Err(Ok(Error::ListNotFound.into()))
+        );
+        assert_eq!(client.try_delete_recipient_list(&other, &list_id), Err(Ok(Error::ListNotFound.into())));
+        assert_eq!(token_client.balance(&recipients.get(0).unwrap()), 0);
+
+        // Each owner numbers their own lists
+        let own_id = client.save_recipient_list(&other, &Symbol::new(&env, "payroll"), &recipients, &weights);
+        assert_eq!(own_id, list_id);
+        assert_eq!(client.get_recipient_list(&owner, &list_id).unwrap().weights, weights);
+
+        // Saving or changing a list needs the owner's own auth
+        env.mock_auths(&[]);
+        assert!(client.try_update_recipient_list(&owner, &list_id, &recipients, &weights).is_err());
+        assert!(client.try_distribute_from_list(&owner, &token_address, &list_id, &1_000).is_err());
+    }
+}
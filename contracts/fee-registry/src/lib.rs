@@ -0,0 +1,86 @@
+#![no_std]
+//! Shared list of fee-exempt addresses. `payment-stream` and `distributor`
+//! each point at one registry with `set_fee_registry` and skip the protocol
+//! fee for any address it reports as exempt.
+use common::{LEDGER_BUMP, LEDGER_THRESHOLD};
+use soroban_sdk::{contract, contractimpl, contracttype, Address, Env};
+
+#[contracttype]
+#[derive(Clone)]
+pub enum DataKey {
+    Admin,
+    Exempt(Address),
+}
+
+/// Exemption added or removed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeExemptionChangedEvent {
+    pub address: Address,
+    pub exempt: bool,
+}
+
+/// Admin handed over event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminChangedEvent {
+    pub old_admin: Address,
+    pub new_admin: Address,
+}
+
+#[contract]
+pub struct FeeRegistryContract;
+
+#[contractimpl]
+impl FeeRegistryContract {
+    pub fn __constructor(env: Env, admin: Address) {
+        env.storage().instance().set(&DataKey::Admin, &admin);
+    }
+
+    /// Exempt `address` from protocol fees (admin only)
+    pub fn add_exempt(env: Env, address: Address) {
+        Self::require_admin(&env);
+        let key = DataKey::Exempt(address.clone());
+        env.storage().persistent().set(&key, &true);
+        env.storage().persistent().extend_ttl(&key, LEDGER_THRESHOLD, LEDGER_BUMP);
+        env.events().publish(
+            ("FeeExemptionChanged",),
+            FeeExemptionChangedEvent { address, exempt: true },
+        );
+    }
+
+    /// Charge `address` protocol fees again (admin only)
+    pub fn remove_exempt(env: Env, address: Address) {
+        Self::require_admin(&env);
+        env.storage().persistent().remove(&DataKey::Exempt(address.clone()));
+        env.events().publish(
+            ("FeeExemptionChanged",),
+            FeeExemptionChangedEvent { address, exempt: false },
+        );
+    }
+
+    pub fn is_fee_exempt(env: Env, address: Address) -> bool {
+        env.storage().persistent().has(&DataKey::Exempt(address))
+    }
+
+    pub fn get_admin(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::Admin).unwrap()
+    }
+
+    /// Hand the registry over to `new_admin` (current admin only)
+    pub fn set_admin(env: Env, new_admin: Address) {
+        let old_admin = Self::require_admin(&env);
+        env.storage().instance().set(&DataKey::Admin, &new_admin);
+        env.events().publish(("AdminChanged",), AdminChangedEvent { old_admin, new_admin });
+    }
+
+    fn require_admin(env: &Env) -> Address {
+        let admin = Self::get_admin(env.clone());
+        admin.require_auth();
+        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+        admin
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,64 @@
+#![cfg(test)]
+
+use super::*;
+use soroban_sdk::testutils::{Address as _, Events, MockAuth, MockAuthInvoke};
+use soroban_sdk::{IntoVal, TryIntoVal};
+
+fn setup(env: &Env) -> (FeeRegistryContractClient<'_>, Address) {
+    let admin = Address::generate(env);
+    let contract_id = env.register(FeeRegistryContract, (&admin,));
+    (FeeRegistryContractClient::new(env, &contract_id), admin)
+}
+
+#[test]
+fn test_add_and_remove_exemption() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, admin) = setup(&env);
+    let partner = Address::generate(&env);
+
+    assert!(!client.is_fee_exempt(&partner));
+    client.add_exempt(&partner);
+    assert_eq!(env.auths()[0].0, admin);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("FeeExemptionChanged",).into_val(&env));
+    let payload: FeeExemptionChangedEvent = data.try_into_val(&env).unwrap();
+    assert_eq!(payload, FeeExemptionChangedEvent { address: partner.clone(), exempt: true });
+    assert!(client.is_fee_exempt(&partner));
+
+    // Adding twice is harmless; one removal clears it
+    client.add_exempt(&partner);
+    client.remove_exempt(&partner);
+    assert!(!client.is_fee_exempt(&partner));
+    client.remove_exempt(&partner);
+    assert!(!client.is_fee_exempt(&partner));
+}
+
+#[test]
+fn test_only_admin_manages_exemptions() {
+    let env = Env::default();
+    let (client, _admin) = setup(&env);
+    let outsider = Address::generate(&env);
+
+    let result = client
+        .mock_auths(&[MockAuth {
+            address: &outsider,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "add_exempt",
+                args: (&outsider,).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .try_add_exempt(&outsider);
+    assert!(result.is_err());
+    assert!(!client.is_fee_exempt(&outsider));
+
+    // A new admin takes over and the old one loses control
+    let new_admin = Address::generate(&env);
+    env.mock_all_auths();
+    client.set_admin(&new_admin);
+    assert_eq!(client.get_admin(), new_admin);
+    client.add_exempt(&outsider);
+    assert_eq!(env.auths()[0].0, new_admin);
+}
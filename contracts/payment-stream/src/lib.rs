@@ -1,5 +1,8 @@
 #![no_std]
-use soroban_sdk::{contract, contracterror, contractimpl, contracttype, panic_with_error, token, Address, Env, Symbol};
+use soroban_sdk::auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation};
+use soroban_sdk::xdr::ToXdr;
+use common::{bps_of, bps_of_with_carry, FeeConfig, FeeConfigError, StorageExt};
+use soroban_sdk::{contract, contracterror, contractimpl, contracttype, panic_with_error, token, Address, Bytes, BytesN, Env, IntoVal, Map, Symbol, Vec};
 
 /// Stream status enum
 #[contracttype]
@@ -9,11 +12,17 @@ pub enum StreamStatus {
     Paused,
     Canceled,
     Completed,
+    /// Frozen by the admin pending a compliance review; no funds move
+    /// until `release_quarantine`
+    Quarantined,
+    /// Turned down by the recipient before any withdrawal; the whole
+    /// escrow went back to the funders
+    Rejected,
 }
 
 /// Stream data structure
 #[contracttype]
-#[derive(Clone)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Stream {
     pub id: u64,
     pub sender: Address,
@@ -27,6 +36,73 @@ pub struct Stream {
     pub status: StreamStatus,
     pub paused_at: Option<u64>,  
     pub total_paused_duration: u64,
+    pub cliff_time: u64,              // Nothing vests before this; equals start_time when there's no cliff
+    pub cancelable: bool,             // False guarantees the sender can neither cancel nor pause
+    pub notify_recipient: bool,       // Call the recipient's `on_stream_payment` after each payout
+    pub claim_deadline: Option<u64>,  // After this, withdrawals stop and the sender may reclaim the balance
+    pub max_pause_seconds: Option<u64>, // Cap on the stream's cumulative pause time; None is unlimited
+}
+
+/// Optional stream behaviour chosen at creation
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamOptions {
+    pub cancelable: bool,
+    pub notify_recipient: bool,
+    /// Release the stream in sender-approved chunks instead of over time.
+    /// Amounts must add up to the stream's total.
+    pub milestones: Option<Vec<Milestone>>,
+    /// Last moment the recipient can withdraw; afterwards the sender can
+    /// `reclaim_stale` whatever is left. Must not be before the end time.
+    pub claim_deadline: Option<u64>,
+    /// Total time the sender may keep the stream paused, in the stream's
+    /// time basis. Once a pause runs past what is left, anyone can
+    /// `force_resume` it.
+    pub max_pause_seconds: Option<u64>,
+    /// Allow a start time more than an hour in the past, for streams that
+    /// are meant to be partly vested on creation
+    pub backdated: bool,
+    /// Accept a token that moves a different amount than requested (a
+    /// transfer fee). Shortfalls are taken off the stream's balance instead
+    /// of failing with `NonStandardTokenBehavior`.
+    pub allow_fee_on_transfer: bool,
+    /// Clock the schedule runs on. With `LedgerSequence`, start, end,
+    /// cliff, claim deadline and pause times are all ledger numbers.
+    pub time_basis: TimeBasis,
+}
+
+/// What a stream's schedule is measured in, fixed at creation
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimeBasis {
+    /// Ledger close time in seconds
+    Timestamp,
+    /// Ledger sequence number, for schedules that must not depend on
+    /// validators' clocks
+    LedgerSequence,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        StreamOptions {
+            cancelable: true,
+            notify_recipient: false,
+            milestones: None,
+            claim_deadline: None,
+            max_pause_seconds: None,
+            backdated: false,
+            allow_fee_on_transfer: false,
+            time_basis: TimeBasis::Timestamp,
+        }
+    }
+}
+
+/// A chunk of a milestone stream, withdrawable once the sender approves it
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Milestone {
+    pub amount: i128,
+    pub approved: bool,
 }
 
 /// Per-stream metrics tracking
@@ -40,6 +116,9 @@ pub struct StreamMetrics {
     pub total_delegations: u32,       // Total number of delegation changes
     pub current_delegate: Option<Address>, // Current delegate (if any)
     pub last_delegation_time: u64,    // Timestamp of last delegation change
+    pub forced_revocations: u32,      // Delegates removed by the sender
+    pub sponsored_fees: i128,         // Protocol fees the fee sponsor paid instead of the recipient
+    pub last_withdrawal_time: u64,    // When the last withdrawal settled; 0 before the first
 }
 
 /// Protocol-wide metrics tracking
@@ -47,9 +126,194 @@ pub struct StreamMetrics {
 #[derive(Clone)]
 pub struct ProtocolMetrics {
     pub total_active_streams: u64,    // Count of currently active streams
-    pub total_tokens_streamed: i128,  // Total tokens ever streamed
+    pub total_tokens_streamed: i128,  // Tokens actually escrowed (initial funding plus deposits)
     pub total_streams_created: u64,   // Total number of streams created
     pub total_delegations: u64,       // Total number of delegations across all streams
+    pub total_committed: i128,        // Totals of running streams plus what closed streams paid or still owe
+    pub total_fees_collected: i128,   // Protocol fees withheld from withdrawals, claimed or not
+}
+
+/// `ProtocolMetrics` as stored under `DataKey::ProtocolMetrics` before fee
+/// revenue was tracked. Read once and rewritten under `ProtocolMetricsV2`.
+#[contracttype]
+#[derive(Clone)]
+struct LegacyProtocolMetrics {
+    total_active_streams: u64,
+    total_tokens_streamed: i128,
+    total_streams_created: u64,
+    total_delegations: u64,
+    total_committed: i128,
+}
+
+/// Transfer that moved a different amount than booked, on a stream that
+/// allows fee-on-transfer tokens. Amounts are the change in the contract's
+/// balance: positive for tokens in, negative for tokens out.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransferShortfallEvent {
+    pub stream_id: u64,
+    pub expected: i128,
+    pub actual: i128,
+    /// Taken off the stream's balance; 0 once the stream has closed
+    pub absorbed: i128,
+}
+
+/// Progress of a chunked `rebuild_protocol_metrics`: the next stream id to
+/// scan and the counters accumulated so far
+#[contracttype]
+#[derive(Clone)]
+struct MetricsRebuild {
+    next_id: u64,
+    metrics: ProtocolMetrics,
+}
+
+/// Lower bounds on new streams and withdrawals, all 0 (off) by default
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StreamLimits {
+    pub min_stream_amount: i128,      // Smallest total_amount a new stream may promise
+    pub min_duration: u64,            // Shortest end_time - start_time for a new stream
+    pub min_withdrawal_amount: i128,  // Smallest gross withdrawal, except one that empties the stream
+}
+
+/// How far a stream's funded balance covers its vesting schedule
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FundingStatus {
+    pub funded_through: u64,  // When vesting catches up with the balance; end_time if fully funded
+    pub shortfall: i128,      // Deposits still needed to cover total_amount
+}
+
+/// One beneficiary of a shared stream
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Beneficiary {
+    pub address: Address,
+    pub share_bps: u32,
+    pub withdrawn: i128,  // Gross amount settled to this beneficiary so far
+}
+
+/// How much of a stream's total has been deposited so far. Withdrawals
+/// don't lower `funded`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FundingProgress {
+    pub funded: i128,     // Initial amount plus every deposit
+    pub total: i128,
+    pub funded_bps: u32,  // funded / total in basis points, rounded down
+}
+
+/// What a `withdraw` of `gross` would pay right now. `valid` is false when
+/// the withdrawal would be rejected (amount not positive, above
+/// `withdrawable`, below the withdrawal minimum, past the claim deadline,
+/// or while the contract is paused).
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalQuote {
+    pub gross: i128,
+    pub fee: i128,
+    pub net: i128,
+    pub withdrawable: i128,
+    pub valid: bool,
+}
+
+/// Per-token stream metrics, stored under `("token_metrics", token)`
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TokenStreamMetrics {
+    pub total_streamed: i128,         // Sum of total_amount of streams created in this token
+    pub active_streams: u64,          // Streams in this token that are currently active
+    pub total_withdrawn: i128,        // Gross amount withdrawn, fees included
+    pub escrowed_balance: i128,       // Tokens held for streams; anything above this is sweepable
+}
+
+/// Aggregated view of a stream for front-ends, bundling the data that
+/// would otherwise take several separate simulation calls to fetch
+#[contracttype]
+#[derive(Clone)]
+pub struct StreamSummary {
+    pub stream: Stream,
+    pub metrics: StreamMetrics,
+    pub delegate: Option<Address>,
+    pub withdrawable: i128,
+    pub fee_rate: u32,
+}
+
+/// Result of `create_stream_detailed`: the new stream as stored, and the
+/// fee rate its withdrawals will be charged
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamCreated {
+    pub id: u64,
+    pub stream: Stream,
+    pub effective_fee_rate: u32,
+}
+
+/// Result of `verify_stream_integrity`: `ok` is true when `violations`,
+/// the names of the invariants that failed, is empty
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    pub violations: Vec<Symbol>,
+}
+
+/// A recipient's totals over one page of its streams. `pending_unvested`
+/// only counts Active and Paused streams (a paused stream is measured as
+/// of its pause); `next_cursor` is set when more streams remain.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientPosition {
+    pub withdrawable_now: i128,
+    pub pending_unvested: i128,
+    pub active_stream_count: u32,
+    pub next_cursor: Option<u32>,
+}
+
+/// Vesting curve applied between start and end time
+#[contracttype]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamCurve {
+    Linear,
+}
+
+/// Reusable stream parameters; only recipient, amount and start time vary
+/// between streams created from the same template
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamTemplate {
+    pub token: Address,
+    pub duration: u64,                // Seconds from start_time to end_time
+    pub cliff: u64,                   // Seconds from start_time until anything vests
+    pub curve: StreamCurve,
+    pub cancelable: bool,
+}
+
+/// Stored template together with its owner and edit version
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TemplateInfo {
+    pub id: u32,
+    pub owner: Address,
+    pub version: u32,
+    pub template: StreamTemplate,
+}
+
+/// Template version a stream was created from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TemplateRef {
+    pub template_id: u32,
+    pub version: u32,
+}
+
+/// Human-readable label and external reference attached to a stream
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamMetadata {
+    pub label: Symbol,
+    pub external_ref: BytesN<32>,
+    pub updated_at: u64,
 }
 
 /// Fee collected event data
@@ -60,12 +324,54 @@ pub struct FeeCollectedEvent {
     pub amount: i128,
 }
 
+/// Accrued fees claimed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeesClaimedEvent {
+    pub token: Address,
+    pub to: Address,
+    pub amount: i128,
+}
+
 /// Stream deposit event data
 #[contracttype]
 #[derive(Clone)]
 pub struct StreamDepositEvent {
     pub stream_id: u64,
     pub amount: i128,
+    pub funded_bps: u32,
+}
+
+/// A deposit brought the stream's funding up to its total. Emitted once;
+/// streams created fully funded never emit it.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamFullyFundedEvent {
+    pub stream_id: u64,
+    pub total_amount: i128,
+}
+
+/// Vesting checkpoint written by `renew_stream`: `vested` had been
+/// released at `time`, by which the stream had spent `paused_duration`
+/// seconds paused. The rest of the total vests linearly from there to
+/// `end_time`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VestingBaseline {
+    pub time: u64,
+    pub vested: i128,
+    pub paused_duration: u64,
+}
+
+/// Stream renewed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamRenewedEvent {
+    pub stream_id: u64,
+    pub additional_amount: i128,
+    pub old_end_time: u64,
+    pub new_end_time: u64,
+    pub vested_at_renewal: i128,
 }
 
 /// Delegation granted event data
@@ -79,10 +385,11 @@ pub struct DelegationGrantedEvent {
 
 /// Delegation revoked event data
 #[contracttype]
-#[derive(Clone)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct DelegationRevokedEvent {
     pub stream_id: u64,
     pub recipient: Address,
+    pub revoked_by: Address,
 }
 
 // Stream paused event
@@ -102,663 +409,4762 @@ pub struct StreamResumedEvent {
     pub paused_duration: u64,
 }
 
-/// Custom errors for the contract
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
-#[repr(u32)]
-pub enum Error {
-    AlreadyInitialized = 1,
-    NotInitialized = 2,
-    Unauthorized = 3,
-    InvalidAmount = 4,
-    InvalidTimeRange = 5,
-    StreamNotFound = 6,
-    StreamNotActive = 7,
-    StreamNotPaused = 8,
-    StreamCannotBeCanceled = 9,
-    InsufficientWithdrawable = 10,
-    TransferFailed = 11,
-    FeeTooHigh = 12,
-    InvalidRecipient = 13,
-    DepositExceedsTotal = 14,
-    ArithmeticOverflow = 15,
-    InvalidDelegate = 16,
+/// A pause outlasted the stream's pause budget and someone other than the
+/// sender resumed it
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamForceResumedEvent {
+    pub stream_id: u64,
+    pub resumed_at: u64,
+    pub paused_duration: u64,
+    pub max_pause_seconds: u64,
 }
 
-// Constants
-const MAX_FEE: u32 = 500; // 5% in basis points
-const LEDGER_THRESHOLD: u32 = 518400; // ~30 days at 5s/ledger
-const LEDGER_BUMP: u32 = 535680; // ~31 days
+/// Flat stream creation fee changed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreationFeeChangedEvent {
+    pub old_fee: i128,
+    pub new_fee: i128,
+}
 
-#[contract]
-pub struct PaymentStreamContract;
+/// Protocol fee rate changed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProtocolFeeRateChangedEvent {
+    pub old_rate: u32,
+    pub new_rate: u32,
+}
 
-#[contractimpl]
-impl PaymentStreamContract {
-    /// Initialize the contract
-    pub fn initialize(env: Env, admin: Address, fee_collector: Address, general_fee_rate: u32) {
-        if env.storage().instance().has(&Symbol::new(&env, "admin")) {
-            panic_with_error!(&env, Error::AlreadyInitialized);
-        }
-        if general_fee_rate > MAX_FEE {
-            panic_with_error!(&env, Error::FeeTooHigh);
-        }
-        admin.require_auth();
-        
-        env.storage().instance().set(&Symbol::new(&env, "admin"), &admin);
-        env.storage().instance().set(&Symbol::new(&env, "stream_count"), &0u64);
-        env.storage().instance().set(&Symbol::new(&env, "fee_collector"), &fee_collector);
-        env.storage().instance().set(&Symbol::new(&env, "general_protocol_fee_rate"), &general_fee_rate);
-        
-        // Initialize protocol metrics
-        let initial_metrics = ProtocolMetrics {
-            total_active_streams: 0,
-            total_tokens_streamed: 0,
-            total_streams_created: 0,
-            total_delegations: 0,
-        };
-        env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &initial_metrics);
-        
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
-    }
+/// Stale funds reclaimed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StaleFundsReclaimedEvent {
+    pub stream_id: u64,
+    pub sender: Address,
+    pub amount: i128,
+}
 
-    /// Create a new payment stream
-    pub fn create_stream(
-        env: Env,
-        sender: Address,
-        recipient: Address,
-        token: Address,
-        total_amount: i128,
-        initial_amount: i128,
-        start_time: u64,
-        end_time: u64,
-    ) -> u64 {
-        sender.require_auth();
+/// Stream rejected by its recipient event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamRejectedEvent {
+    pub stream_id: u64,
+    pub recipient: Address,
+    pub refunded: i128,
+}
 
-        // Validate inputs
-        if total_amount <= 0 {
-            panic_with_error!(&env, Error::InvalidAmount);
-        }
-        if initial_amount < 0 || initial_amount > total_amount {
-            panic_with_error!(&env, Error::InvalidAmount);
-        }
-        if end_time <= start_time {
-            panic_with_error!(&env, Error::InvalidTimeRange);
-        }
+/// Milestone approved event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MilestoneApprovedEvent {
+    pub stream_id: u64,
+    pub index: u32,
+    pub amount: i128,
+}
 
-        // Get and increment stream count
-        let mut stream_count: u64 = env.storage().instance().get(&Symbol::new(&env, "stream_count")).unwrap_or(0);
-        let stream_id = stream_count + 1;
-        stream_count += 1;
-        env.storage().instance().set(&Symbol::new(&env, "stream_count"), &stream_count);
+/// Fee rate proposal event data, emitted on proposal and on cancel
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeChangeProposedEvent {
+    pub new_rate: u32,
+    pub activates_at: u64,
+}
 
-        let current_time = env.ledger().timestamp();
+/// Fee collector changed event data (`old_collector` is None on initialize,
+/// `new_collector` is None when the collector is cleared).
+/// `collector_is_admin` flags single-key deployments where the admin also
+/// receives fees; this is allowed but worth surfacing to monitoring.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeCollectorChangedEvent {
+    pub old_collector: Option<Address>,
+    pub new_collector: Option<Address>,
+    pub collector_is_admin: bool,
+}
 
-        // Create stream
-        let stream = Stream {
-            id: stream_id,
-            sender: sender.clone(),
-            recipient: recipient.clone(),
-            token: token.clone(),
-            total_amount,
-            balance: initial_amount,
-            withdrawn_amount: 0,
-            start_time,
-            end_time,
-            status: StreamStatus::Active,
-            paused_at: None,
-            total_paused_duration: 0,
-        };
+/// Stream metadata updated event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamMetadataUpdatedEvent {
+    pub stream_id: u64,
+    pub label: Symbol,
+    pub external_ref: BytesN<32>,
+}
 
-        // Initialize stream metrics
-        let stream_metrics = StreamMetrics {
-            last_activity: current_time,
-            total_withdrawn: 0,
-            withdrawal_count: 0,
-            pause_count: 0,
-            total_delegations: 0,
-            current_delegate: None,
-            last_delegation_time: 0,
-        };
+/// Withdrawal routed event data (`amount` is gross, before the fee)
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalRoutedEvent {
+    pub stream_id: u64,
+    pub destination: Address,
+    pub amount: i128,
+    pub net_amount: i128,
+}
 
-        // Store stream and metrics
-        env.storage().persistent().set(&stream_id, &stream);
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &stream_metrics);
-        env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+/// Emitted on every withdrawal, including zero-fee ones. `recipient` is
+/// the stream's recipient even when the payout was routed elsewhere.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalEvent {
+    pub stream_id: u64,
+    pub caller_is_delegate: bool,
+    pub gross_amount: i128,
+    pub fee: i128,
+    pub tip: i128,                    // Paid to the delegate that triggered the withdrawal
+    pub net_amount: i128,             // What the recipient (or routed destination) receives
+    pub recipient: Address,
+    pub sponsor: Option<Address>,     // Set when a fee sponsor paid the fee on top of the net amount
+}
 
-        // Update protocol metrics
-        let mut protocol_metrics: ProtocolMetrics = env.storage().instance()
-            .get(&Symbol::new(&env, "protocol_metrics"))
-            .unwrap_or(ProtocolMetrics {
-                total_active_streams: 0,
-                total_tokens_streamed: 0,
-                total_streams_created: 0,
-                total_delegations: 0,
-            });
+/// One entry of the contract's append-only log of privileged calls.
+/// `detail` carries the call's main numeric argument (new fee rate, swept
+/// amount, new version, ...) or 0 when there isn't one.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminAction {
+    pub action: Symbol,
+    pub actor: Address,
+    pub timestamp: u64,
+    pub detail: i128,
+}
 
-        protocol_metrics.total_active_streams += 1;
-        protocol_metrics.total_tokens_streamed += total_amount;
-        protocol_metrics.total_streams_created += 1;
+/// One entry of a stream's append-only withdrawal log. `seq` counts from 0
+/// in the order withdrawals happened.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalRecord {
+    pub seq: u32,
+    pub amount: i128,                 // Gross amount, fee included
+    pub fee: i128,
+    pub timestamp: u64,
+    pub caller: Address,              // Recipient or delegate that authorized it
+}
 
-        env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &protocol_metrics);
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+/// One entry of a stream's append-only activity log. `kind` is one of
+/// `created`, `deposit`, `withdraw`, `settle`, `pause`, `resume`,
+/// `cancel`, `reclaim`, `split`, `recipient`, `milestone`, `delegate` or
+/// `undelegate`; `amount` is 0 where none applies.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActivityEntry {
+    pub seq: u32,
+    pub kind: Symbol,
+    pub amount: i128,
+    pub actor: Address,
+    pub timestamp: u64,
+}
 
-        // Transfer tokens from sender to contract (escrow)
-        if initial_amount > 0 {
-            let token_client = token::Client::new(&env, &token);
-            token_client.transfer(&sender, &env.current_contract_address(), &initial_amount);
-        }
+/// A recipient's `on_stream_payment` callback failed; the payout itself
+/// went through
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CallbackFailedEvent {
+    pub stream_id: u64,
+    pub recipient: Address,
+    pub amount: i128,
+}
 
-        stream_id
-    }
+/// Which party authorized a withdrawal
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WithdrawCaller {
+    Recipient,
+    Delegate,
+    Sender, // Settling the vested balance while canceling
+}
 
-    /// Deposit tokens to an existing stream
-    pub fn deposit(env: Env, stream_id: u64, amount: i128) {
-        let mut stream: Stream = Self::get_stream(env.clone(), stream_id);
+/// Recipient changed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecipientChangedEvent {
+    pub stream_id: u64,
+    pub old_recipient: Address,
+    pub new_recipient: Address,
+    pub approved_by_admin: bool,
+}
 
-        if matches!(stream.status, StreamStatus::Canceled | StreamStatus::Completed) {
-            panic_with_error!(&env, Error::StreamNotActive);
-        }
+/// Excess swept event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExcessSweptEvent {
+    pub token: Address,
+    pub to: Address,
+    pub amount: i128,
+}
 
-        stream.sender.require_auth();
+/// Contract upgraded event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractUpgradedEvent {
+    pub new_wasm_hash: BytesN<32>,
+    pub version: u32,
+}
 
-        if amount <= 0 {
-            panic_with_error!(&env, Error::InvalidAmount);
+/// Underfunded stream event data. `balance` is what is left in escrow.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LowBalanceWarningEvent {
+    pub stream_id: u64,
+    pub balance: i128,
+    pub projected_runout_time: u64,
+}
+
+/// Where a stream's withdrawals are swapped into another token. The
+/// adapter must deliver at least `min_out_bps` of the input amount.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SettlementAdapter {
+    pub adapter: Address,
+    pub min_out_bps: u32,
+}
+
+/// Withdrawal swapped through the settlement adapter event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SettlementSwappedEvent {
+    pub stream_id: u64,
+    pub adapter: Address,
+    pub amount_in: i128,
+    pub amount_out: i128,
+}
+
+/// Settlement adapter call failed, so the withdrawal was paid out
+/// directly event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SettlementFallbackEvent {
+    pub stream_id: u64,
+    pub adapter: Address,
+    pub amount: i128,
+}
+
+/// Auto-forward fell back to a transfer event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ForwardSkippedEvent {
+    pub stream_id: u64,
+    pub target_stream_id: u64,
+    pub amount: i128,
+}
+
+/// Stream split event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamSplitEvent {
+    pub parent: u64,
+    pub child: u64,
+    pub split_bps: u32,
+}
+
+/// How the admin closes out a quarantine
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum QuarantineResolution {
+    /// Refund everything left in escrow to the funders and cancel
+    ReturnToSender = 0,
+    /// Pay everything left in escrow to the recipient, less the
+    /// withdrawal fee, and complete the stream
+    ReleaseToRecipient = 1,
+    /// Put the stream back as it was; time spent in quarantine counts as
+    /// a pause
+    Resume = 2,
+}
+
+/// Why and from which status a stream was quarantined
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuarantineInfo {
+    pub reason: Symbol,
+    pub previous_status: StreamStatus,
+    pub quarantined_at: u64,
+}
+
+/// Stream quarantined event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamQuarantinedEvent {
+    pub stream_id: u64,
+    pub reason: Symbol,
+    pub previous_status: StreamStatus,
+}
+
+/// Quarantine released event data. `amount` is what left escrow: the
+/// refund, the gross payout, or 0 on resume.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuarantineReleasedEvent {
+    pub stream_id: u64,
+    pub reason: Symbol,
+    pub resolution: QuarantineResolution,
+    pub amount: i128,
+}
+
+/// Fee registry set or removed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeRegistryChangedEvent {
+    pub old_registry: Option<Address>,
+    pub new_registry: Option<Address>,
+}
+
+/// Fee holiday set or cleared event data; `until` is the holiday's expiry
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeHolidayEvent {
+    pub until: u64,
+}
+
+/// Maximum stream duration changed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MaxDurationChangedEvent {
+    pub old_max_duration: u64,
+    pub new_max_duration: u64,
+}
+
+/// Stream limits changed event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamLimitsChangedEvent {
+    pub old_limits: StreamLimits,
+    pub new_limits: StreamLimits,
+}
+
+/// How far, in ledgers, storage TTLs are extended: entries whose TTL has
+/// dropped below `threshold` are extended to `bump`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TtlPolicy {
+    pub threshold: u32,
+    pub bump: u32,
+}
+
+/// Every instance-level setting in one read, for clients rendering a
+/// create-stream form. Fields are only ever appended; `config_version`
+/// counts the fields a client can expect.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractConfig {
+    pub config_version: u32,
+    pub admin: Option<Address>,
+    pub fee_collector: Option<Address>,
+    pub protocol_fee_rate: u32,
+    pub paused: bool,
+    pub stream_limits: StreamLimits,
+    pub version: u32,
+    pub recipient_rescue_delay: u64,
+    pub ttl_policy: TtlPolicy,
+    pub max_duration: u64,
+    pub fee_registry: Option<Address>,
+    pub fee_holiday: Option<u64>,
+    pub creation_fee: i128,
+}
+
+/// Keeper TTL bump event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamTtlBumpedEvent {
+    pub stream_ids: Vec<u64>,         // Ids that were found and extended
+    pub live_until_ledger: u32,       // Ledger their entries now live until, at least
+}
+
+/// Role granted/revoked event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RoleChangedEvent {
+    pub role: Symbol,
+    pub account: Address,
+}
+
+/// Contract-wide pause event data
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractPausedEvent {
+    pub paused: bool,
+    pub by: Address,
+}
+
+/// Keys of the per-stream activity log, kept apart from `DataKey` because
+/// a contract enum can't have more than 50 variants
+#[contracttype]
+#[derive(Clone)]
+pub enum ActivityKey {
+    Len(u64),
+    Entry(u64, u32),
+}
+
+/// Per-stream keys added after `DataKey` reached the 50-variant limit
+#[contracttype]
+#[derive(Clone)]
+pub enum StreamKey {
+    Beneficiaries(u64),
+    MinWithdrawInterval(u64),
+    VestingBaseline(u64),
+    AllowFeeOnTransfer(u64),
+    TransferLoss(u64),
+    TimeBasis(u64),
+}
+
+/// Instance config keys added after `DataKey` reached the variant limit
+#[contracttype]
+#[derive(Clone)]
+pub enum ConfigKey {
+    FeeRegistry,
+    FeeHoliday,
+    CreationFee,
+    MetricsRebuild,
+}
+
+/// Per-token persistent keys added after `DataKey` reached the variant limit
+#[contracttype]
+#[derive(Clone)]
+pub enum TokenKey {
+    CreationFeesCollected(Address),
+}
+
+/// Per-sender persistent keys added after `DataKey` reached the variant limit
+#[contracttype]
+#[derive(Clone)]
+pub enum SenderKey {
+    IdempotencyKey(Address, BytesN<32>),
+}
+
+/// Storage keys. Instance: config and counters; persistent: per-stream and
+/// per-account records; temporary: `Lock`.
+#[contracttype]
+#[derive(Clone)]
+pub enum DataKey {
+    Admin,
+    StreamCount,
+    FeeCollector,
+    FeeRate,
+    Version,
+    ProtocolMetrics,
+    ProtocolMetricsV2,
+    TemplateCount,
+    RescueDelay,
+    PendingFeeRate,
+    Paused,
+    StreamLimits,
+    MaxDuration,
+    AdminActionCount,
+    TtlPolicy,
+    Stream(u64),
+    Metrics(u64),
+    Delegate(u64),
+    DelegateCanRedirect(u64),
+    CallerTipBps(u64),
+    AutoForward(u64),
+    SettlementAdapter(u64),
+    PendingRecipient(u64),
+    Metadata(u64),
+    StreamTemplate(u64),
+    Contributors(u64),
+    FundingSource(u64),
+    Withdrawal(u64, u32),
+    AdminAction(u64),
+    YieldAdapter(Address),
+    YieldDeposited(Address),
+    FeeSponsor(u64),
+    FeeCarry(u64),
+    Milestones(u64),
+    Quarantine(u64),
+    Lock(u64),
+    Template(u32),
+    OwnerTemplates(Address),
+    FeeTracking(Address),
+    FeesByDay(Address, u64),
+    TokenMetrics(Address),
+    SenderTvl(Address),
+    AccruedFees(Address),
+    FeesCollected(Address),
+    StatusLen(StreamStatus),
+    StatusIndex(StreamStatus, u32),
+    StatusPos(u64),
+    RecipientStreamCount(Address),
+    RecipientStream(Address, u32),
+    Role(Symbol, Address),
+}
+
+/// Custom errors for the contract
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    Unauthorized = 3,
+    InvalidAmount = 4,
+    InvalidTimeRange = 5,
+    StreamNotFound = 6,
+    StreamNotActive = 7,
+    StreamNotPaused = 8,
+    StreamCannotBeCanceled = 9,
+    InsufficientWithdrawable = 10,
+    TransferFailed = 11,
+    FeeTooHigh = 12,
+    InvalidRecipient = 13,
+    DepositExceedsTotal = 14,
+    ArithmeticOverflow = 15,
+    InvalidDelegate = 16,
+    BatchTooLarge = 17,
+    FeeQueryRangeTooLarge = 18,
+    TemplateNotFound = 19,
+    /// Cancel or pause on a stream created with `cancelable = false`. Pausing
+    /// is refused too, since an indefinite pause is equivalent to canceling.
+    StreamNotCancelable = 20,
+    NoPendingRecipientChange = 21,
+    RescueWindowNotElapsed = 22,
+    RedirectNotAllowed = 23,
+    ReentrantCall = 24,
+    /// Admin and fee collector are the same address, or a nonzero fee rate
+    /// is configured without a fee collector
+    InvalidConfiguration = 25,
+    TokenTransferRejected = 26,
+    FeeIncreaseRequiresTimelock = 27,
+    NoPendingFeeChange = 28,
+    FeeTimelockNotElapsed = 29,
+    MilestoneNotFound = 30,
+    MilestoneAlreadyApproved = 31,
+    ClaimWindowExpired = 32,
+    ClaimWindowOpen = 33,
+    TipTooHigh = 34,
+    ContractPaused = 35,
+    InvalidRole = 36,
+    InvalidSplit = 37,
+    AmountBelowMinimum = 38,
+    DurationBelowMinimum = 39,
+    PauseBudgetExceeded = 40,
+    PauseWithinBudget = 41,
+    YieldAdapterError = 42,
+    InvalidTtlPolicy = 43,
+    SlippageExceeded = 44,
+    StartTimeInPast = 45,
+    DurationTooLong = 46,
+    StreamQuarantined = 47,
+    StreamNotQuarantined = 48,
+    /// Shared stream beneficiaries empty, over 10, duplicated, or with
+    /// shares that are zero or don't add up to 10000 bps
+    InvalidShares = 49,
+    /// Shared streams pay out through `withdraw_share`; recipient-level
+    /// calls (withdraw, delegate, split, recipient change) are refused
+    SharedStream = 50,
+}
+
+// Codes stay at or below `common::CONTRACT_ERROR_MAX`; codes from
+// `common::SHARED_ERROR_BASE` up are reserved for shared errors
+/// Errors added after `Error` reached the 50-variant limit; codes continue
+/// from 51
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum ErrorExt {
+    /// The stream's minimum interval since the last withdrawal hasn't
+    /// passed; `get_next_withdrawal_time` says when it will
+    WithdrawTooSoon = 51,
+    /// `rebuild_protocol_metrics` was called with a start id other than 1
+    /// or the next id of the rebuild in progress
+    RebuildOutOfOrder = 52,
+    /// A transfer moved a different amount in or out of the contract than
+    /// requested, e.g. a token that takes a transfer fee, on a stream that
+    /// doesn't allow it
+    NonStandardTokenBehavior = 53,
+    /// `reject_stream` on a stream the recipient has already withdrawn from
+    AlreadyAccepted = 54,
+}
+
+// Constants
+const MAX_CALLER_TIP_BPS: u32 = 100; // 1% of the net amount
+const ROLE_PAUSER: &str = "pauser";
+const ROLE_FEE_MANAGER: &str = "fee_manager";
+const ROLE_UPGRADER: &str = "upgrader";
+const LEDGER_THRESHOLD: u32 = common::LEDGER_THRESHOLD;
+const LEDGER_BUMP: u32 = common::LEDGER_BUMP;
+const MIN_LEDGER_THRESHOLD: u32 = common::MIN_LEDGER_THRESHOLD;
+const MAX_TTL_BATCH: u32 = 20;
+const MAX_SUMMARY_BATCH: u32 = 25;
+const MAX_WITHDRAW_BATCH: u32 = 20;
+const MAX_STATUS_PAGE: u32 = 100;
+const MAX_PROJECTION_POINTS: u32 = 50;
+const MAX_POSITION_PAGE: u32 = 50;
+const MAX_MILESTONES: u32 = 20;
+const MAX_BENEFICIARIES: u32 = 10;
+const MAX_WITHDRAWAL_PAGE: u32 = 50;
+const MAX_ACTIVITY_PAGE: u32 = 50;
+const MAX_STREAM_PAGE: u32 = 50;
+const MAX_ADMIN_ACTION_PAGE: u32 = 50;
+const CONTRACT_VERSION: u32 = 1;
+const CONFIG_VERSION: u32 = 5; // Bump when a field is appended to ContractConfig
+const STATE_HASH_VERSION: u8 = 1; // Bump whenever the `get_stream_state_hash` encoding changes
+const SECONDS_PER_DAY: u64 = 86400;
+const MAX_FEE_QUERY_DAYS: u64 = 366;
+const DEFAULT_RESCUE_DELAY: u64 = 30 * SECONDS_PER_DAY;
+const FEE_TIMELOCK: u64 = 3 * SECONDS_PER_DAY;
+const START_TIME_GRACE: u64 = 3600;
+const SECONDS_PER_LEDGER: u64 = 5; // Target close time, to hold ledger-based streams to the duration limits
+const DEFAULT_MAX_DURATION: u64 = 10 * 365 * SECONDS_PER_DAY;
+
+#[contract]
+pub struct PaymentStreamContract;
+
+#[contractimpl]
+impl PaymentStreamContract {
+    /// Set up admin and fees atomically with deployment, so nobody can
+    /// claim a freshly deployed contract by calling `initialize` first
+    pub fn __constructor(env: Env, admin: Address, fee_collector: Option<Address>, general_fee_rate: u32) {
+        Self::configure(&env, admin, fee_collector, general_fee_rate);
+    }
+
+    /// Kept for existing tooling. Contracts deployed with the constructor
+    /// are already set up, so this fails with `AlreadyInitialized`.
+    pub fn initialize(env: Env, admin: Address, fee_collector: Option<Address>, general_fee_rate: u32) {
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic_with_error!(&env, Error::AlreadyInitialized);
+        }
+        admin.require_auth();
+        Self::configure(&env, admin, fee_collector, general_fee_rate);
+    }
+
+    /// Shared setup for the constructor and `initialize`
+    fn configure(env: &Env, admin: Address, fee_collector: Option<Address>, general_fee_rate: u32) {
+        Self::check_fee_config(env, FeeConfig { rate_bps: general_fee_rate, collector: fee_collector.clone() });
+        if fee_collector.as_ref() == Some(&admin) {
+            panic_with_error!(env, Error::InvalidConfiguration);
+        }
+        
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::StreamCount, &0u64);
+        if let Some(fee_collector) = &fee_collector {
+            env.storage().instance().set(&DataKey::FeeCollector, fee_collector);
+        }
+        env.storage().instance().set(&DataKey::FeeRate, &general_fee_rate);
+        env.storage().instance().set(&DataKey::Version, &CONTRACT_VERSION);
+        
+        // Initialize protocol metrics
+        Self::store_protocol_metrics(env, &Self::load_protocol_metrics(env));
+        
+        Self::extend_instance(env);
+
+        env.events().publish(
+            ("ProtocolFeeRateChanged",),
+            ProtocolFeeRateChangedEvent { old_rate: 0, new_rate: general_fee_rate },
+        );
+        if fee_collector.is_some() {
+            env.events().publish(
+                ("FeeCollectorChanged",),
+                FeeCollectorChangedEvent { old_collector: None, new_collector: fee_collector, collector_is_admin: false },
+            );
+        }
+    }
+
+    /// Create a new payment stream
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_stream(
+        env: Env,
+        sender: Address,
+        recipient: Address,
+        token: Address,
+        total_amount: i128,
+        initial_amount: i128,
+        start_time: u64,
+        end_time: u64,
+    ) -> u64 {
+        sender.require_auth();
+
+        Self::open_stream(
+            &env, sender.clone(), sender, recipient, token, total_amount, initial_amount, start_time, end_time, start_time,
+            StreamOptions::default(),
+        )
+    }
+
+    /// Same as `create_stream`, but returns the stored stream and the fee
+    /// rate its withdrawals will be charged, saving a follow-up `get_stream`
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_stream_detailed(
+        env: Env,
+        sender: Address,
+        recipient: Address,
+        token: Address,
+        total_amount: i128,
+        initial_amount: i128,
+        start_time: u64,
+        end_time: u64,
+    ) -> StreamCreated {
+        sender.require_auth();
+
+        let id = Self::open_stream(
+            &env, sender.clone(), sender, recipient, token, total_amount, initial_amount, start_time, end_time, start_time,
+            StreamOptions::default(),
+        );
+        let stream = Self::read_stream(&env, id);
+        let effective_fee_rate = Self::effective_fee_rate(&env, &stream);
+        StreamCreated { id, stream, effective_fee_rate }
+    }
+
+    /// Where a stream's initial funding came from: the sender, unless it
+    /// was created with `create_stream_for`
+    pub fn get_funding_source(env: Env, stream_id: u64) -> Address {
+        let stream = Self::read_stream(&env, stream_id);
+        env.storage().persistent().get(&DataKey::FundingSource(stream_id)).unwrap_or(stream.sender)
+    }
+
+    /// Stream created by `sender` through `create_stream_with_key` with this
+    /// key, if any
+    pub fn get_stream_by_key(env: Env, sender: Address, idempotency_key: BytesN<32>) -> Option<u64> {
+        env.storage().persistent().get(&SenderKey::IdempotencyKey(sender, idempotency_key))
+    }
+
+    /// Create one escrowed stream that vests to several beneficiaries by
+    /// fixed basis-point shares (at most 10, adding up to 10000). Each
+    /// beneficiary claims its vested share with `withdraw_share`. The
+    /// first beneficiary is stored as the stream's recipient.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_shared_stream(
+        env: Env,
+        sender: Address,
+        token: Address,
+        total_amount: i128,
+        initial_amount: i128,
+        start_time: u64,
+        end_time: u64,
+        beneficiaries: Vec<(Address, u32)>,
+    ) -> u64 {
+        sender.require_auth();
+
+        if beneficiaries.is_empty() || beneficiaries.len() > MAX_BENEFICIARIES {
+            panic_with_error!(&env, Error::InvalidShares);
+        }
+        let mut entries = Vec::new(&env);
+        let mut total_bps: u32 = 0;
+        for (address, share_bps) in beneficiaries.iter() {
+            if share_bps == 0 || entries.iter().any(|b: Beneficiary| b.address == address) {
+                panic_with_error!(&env, Error::InvalidShares);
+            }
+            Self::assert_valid_recipient(&env, &sender, &token, &address);
+            total_bps = total_bps.saturating_add(share_bps);
+            entries.push_back(Beneficiary { address, share_bps, withdrawn: 0 });
+        }
+        if total_bps != 10_000 {
+            panic_with_error!(&env, Error::InvalidShares);
+        }
+
+        let recipient = entries.get(0).unwrap().address;
+        let stream_id = Self::open_stream(
+            &env, sender.clone(), sender, recipient, token, total_amount, initial_amount, start_time, end_time, start_time,
+            StreamOptions::default(),
+        );
+        let key = StreamKey::Beneficiaries(stream_id);
+        env.storage().persistent().set(&key, &entries);
+        Self::extend_persistent(&env, &key);
+        stream_id
+    }
+
+    /// Beneficiaries of a shared stream with what each has withdrawn,
+    /// `None` for ordinary streams
+    pub fn get_beneficiaries(env: Env, stream_id: u64) -> Option<Vec<Beneficiary>> {
+        env.storage().persistent().get(&StreamKey::Beneficiaries(stream_id))
+    }
+
+    /// Create a template of shared stream parameters, returning its id
+    pub fn create_template(env: Env, owner: Address, template: StreamTemplate) -> u32 {
+        owner.require_auth();
+        Self::validate_template(&env, &template);
+
+        let template_id: u32 = env.storage().instance().get_or(&DataKey::TemplateCount, 0) + 1;
+        env.storage().instance().set(&DataKey::TemplateCount, &template_id);
+        Self::extend_instance(&env);
+
+        let info = TemplateInfo { id: template_id, owner: owner.clone(), version: 1, template };
+        let key = DataKey::Template(template_id);
+        env.storage().persistent().set(&key, &info);
+        Self::extend_persistent(&env, &key);
+
+        let owner_key = DataKey::OwnerTemplates(owner);
+        let mut ids: Vec<u32> = env.storage().persistent().get(&owner_key).unwrap_or(Vec::new(&env));
+        ids.push_back(template_id);
+        env.storage().persistent().set(&owner_key, &ids);
+        Self::extend_persistent(&env, &owner_key);
+
+        template_id
+    }
+
+    /// Replace a template's parameters (owner only). Bumps the version;
+    /// streams already created keep the parameters they were created with.
+    pub fn update_template(env: Env, template_id: u32, template: StreamTemplate) {
+        let mut info = Self::get_template(env.clone(), template_id);
+        info.owner.require_auth();
+        Self::validate_template(&env, &template);
+
+        info.template = template;
+        info.version += 1;
+        let key = DataKey::Template(template_id);
+        env.storage().persistent().set(&key, &info);
+        Self::extend_persistent(&env, &key);
+    }
+
+    /// Get a template by id
+    pub fn get_template(env: Env, template_id: u32) -> TemplateInfo {
+        let key = DataKey::Template(template_id);
+        match env.storage().persistent().get(&key) {
+            Some(info) => {
+                Self::extend_persistent(&env, &key);
+                info
+            },
+            None => panic_with_error!(&env, Error::TemplateNotFound),
+        }
+    }
+
+    /// Ids of all templates created by `owner`
+    pub fn list_templates(env: Env, owner: Address) -> Vec<u32> {
+        env.storage().persistent()
+            .get(&DataKey::OwnerTemplates(owner))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Create a fully funded stream from a template. End time and cliff are
+    /// derived from `start_time` using the template's current version.
+    pub fn create_stream_from_template(
+        env: Env,
+        sender: Address,
+        template_id: u32,
+        recipient: Address,
+        total_amount: i128,
+        start_time: u64,
+    ) -> u64 {
+        sender.require_auth();
+
+        let info = Self::get_template(env.clone(), template_id);
+        let template = info.template;
+        let end_time = start_time.checked_add(template.duration)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::ArithmeticOverflow));
+
+        let stream_id = Self::open_stream(
+            &env,
+            sender.clone(),
+            sender,
+            recipient,
+            template.token,
+            total_amount,
+            total_amount,
+            start_time,
+            end_time,
+            start_time + template.cliff,
+            StreamOptions { cancelable: template.cancelable, ..StreamOptions::default() },
+        );
+
+        let template_ref = TemplateRef { template_id, version: info.version };
+        let key = DataKey::StreamTemplate(stream_id);
+        env.storage().persistent().set(&key, &template_ref);
+        Self::extend_persistent(&env, &key);
+
+        stream_id
+    }
+
+    /// Attach or overwrite a stream's label and external reference (sender
+    /// only). Completed streams can no longer be relabelled.
+    pub fn set_stream_metadata(env: Env, stream_id: u64, label: Symbol, external_ref: BytesN<32>) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+
+        if stream.status == StreamStatus::Completed {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+
+        let metadata = StreamMetadata {
+            label: label.clone(),
+            external_ref: external_ref.clone(),
+            updated_at: env.ledger().timestamp(),
+        };
+        let key = DataKey::Metadata(stream_id);
+        env.storage().persistent().set(&key, &metadata);
+        Self::extend_persistent(&env, &key);
+
+        env.events().publish(
+            ("StreamMetadataUpdated", stream_id),
+            StreamMetadataUpdatedEvent { stream_id, label, external_ref },
+        );
+    }
+
+    /// Get a stream's metadata, if any was set
+    pub fn get_stream_metadata(env: Env, stream_id: u64) -> Option<StreamMetadata> {
+        let key = DataKey::Metadata(stream_id);
+        let metadata = env.storage().persistent().get(&key);
+        if metadata.is_some() {
+            Self::extend_persistent(&env, &key);
+        }
+        metadata
+    }
+
+    /// Template version a stream was created from, if any
+    pub fn get_stream_template(env: Env, stream_id: u64) -> Option<TemplateRef> {
+        Self::read_stream(&env, stream_id);
+        env.storage().persistent().get(&DataKey::StreamTemplate(stream_id))
+    }
+
+    fn validate_template(env: &Env, template: &StreamTemplate) {
+        if template.duration == 0 || template.cliff > template.duration {
+            panic_with_error!(env, Error::InvalidTimeRange);
+        }
+    }
+
+    /// A stream can't pay its own sender, this contract (which can never
+    /// withdraw from itself) or the token contract
+    fn assert_valid_recipient(env: &Env, sender: &Address, token: &Address, recipient: &Address) {
+        if recipient == sender || *recipient == env.current_contract_address() || recipient == token {
+            panic_with_error!(env, Error::InvalidRecipient);
+        }
+    }
+
+    /// Validate, store and escrow a new stream, pulling the initial amount
+    /// from `funding_source`. Callers handle auth.
+    #[allow(clippy::too_many_arguments)]
+    fn open_stream(
+        env: &Env,
+        sender: Address,
+        funding_source: Address,
+        recipient: Address,
+        token: Address,
+        total_amount: i128,
+        initial_amount: i128,
+        start_time: u64,
+        end_time: u64,
+        cliff_time: u64,
+        options: StreamOptions,
+    ) -> u64 {
+        Self::assert_not_paused(env);
+
+        // Validate inputs
+        if total_amount <= 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        if initial_amount < 0 || initial_amount > total_amount {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        if end_time <= start_time {
+            panic_with_error!(env, Error::InvalidTimeRange);
+        }
+        Self::assert_valid_recipient(env, &sender, &token, &recipient);
+        let limits = Self::get_stream_limits(env.clone());
+        if total_amount < limits.min_stream_amount {
+            panic_with_error!(env, Error::AmountBelowMinimum);
+        }
+        let duration = Self::span_in_seconds(options.time_basis, end_time - start_time);
+        if duration < limits.min_duration {
+            panic_with_error!(env, Error::DurationBelowMinimum);
+        }
+        if duration > Self::get_max_duration(env.clone()) {
+            panic_with_error!(env, Error::DurationTooLong);
+        }
+        let grace = match options.time_basis {
+            TimeBasis::Timestamp => START_TIME_GRACE,
+            TimeBasis::LedgerSequence => START_TIME_GRACE / SECONDS_PER_LEDGER,
+        };
+        if !options.backdated && start_time < Self::clock(env, options.time_basis).saturating_sub(grace) {
+            panic_with_error!(env, Error::StartTimeInPast);
+        }
+        if let Some(milestones) = &options.milestones {
+            Self::validate_milestones(env, milestones, total_amount);
+        }
+        if options.claim_deadline.is_some_and(|deadline| deadline < end_time) {
+            panic_with_error!(env, Error::InvalidTimeRange);
+        }
+
+        let stream_id = Self::next_stream_id(env);
+
+        let current_time = env.ledger().timestamp();
+
+        // Create stream
+        let stream = Stream {
+            id: stream_id,
+            sender: sender.clone(),
+            recipient: recipient.clone(),
+            token: token.clone(),
+            total_amount,
+            balance: initial_amount,
+            withdrawn_amount: 0,
+            start_time,
+            end_time,
+            status: StreamStatus::Active,
+            paused_at: None,
+            total_paused_duration: 0,
+            cliff_time,
+            cancelable: options.cancelable,
+            notify_recipient: options.notify_recipient,
+            claim_deadline: options.claim_deadline,
+            max_pause_seconds: options.max_pause_seconds,
+        };
+
+        Self::transition_status(env, stream_id, &token, None, StreamStatus::Active);
+        Self::index_recipient_stream(env, &recipient, stream_id);
+
+        // Initialize stream metrics
+        let stream_metrics = StreamMetrics {
+            last_activity: current_time,
+            total_withdrawn: 0,
+            withdrawal_count: 0,
+            pause_count: 0,
+            total_delegations: 0,
+            current_delegate: None,
+            last_delegation_time: 0,
+            forced_revocations: 0,
+            sponsored_fees: 0,
+            last_withdrawal_time: 0,
+        };
+
+        // Store stream and metrics
+        Self::store_stream(env, &stream, "created", total_amount, &sender);
+        Self::store_stream_metrics(env, stream_id, &stream_metrics);
+        if let Some(milestones) = &options.milestones {
+            env.storage().persistent().set(&DataKey::Milestones(stream_id), milestones);
+            Self::extend_persistent(env, &DataKey::Milestones(stream_id));
+        }
+        if options.allow_fee_on_transfer {
+            env.storage().persistent().set(&StreamKey::AllowFeeOnTransfer(stream_id), &true);
+            Self::extend_persistent(env, &StreamKey::AllowFeeOnTransfer(stream_id));
+        }
+        if options.time_basis != TimeBasis::Timestamp {
+            env.storage().persistent().set(&StreamKey::TimeBasis(stream_id), &options.time_basis);
+            Self::extend_persistent(env, &StreamKey::TimeBasis(stream_id));
+        }
+        if funding_source != sender {
+            let storage = env.storage().persistent();
+            storage.set(&DataKey::FundingSource(stream_id), &funding_source);
+            Self::extend_persistent(env, &DataKey::FundingSource(stream_id));
+            // Refunds follow the contribution ledger, so seed it with the
+            // funding source instead of the sender
+            if initial_amount > 0 {
+                let ledger = soroban_sdk::vec![env, (funding_source.clone(), initial_amount)];
+                storage.set(&DataKey::Contributors(stream_id), &ledger);
+                Self::extend_persistent(env, &DataKey::Contributors(stream_id));
+            }
+        }
+
+        // Update protocol metrics
+        let mut protocol_metrics = Self::load_protocol_metrics(env);
+
+        protocol_metrics.total_streams_created += 1;
+        protocol_metrics.total_tokens_streamed += initial_amount;
+        protocol_metrics.total_committed += total_amount;
+
+        Self::store_protocol_metrics(env, &protocol_metrics);
+
+        Self::update_token_metrics(env, &token, |m| {
+            m.total_streamed += total_amount;
+            m.escrowed_balance += initial_amount;
+        });
+        Self::adjust_sender_tvl(env, &sender, initial_amount);
+
+        // Transfer tokens from sender to contract (escrow), with the flat
+        // creation fee on top
+        let creation_fee = Self::creation_fee_for(env, &sender);
+        let pulled = initial_amount.checked_add(creation_fee)
+            .unwrap_or_else(|| panic_with_error!(env, Error::ArithmeticOverflow));
+        let mut parked = initial_amount;
+        if pulled > 0 {
+            let token_client = token::Client::new(env, &token);
+            let held = Self::held(env, &token_client);
+            token_client.transfer(&funding_source, &env.current_contract_address(), &pulled);
+            parked -= Self::check_transfer(env, &token_client, Some(stream_id), held, pulled);
+        }
+        if parked > 0 {
+            Self::park_in_yield(env, &token, parked);
+        }
+        if creation_fee > 0 {
+            Self::collect_creation_fee(env, stream_id, &token, &funding_source, creation_fee);
+        }
+
+        stream_id
+    }
+
+    /// Take the next stream id and bump the counter
+    fn next_stream_id(env: &Env) -> u64 {
+        let stream_count: u64 = env.storage().instance().get_or(&DataKey::StreamCount, 0);
+        env.storage().instance().set(&DataKey::StreamCount, &(stream_count + 1));
+        stream_count + 1
+    }
+
+    fn validate_milestones(env: &Env, milestones: &Vec<Milestone>, total_amount: i128) {
+        if milestones.is_empty() {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        if milestones.len() > MAX_MILESTONES {
+            panic_with_error!(env, Error::BatchTooLarge);
+        }
+        let mut sum: i128 = 0;
+        for milestone in milestones.iter() {
+            if milestone.amount <= 0 {
+                panic_with_error!(env, Error::InvalidAmount);
+            }
+            sum = sum.checked_add(milestone.amount)
+                .unwrap_or_else(|| panic_with_error!(env, Error::ArithmeticOverflow));
+        }
+        if sum != total_amount {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+    }
+
+    /// Release a milestone of a milestone stream to the recipient (sender only)
+    pub fn approve_milestone(env: Env, stream_id: u64, index: u32) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+
+        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+
+        let key = DataKey::Milestones(stream_id);
+        let mut milestones: Vec<Milestone> = env.storage().persistent()
+            .get(&key)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::MilestoneNotFound));
+        let mut milestone = milestones.get(index)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::MilestoneNotFound));
+        if milestone.approved {
+            panic_with_error!(&env, Error::MilestoneAlreadyApproved);
+        }
+
+        milestone.approved = true;
+        milestones.set(index, milestone.clone());
+        env.storage().persistent().set(&key, &milestones);
+        Self::extend_persistent(&env, &key);
+        Self::record_activity(&env, stream_id, "milestone", milestone.amount, &stream.sender);
+
+        env.events().publish(
+            ("MilestoneApproved", stream_id),
+            MilestoneApprovedEvent { stream_id, index, amount: milestone.amount },
+        );
+    }
+
+    /// Milestones of a milestone stream, None for time-based streams
+    pub fn get_milestones(env: Env, stream_id: u64) -> Option<Vec<Milestone>> {
+        env.storage().persistent().get(&DataKey::Milestones(stream_id))
+    }
+
+    /// Deposit tokens to an existing stream
+    pub fn deposit(env: Env, stream_id: u64, amount: i128) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if matches!(stream.status, StreamStatus::Canceled | StreamStatus::Completed | StreamStatus::Rejected) {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+
+        let sender = stream.sender.clone();
+        Self::fund_stream(&env, stream, &sender, amount);
+    }
+
+    /// Top up someone else's stream. The sponsor is recorded in the
+    /// stream's contribution ledger and gets its share back on cancel.
+    pub fn deposit_from(env: Env, stream_id: u64, from: Address, amount: i128) {
+        from.require_auth();
+
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        Self::assert_not_quarantined(&env, &stream);
+        if matches!(stream.status, StreamStatus::Canceled | StreamStatus::Completed | StreamStatus::Rejected) {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+
+        Self::fund_stream(&env, stream, &from, amount);
+    }
+
+    /// Push an Active stream's end to `new_end_time` and fund
+    /// `additional_amount` more in one step, so a subscription never lapses
+    /// between the two (sender only). A stream past its end that hasn't
+    /// been fully withdrawn can be renewed too. Whatever had vested at the
+    /// renewal instant stays vested, and the rest of the new total vests
+    /// linearly up to `new_end_time`. A claim deadline moves out by the
+    /// same extension. Milestone streams have no schedule to extend.
+    pub fn renew_stream(env: Env, stream_id: u64, additional_amount: i128, new_end_time: u64) {
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if stream.status != StreamStatus::Active {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+        // Reopening an expired window would race the sender's own reclaim
+        if Self::claim_window_expired(&env, &stream) {
+            panic_with_error!(&env, Error::ClaimWindowExpired);
+        }
+
+        if additional_amount <= 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        let basis = Self::get_time_basis(env.clone(), stream_id);
+        let now = Self::clock(&env, basis);
+        if new_end_time <= stream.end_time.max(now) || env.storage().persistent().has(&DataKey::Milestones(stream_id)) {
+            panic_with_error!(&env, Error::InvalidTimeRange);
+        }
+        let duration = (new_end_time - stream.start_time).saturating_sub(stream.total_paused_duration);
+        if Self::span_in_seconds(basis, duration) > Self::get_max_duration(env.clone()) {
+            panic_with_error!(&env, Error::DurationTooLong);
+        }
+
+        // Checkpoint the schedule before changing it, so nothing vested
+        // is clawed back and nothing unvested jumps forward
+        let baseline_time = now.max(stream.start_time);
+        let vested_at_renewal = Self::vested_amount(&env, &stream, baseline_time);
+        let baseline_key = StreamKey::VestingBaseline(stream_id);
+        env.storage().persistent().set(
+            &baseline_key,
+            &VestingBaseline {
+                time: baseline_time,
+                vested: vested_at_renewal,
+                paused_duration: stream.total_paused_duration,
+            },
+        );
+        Self::extend_persistent(&env, &baseline_key);
+
+        let old_end_time = stream.end_time;
+        stream.total_amount = stream.total_amount.checked_add(additional_amount)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::ArithmeticOverflow));
+        stream.end_time = new_end_time;
+        stream.claim_deadline = stream.claim_deadline.map(|deadline| {
+            deadline.checked_add(new_end_time - old_end_time)
+                .unwrap_or_else(|| panic_with_error!(&env, Error::ArithmeticOverflow))
+        });
+
+        let mut protocol_metrics = Self::load_protocol_metrics(&env);
+        protocol_metrics.total_committed += additional_amount;
+        Self::store_protocol_metrics(&env, &protocol_metrics);
+
+        let sender = stream.sender.clone();
+        Self::fund_stream(&env, stream, &sender, additional_amount);
+
+        env.events().publish(
+            ("StreamRenewed", stream_id),
+            StreamRenewedEvent { stream_id, additional_amount, old_end_time, new_end_time, vested_at_renewal },
+        );
+    }
+
+    /// Checkpoint the stream's schedule was last renewed from, if ever
+    pub fn get_vesting_baseline(env: Env, stream_id: u64) -> Option<VestingBaseline> {
+        env.storage().persistent().get(&StreamKey::VestingBaseline(stream_id))
+    }
+
+    /// Who funded a stream and how much. Streams only the sender has
+    /// funded report a single entry for the sender.
+    pub fn get_stream_contributors(env: Env, stream_id: u64) -> Vec<(Address, i128)> {
+        let stream = Self::read_stream(&env, stream_id);
+        env.storage().persistent()
+            .get(&DataKey::Contributors(stream_id))
+            .unwrap_or_else(|| soroban_sdk::vec![&env, (stream.sender, stream.balance)])
+    }
+
+    /// Shared deposit logic: pull `amount` from `from` into the stream's
+    /// escrow. Callers handle status and auth checks.
+    fn fund_stream(env: &Env, stream: Stream, from: &Address, amount: i128) {
+        Self::assert_not_paused(env);
+
+        if amount <= 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+
+        let new_balance = stream.balance.checked_add(amount)
+            .unwrap_or_else(|| panic_with_error!(env, Error::ArithmeticOverflow));
+
+        if new_balance > stream.total_amount {
+            panic_with_error!(env, Error::DepositExceedsTotal);
+        }
+
+        // Transfer tokens from the funder to contract
+        let stream_id = stream.id;
+        let token_client = token::Client::new(env, &stream.token);
+        let held = Self::held(env, &token_client);
+        token_client.transfer(from, &env.current_contract_address(), &amount);
+
+        // Book the full deposit first, so a shortfall comes off it
+        let token = stream.token.clone();
+        Self::credit_stream(env, stream, from, amount);
+        let absorbed = Self::check_transfer(env, &token_client, Some(stream_id), held, amount);
+        if amount > absorbed {
+            Self::park_in_yield(env, &token, amount - absorbed);
+        }
+    }
+
+    /// Book `amount`, already held by the contract, as `from`'s deposit
+    /// into the stream
+    fn credit_stream(env: &Env, mut stream: Stream, from: &Address, amount: i128) {
+        let stream_id = stream.id;
+
+        // The ledger only exists once someone other than the sender funds
+        // the stream; until then the sender owns the whole balance
+        let ledger_key = DataKey::Contributors(stream_id);
+        let ledger: Option<Vec<(Address, i128)>> = env.storage().persistent().get(&ledger_key);
+        if ledger.is_some() || *from != stream.sender {
+            let mut ledger = ledger.unwrap_or_else(|| soroban_sdk::vec![env, (stream.sender.clone(), stream.balance)]);
+            match ledger.iter().position(|(funder, _)| funder == *from) {
+                Some(i) => {
+                    let (funder, contributed) = ledger.get(i as u32).unwrap();
+                    ledger.set(i as u32, (funder, contributed + amount));
+                },
+                None => ledger.push_back((from.clone(), amount)),
+            }
+            env.storage().persistent().set(&ledger_key, &ledger);
+            Self::extend_persistent(env, &ledger_key);
+        }
+
+        // Update balance
+        stream.balance += amount;
+        Self::store_stream(env, &stream, "deposit", amount, from);
+        Self::warn_if_underfunded(env, &stream);
+
+        // Update stream metrics
+        let mut metrics = Self::load_stream_metrics(env, stream_id, stream.withdrawn_amount);
+
+        metrics.last_activity = env.ledger().timestamp();
+
+        Self::store_stream_metrics(env, stream_id, &metrics);
+
+        let mut protocol_metrics = Self::load_protocol_metrics(env);
+        protocol_metrics.total_tokens_streamed += amount;
+        Self::store_protocol_metrics(env, &protocol_metrics);
+        Self::update_token_metrics(env, &stream.token, |m| m.escrowed_balance += amount);
+        Self::adjust_sender_tvl(env, &stream.sender, amount);
+
+        // Emit StreamDeposit event
+        let funded_bps = Self::funded_bps(&stream);
+        env.events().publish(("StreamDeposit", stream_id), StreamDepositEvent { stream_id, amount, funded_bps });
+        // Deposits can't exceed the total, so only one ever lands exactly on it
+        if stream.balance == stream.total_amount {
+            env.events().publish(
+                ("StreamFullyFunded", stream_id),
+                StreamFullyFundedEvent { stream_id, total_amount: stream.total_amount },
+            );
+        }
+    }
+
+    /// Hand `split_bps` of an Active stream's unwithdrawn total and escrow
+    /// to `new_recipient` as a child stream with the same sender, token and
+    /// timeline (recipient only). Past withdrawals stay on the original,
+    /// which keeps vesting its reduced total on the same schedule.
+    /// Milestone and renewed streams can't be split. Returns the child's id.
+    pub fn split_stream(env: Env, stream_id: u64, split_bps: u32, new_recipient: Address) -> u64 {
+        let mut parent: Stream = Self::read_stream(&env, stream_id);
+        parent.recipient.require_auth();
+        Self::assert_not_quarantined(&env, &parent);
+        Self::assert_not_shared(&env, stream_id);
+
+        if parent.status != StreamStatus::Active {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+        let storage = env.storage().persistent();
+        let scheduled = storage.has(&DataKey::Milestones(stream_id)) || storage.has(&StreamKey::VestingBaseline(stream_id));
+        if split_bps == 0 || split_bps >= 10_000 || scheduled {
+            panic_with_error!(&env, Error::InvalidSplit);
+        }
+        Self::assert_valid_recipient(&env, &parent.sender, &parent.token, &new_recipient);
+
+        let child_total = bps_of(parent.total_amount - parent.withdrawn_amount, split_bps);
+        let child_balance = bps_of(parent.balance - parent.withdrawn_amount, split_bps);
+        if child_total <= 0 {
+            panic_with_error!(&env, Error::InvalidSplit);
+        }
+        parent.total_amount -= child_total;
+        parent.balance -= child_balance;
+
+        let child_id = Self::next_stream_id(&env);
+        let child = Stream {
+            id: child_id,
+            recipient: new_recipient.clone(),
+            total_amount: child_total,
+            balance: child_balance,
+            withdrawn_amount: 0,
+            ..parent.clone()
+        };
+
+        let storage = env.storage().persistent();
+        Self::store_stream(&env, &parent, "split", child_total, &parent.recipient);
+        Self::store_stream(&env, &child, "created", child_total, &parent.recipient);
+
+        let mut parent_metrics = Self::load_stream_metrics(&env, stream_id, parent.withdrawn_amount);
+        parent_metrics.last_activity = env.ledger().timestamp();
+        Self::store_stream_metrics(&env, stream_id, &parent_metrics);
+        Self::store_stream_metrics(&env, child_id, &Self::default_stream_metrics(&env));
+        if storage.has(&StreamKey::AllowFeeOnTransfer(stream_id)) {
+            storage.set(&StreamKey::AllowFeeOnTransfer(child_id), &true);
+            Self::extend_persistent(&env, &StreamKey::AllowFeeOnTransfer(child_id));
+        }
+        let basis: Option<TimeBasis> = storage.get(&StreamKey::TimeBasis(stream_id));
+        if let Some(basis) = basis {
+            storage.set(&StreamKey::TimeBasis(child_id), &basis);
+            Self::extend_persistent(&env, &StreamKey::TimeBasis(child_id));
+        }
+
+        // Sponsors keep their refund shares on both halves
+        let contributors: Option<Vec<(Address, i128)>> = storage.get(&DataKey::Contributors(stream_id));
+        if let Some(contributors) = contributors {
+            storage.set(&DataKey::Contributors(child_id), &contributors);
+            Self::extend_persistent(&env, &DataKey::Contributors(child_id));
+        }
+        let funding_source: Option<Address> = storage.get(&DataKey::FundingSource(stream_id));
+        if let Some(funding_source) = funding_source {
+            storage.set(&DataKey::FundingSource(child_id), &funding_source);
+            Self::extend_persistent(&env, &DataKey::FundingSource(child_id));
+        }
+
+        Self::transition_status(&env, child_id, &parent.token, None, StreamStatus::Active);
+        Self::index_recipient_stream(&env, &new_recipient, child_id);
+
+        // Committed and escrowed amounts only move between the two streams
+        let mut protocol_metrics = Self::load_protocol_metrics(&env);
+        protocol_metrics.total_streams_created += 1;
+        Self::store_protocol_metrics(&env, &protocol_metrics);
+
+        env.events().publish(
+            ("StreamSplit", stream_id),
+            StreamSplitEvent { parent: stream_id, child: child_id, split_bps },
+        );
+        child_id
+    }
+
+    /// Get stream details
+    pub fn get_stream(env: Env, stream_id: u64) -> Stream {
+        Self::read_stream(&env, stream_id)
+    }
+
+    /// Like `get_stream`, but returns None for an unknown stream instead of
+    /// failing, for callers composing it inside other contracts
+    pub fn find_stream(env: Env, stream_id: u64) -> Option<Stream> {
+        Self::lookup_stream(&env, stream_id)
+    }
+
+    /// Number of stream ids handed out so far; ids run from 1 to this
+    pub fn get_stream_count(env: Env) -> u64 {
+        env.storage().instance().get_or(&DataKey::StreamCount, 0)
+    }
+
+    /// Streams with ids `start_id` to `start_id + limit - 1` (at most 50
+    /// ids per call), skipping ids that don't exist. For indexers: unlike
+    /// `get_stream` this leaves TTLs and legacy entries untouched.
+    pub fn get_streams(env: Env, start_id: u64, limit: u32) -> Vec<Stream> {
+        let start = start_id.max(1);
+        let end = start
+            .saturating_add(limit.min(MAX_STREAM_PAGE) as u64)
+            .min(Self::get_stream_count(env.clone()) + 1);
+
+        let mut streams = Vec::new(&env);
+        for stream_id in start..end {
+            if let Some(stream) = Self::peek_stream(&env, stream_id) {
+                streams.push_back(stream);
+            }
+        }
+        streams
+    }
+
+    /// Load a stream from either layout without bumping its TTL or
+    /// migrating it
+    fn peek_stream(env: &Env, stream_id: u64) -> Option<Stream> {
+        let storage = env.storage().persistent();
+        storage.get(&DataKey::Stream(stream_id)).or_else(|| storage.get(&stream_id))
+    }
+
+    /// Load a stream, failing with StreamNotFound if it doesn't exist
+    fn read_stream(env: &Env, stream_id: u64) -> Stream {
+        Self::lookup_stream(env, stream_id).unwrap_or_else(|| panic_with_error!(env, Error::StreamNotFound))
+    }
+
+    /// Load a stream and bump its TTL, moving it over from the legacy
+    /// layout on first access
+    fn lookup_stream(env: &Env, stream_id: u64) -> Option<Stream> {
+        let key = DataKey::Stream(stream_id);
+        match env.storage().persistent().get(&key) {
+            Some(stream) => {
+                Self::extend_persistent(env, &key);
+                Some(stream)
+            },
+            None => Self::migrate_legacy_stream(env, stream_id),
+        }
+    }
+
+    /// Streams written before `DataKey` sit under the bare id, with their
+    /// metrics and delegate under `(id, Symbol)` tuples. Rewrite all three
+    /// under the typed keys and drop the old entries.
+    fn migrate_legacy_stream(env: &Env, stream_id: u64) -> Option<Stream> {
+        let storage = env.storage().persistent();
+        let stream: Stream = storage.get(&stream_id)?;
+        storage.remove(&stream_id);
+        storage.set(&DataKey::Stream(stream_id), &stream);
+        Self::extend_persistent(env, &DataKey::Stream(stream_id));
+
+        let metrics_key = (stream_id, Symbol::new(env, "metrics"));
+        if let Some(metrics) = storage.get::<_, StreamMetrics>(&metrics_key) {
+            storage.remove(&metrics_key);
+            storage.set(&DataKey::Metrics(stream_id), &metrics);
+            Self::extend_persistent(env, &DataKey::Metrics(stream_id));
+        }
+        let delegate_key = (stream_id, Symbol::new(env, "delegate"));
+        if let Some(delegate) = storage.get::<_, Address>(&delegate_key) {
+            storage.remove(&delegate_key);
+            storage.set(&DataKey::Delegate(stream_id), &delegate);
+            Self::extend_persistent(env, &DataKey::Delegate(stream_id));
+        }
+        Some(stream)
+    }
+
+    /// Stored metrics for a stream. Missing metrics (archived, or never
+    /// written by an older version) are rebuilt from `withdrawn` and the
+    /// withdrawal log, so the log isn't overwritten from seq 0 and
+    /// `total_withdrawn` keeps matching the stream.
+    fn load_stream_metrics(env: &Env, stream_id: u64, withdrawn: i128) -> StreamMetrics {
+        env.storage().persistent()
+            .get(&DataKey::Metrics(stream_id))
+            .unwrap_or_else(|| Self::derive_stream_metrics(env, stream_id, withdrawn, Self::default_stream_metrics(env)))
+    }
+
+    fn store_stream_metrics(env: &Env, stream_id: u64, metrics: &StreamMetrics) {
+        env.storage().persistent().set(&DataKey::Metrics(stream_id), metrics);
+        Self::extend_persistent(env, &DataKey::Metrics(stream_id));
+    }
+
+    /// `base` with the fields that can be recomputed taken from the stream
+    /// and the withdrawal log: total withdrawn, withdrawal count, last
+    /// withdrawal time and current delegate. Pause and delegation counters
+    /// and sponsored fees have no other record and are kept.
+    fn derive_stream_metrics(env: &Env, stream_id: u64, withdrawn: i128, base: StreamMetrics) -> StreamMetrics {
+        let storage = env.storage().persistent();
+        let mut withdrawal_count = 0u32;
+        let mut last_withdrawal_time = 0u64;
+        while let Some(record) = storage.get::<_, WithdrawalRecord>(&DataKey::Withdrawal(stream_id, withdrawal_count)) {
+            last_withdrawal_time = record.timestamp;
+            withdrawal_count += 1;
+        }
+        StreamMetrics {
+            total_withdrawn: withdrawn,
+            withdrawal_count,
+            last_withdrawal_time,
+            current_delegate: storage.get(&DataKey::Delegate(stream_id)),
+            ..base
+        }
+    }
+
+    fn default_stream_metrics(env: &Env) -> StreamMetrics {
+        StreamMetrics {
+            last_activity: env.ledger().timestamp(),
+            total_withdrawn: 0,
+            withdrawal_count: 0,
+            pause_count: 0,
+            total_delegations: 0,
+            current_delegate: None,
+            last_delegation_time: 0,
+            forced_revocations: 0,
+            sponsored_fees: 0,
+            last_withdrawal_time: 0,
+        }
+    }
+
+    /// Assert that the caller is authorized to withdraw (recipient or delegate)
+    /// and report which of the two it was. Without an explicit caller the
+    /// delegate must sign whenever one is set, since a contract can't try
+    /// one signer and fall back to another.
+    fn assert_is_recipient_or_delegate(env: &Env, stream: &Stream) -> WithdrawCaller {
+        let delegate: Option<Address> = env.storage().persistent().get(&DataKey::Delegate(stream.id));
+        match delegate {
+            Some(delegate) => {
+                delegate.require_auth();
+                WithdrawCaller::Delegate
+            }
+            None => {
+                stream.recipient.require_auth();
+                WithdrawCaller::Recipient
+            }
+        }
+    }
+
+    /// Require `caller`'s auth and that it is the stream's recipient or
+    /// current delegate
+    fn assert_withdraw_caller(env: &Env, stream: &Stream, caller: &Address) -> WithdrawCaller {
+        caller.require_auth();
+        if *caller == stream.recipient {
+            return WithdrawCaller::Recipient;
+        }
+        let delegate: Option<Address> = env.storage().persistent().get(&DataKey::Delegate(stream.id));
+        if delegate.as_ref() == Some(caller) {
+            return WithdrawCaller::Delegate;
+        }
+        panic_with_error!(env, Error::Unauthorized)
+    }
+
+    /// Set a delegate for withdrawal rights on a stream
+    pub fn set_delegate(env: Env, stream_id: u64, delegate: Address) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+        Self::assert_not_shared(&env, stream_id);
+    
+        // Prevent self-delegation
+        if delegate == stream.recipient {
+            panic_with_error!(&env, Error::InvalidDelegate);
+        }
+
+        // Check if there's an existing delegate and emit revocation event
+        let delegate_key = DataKey::Delegate(stream_id);
+        if let Some(old_delegate) = env.storage().persistent().get::<_, Address>(&delegate_key) {
+            if old_delegate != delegate {
+                let revoke_event = DelegationRevokedEvent {
+                    stream_id,
+                    recipient: stream.recipient.clone(),
+                    revoked_by: stream.recipient.clone(),
+                };
+                env.events().publish(("DelegationRevoked", stream_id), revoke_event);
+            }
+        }
+
+        let current_time = env.ledger().timestamp();
+
+        // Store delegate
+        env.storage().persistent().set(&DataKey::Delegate(stream_id), &delegate);
+        Self::extend_persistent(&env, &DataKey::Delegate(stream_id));
+        Self::record_activity(&env, stream_id, "delegate", 0, &stream.recipient);
+
+        // Update stream metrics
+        let mut metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
+
+        metrics.total_delegations += 1;
+        metrics.current_delegate = Some(delegate.clone());
+        metrics.last_delegation_time = current_time;
+        metrics.last_activity = current_time;
+
+        Self::store_stream_metrics(&env, stream_id, &metrics);
+
+        // Update protocol metrics
+        let mut protocol_metrics = Self::load_protocol_metrics(&env);
+        protocol_metrics.total_delegations += 1;
+        Self::store_protocol_metrics(&env, &protocol_metrics);
+
+        // Emit event
+        let event = DelegationGrantedEvent {
+            stream_id,
+            recipient: stream.recipient,
+            delegate: delegate.clone(),
+        };
+        env.events().publish(("DelegationGranted", stream_id), event);
+    }
+
+    /// Propose moving a stream to a new recipient, e.g. after the current
+    /// recipient lost their keys. Takes effect once approved.
+    pub fn propose_recipient_change(env: Env, stream_id: u64, new_recipient: Address) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        Self::assert_not_shared(&env, stream_id);
+        if matches!(stream.status, StreamStatus::Canceled | StreamStatus::Completed | StreamStatus::Rejected) {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+        if new_recipient == stream.recipient {
+            panic_with_error!(&env, Error::InvalidRecipient);
+        }
+        Self::assert_valid_recipient(&env, &stream.sender, &stream.token, &new_recipient);
+
+        let key = DataKey::PendingRecipient(stream_id);
+        env.storage().persistent().set(&key, &new_recipient);
+        Self::extend_persistent(&env, &key);
+    }
+
+    /// Approve a pending recipient change. `approver` must be the current
+    /// recipient, or the admin once the stream has been inactive for the
+    /// rescue delay.
+    pub fn approve_recipient_change(env: Env, stream_id: u64, approver: Address) {
+        approver.require_auth();
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+        Self::assert_not_quarantined(&env, &stream);
+
+        let pending_key = DataKey::PendingRecipient(stream_id);
+        let new_recipient: Address = env.storage().persistent().get(&pending_key)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::NoPendingRecipientChange));
+
+        let mut metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
+        let current_time = env.ledger().timestamp();
+
+        let approved_by_admin = if approver == stream.recipient {
+            false
+        } else if approver == Self::read_admin(&env) {
+            let unlock_time = metrics.last_activity.saturating_add(Self::get_recipient_rescue_delay(env.clone()));
+            if current_time < unlock_time {
+                panic_with_error!(&env, Error::RescueWindowNotElapsed);
+            }
+            Self::record_admin_action(&env, "rescue_recipient", &approver, stream_id as i128);
+            true
+        } else {
+            panic_with_error!(&env, Error::Unauthorized);
+        };
+
+        let old_recipient = stream.recipient.clone();
+        stream.recipient = new_recipient.clone();
+        Self::store_stream(&env, &stream, "recipient", 0, &approver);
+        env.storage().persistent().remove(&pending_key);
+        Self::unindex_recipient_stream(&env, &old_recipient, stream_id);
+        Self::index_recipient_stream(&env, &new_recipient, stream_id);
+
+        // The old recipient's delegate must not keep withdrawal rights
+        let delegate_key = DataKey::Delegate(stream_id);
+        if env.storage().persistent().has(&delegate_key) {
+            env.storage().persistent().remove(&delegate_key);
+            metrics.current_delegate = None;
+            env.events().publish(
+                ("DelegationRevoked", stream_id),
+                DelegationRevokedEvent { stream_id, recipient: old_recipient.clone(), revoked_by: approver.clone() },
+            );
+        }
+
+        metrics.last_activity = current_time;
+        Self::store_stream_metrics(&env, stream_id, &metrics);
+
+        env.events().publish(
+            ("RecipientChanged", stream_id),
+            RecipientChangedEvent { stream_id, old_recipient, new_recipient, approved_by_admin },
+        );
+    }
+
+    /// Get the recipient proposed for a stream, if any
+    pub fn get_pending_recipient_change(env: Env, stream_id: u64) -> Option<Address> {
+        env.storage().persistent().get(&DataKey::PendingRecipient(stream_id))
+    }
+
+    /// Set how long a stream must be inactive before the admin may approve a
+    /// recipient change on the recipient's behalf
+    pub fn set_recipient_rescue_delay(env: Env, delay: u64) {
+        Self::require_admin(&env, "rescue_delay", delay as i128);
+
+        env.storage().instance().set(&DataKey::RescueDelay, &delay);
+        Self::extend_instance(&env);
+    }
+
+    /// Get the admin rescue inactivity window in seconds (30 days by default)
+    pub fn get_recipient_rescue_delay(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::RescueDelay).unwrap_or(DEFAULT_RESCUE_DELAY)
+    }
+
+    /// Revoke the delegate for a stream
+    pub fn revoke_delegate(env: Env, stream_id: u64) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+
+        let revoked_by = stream.recipient.clone();
+        Self::remove_delegate(&env, stream, revoked_by);
+    }
+
+    /// Emergency removal of a compromised delegate by the sender, for when
+    /// the recipient can't act. The sender can't set a delegate; only the
+    /// recipient can delegate again afterwards.
+    pub fn revoke_delegate_by_sender(env: Env, stream_id: u64) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+
+        let revoked_by = stream.sender.clone();
+        Self::remove_delegate(&env, stream, revoked_by);
+    }
+
+    /// Remove the stream's delegate, if any, and record who did it.
+    /// Callers handle auth.
+    fn remove_delegate(env: &Env, stream: Stream, revoked_by: Address) {
+        let stream_id = stream.id;
+        let delegate_key = DataKey::Delegate(stream_id);
+        let had_delegate = env.storage().persistent().has(&delegate_key);
+
+        // Remove delegate
+        env.storage().persistent().remove(&delegate_key);
+
+        // Update stream metrics
+        if had_delegate {
+            Self::record_activity(env, stream_id, "undelegate", 0, &revoked_by);
+            let mut metrics = Self::load_stream_metrics(env, stream_id, stream.withdrawn_amount);
+
+            metrics.current_delegate = None;
+            metrics.last_activity = env.ledger().timestamp();
+            if revoked_by == stream.sender {
+                metrics.forced_revocations += 1;
+            }
+
+            Self::store_stream_metrics(env, stream_id, &metrics);
+
+            // Emit event
+            let event = DelegationRevokedEvent {
+                stream_id,
+                recipient: stream.recipient,
+                revoked_by,
+            };
+            env.events().publish(("DelegationRevoked", stream_id), event);
+        }
+    }
+
+    /// Get the delegate for a stream
+    pub fn get_delegate(env: Env, stream_id: u64) -> Option<Address> {
+        // Ensure stream exists
+        Self::read_stream(&env, stream_id);
+        env.storage().persistent().get(&DataKey::Delegate(stream_id))
+    }
+
+    /// Fee rate charged on the stream's withdrawals. Creation, quotes,
+    /// summaries and withdrawals all resolve the rate here. Nothing is
+    /// charged during a fee holiday or on streams whose sender or
+    /// recipient the fee registry exempts.
+    fn effective_fee_rate(env: &Env, stream: &Stream) -> u32 {
+        if Self::get_fee_holiday(env.clone()).is_some() {
+            return 0;
+        }
+        if Self::is_fee_exempt(env, &stream.sender) || Self::is_fee_exempt(env, &stream.recipient) {
+            return 0;
+        }
+        env.storage().instance().get_or(&DataKey::FeeRate, 0)
+    }
+
+    /// Whether the fee registry, if one is set, exempts `address`. A
+    /// registry call that fails counts as not exempt.
+    fn is_fee_exempt(env: &Env, address: &Address) -> bool {
+        let Some(registry) = Self::get_fee_registry(env.clone()) else {
+            return false;
+        };
+        let result = env.try_invoke_contract::<bool, soroban_sdk::Error>(
+            &registry,
+            &Symbol::new(env, "is_fee_exempt"),
+            (address.clone(),).into_val(env),
+        );
+        matches!(result, Ok(Ok(true)))
+    }
+
+    /// Calculate the protocol fee on a withdrawal of `amount` from the stream
+    fn calculate_protocol_fee(env: &Env, stream: &Stream, amount: i128) -> i128 {
+        Self::fee_with_carry(env, stream, amount).0
+    }
+
+    /// Fee on `amount` plus the stream's carried fractional fee, and the new
+    /// carry. The fraction of a unit that rounding down drops is kept (in
+    /// 1/10000ths) and added to the next withdrawal, so splitting a
+    /// withdrawal into tiny pieces can't dodge the fee: the fees paid add
+    /// up to what one withdrawal of the same total would pay.
+    fn fee_with_carry(env: &Env, stream: &Stream, amount: i128) -> (i128, i128) {
+        let carry: i128 = env.storage().persistent().get_or(&DataKey::FeeCarry(stream.id), 0);
+        let fee_rate = Self::effective_fee_rate(env, stream);
+
+        bps_of_with_carry(amount, fee_rate, carry)
+    }
+
+    /// Share of each delegate-triggered withdrawal's net amount paid to the
+    /// delegate, to reward keepers that withdraw for the recipient.
+    /// Recipient only, at most 100 bps.
+    pub fn set_caller_tip_bps(env: Env, stream_id: u64, tip_bps: u32) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+
+        if tip_bps > MAX_CALLER_TIP_BPS {
+            panic_with_error!(&env, Error::TipTooHigh);
+        }
+
+        let key = DataKey::CallerTipBps(stream_id);
+        env.storage().persistent().set(&key, &tip_bps);
+        Self::extend_persistent(&env, &key);
+    }
+
+    pub fn get_caller_tip_bps(env: Env, stream_id: u64) -> u32 {
+        env.storage().persistent().get_or(&DataKey::CallerTipBps(stream_id), 0)
+    }
+
+    /// Preview a withdrawal of `amount` without executing it, using the same
+    /// checks and fee math as `withdraw`
+    pub fn quote_withdrawal(env: Env, stream_id: u64, amount: i128) -> WithdrawalQuote {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        let valid = Self::check_withdrawal(&env, &stream, amount).is_ok();
+        let (net, fee) = Self::split_fee(&env, &stream, amount.max(0));
+        WithdrawalQuote {
+            gross: amount,
+            fee,
+            net,
+            withdrawable: Self::compute_withdrawable(&env, &stream),
+            valid,
+        }
+    }
+
+    /// Whether `amount` can be withdrawn from the stream right now
+    fn check_withdrawal(env: &Env, stream: &Stream, amount: i128) -> Result<(), soroban_sdk::Error> {
+        if Self::is_paused(env.clone()) {
+            return Err(Error::ContractPaused.into());
+        }
+        if stream.status == StreamStatus::Quarantined {
+            return Err(Error::StreamQuarantined.into());
+        }
+        if Self::is_shared(env, stream.id) {
+            return Err(Error::SharedStream.into());
+        }
+        if Self::claim_window_expired(env, stream) {
+            return Err(Error::ClaimWindowExpired.into());
+        }
+        if amount <= 0 || amount > Self::compute_withdrawable(env, stream) {
+            return Err(Error::InsufficientWithdrawable.into());
+        }
+        // The final withdrawal may be smaller so a stream can always be emptied
+        let min_withdrawal = Self::get_stream_limits(env.clone()).min_withdrawal_amount;
+        if amount < min_withdrawal && amount < stream.total_amount - stream.withdrawn_amount {
+            return Err(Error::AmountBelowMinimum.into());
+        }
+        if env.ledger().timestamp() < Self::get_next_withdrawal_time(env.clone(), stream.id) {
+            return Err(ErrorExt::WithdrawTooSoon.into());
+        }
+        Ok(())
+    }
+
+    /// Split a gross withdrawal into (net, fee) at the stream's fee rate
+    fn split_fee(env: &Env, stream: &Stream, amount: i128) -> (i128, i128) {
+        let fee = Self::calculate_protocol_fee(env, stream, amount);
+        (amount - fee, fee)
+    }
+
+    /// Calculate withdrawable amount for a stream
+    pub fn withdrawable_amount(env: Env, stream_id: u64) -> i128 {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        Self::compute_withdrawable(&env, &stream)
+    }
+
+    /// Withdrawable amount at each of up to 50 timestamps (ledger numbers
+    /// for a `LedgerSequence` stream), assuming no further withdrawals. A
+    /// paused stream stays frozen at `paused_at`.
+    /// Read-only: no storage writes or TTL bumps, so it's cheap to simulate.
+    pub fn projected_withdrawable(env: Env, stream_id: u64, timestamps: Vec<u64>) -> Vec<i128> {
+        if timestamps.len() > MAX_PROJECTION_POINTS {
+            panic_with_error!(&env, Error::BatchTooLarge);
+        }
+        // Legacy-layout streams are read in place; migrating would write
+        let stream: Stream = env.storage().persistent()
+            .get(&DataKey::Stream(stream_id))
+            .or_else(|| env.storage().persistent().get(&stream_id))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotFound));
+
+        let mut projection = Vec::new(&env);
+        for timestamp in timestamps.iter() {
+            let withdrawable = match (stream.status, stream.paused_at) {
+                (StreamStatus::Active, _) => Self::vested_amount(&env, &stream, timestamp) - stream.withdrawn_amount,
+                (StreamStatus::Paused, Some(paused_at)) => {
+                    Self::vested_amount(&env, &stream, timestamp.min(paused_at)) - stream.withdrawn_amount
+                },
+                _ => 0,
+            };
+            projection.push_back(withdrawable.max(0));
+        }
+        projection
+    }
+
+    /// Vested-but-unwithdrawn amount for an already loaded stream
+    fn compute_withdrawable(env: &Env, stream: &Stream) -> i128 {
+        // Paused streams have no withdrawable amount
+        if stream.status == StreamStatus::Paused {
+            return 0;
+        }
+
+        // Canceled milestone streams keep approved milestones claimable
+        let claimable = stream.status == StreamStatus::Active
+            || (stream.status == StreamStatus::Canceled && Self::milestone_total(env, stream.id).is_some());
+        if !claimable || Self::claim_window_expired(env, stream) {
+            return 0;
+        }
+
+        // Only what has been deposited can be paid out, and a split stream
+        // may have withdrawn more than its reduced total has vested so far;
+        // it catches up as vesting continues
+        let vested = Self::vested_amount(env, stream, Self::stream_clock(env, stream.id));
+        (vested.min(stream.balance) - stream.withdrawn_amount).max(0)
+    }
+
+    /// Whether the stream's claim deadline, if any, has passed
+    fn claim_window_expired(env: &Env, stream: &Stream) -> bool {
+        stream.claim_deadline.is_some_and(|deadline| Self::stream_clock(env, stream.id) > deadline)
+    }
+
+    /// Clock a stream was created with (`Timestamp` unless chosen otherwise)
+    pub fn get_time_basis(env: Env, stream_id: u64) -> TimeBasis {
+        env.storage().persistent().get_or(&StreamKey::TimeBasis(stream_id), TimeBasis::Timestamp)
+    }
+
+    /// Current time on the given basis
+    fn clock(env: &Env, basis: TimeBasis) -> u64 {
+        match basis {
+            TimeBasis::Timestamp => env.ledger().timestamp(),
+            TimeBasis::LedgerSequence => env.ledger().sequence().into(),
+        }
+    }
+
+    /// Current time in the units of the stream's own schedule. Everything
+    /// compared against start, end, cliff, deadline or pause times goes
+    /// through here, so the two clocks never get mixed.
+    fn stream_clock(env: &Env, stream_id: u64) -> u64 {
+        Self::clock(env, Self::get_time_basis(env.clone(), stream_id))
+    }
+
+    /// A schedule span converted to seconds for the duration limits
+    fn span_in_seconds(basis: TimeBasis, span: u64) -> u64 {
+        match basis {
+            TimeBasis::Timestamp => span,
+            TimeBasis::LedgerSequence => span.saturating_mul(SECONDS_PER_LEDGER),
+        }
+    }
+
+    /// Amount released at `current_time`: the approved milestones for
+    /// milestone streams, the time-based schedule otherwise
+    fn vested_amount(env: &Env, stream: &Stream, current_time: u64) -> i128 {
+        Self::milestone_total(env, stream.id).unwrap_or_else(|| {
+            let baseline = Self::get_vesting_baseline(env.clone(), stream.id);
+            Self::vested_at(stream, baseline.as_ref(), current_time)
+        })
+    }
+
+    /// Sum of approved milestone amounts, None for time-based streams
+    fn milestone_total(env: &Env, stream_id: u64) -> Option<i128> {
+        let milestones: Vec<Milestone> = env.storage().persistent().get(&DataKey::Milestones(stream_id))?;
+        Some(milestones.iter().filter(|m| m.approved).map(|m| m.amount).sum())
+    }
+
+    /// Total amount vested at `current_time`, ignoring stream status. A
+    /// renewed stream vests from its baseline: up to it the baseline's
+    /// amount is reached linearly, after it the rest of the total.
+    fn vested_at(stream: &Stream, baseline: Option<&VestingBaseline>, current_time: u64) -> i128 {
+        if current_time <= stream.start_time || current_time < stream.cliff_time {
+            return 0;
+        }
+        // `end_time` already includes pauses. From then on everything has
+        // vested, whatever the rounding below would give.
+        if current_time >= stream.end_time {
+            return stream.total_amount;
+        }
+
+        if let Some(baseline) = baseline {
+            if current_time < baseline.time {
+                let elapsed = (current_time - stream.start_time).saturating_sub(baseline.paused_duration);
+                let duration = (baseline.time - stream.start_time).saturating_sub(baseline.paused_duration);
+                if duration == 0 {
+                    return 0;
+                }
+                return baseline.vested * elapsed as i128 / duration as i128;
+            }
+            let paused_since = stream.total_paused_duration.saturating_sub(baseline.paused_duration);
+            let elapsed = (current_time - baseline.time).saturating_sub(paused_since);
+            let duration = (stream.end_time - baseline.time).saturating_sub(paused_since);
+            if duration == 0 {
+                return baseline.vested;
+            }
+            return baseline.vested + (stream.total_amount - baseline.vested) * elapsed as i128 / duration as i128;
+        }
+
+        // Effective elapsed time, excluding paused duration
+        let elapsed = (current_time - stream.start_time).saturating_sub(stream.total_paused_duration);
+
+        let duration = Self::vesting_duration(stream);
+        if duration == 0 {
+            return 0;
+        }
+
+        (stream.total_amount * elapsed as i128) / duration as i128
+    }
+
+    /// Seconds of actual vesting between start and end, excluding pauses
+    fn vesting_duration(stream: &Stream) -> u64 {
+        (stream.end_time - stream.start_time).saturating_sub(stream.total_paused_duration)
+    }
+
+    /// Inverse of `vested_at`: when the time schedule reaches `amount`,
+    /// assuming no further pauses. `end_time` if it never does.
+    fn vesting_time_of(env: &Env, stream: &Stream, amount: i128) -> u64 {
+        if amount >= stream.total_amount {
+            return stream.end_time;
+        }
+        if let Some(baseline) = Self::get_vesting_baseline(env.clone(), stream.id) {
+            if amount >= baseline.vested {
+                let paused_since = stream.total_paused_duration.saturating_sub(baseline.paused_duration);
+                let duration = (stream.end_time - baseline.time).saturating_sub(paused_since);
+                let elapsed = (amount - baseline.vested) * duration as i128 / (stream.total_amount - baseline.vested);
+                return (baseline.time + paused_since + elapsed as u64).max(stream.cliff_time).min(stream.end_time);
+            }
+            let duration = (baseline.time - stream.start_time).saturating_sub(baseline.paused_duration);
+            let elapsed = amount.max(0) * duration as i128 / baseline.vested;
+            return (stream.start_time + baseline.paused_duration + elapsed as u64).max(stream.cliff_time);
+        }
+        let elapsed = amount.max(0) * Self::vesting_duration(stream) as i128 / stream.total_amount;
+        (stream.start_time + stream.total_paused_duration + elapsed as u64)
+            .max(stream.cliff_time)
+            .min(stream.end_time)
+    }
+
+    /// Whether the stream's deposits cover its schedule, and if not, when
+    /// they run out and how much is missing
+    pub fn get_funding_status(env: Env, stream_id: u64) -> FundingStatus {
+        let stream = Self::read_stream(&env, stream_id);
+        FundingStatus {
+            funded_through: Self::vesting_time_of(&env, &stream, stream.balance),
+            shortfall: (stream.total_amount - stream.balance).max(0),
+        }
+    }
+
+    /// Deposited amount against the stream's total. `balance` only grows
+    /// with deposits (payouts are tracked in `withdrawn_amount`), so it is
+    /// the cumulative funding.
+    pub fn get_funding_progress(env: Env, stream_id: u64) -> FundingProgress {
+        let stream = Self::read_stream(&env, stream_id);
+        FundingProgress {
+            funded: stream.balance,
+            total: stream.total_amount,
+            funded_bps: Self::funded_bps(&stream),
+        }
+    }
+
+    /// `balance / total_amount` in basis points, rounded down so that
+    /// 10000 means exactly fully funded
+    fn funded_bps(stream: &Stream) -> u32 {
+        if stream.balance >= stream.total_amount {
+            return 10_000;
+        }
+        let bps = match stream.balance.checked_mul(10_000) {
+            Some(scaled) => scaled / stream.total_amount,
+            None => stream.balance / (stream.total_amount / 10_000),
+        };
+        bps as u32
+    }
+
+    /// Emit `LowBalanceWarning` for a running stream whose balance will
+    /// run out before `end_time`
+    fn warn_if_underfunded(env: &Env, stream: &Stream) {
+        let running = matches!(stream.status, StreamStatus::Active | StreamStatus::Paused);
+        if !running || stream.balance >= stream.total_amount {
+            return;
+        }
+        env.events().publish(
+            ("LowBalanceWarning", stream.id),
+            LowBalanceWarningEvent {
+                stream_id: stream.id,
+                balance: stream.balance - stream.withdrawn_amount,
+                projected_runout_time: Self::vesting_time_of(env, stream, stream.balance),
+            },
+        );
+    }
+
+    /// Withdraw from a stream. Signed by the delegate while one is set,
+    /// by the recipient otherwise; `withdraw_as` lets either sign.
+    pub fn withdraw(env: Env, stream_id: u64, amount: i128) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        let caller = Self::assert_is_recipient_or_delegate(&env, &stream);
+
+        let recipient = stream.recipient.clone();
+        let forward_to = Self::get_auto_forward(env.clone(), stream_id);
+        Self::execute_withdrawal(&env, stream, amount, &recipient, caller, forward_to);
+    }
+
+    /// `withdraw` signed by `caller`, which must be the recipient or the
+    /// current delegate. Lets the recipient withdraw while a delegate is set.
+    pub fn withdraw_as(env: Env, caller: Address, stream_id: u64, amount: i128) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        let caller = Self::assert_withdraw_caller(&env, &stream, &caller);
+
+        let recipient = stream.recipient.clone();
+        let forward_to = Self::get_auto_forward(env.clone(), stream_id);
+        Self::execute_withdrawal(&env, stream, amount, &recipient, caller, forward_to);
+    }
+
+    /// Have `withdraw` deposit the net amount into `target_stream_id`, a
+    /// stream the recipient sends, instead of paying it out (recipient
+    /// only). `None` clears the setting. Withdrawals the target can't take
+    /// are paid out as usual.
+    pub fn set_auto_forward(env: Env, stream_id: u64, target_stream_id: Option<u64>) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+
+        let key = DataKey::AutoForward(stream_id);
+        match target_stream_id {
+            Some(target_id) => {
+                let target = Self::read_stream(&env, target_id);
+                if target_id == stream_id || target.sender != stream.recipient {
+                    panic_with_error!(&env, Error::InvalidConfiguration);
+                }
+                env.storage().persistent().set(&key, &target_id);
+                Self::extend_persistent(&env, &key);
+            },
+            None => env.storage().persistent().remove(&key),
+        }
+    }
+
+    /// Stream that `withdraw` forwards this stream's payouts into, if any
+    pub fn get_auto_forward(env: Env, stream_id: u64) -> Option<u64> {
+        env.storage().persistent().get(&DataKey::AutoForward(stream_id))
+    }
+
+    /// Have the recipient's withdrawals paid through `adapter` (recipient
+    /// only): the contract calls `swap(token_in, amount, recipient,
+    /// min_out) -> i128` on it with the net amount, pre-authorizing the
+    /// adapter to pull exactly that amount from this contract. `min_out`
+    /// is `min_out_bps` of the input amount. If the call fails, the
+    /// withdrawal is paid out directly instead; an adapter that reports
+    /// less than `min_out` reverts it with `SlippageExceeded`.
+    pub fn set_settlement_adapter(env: Env, stream_id: u64, adapter: Address, min_out_bps: u32) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+        if min_out_bps > 10000 || adapter == env.current_contract_address() {
+            panic_with_error!(&env, Error::InvalidConfiguration);
+        }
+
+        let key = DataKey::SettlementAdapter(stream_id);
+        env.storage().persistent().set(&key, &SettlementAdapter { adapter, min_out_bps });
+        Self::extend_persistent(&env, &key);
+    }
+
+    /// Go back to paying withdrawals out in the stream's token (recipient only)
+    pub fn clear_settlement_adapter(env: Env, stream_id: u64) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+        env.storage().persistent().remove(&DataKey::SettlementAdapter(stream_id));
+    }
+
+    pub fn get_settlement_adapter(env: Env, stream_id: u64) -> Option<SettlementAdapter> {
+        env.storage().persistent().get(&DataKey::SettlementAdapter(stream_id))
+    }
+
+    /// Withdraw from a stream, sending the net amount to `destination`
+    /// instead of the recipient. A delegate may only route funds to the
+    /// recipient unless the recipient enabled `delegate_can_redirect`.
+    pub fn withdraw_to(env: Env, stream_id: u64, amount: i128, destination: Address) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        let caller = Self::assert_is_recipient_or_delegate(&env, &stream);
+        if caller == WithdrawCaller::Delegate
+            && destination != stream.recipient
+            && !Self::get_delegate_can_redirect(env.clone(), stream_id)
+        {
+            panic_with_error!(&env, Error::RedirectNotAllowed);
+        }
+
+        let net_amount = Self::execute_withdrawal(&env, stream, amount, &destination, caller, None);
+        env.events().publish(
+            ("WithdrawalRouted", stream_id),
+            WithdrawalRoutedEvent { stream_id, destination, amount, net_amount },
+        );
+    }
+
+    /// Escape hatch for a recipient whose own balance the token issuer has
+    /// frozen: pays everything currently withdrawable to `alt`. Recipient
+    /// auth only, even when a delegate is set. Returns the net amount paid.
+    pub fn rescue_to_alternate(env: Env, stream_id: u64, alt: Address) -> i128 {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+        Self::assert_not_quarantined(&env, &stream);
+
+        let amount = Self::compute_withdrawable(&env, &stream);
+        if amount <= 0 {
+            panic_with_error!(&env, Error::InsufficientWithdrawable);
+        }
+
+        let net_amount = Self::execute_withdrawal(&env, stream, amount, &alt, WithdrawCaller::Recipient, None);
+        env.events().publish(
+            ("WithdrawalRouted", stream_id),
+            WithdrawalRoutedEvent { stream_id, destination: alt, amount, net_amount },
+        );
+        net_amount
+    }
+
+    /// Allow or forbid the delegate to route withdrawals to addresses other
+    /// than the recipient (recipient only)
+    pub fn set_delegate_can_redirect(env: Env, stream_id: u64, allowed: bool) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+
+        let key = DataKey::DelegateCanRedirect(stream_id);
+        env.storage().persistent().set(&key, &allowed);
+        Self::extend_persistent(&env, &key);
+    }
+
+    /// Have `sponsor` pay the protocol fee on this stream's withdrawals so
+    /// the recipient receives the gross amount (sender and sponsor). The
+    /// sponsor funds it by approving this contract on the stream's token;
+    /// withdrawals fall back to deducting the fee when the pull fails.
+    pub fn set_fee_sponsor(env: Env, stream_id: u64, sponsor: Address) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+        sponsor.require_auth();
+
+        let key = DataKey::FeeSponsor(stream_id);
+        env.storage().persistent().set(&key, &sponsor);
+        Self::extend_persistent(&env, &key);
+    }
+
+    /// The stream's fee sponsor, if any
+    pub fn get_fee_sponsor(env: Env, stream_id: u64) -> Option<Address> {
+        env.storage().persistent().get(&DataKey::FeeSponsor(stream_id))
+    }
+
+    /// Whether the delegate may route withdrawals away from the recipient
+    pub fn get_delegate_can_redirect(env: Env, stream_id: u64) -> bool {
+        env.storage().persistent().get_or(&DataKey::DelegateCanRedirect(stream_id), false)
+    }
+
+    /// Shared withdrawal logic: update the stream and metrics, then pay the
+    /// net amount to `destination` and the fee to the collector. Callers
+    /// handle auth. Returns the net amount paid out.
+    fn execute_withdrawal(
+        env: &Env,
+        stream: Stream,
+        amount: i128,
+        destination: &Address,
+        caller: WithdrawCaller,
+        forward_to: Option<u64>,
+    ) -> i128 {
+        let stream_id = stream.id;
+        Self::enter_stream(env, stream_id);
+        let token = stream.token.clone();
+        if let Err(error) = Self::check_withdrawal(env, &stream, amount) {
+            panic_with_error!(env, error);
+        }
+        let recipient = stream.recipient.clone();
+        let mut notify = (stream.notify_recipient && recipient == *destination).then(|| recipient.clone());
+        let sponsor = Self::collect_sponsored_fee(env, &stream, amount);
+        let (net_amount, tip) = Self::settle_to_recipient(env, stream, amount, caller, sponsor, None);
+
+        let forwarded = net_amount > 0
+            && forward_to.is_some_and(|target_id| {
+                Self::forward_withdrawal(env, stream_id, target_id, destination, &token, net_amount)
+            });
+        if forwarded {
+            notify = None;
+        }
+
+        // All state is final at this point; token calls come last. The fee
+        // stays in the contract until `claim_fees`. Zero-amount transfers are
+        // skipped so token contracts that reject them can't block withdrawals.
+        let token_client = token::Client::new(env, &token);
+        let swapped = net_amount > 0
+            && !forwarded
+            && *destination == recipient
+            && Self::settle_via_adapter(env, stream_id, &token, destination, net_amount);
+        if swapped {
+            notify = None;
+        }
+        if net_amount > 0 && !forwarded && !swapped {
+            Self::pay_out(env, &token_client, destination, net_amount, Some(stream_id));
+        }
+        if let Some((delegate, tip)) = tip {
+            Self::pay_out(env, &token_client, &delegate, tip, Some(stream_id));
+        }
+
+        Self::exit_stream(env, stream_id);
+        if let Some(recipient) = notify {
+            Self::notify_recipient(env, stream_id, &recipient, net_amount, &token);
+        }
+        net_amount
+    }
+
+    /// Pay `amount` to the recipient through the stream's settlement
+    /// adapter, if one is set. Returns false, after emitting
+    /// `SettlementFallback`, when the swap call fails; a failed call is
+    /// rolled back, so the tokens are still here to pay out directly. A
+    /// swap that went through below the recipient's minimum can't be
+    /// undone that way, so it reverts the withdrawal.
+    fn settle_via_adapter(env: &Env, stream_id: u64, token: &Address, recipient: &Address, amount: i128) -> bool {
+        let Some(settlement) = Self::get_settlement_adapter(env.clone(), stream_id) else {
+            return false;
+        };
+        Self::ensure_liquid(env, token, amount);
+        let contract = env.current_contract_address();
+        env.authorize_as_current_contract(soroban_sdk::vec![
+            env,
+            InvokerContractAuthEntry::Contract(SubContractInvocation {
+                context: ContractContext {
+                    contract: token.clone(),
+                    fn_name: Symbol::new(env, "transfer"),
+                    args: (contract, settlement.adapter.clone(), amount).into_val(env),
+                },
+                sub_invocations: Vec::new(env),
+            }),
+        ]);
+
+        let min_out = bps_of(amount, settlement.min_out_bps);
+        let result = env.try_invoke_contract::<i128, soroban_sdk::Error>(
+            &settlement.adapter,
+            &Symbol::new(env, "swap"),
+            (token.clone(), amount, recipient.clone(), min_out).into_val(env),
+        );
+        match result {
+            Ok(Ok(amount_out)) => {
+                if amount_out < min_out {
+                    panic_with_error!(env, Error::SlippageExceeded);
+                }
+                env.events().publish(
+                    ("SettlementSwapped", stream_id),
+                    SettlementSwappedEvent { stream_id, adapter: settlement.adapter, amount_in: amount, amount_out },
+                );
+                true
+            },
+            _ => {
+                env.events().publish(
+                    ("SettlementFallback", stream_id),
+                    SettlementFallbackEvent { stream_id, adapter: settlement.adapter, amount },
+                );
+                false
+            },
+        }
+    }
+
+    /// Deposit a withdrawal's net amount into the recipient's own stream
+    /// `target_id` instead of paying it out. Returns false, after emitting
+    /// `ForwardSkipped`, when the target can't take it: not sent by the
+    /// recipient, another token, no longer running, or not enough room.
+    fn forward_withdrawal(
+        env: &Env,
+        stream_id: u64,
+        target_id: u64,
+        recipient: &Address,
+        token: &Address,
+        amount: i128,
+    ) -> bool {
+        let target = Self::lookup_stream(env, target_id).filter(|target| {
+            target.sender == *recipient
+                && target.token == *token
+                && matches!(target.status, StreamStatus::Active | StreamStatus::Paused)
+                && target.balance.checked_add(amount).is_some_and(|balance| balance <= target.total_amount)
+        });
+        match target {
+            Some(target) => {
+                Self::credit_stream(env, target, recipient, amount);
+                true
+            },
+            None => {
+                env.events().publish(
+                    ("ForwardSkipped", stream_id),
+                    ForwardSkippedEvent { stream_id, target_stream_id: target_id, amount },
+                );
+                false
+            },
+        }
+    }
+
+    /// Call `on_stream_payment(stream_id, amount, token)` on a contract
+    /// recipient. Failures are reported with `CallbackFailed` and otherwise
+    /// ignored so a broken receiver can't block its own payouts.
+    fn notify_recipient(env: &Env, stream_id: u64, recipient: &Address, amount: i128, token: &Address) {
+        let args = (stream_id, amount, token.clone()).into_val(env);
+        let result = env.try_invoke_contract::<(), soroban_sdk::Error>(
+            recipient,
+            &Symbol::new(env, "on_stream_payment"),
+            args,
+        );
+        if !matches!(result, Ok(Ok(()))) {
+            env.events().publish(
+                ("CallbackFailed", stream_id),
+                CallbackFailedEvent { stream_id, recipient: recipient.clone(), amount },
+            );
+        }
+    }
+
+    /// Pull the protocol fee on a withdrawal of `amount` from the stream's
+    /// fee sponsor, using the allowance it granted this contract. Returns
+    /// the sponsor if it paid; on any failure (no allowance, no balance)
+    /// the fee is deducted from the payout as usual.
+    fn collect_sponsored_fee(env: &Env, stream: &Stream, amount: i128) -> Option<Address> {
+        let sponsor: Address = env.storage().persistent().get(&DataKey::FeeSponsor(stream.id))?;
+        let fee = Self::calculate_protocol_fee(env, stream, amount);
+        if fee <= 0 {
+            return None;
+        }
+        let contract = env.current_contract_address();
+        let pulled = token::Client::new(env, &stream.token).try_transfer_from(&contract, &sponsor, &contract, &fee);
+        matches!(pulled, Ok(Ok(()))).then_some(sponsor)
+    }
+
+    /// Persist a changed stream and log the change to its activity feed.
+    /// Every stream write goes through here, so nothing that changes a
+    /// stream can skip the log.
+    fn store_stream(env: &Env, stream: &Stream, kind: &str, amount: i128, actor: &Address) {
+        let key = DataKey::Stream(stream.id);
+        env.storage().persistent().set(&key, stream);
+        Self::extend_persistent(env, &key);
+        Self::record_activity(env, stream.id, kind, amount, actor);
+    }
+
+    /// Append an entry to the stream's activity log, for changes that live
+    /// outside the stream record itself (delegates, milestones)
+    fn record_activity(env: &Env, stream_id: u64, kind: &str, amount: i128, actor: &Address) {
+        let len_key = ActivityKey::Len(stream_id);
+        let seq: u32 = env.storage().persistent().get_or(&len_key, 0);
+        let entry = ActivityEntry {
+            seq,
+            kind: Symbol::new(env, kind),
+            amount,
+            actor: actor.clone(),
+            timestamp: env.ledger().timestamp(),
+        };
+        let key = ActivityKey::Entry(stream_id, seq);
+        env.storage().persistent().set(&key, &entry);
+        Self::extend_persistent(env, &key);
+        env.storage().persistent().set(&len_key, &(seq + 1));
+        Self::extend_persistent(env, &len_key);
+    }
+
+    fn store_fee_carry(env: &Env, stream_id: u64, carry: i128) {
+        let key = DataKey::FeeCarry(stream_id);
+        env.storage().persistent().set(&key, &carry);
+        Self::extend_persistent(env, &key);
+    }
+
+    /// Record `amount` of the stream as delivered to the recipient, by a
+    /// withdrawal or by a cancel settling the vested balance: status,
+    /// metrics, fee accrual and the `Withdrawal` event. Both paths charge
+    /// the same fee. Moves no tokens and leaves the withdrawal checks to
+    /// the caller. Returns the net amount for the recipient and the
+    /// delegate's tip, if one is owed. With a `sponsor` the fee was already
+    /// paid in separately, so the recipient gets the full amount. On a
+    /// shared stream `beneficiary` names the payee in place of the
+    /// recipient.
+    fn settle_to_recipient(
+        env: &Env,
+        mut stream: Stream,
+        amount: i128,
+        caller: WithdrawCaller,
+        sponsor: Option<Address>,
+        beneficiary: Option<&Address>,
+    ) -> (i128, Option<(Address, i128)>) {
+        let stream_id = stream.id;
+        let payee = beneficiary.unwrap_or(&stream.recipient).clone();
+
+        let (fee, carry) = Self::fee_with_carry(env, &stream, amount);
+        let mut net_amount = match sponsor {
+            Some(_) => amount,
+            None => amount - fee,
+        };
+        if fee > 0 || carry > 0 {
+            Self::store_fee_carry(env, stream_id, carry);
+        }
+
+        // The tip comes out of the net amount, so fee + tip never exceeds gross
+        let mut tip = None;
+        if caller == WithdrawCaller::Delegate {
+            let tip_amount = bps_of(net_amount, Self::get_caller_tip_bps(env.clone(), stream_id));
+            if tip_amount > 0 {
+                let delegate: Address = env.storage().persistent().get(&DataKey::Delegate(stream_id)).unwrap();
+                net_amount -= tip_amount;
+                tip = Some((delegate, tip_amount));
+            }
+        }
+
+        stream.withdrawn_amount += amount;
+
+        // Check if stream is completed. A canceled milestone stream paying
+        // out its last approved milestone stays canceled, and so does a
+        // stream being canceled after it fully vested.
+        if stream.status == StreamStatus::Active
+            && caller != WithdrawCaller::Sender
+            && stream.withdrawn_amount >= stream.total_amount
+        {
+            Self::set_status(env, &mut stream, StreamStatus::Completed);
+        }
+
+        Self::update_token_metrics(env, &stream.token, |m| {
+            m.total_withdrawn += amount;
+            m.escrowed_balance -= amount;
+        });
+        Self::adjust_sender_tvl(env, &stream.sender, -amount);
+
+        let actor: Address = match caller {
+            WithdrawCaller::Recipient => payee.clone(),
+            WithdrawCaller::Delegate => env.storage().persistent().get(&DataKey::Delegate(stream_id)).unwrap(),
+            WithdrawCaller::Sender => stream.sender.clone(),
+        };
+        let kind = if caller == WithdrawCaller::Sender { "settle" } else { "withdraw" };
+        Self::store_stream(env, &stream, kind, amount, &actor);
+        Self::warn_if_underfunded(env, &stream);
+
+        // Update stream metrics
+        let mut metrics = Self::load_stream_metrics(env, stream_id, stream.withdrawn_amount - amount);
+
+        let record = WithdrawalRecord {
+            seq: metrics.withdrawal_count,
+            amount,
+            fee,
+            timestamp: env.ledger().timestamp(),
+            caller: actor,
+        };
+        let record_key = DataKey::Withdrawal(stream_id, record.seq);
+        env.storage().persistent().set(&record_key, &record);
+        Self::extend_persistent(env, &record_key);
+
+        metrics.total_withdrawn += amount;
+        metrics.withdrawal_count += 1;
+        metrics.last_activity = env.ledger().timestamp();
+        metrics.last_withdrawal_time = metrics.last_activity;
+        if sponsor.is_some() {
+            metrics.sponsored_fees += fee;
+        }
+
+        Self::store_stream_metrics(env, stream_id, &metrics);
+
+        if fee > 0 {
+            Self::record_fee_paid(env, sponsor.as_ref().unwrap_or(&payee), fee);
+            let key = DataKey::AccruedFees(stream.token.clone());
+            let accrued: i128 = env.storage().persistent().get_or(&key, 0);
+            env.storage().persistent().set(&key, &(accrued + fee));
+            Self::extend_persistent(env, &key);
+
+            let key = DataKey::FeesCollected(stream.token.clone());
+            let collected: i128 = env.storage().persistent().get_or(&key, 0);
+            env.storage().persistent().set(&key, &(collected + fee));
+            Self::extend_persistent(env, &key);
+            let mut protocol_metrics = Self::load_protocol_metrics(env);
+            protocol_metrics.total_fees_collected += fee;
+            Self::store_protocol_metrics(env, &protocol_metrics);
+            env.events().publish(("FeeCollected", stream_id), fee);
+        }
+        env.events().publish(
+            ("Withdrawal", stream_id),
+            WithdrawalEvent {
+                stream_id,
+                caller_is_delegate: caller == WithdrawCaller::Delegate,
+                gross_amount: amount,
+                fee,
+                tip: tip.as_ref().map_or(0, |(_, tip)| *tip),
+                net_amount,
+                recipient: payee,
+                sponsor,
+            },
+        );
+
+        (net_amount, tip)
+    }
+
+    /// Transfer out of escrow, turning a token-side refusal (frozen
+    /// trustline, deauthorized balance) into `TokenTransferRejected`
+    /// instead of an opaque host error. The panic reverts every write made
+    /// earlier in the invocation, so stream state is never left half-updated.
+    ///
+    /// The contract's balance must drop by exactly `amount`; see
+    /// `check_transfer` for `stream_id`.
+    fn pay_out(env: &Env, token_client: &token::Client, to: &Address, amount: i128, stream_id: Option<u64>) {
+        Self::ensure_liquid(env, &token_client.address, amount);
+        let held = Self::held(env, token_client);
+        let result = token_client.try_transfer(&env.current_contract_address(), to, &amount);
+        if !matches!(result, Ok(Ok(()))) {
+            panic_with_error!(env, Error::TokenTransferRejected);
+        }
+        Self::check_transfer(env, token_client, stream_id, held, -amount);
+    }
+
+    fn held(env: &Env, token_client: &token::Client) -> i128 {
+        token_client.balance(&env.current_contract_address())
+    }
+
+    /// Compare the change in the contract's balance since it was `held`
+    /// with the `expected` change (negative for tokens sent out). Tokens
+    /// that take a transfer fee make these differ, and booking the
+    /// requested amount would leave the escrow short of what streams
+    /// record, so a mismatch fails with `NonStandardTokenBehavior`. On a
+    /// stream created with `allow_fee_on_transfer` the mismatch is recorded
+    /// instead, and any shortfall comes off the stream's balance while it
+    /// is still open. Returns the amount taken off.
+    fn check_transfer(env: &Env, token_client: &token::Client, stream_id: Option<u64>, held: i128, expected: i128) -> i128 {
+        let actual = Self::held(env, token_client) - held;
+        if actual == expected {
+            return 0;
+        }
+        let Some(stream_id) = stream_id.filter(|id| Self::allows_fee_on_transfer(env.clone(), *id)) else {
+            panic_with_error!(env, ErrorExt::NonStandardTokenBehavior);
+        };
+
+        let shortfall = expected - actual;
+        let mut absorbed = 0;
+        if shortfall > 0 {
+            let key = StreamKey::TransferLoss(stream_id);
+            let lost: i128 = env.storage().persistent().get_or(&key, 0);
+            env.storage().persistent().set(&key, &(lost + shortfall));
+            Self::extend_persistent(env, &key);
+
+            let mut stream = Self::read_stream(env, stream_id);
+            if stream.status == StreamStatus::Active || stream.status == StreamStatus::Paused {
+                absorbed = shortfall.min(stream.balance - stream.withdrawn_amount).max(0);
+            }
+            if absorbed > 0 {
+                stream.balance -= absorbed;
+                Self::store_stream(env, &stream, "transfer_loss", absorbed, &stream.token);
+                let mut protocol_metrics = Self::load_protocol_metrics(env);
+                protocol_metrics.total_tokens_streamed -= absorbed;
+                Self::store_protocol_metrics(env, &protocol_metrics);
+                Self::update_token_metrics(env, &stream.token, |m| m.escrowed_balance -= absorbed);
+                Self::adjust_sender_tvl(env, &stream.sender, -absorbed);
+            }
+        }
+        env.events().publish(
+            ("TransferShortfall", stream_id),
+            TransferShortfallEvent { stream_id, expected, actual, absorbed },
+        );
+        absorbed
+    }
+
+    /// Whether the stream was created with `allow_fee_on_transfer`
+    pub fn allows_fee_on_transfer(env: Env, stream_id: u64) -> bool {
+        env.storage().persistent().has(&StreamKey::AllowFeeOnTransfer(stream_id))
+    }
+
+    /// Tokens a fee-on-transfer token has taken on this stream's transfers
+    pub fn get_transfer_loss(env: Env, stream_id: u64) -> i128 {
+        env.storage().persistent().get_or(&StreamKey::TransferLoss(stream_id), 0)
+    }
+
+    /// Mark a stream as mid-operation so a token contract can't reenter it
+    /// between state updates and transfers. The flag lives in temporary
+    /// storage and never outlives the invocation that set it.
+    fn enter_stream(env: &Env, stream_id: u64) {
+        let key = DataKey::Lock(stream_id);
+        if env.storage().temporary().has(&key) {
+            panic_with_error!(env, Error::ReentrantCall);
+        }
+        env.storage().temporary().set(&key, &true);
+    }
+
+    fn exit_stream(env: &Env, stream_id: u64) {
+        env.storage().temporary().remove(&DataKey::Lock(stream_id));
+    }
+
+    /// Pay `beneficiary` everything its share of a shared stream has
+    /// vested and been funded, less what it already withdrew and the
+    /// protocol fee. Returns the net amount paid.
+    pub fn withdraw_share(env: Env, stream_id: u64, beneficiary: Address) -> i128 {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+        beneficiary.require_auth();
+
+        Self::assert_not_paused(&env);
+        Self::assert_not_quarantined(&env, &stream);
+        let shares = Self::get_beneficiaries(env.clone(), stream_id)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::Unauthorized));
+        if !shares.iter().any(|b| b.address == beneficiary) {
+            panic_with_error!(&env, Error::Unauthorized);
+        }
+        if stream.status != StreamStatus::Active || Self::claim_window_expired(&env, &stream) {
+            panic_with_error!(&env, Error::InsufficientWithdrawable);
+        }
+        Self::enter_stream(&env, stream_id);
+
+        let released = Self::vested_amount(&env, &stream, Self::stream_clock(&env, stream_id)).min(stream.balance);
+        let (stream, payouts) = Self::settle_shares(&env, stream, released, WithdrawCaller::Recipient, Some(&beneficiary));
+        let Some((_, net_amount)) = payouts.first() else {
+            panic_with_error!(&env, Error::InsufficientWithdrawable);
+        };
+        Self::pay_out_all(&env, &stream, &payouts);
+
+        Self::exit_stream(&env, stream_id);
+        net_amount
+    }
+
+    fn is_shared(env: &Env, stream_id: u64) -> bool {
+        env.storage().persistent().has(&StreamKey::Beneficiaries(stream_id))
+    }
+
+    fn assert_not_shared(env: &Env, stream_id: u64) {
+        if Self::is_shared(env, stream_id) {
+            panic_with_error!(env, Error::SharedStream);
+        }
+    }
+
+    /// A beneficiary's entitlement once `released` of the stream has been
+    /// freed: its share, rounded down. The rounding dust goes to the last
+    /// beneficiary when the whole total is released, so every entitlement
+    /// only ever grows and together they never exceed `released`.
+    fn share_entitlement(stream: &Stream, shares: &Vec<Beneficiary>, index: u32, released: i128) -> i128 {
+        let last = index + 1 == shares.len();
+        if last && released >= stream.total_amount {
+            let others: i128 = shares.iter()
+                .take(index as usize)
+                .map(|b| bps_of(stream.total_amount, b.share_bps))
+                .sum();
+            return stream.total_amount - others;
+        }
+        bps_of(released, shares.get(index).unwrap().share_bps)
+    }
+
+    /// Settle each beneficiary (or just `only`) up to its entitlement out of
+    /// `released`, through the same path as a withdrawal. Returns the
+    /// updated stream and the net payouts; the caller transfers them once
+    /// its own state is written.
+    fn settle_shares(
+        env: &Env,
+        mut stream: Stream,
+        released: i128,
+        caller: WithdrawCaller,
+        only: Option<&Address>,
+    ) -> (Stream, Vec<(Address, i128)>) {
+        let stream_id = stream.id;
+        let key = StreamKey::Beneficiaries(stream_id);
+        let mut shares: Vec<Beneficiary> = env.storage().persistent().get(&key).unwrap();
+        let mut payouts = Vec::new(env);
+        for index in 0..shares.len() {
+            let mut entry = shares.get(index).unwrap();
+            if only.is_some_and(|address| *address != entry.address) {
+                continue;
+            }
+            let owed = Self::share_entitlement(&stream, &shares, index, released) - entry.withdrawn;
+            if owed <= 0 {
+                continue;
+            }
+            let sponsor = Self::collect_sponsored_fee(env, &stream, owed);
+            let net_amount = Self::settle_to_recipient(env, stream, owed, caller, sponsor, Some(&entry.address)).0;
+            stream = Self::read_stream(env, stream_id);
+            entry.withdrawn += owed;
+            payouts.push_back((entry.address.clone(), net_amount));
+            shares.set(index, entry);
+        }
+        env.storage().persistent().set(&key, &shares);
+        Self::extend_persistent(env, &key);
+        (stream, payouts)
+    }
+
+    fn pay_out_all(env: &Env, stream: &Stream, payouts: &Vec<(Address, i128)>) {
+        let token_client = token::Client::new(env, &stream.token);
+        for (payee, amount) in payouts.iter() {
+            if amount > 0 {
+                Self::pay_out(env, &token_client, &payee, amount, Some(stream.id));
+            }
+        }
+    }
+
+    /// Require at least `interval` seconds between the stream's withdrawals
+    /// (sender only); 0 removes the limit. Once the stream has ended,
+    /// withdrawals are always allowed.
+    pub fn set_min_withdraw_interval(env: Env, stream_id: u64, interval: u64) {
+        let stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+
+        let key = StreamKey::MinWithdrawInterval(stream_id);
+        if interval == 0 {
+            env.storage().persistent().remove(&key);
+        } else {
+            env.storage().persistent().set(&key, &interval);
+            Self::extend_persistent(&env, &key);
+        }
+    }
+
+    pub fn get_min_withdraw_interval(env: Env, stream_id: u64) -> u64 {
+        env.storage().persistent().get_or(&StreamKey::MinWithdrawInterval(stream_id), 0)
+    }
+
+    /// Earliest time the next withdrawal is accepted, for clients to
+    /// schedule around `WithdrawTooSoon`. 0 when there is no wait: no
+    /// interval set, no withdrawal yet, or the stream has ended.
+    pub fn get_next_withdrawal_time(env: Env, stream_id: u64) -> u64 {
+        let interval = Self::get_min_withdraw_interval(env.clone(), stream_id);
+        let stream = Self::read_stream(&env, stream_id);
+        let metrics = Self::get_stream_metrics(env.clone(), stream_id);
+        if interval == 0 || metrics.withdrawal_count == 0 || Self::stream_clock(&env, stream_id) >= stream.end_time {
+            return 0;
+        }
+        metrics.last_withdrawal_time.saturating_add(interval)
+    }
+
+    /// Withdraw the maximum available amount from a stream
+    pub fn withdraw_max(env: Env, stream_id: u64) {
+        let available = Self::withdrawable_amount(env.clone(), stream_id);
+        if available <= 0 {
+            panic_with_error!(&env, Error::InsufficientWithdrawable);
+        }
+        Self::withdraw(env, stream_id, available);
+    }
+
+    /// Withdraw everything available from several streams at once. The
+    /// caller must be the recipient (or delegate) of every stream; streams
+    /// with nothing withdrawable yield 0. Streams with an auto-forward
+    /// target deposit into it and streams with a settlement adapter are
+    /// swapped, as `withdraw` would; other payouts are summed per token and
+    /// payee so each gets a single transfer. Returns per-stream net amounts.
+    pub fn withdraw_max_batch(env: Env, stream_ids: Vec<u64>) -> Vec<i128> {
+        if stream_ids.len() > MAX_WITHDRAW_BATCH {
+            panic_with_error!(&env, Error::BatchTooLarge);
+        }
+        Self::assert_not_paused(&env);
+
+        let mut net_amounts = Vec::new(&env);
+        let mut payouts: Map<(Address, Address), i128> = Map::new(&env);
+        let mut locked = Vec::new(&env);
+        let mut notifications: Vec<(u64, Address, i128, Address)> = Vec::new(&env);
+        // Streams with a settlement adapter are swapped one by one
+        let mut swaps: Vec<(u64, Address, i128, Address, bool)> = Vec::new(&env);
+        // Fee-on-transfer streams are paid one by one so a transfer fee is
+        // booked against the stream that incurred it
+        let mut direct: Vec<(u64, Address, i128, Address)> = Vec::new(&env);
+
+        for stream_id in stream_ids.iter() {
+            let stream: Stream = Self::read_stream(&env, stream_id);
+            let caller = Self::assert_is_recipient_or_delegate(&env, &stream);
+
+            let available = Self::compute_withdrawable(&env, &stream);
+            if Self::check_withdrawal(&env, &stream, available).is_err() {
+                net_amounts.push_back(0);
+                continue;
+            }
+
+            Self::enter_stream(&env, stream_id);
+            locked.push_back(stream_id);
+
+            let token = stream.token.clone();
+            let recipient = stream.recipient.clone();
+            let notify = stream.notify_recipient;
+            let fee_on_transfer = Self::allows_fee_on_transfer(env.clone(), stream_id);
+            let sponsor = Self::collect_sponsored_fee(&env, &stream, available);
+            let (net_amount, tip) = Self::settle_to_recipient(&env, stream, available, caller, sponsor, None);
+            net_amounts.push_back(net_amount);
+
+            // Auto-forwarding applies as it does to a single withdrawal
+            let forwarded = net_amount > 0
+                && Self::get_auto_forward(env.clone(), stream_id).is_some_and(|target_id| {
+                    Self::forward_withdrawal(&env, stream_id, target_id, &recipient, &token, net_amount)
+                });
+            let adapted = net_amount > 0
+                && !forwarded
+                && env.storage().persistent().has(&DataKey::SettlementAdapter(stream_id));
+            if adapted {
+                swaps.push_back((stream_id, recipient.clone(), net_amount, token.clone(), notify));
+            } else if !forwarded {
+                if notify {
+                    notifications.push_back((stream_id, recipient.clone(), net_amount, token.clone()));
+                }
+                if fee_on_transfer {
+                    direct.push_back((stream_id, recipient, net_amount, token.clone()));
+                } else {
+                    let key = (token.clone(), recipient);
+                    payouts.set(key.clone(), payouts.get(key).unwrap_or(0) + net_amount);
+                }
+            }
+            if let Some((delegate, tip)) = tip {
+                if fee_on_transfer {
+                    direct.push_back((stream_id, delegate, tip, token.clone()));
+                } else {
+                    let key = (token.clone(), delegate);
+                    payouts.set(key.clone(), payouts.get(key).unwrap_or(0) + tip);
+                }
+            }
+        }
+
+        // All state is final at this point; token calls come last
+        for ((token, payee), amount) in payouts.iter() {
+            if amount > 0 {
+                Self::pay_out(&env, &token::Client::new(&env, &token), &payee, amount, None);
+            }
+        }
+        for (stream_id, payee, amount, token) in direct.iter() {
+            if amount > 0 {
+                Self::pay_out(&env, &token::Client::new(&env, &token), &payee, amount, Some(stream_id));
+            }
+        }
+        for (stream_id, recipient, amount, token, notify) in swaps.iter() {
+            if !Self::settle_via_adapter(&env, stream_id, &token, &recipient, amount) {
+                Self::pay_out(&env, &token::Client::new(&env, &token), &recipient, amount, Some(stream_id));
+                if notify {
+                    notifications.push_back((stream_id, recipient, amount, token));
+                }
+            }
+        }
+
+        for stream_id in locked.iter() {
+            Self::exit_stream(&env, stream_id);
+        }
+        for (stream_id, recipient, amount, token) in notifications.iter() {
+            Self::notify_recipient(&env, stream_id, &recipient, amount, &token);
+        }
+        net_amounts
+    }
+
+    /// Pause a stream (sender only)
+    pub fn pause_stream(env: Env, stream_id: u64) {
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if !stream.cancelable {
+            panic_with_error!(&env, Error::StreamNotCancelable);
+        }
+        if stream.status != StreamStatus::Active {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+        if stream.max_pause_seconds.is_some_and(|budget| stream.total_paused_duration >= budget) {
+            panic_with_error!(&env, Error::PauseBudgetExceeded);
+        }
+
+        let current_time = env.ledger().timestamp();
+        let paused_at = Self::stream_clock(&env, stream_id);
+        
+        Self::set_status(&env, &mut stream, StreamStatus::Paused);
+        stream.paused_at = Some(paused_at);
+
+        Self::store_stream(&env, &stream, "pause", 0, &stream.sender);
+
+        // Update stream metrics
+        let mut metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
+
+        metrics.pause_count += 1;
+        metrics.last_activity = current_time;
+
+        Self::store_stream_metrics(&env, stream_id, &metrics);
+
+        // Emit StreamPaused event
+        env.events().publish(
+            ("StreamPaused", stream_id),
+            StreamPausedEvent {
+                stream_id,
+                paused_at,
+            },
+        );
+    }
+
+    /// Resume a paused stream (sender only)
+    pub fn resume_stream(env: Env, stream_id: u64) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamNotPaused);
+        }
+
+        let sender = stream.sender.clone();
+        Self::apply_resume(&env, stream, &sender);
+    }
+
+    /// Resume a stream whose current pause has outlasted what was left of
+    /// its pause budget. Anyone can call this.
+    pub fn force_resume(env: Env, stream_id: u64) {
+        let stream: Stream = Self::read_stream(&env, stream_id);
+
+        if stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamNotPaused);
+        }
+        let Some(budget) = stream.max_pause_seconds else {
+            panic_with_error!(&env, Error::PauseWithinBudget);
+        };
+        let remaining = budget.saturating_sub(stream.total_paused_duration);
+        let paused_for = Self::stream_clock(&env, stream_id).saturating_sub(stream.paused_at.unwrap_or(u64::MAX));
+        if paused_for <= remaining {
+            panic_with_error!(&env, Error::PauseWithinBudget);
+        }
+
+        let paused_duration = Self::apply_resume(&env, stream, &env.current_contract_address());
+        env.events().publish(
+            ("StreamForceResumed", stream_id),
+            StreamForceResumedEvent {
+                stream_id,
+                resumed_at: env.ledger().timestamp(),
+                paused_duration,
+                max_pause_seconds: budget,
+            },
+        );
+    }
+
+    /// Shared resume logic: count the pause, push the end time back by it
+    /// and reactivate the stream. Callers handle auth and status checks;
+    /// `actor` is logged as the resuming party (this contract for
+    /// `force_resume`). Returns how long this pause lasted.
+    fn apply_resume(env: &Env, mut stream: Stream, actor: &Address) -> u64 {
+        let stream_id = stream.id;
+        let current_time = env.ledger().timestamp();
+        
+        // Calculate pause duration
+        let paused_duration = if let Some(paused_at) = stream.paused_at {
+            Self::stream_clock(env, stream_id).saturating_sub(paused_at)
+        } else {
+            0
+        };
+
+        // Update total paused duration
+        stream.total_paused_duration += paused_duration;
+        
+        // Extend end_time by the paused duration
+        stream.end_time += paused_duration;
+        
+        Self::set_status(env, &mut stream, StreamStatus::Active);
+        stream.paused_at = None;
+
+        Self::store_stream(env, &stream, "resume", 0, actor);
+
+        // Update stream metrics
+        let mut metrics = Self::load_stream_metrics(env, stream_id, stream.withdrawn_amount);
+
+        metrics.last_activity = current_time;
+
+        Self::store_stream_metrics(env, stream_id, &metrics);
+
+        // Emit StreamResumed event
+        env.events().publish(
+            ("StreamResumed", stream_id),
+            StreamResumedEvent {
+                stream_id,
+                resumed_at: current_time,
+                paused_duration,
+            },
+        );
+        paused_duration
+    }
+
+    /// Cancel a stream. The recipient is paid whatever has vested and been
+    /// funded, less the same fee a withdrawal would charge, and the rest is
+    /// refunded to the funders.
+    pub fn cancel_stream(env: Env, stream_id: u64) {
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if !stream.cancelable {
+            panic_with_error!(&env, Error::StreamNotCancelable);
+        }
+        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamCannotBeCanceled);
+        }
+        Self::enter_stream(&env, stream_id);
+        
+        let was_active = stream.status == StreamStatus::Active;
+        let vested_as_of = stream.paused_at.filter(|_| !was_active).unwrap_or(Self::stream_clock(&env, stream_id));
+        let vested = Self::vested_amount(&env, &stream, vested_as_of);
+        // Approved milestones stay in escrow for the recipient to withdraw.
+        // Time-based streams pay the recipient what has vested and been
+        // funded, charged the same fee as a withdrawal.
+        let (owed, due) = match Self::milestone_total(&env, stream_id) {
+            Some(approved) => (approved - stream.withdrawn_amount, 0),
+            None if Self::claim_window_expired(&env, &stream) => (0, 0),
+            None => (0, (vested.min(stream.balance) - stream.withdrawn_amount).max(0)),
+        };
+        let mut payouts = Vec::new(&env);
+        if due > 0 && Self::is_shared(&env, stream_id) {
+            let released = vested.min(stream.balance);
+            (stream, payouts) = Self::settle_shares(&env, stream, released, WithdrawCaller::Sender, None);
+        } else if due > 0 {
+            let recipient = stream.recipient.clone();
+            let sponsor = Self::collect_sponsored_fee(&env, &stream, due);
+            let settled = Self::settle_to_recipient(&env, stream, due, WithdrawCaller::Sender, sponsor, None).0;
+            payouts.push_back((recipient, settled));
+            stream = Self::read_stream(&env, stream_id);
+        }
+        Self::set_status(&env, &mut stream, StreamStatus::Canceled);
+        stream.paused_at = None;
+
+        let remaining = (stream.balance - stream.withdrawn_amount - owed).max(0);
+        Self::store_stream(&env, &stream, "cancel", remaining, &stream.sender);
+
+        // Update stream metrics
+        let mut metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
+
+        metrics.last_activity = env.ledger().timestamp();
+
+        Self::store_stream_metrics(&env, stream_id, &metrics);
+
+        Self::release_commitment(&env, &stream);
+        // Refund remaining tokens to sender
+        Self::update_token_metrics(&env, &stream.token, |m| m.escrowed_balance -= remaining);
+        Self::adjust_sender_tvl(&env, &stream.sender, -remaining);
+        Self::pay_out_all(&env, &stream, &payouts);
+        Self::refund_funders(&env, &stream, remaining);
+
+        Self::exit_stream(&env, stream_id);
+    }
+
+    /// Turn down a stream (recipient only), e.g. unsolicited tokens. Only
+    /// possible before anything has been withdrawn from it (`AlreadyAccepted`
+    /// otherwise). The whole escrow goes back to the funders regardless of
+    /// vesting, the stream ends up `Rejected` and it is dropped from the
+    /// recipient's stream index. Returns the amount refunded.
+    pub fn reject_stream(env: Env, stream_id: u64) -> i128 {
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+        stream.recipient.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if Self::is_shared(&env, stream_id) {
+            panic_with_error!(&env, Error::SharedStream);
+        }
+        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamNotActive);
         }
+        if stream.withdrawn_amount > 0 {
+            panic_with_error!(&env, ErrorExt::AlreadyAccepted);
+        }
+        Self::enter_stream(&env, stream_id);
+
+        let remaining = stream.balance.max(0);
+        Self::set_status(&env, &mut stream, StreamStatus::Rejected);
+        stream.paused_at = None;
 
-        let new_balance = stream.balance.checked_add(amount)
-            .unwrap_or_else(|| panic_with_error!(&env, Error::ArithmeticOverflow));
+        Self::store_stream(&env, &stream, "reject", remaining, &stream.recipient);
+        Self::unindex_recipient_stream(&env, &stream.recipient, stream_id);
 
-        if new_balance > stream.total_amount {
-            panic_with_error!(&env, Error::DepositExceedsTotal);
-        }
+        let mut metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
+        metrics.last_activity = env.ledger().timestamp();
+        Self::store_stream_metrics(&env, stream_id, &metrics);
 
-        // Transfer tokens from sender to contract
-        let token_client = token::Client::new(&env, &stream.token);
-        token_client.transfer(&stream.sender, &env.current_contract_address(), &amount);
+        Self::release_commitment(&env, &stream);
+        Self::update_token_metrics(&env, &stream.token, |m| m.escrowed_balance -= remaining);
+        Self::adjust_sender_tvl(&env, &stream.sender, -remaining);
 
-        // Update balance
-        stream.balance = new_balance;
-        env.storage().persistent().set(&stream_id, &stream);
-        env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
+        Self::refund_funders(&env, &stream, remaining);
 
-        // Update stream metrics
-        let mut metrics: StreamMetrics = env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+        env.events().publish(
+            ("StreamRejected", stream_id),
+            StreamRejectedEvent { stream_id, recipient: stream.recipient, refunded: remaining },
+        );
+        Self::exit_stream(&env, stream_id);
+        remaining
+    }
+
+    /// Once the claim deadline has passed, return everything still in
+    /// escrow, vested or not, to the stream's funders (sender only) and
+    /// mark the stream Completed. Returns the amount reclaimed.
+    pub fn reclaim_stale(env: Env, stream_id: u64) -> i128 {
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+        stream.sender.require_auth();
+
+        Self::assert_not_quarantined(&env, &stream);
+        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+        if !Self::claim_window_expired(&env, &stream) {
+            panic_with_error!(&env, Error::ClaimWindowOpen);
+        }
+        Self::enter_stream(&env, stream_id);
+
+        let remaining = (stream.balance - stream.withdrawn_amount).max(0);
+        Self::set_status(&env, &mut stream, StreamStatus::Completed);
+        stream.paused_at = None;
 
+        Self::store_stream(&env, &stream, "reclaim", remaining, &stream.sender);
+
+        let mut metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
         metrics.last_activity = env.ledger().timestamp();
+        Self::store_stream_metrics(&env, stream_id, &metrics);
 
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+        Self::release_commitment(&env, &stream);
+        Self::update_token_metrics(&env, &stream.token, |m| m.escrowed_balance -= remaining);
+        Self::adjust_sender_tvl(&env, &stream.sender, -remaining);
 
-        // Emit StreamDeposit event
-        env.events().publish(("StreamDeposit", stream_id), StreamDepositEvent { stream_id, amount });
+        Self::refund_funders(&env, &stream, remaining);
+
+        env.events().publish(
+            ("StaleFundsReclaimed", stream_id),
+            StaleFundsReclaimedEvent { stream_id, sender: stream.sender, amount: remaining },
+        );
+        Self::exit_stream(&env, stream_id);
+        remaining
     }
 
-    /// Get stream details
-    pub fn get_stream(env: Env, stream_id: u64) -> Stream {
-        match env.storage().persistent().get(&stream_id) {
-            Some(stream) => {
-                env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
-                stream
-            },
-            None => panic_with_error!(&env, Error::StreamNotFound),
+    /// Freeze a stream flagged for compliance review (admin only). Nothing
+    /// can be withdrawn, deposited, canceled or reassigned until
+    /// `release_quarantine`; vesting stops as it would for a pause.
+    pub fn quarantine_stream(env: Env, stream_id: u64, reason: Symbol) {
+        let admin = Self::require_admin(&env, "quarantine", stream_id as i128);
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+
+        Self::assert_not_quarantined(&env, &stream);
+        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
+            panic_with_error!(&env, Error::StreamNotActive);
+        }
+
+        let previous_status = stream.status;
+        let info = QuarantineInfo {
+            reason: reason.clone(),
+            previous_status,
+            quarantined_at: env.ledger().timestamp(),
+        };
+        let key = DataKey::Quarantine(stream_id);
+        env.storage().persistent().set(&key, &info);
+        Self::extend_persistent(&env, &key);
+
+        if previous_status == StreamStatus::Active {
+            stream.paused_at = Some(Self::stream_clock(&env, stream_id));
         }
+        Self::set_status(&env, &mut stream, StreamStatus::Quarantined);
+        Self::store_stream(&env, &stream, "quarantine", 0, &admin);
+
+        env.events().publish(
+            ("StreamQuarantined", stream_id),
+            StreamQuarantinedEvent { stream_id, reason, previous_status },
+        );
     }
 
-    /// Helper function to create default stream metrics
-    fn default_stream_metrics(env: &Env) -> StreamMetrics {
-        StreamMetrics {
-            last_activity: env.ledger().timestamp(),
-            total_withdrawn: 0,
-            withdrawal_count: 0,
-            pause_count: 0,
-            total_delegations: 0,
-            current_delegate: None,
-            last_delegation_time: 0,
+    /// Close out a quarantine (admin only): refund the escrow to the
+    /// funders, pay it to the recipient, or put the stream back as it was.
+    /// Returns the amount that left escrow.
+    pub fn release_quarantine(env: Env, stream_id: u64, resolution: QuarantineResolution) -> i128 {
+        let admin = Self::require_admin(&env, "release_quarantine", resolution as i128);
+        let mut stream: Stream = Self::read_stream(&env, stream_id);
+
+        let key = DataKey::Quarantine(stream_id);
+        let info: QuarantineInfo = env.storage().persistent()
+            .get(&key)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotQuarantined));
+        env.storage().persistent().remove(&key);
+        Self::enter_stream(&env, stream_id);
+
+        let remaining = (stream.balance - stream.withdrawn_amount).max(0);
+        let amount = match resolution {
+            QuarantineResolution::Resume => {
+                Self::set_status(&env, &mut stream, StreamStatus::Paused);
+                if info.previous_status == StreamStatus::Active {
+                    Self::apply_resume(&env, stream, &admin);
+                } else {
+                    Self::store_stream(&env, &stream, "release", 0, &admin);
+                }
+                0
+            },
+            QuarantineResolution::ReturnToSender => {
+                Self::set_status(&env, &mut stream, StreamStatus::Canceled);
+                stream.paused_at = None;
+                Self::store_stream(&env, &stream, "release", remaining, &admin);
+
+                Self::release_commitment(&env, &stream);
+                Self::update_token_metrics(&env, &stream.token, |m| m.escrowed_balance -= remaining);
+                Self::adjust_sender_tvl(&env, &stream.sender, -remaining);
+
+                Self::refund_funders(&env, &stream, remaining);
+                remaining
+            },
+            QuarantineResolution::ReleaseToRecipient => {
+                let mut payouts = Vec::new(&env);
+                if remaining > 0 && Self::is_shared(&env, stream_id) {
+                    let released = stream.balance;
+                    (stream, payouts) = Self::settle_shares(&env, stream, released, WithdrawCaller::Sender, None);
+                } else if remaining > 0 {
+                    let recipient = stream.recipient.clone();
+                    let sponsor = Self::collect_sponsored_fee(&env, &stream, remaining);
+                    let settled = Self::settle_to_recipient(&env, stream, remaining, WithdrawCaller::Sender, sponsor, None).0;
+                    payouts.push_back((recipient, settled));
+                    stream = Self::read_stream(&env, stream_id);
+                }
+                Self::set_status(&env, &mut stream, StreamStatus::Completed);
+                stream.paused_at = None;
+                Self::store_stream(&env, &stream, "release", 0, &admin);
+
+                Self::release_commitment(&env, &stream);
+
+                // Rounding dust a shared stream's shares leave behind goes
+                // back to the funders
+                let dust = (stream.balance - stream.withdrawn_amount).max(0);
+                Self::update_token_metrics(&env, &stream.token, |m| m.escrowed_balance -= dust);
+                Self::adjust_sender_tvl(&env, &stream.sender, -dust);
+
+                Self::pay_out_all(&env, &stream, &payouts);
+                Self::refund_funders(&env, &stream, dust);
+                remaining
+            },
+        };
+
+        env.events().publish(
+            ("QuarantineReleased", stream_id),
+            QuarantineReleasedEvent { stream_id, reason: info.reason, resolution, amount },
+        );
+        Self::exit_stream(&env, stream_id);
+        amount
+    }
+
+    /// Quarantine details while a stream is held, `None` otherwise
+    pub fn get_quarantine(env: Env, stream_id: u64) -> Option<QuarantineInfo> {
+        env.storage().persistent().get(&DataKey::Quarantine(stream_id))
+    }
+
+    fn assert_not_quarantined(env: &Env, stream: &Stream) {
+        if stream.status == StreamStatus::Quarantined {
+            panic_with_error!(env, Error::StreamQuarantined);
         }
     }
 
-    /// Assert that the caller is authorized to withdraw (recipient or delegate).
-    fn assert_is_recipient_or_delegate(env: &Env, stream_id: u64) {
-        let stream: Stream = Self::get_stream(env.clone(), stream_id);
-        
-        // First, check if a delegate is set and try to require auth from them
-        let delegate_opt: Option<Address> = env.storage().persistent().get(&(stream_id, Symbol::new(env, "delegate")));
-        
-        if let Some(delegate) = delegate_opt {
-            // If delegate exists, require auth from delegate (they're the one calling)
-            delegate.require_auth();
-        } else {
-            // No delegate, require auth from recipient
-            stream.recipient.require_auth();
+    /// Return `remaining` escrow to whoever funded the stream: the sender,
+    /// or every contributor pro rata once sponsors have deposited
+    fn refund_funders(env: &Env, stream: &Stream, remaining: i128) {
+        if remaining <= 0 {
+            return;
         }
+        Self::ensure_liquid(env, &stream.token, remaining);
+        let token_client = token::Client::new(env, &stream.token);
+        let ledger: Option<Vec<(Address, i128)>> = env.storage().persistent().get(&DataKey::Contributors(stream.id));
+        let held = Self::held(env, &token_client);
+        match ledger {
+            Some(ledger) => Self::refund_contributors(env, &token_client, &stream.sender, &ledger, remaining),
+            None => token_client.transfer(&env.current_contract_address(), &stream.sender, &remaining),
+        }
+        Self::check_transfer(env, &token_client, Some(stream.id), held, -remaining);
     }
 
-    /// Set a delegate for withdrawal rights on a stream
-    pub fn set_delegate(env: Env, stream_id: u64, delegate: Address) {
-        let stream: Stream = Self::get_stream(env.clone(), stream_id);
-        stream.recipient.require_auth();
-    
-        // Prevent self-delegation
-        if delegate == stream.recipient {
-            panic_with_error!(&env, Error::InvalidDelegate);
+    /// Split a cancel refund across the stream's funders in proportion to
+    /// what each contributed. Rounding dust goes to the sender.
+    fn refund_contributors(
+        env: &Env,
+        token_client: &token::Client,
+        sender: &Address,
+        ledger: &Vec<(Address, i128)>,
+        remaining: i128,
+    ) {
+        let contributed: i128 = ledger.iter().map(|(_, amount)| amount).sum();
+        let mut refunds: Map<Address, i128> = Map::new(env);
+        let mut refunded = 0;
+        for (funder, amount) in ledger.iter() {
+            let share = remaining * amount / contributed;
+            refunds.set(funder, share);
+            refunded += share;
         }
+        refunds.set(sender.clone(), refunds.get(sender.clone()).unwrap_or(0) + remaining - refunded);
 
-        // Check if there's an existing delegate and emit revocation event
-        let delegate_key = (stream_id, Symbol::new(&env, "delegate"));
-        if let Some(old_delegate) = env.storage().persistent().get::<_, Address>(&delegate_key) {
-            if old_delegate != delegate {
-                let revoke_event = DelegationRevokedEvent {
-                    stream_id,
-                    recipient: stream.recipient.clone(),
-                };
-                env.events().publish(("DelegationRevoked", stream_id), revoke_event);
+        for (funder, share) in refunds.iter() {
+            if share > 0 {
+                token_client.transfer(&env.current_contract_address(), &funder, &share);
             }
         }
+    }
 
-        let current_time = env.ledger().timestamp();
+    /// Send tokens held beyond what streams have escrowed (e.g. sent straight
+    /// to the contract) to `to` (admin only). Returns the amount swept.
+    pub fn sweep_excess(env: Env, token: Address, to: Address) -> i128 {
+        let escrowed = Self::get_token_metrics(env.clone(), token.clone()).escrowed_balance
+            + Self::get_accrued_fees(env.clone(), token.clone());
+        let token_client = token::Client::new(&env, &token);
+        let held = token_client.balance(&env.current_contract_address()) + Self::get_yield_deposited(env.clone(), token.clone());
+        let excess = held - escrowed;
+        Self::require_admin(&env, "sweep", excess.max(0));
+        if excess <= 0 {
+            return 0;
+        }
 
-        // Store delegate
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "delegate")), &delegate);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "delegate")), LEDGER_THRESHOLD, LEDGER_BUMP);
+        Self::ensure_liquid(&env, &token, excess);
+        token_client.transfer(&env.current_contract_address(), &to, &excess);
+        env.events().publish(("ExcessSwept",), ExcessSweptEvent { token, to, amount: excess });
+        excess
+    }
 
-        // Update stream metrics
-        let mut metrics: StreamMetrics = env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+    /// Park newly escrowed `token` in an external yield contract (admin
+    /// only). From then on deposits into streams are sent to `adapter`
+    /// and its `deposit(amount)` is called; payouts that need more than the
+    /// contract holds call its `withdraw(amount)`, which must send the
+    /// tokens back. Funds parked with a previous adapter are recalled first.
+    pub fn set_yield_adapter(env: Env, token: Address, adapter: Address) {
+        Self::require_admin(&env, "set_yield_adapter", 0);
+        Self::recall_all_from_yield(&env, &token);
 
-        metrics.total_delegations += 1;
-        metrics.current_delegate = Some(delegate.clone());
-        metrics.last_delegation_time = current_time;
-        metrics.last_activity = current_time;
+        let key = DataKey::YieldAdapter(token);
+        env.storage().persistent().set(&key, &adapter);
+        Self::extend_persistent(&env, &key);
+    }
 
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// Recall everything parked for `token` and stop using its adapter
+    /// (admin only)
+    pub fn remove_yield_adapter(env: Env, token: Address) {
+        Self::require_admin(&env, "remove_yield_adapter", 0);
+        Self::recall_all_from_yield(&env, &token);
+        env.storage().persistent().remove(&DataKey::YieldAdapter(token));
+    }
 
-        // Update protocol metrics
-        let mut protocol_metrics: ProtocolMetrics = env.storage().instance()
-            .get(&Symbol::new(&env, "protocol_metrics"))
-            .unwrap();
-        protocol_metrics.total_delegations += 1;
-        env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &protocol_metrics);
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+    pub fn get_yield_adapter(env: Env, token: Address) -> Option<Address> {
+        env.storage().persistent().get(&DataKey::YieldAdapter(token))
+    }
 
-        // Emit event
-        let event = DelegationGrantedEvent {
-            stream_id,
-            recipient: stream.recipient,
-            delegate: delegate.clone(),
+    /// Escrowed `token` currently parked with its yield adapter
+    pub fn get_yield_deposited(env: Env, token: Address) -> i128 {
+        env.storage().persistent().get_or(&DataKey::YieldDeposited(token), 0)
+    }
+
+    /// Send `amount` just escrowed to the token's yield adapter, if any
+    fn park_in_yield(env: &Env, token: &Address, amount: i128) {
+        let Some(adapter) = Self::get_yield_adapter(env.clone(), token.clone()) else {
+            return;
         };
-        env.events().publish(("DelegationGranted", stream_id), event);
+        let sent = token::Client::new(env, token).try_transfer(&env.current_contract_address(), &adapter, &amount);
+        let deposited = env.try_invoke_contract::<(), soroban_sdk::Error>(
+            &adapter,
+            &Symbol::new(env, "deposit"),
+            (amount,).into_val(env),
+        );
+        if !matches!(sent, Ok(Ok(()))) || !matches!(deposited, Ok(Ok(()))) {
+            panic_with_error!(env, Error::YieldAdapterError);
+        }
+        Self::set_yield_deposited(env, token, Self::get_yield_deposited(env.clone(), token.clone()) + amount);
     }
 
-    /// Revoke the delegate for a stream
-    pub fn revoke_delegate(env: Env, stream_id: u64) {
-        let stream: Stream = Self::get_stream(env.clone(), stream_id);
-        stream.recipient.require_auth();
+    /// Make sure the contract holds at least `amount` of `token`, pulling
+    /// the shortfall back from the yield adapter
+    fn ensure_liquid(env: &Env, token: &Address, amount: i128) {
+        let parked = Self::get_yield_deposited(env.clone(), token.clone());
+        if parked <= 0 {
+            return;
+        }
+        let liquid = token::Client::new(env, token).balance(&env.current_contract_address());
+        let shortfall = (amount - liquid).min(parked);
+        if shortfall > 0 {
+            Self::recall_from_yield(env, token, shortfall);
+        }
+    }
 
-        let delegate_key = (stream_id, Symbol::new(&env, "delegate"));
-        let had_delegate = env.storage().persistent().has(&delegate_key);
+    fn recall_all_from_yield(env: &Env, token: &Address) {
+        let parked = Self::get_yield_deposited(env.clone(), token.clone());
+        if parked > 0 {
+            Self::recall_from_yield(env, token, parked);
+        }
+    }
 
-        // Remove delegate
-        env.storage().persistent().remove(&delegate_key);
+    /// Call the adapter's `withdraw(amount)` and check the tokens arrived
+    fn recall_from_yield(env: &Env, token: &Address, amount: i128) {
+        let adapter = Self::get_yield_adapter(env.clone(), token.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::YieldAdapterError));
+        let token_client = token::Client::new(env, token);
+        let before = token_client.balance(&env.current_contract_address());
+        let result = env.try_invoke_contract::<(), soroban_sdk::Error>(
+            &adapter,
+            &Symbol::new(env, "withdraw"),
+            (amount,).into_val(env),
+        );
+        let received = token_client.balance(&env.current_contract_address()) - before;
+        if !matches!(result, Ok(Ok(()))) || received < amount {
+            panic_with_error!(env, Error::YieldAdapterError);
+        }
+        Self::set_yield_deposited(env, token, Self::get_yield_deposited(env.clone(), token.clone()) - amount);
+    }
 
-        // Update stream metrics
-        if had_delegate {
-            let mut metrics: StreamMetrics = env.storage().persistent()
-                .get(&(stream_id, Symbol::new(&env, "metrics")))
-                .unwrap_or_else(|| Self::default_stream_metrics(&env));
+    fn set_yield_deposited(env: &Env, token: &Address, amount: i128) {
+        let key = DataKey::YieldDeposited(token.clone());
+        env.storage().persistent().set(&key, &amount);
+        Self::extend_persistent(env, &key);
+    }
 
-            metrics.current_delegate = None;
-            metrics.last_activity = env.ledger().timestamp();
+    /// Read the admin, failing with NotInitialized before `initialize`
+    fn read_admin(env: &Env) -> Address {
+        env.storage().instance()
+            .get(&DataKey::Admin)
+            .unwrap_or_else(|| panic_with_error!(env, Error::NotInitialized))
+    }
+
+    /// Require the admin's auth and log `action` to the admin audit trail
+    fn require_admin(env: &Env, action: &str, detail: i128) -> Address {
+        let admin = Self::read_admin(env);
+        admin.require_auth();
+        Self::record_admin_action(env, action, &admin, detail);
+        admin
+    }
+
+    /// Append to the admin audit trail. Every privileged entrypoint reaches
+    /// this through `require_admin` or `require_role`.
+    fn record_admin_action(env: &Env, action: &str, actor: &Address, detail: i128) {
+        let index: u64 = env.storage().instance().get_or(&DataKey::AdminActionCount, 0);
+        env.storage().instance().set(&DataKey::AdminActionCount, &(index + 1));
+        Self::extend_instance(env);
+
+        let entry = AdminAction {
+            action: Symbol::new(env, action),
+            actor: actor.clone(),
+            timestamp: env.ledger().timestamp(),
+            detail,
+        };
+        let key = DataKey::AdminAction(index);
+        env.storage().persistent().set(&key, &entry);
+        Self::extend_persistent(env, &key);
+    }
+
+    /// Admin audit trail, newest first: `offset` 0 is the latest entry.
+    /// At most 50 entries per call.
+    pub fn get_admin_actions(env: Env, offset: u64, limit: u32) -> Vec<AdminAction> {
+        let count: u64 = env.storage().instance().get_or(&DataKey::AdminActionCount, 0);
+        let end = count.saturating_sub(offset);
+        let start = end.saturating_sub(limit.min(MAX_ADMIN_ACTION_PAGE) as u64);
+
+        let mut actions = Vec::new(&env);
+        for index in (start..end).rev() {
+            let key = DataKey::AdminAction(index);
+            if let Some(entry) = env.storage().persistent().get::<_, AdminAction>(&key) {
+                Self::extend_persistent(&env, &key);
+                actions.push_back(entry);
+            }
+        }
+        actions
+    }
+
+    /// Grant `role` (`pauser`, `fee_manager` or `upgrader`) to `who` (admin only)
+    pub fn grant_role(env: Env, role: Symbol, who: Address) {
+        Self::require_admin(&env, "grant_role", 0);
+        Self::validate_role(&env, &role);
+
+        let key = DataKey::Role(role.clone(), who.clone());
+        env.storage().persistent().set(&key, &true);
+        Self::extend_persistent(&env, &key);
+
+        env.events().publish(("RoleGranted",), RoleChangedEvent { role, account: who });
+    }
+
+    /// Take `role` away from `who` (admin only)
+    pub fn revoke_role(env: Env, role: Symbol, who: Address) {
+        Self::require_admin(&env, "revoke_role", 0);
+        Self::validate_role(&env, &role);
+
+        env.storage().persistent().remove(&DataKey::Role(role.clone(), who.clone()));
+
+        env.events().publish(("RoleRevoked",), RoleChangedEvent { role, account: who });
+    }
+
+    /// Whether `who` holds `role`. The admin holds every role.
+    pub fn has_role(env: Env, role: Symbol, who: Address) -> bool {
+        who == Self::read_admin(&env)
+            || env.storage().persistent().has(&DataKey::Role(role, who))
+    }
+
+    fn validate_role(env: &Env, role: &Symbol) {
+        let known = [ROLE_PAUSER, ROLE_FEE_MANAGER, ROLE_UPGRADER];
+        if !known.iter().any(|name| *role == Symbol::new(env, name)) {
+            panic_with_error!(env, Error::InvalidRole);
+        }
+    }
+
+    /// Require `caller`'s auth and that it holds `role`, then log `action`
+    /// to the admin audit trail
+    fn require_role(env: &Env, caller: &Address, role: &str, action: &str, detail: i128) {
+        caller.require_auth();
+        if !Self::has_role(env.clone(), Symbol::new(env, role), caller.clone()) {
+            panic_with_error!(env, Error::Unauthorized);
+        }
+        Self::record_admin_action(env, action, caller, detail);
+    }
+
+    /// Pause or resume stream creation, deposits and withdrawals contract-wide
+    /// (pauser). Cancellation stays available so senders can always exit.
+    pub fn set_paused(env: Env, caller: Address, paused: bool) {
+        Self::require_role(&env, &caller, ROLE_PAUSER, "set_paused", paused as i128);
+
+        env.storage().instance().set(&DataKey::Paused, &paused);
+        Self::extend_instance(&env);
+
+        env.events().publish(("ContractPaused",), ContractPausedEvent { paused, by: caller });
+    }
+
+    /// Whether the contract-wide pause is on
+    pub fn is_paused(env: Env) -> bool {
+        env.storage().instance().get_or(&DataKey::Paused, false)
+    }
+
+    fn assert_not_paused(env: &Env) {
+        if Self::is_paused(env.clone()) {
+            panic_with_error!(env, Error::ContractPaused);
+        }
+    }
+
+    /// Set the protocol fee rate. Takes effect immediately, so only
+    /// decreases are allowed here; increases go through
+    /// `propose_protocol_fee_rate`.
+    pub fn set_protocol_fee_rate(env: Env, caller: Address, new_fee_rate: u32) {
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "set_fee_rate", new_fee_rate as i128);
+
+        Self::validate_fee_rate(&env, new_fee_rate);
+        if new_fee_rate > Self::get_protocol_fee_rate(env.clone()) {
+            panic_with_error!(&env, Error::FeeIncreaseRequiresTimelock);
+        }
+
+        let old_rate = Self::write_fee_rate(&env, new_fee_rate);
+        env.events().publish(
+            ("ProtocolFeeRateChanged",),
+            ProtocolFeeRateChangedEvent { old_rate, new_rate: new_fee_rate },
+        );
+    }
+
+    /// Schedule a fee rate change that anyone can apply once `FEE_TIMELOCK`
+    /// (3 days) has passed. Replaces any earlier proposal.
+    pub fn propose_protocol_fee_rate(env: Env, caller: Address, new_rate: u32) {
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "propose_fee", new_rate as i128);
+
+        Self::validate_fee_rate(&env, new_rate);
+
+        let activates_at = env.ledger().timestamp() + FEE_TIMELOCK;
+        env.storage().instance().set(&DataKey::PendingFeeRate, &(new_rate, activates_at));
+        Self::extend_instance(&env);
+
+        env.events().publish(("FeeChangeProposed",), FeeChangeProposedEvent { new_rate, activates_at });
+    }
+
+    /// Activate the pending fee rate once its timelock has elapsed
+    pub fn apply_protocol_fee_rate(env: Env) {
+        let (new_rate, activates_at) = Self::get_pending_fee_change(env.clone())
+            .unwrap_or_else(|| panic_with_error!(&env, Error::NoPendingFeeChange));
+        if env.ledger().timestamp() < activates_at {
+            panic_with_error!(&env, Error::FeeTimelockNotElapsed);
+        }
+        // The collector may have been cleared since the proposal
+        Self::validate_fee_rate(&env, new_rate);
+
+        env.storage().instance().remove(&DataKey::PendingFeeRate);
+        let old_rate = Self::write_fee_rate(&env, new_rate);
+        env.events().publish(("FeeChangeApplied",), ProtocolFeeRateChangedEvent { old_rate, new_rate });
+    }
+
+    /// Drop the pending fee rate change (fee manager)
+    pub fn cancel_fee_proposal(env: Env, caller: Address) {
+        let (new_rate, activates_at) = Self::get_pending_fee_change(env.clone())
+            .unwrap_or_else(|| panic_with_error!(&env, Error::NoPendingFeeChange));
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "cancel_fee", new_rate as i128);
+        env.storage().instance().remove(&DataKey::PendingFeeRate);
+
+        env.events().publish(("FeeChangeCanceled",), FeeChangeProposedEvent { new_rate, activates_at });
+    }
+
+    /// Pending fee rate and the timestamp from which it can be applied
+    pub fn get_pending_fee_change(env: Env) -> Option<(u32, u64)> {
+        env.storage().instance().get(&DataKey::PendingFeeRate)
+    }
+
+    fn validate_fee_rate(env: &Env, rate: u32) {
+        Self::check_fee_config(env, FeeConfig { rate_bps: rate, collector: Self::get_fee_collector(env.clone()) });
+    }
+
+    fn check_fee_config(env: &Env, config: FeeConfig) {
+        match config.check() {
+            Ok(()) => {}
+            Err(FeeConfigError::RateTooHigh) => panic_with_error!(env, Error::FeeTooHigh),
+            Err(FeeConfigError::MissingCollector) => panic_with_error!(env, Error::InvalidConfiguration),
+        }
+    }
+
+    /// Store the new fee rate and return the previous one
+    fn write_fee_rate(env: &Env, new_rate: u32) -> u32 {
+        let old_rate = Self::get_protocol_fee_rate(env.clone());
+        env.storage().instance().set(&DataKey::FeeRate, &new_rate);
+        Self::extend_instance(env);
+        old_rate
+    }
+
+    /// Set or clear the fee collector address. Clearing is only allowed
+    /// while the protocol fee rate and the creation fee are zero.
+    pub fn set_fee_collector(env: Env, caller: Address, new_fee_collector: Option<Address>) {
+        let admin = Self::read_admin(&env);
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "set_collector", new_fee_collector.is_some() as i128);
+
+        let charging = Self::get_protocol_fee_rate(env.clone()) > 0 || Self::get_creation_fee(env.clone()) > 0;
+        if new_fee_collector.is_none() && charging {
+            panic_with_error!(&env, Error::InvalidConfiguration);
+        }
+
+        let old_collector = Self::get_fee_collector(env.clone());
+        match &new_fee_collector {
+            Some(collector) => env.storage().instance().set(&DataKey::FeeCollector, collector),
+            None => env.storage().instance().remove(&DataKey::FeeCollector),
+        }
+        Self::extend_instance(&env);
+
+        env.events().publish(
+            ("FeeCollectorChanged",),
+            FeeCollectorChangedEvent {
+                old_collector,
+                collector_is_admin: new_fee_collector.as_ref() == Some(&admin),
+                new_collector: new_fee_collector,
+            },
+        );
+    }
+
+    /// Replace the minimum stream amount, duration and withdrawal size
+    /// (fee manager). Existing streams are only affected by the withdrawal
+    /// minimum.
+    pub fn set_stream_limits(env: Env, caller: Address, limits: StreamLimits) {
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "set_limits", 0);
+        if limits.min_stream_amount < 0 || limits.min_withdrawal_amount < 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+
+        let old_limits = Self::get_stream_limits(env.clone());
+        env.storage().instance().set(&DataKey::StreamLimits, &limits);
+        Self::extend_instance(&env);
+
+        env.events().publish(
+            ("StreamLimitsChanged",),
+            StreamLimitsChangedEvent { old_limits, new_limits: limits },
+        );
+    }
+
+    /// Current stream and withdrawal minimums
+    pub fn get_stream_limits(env: Env) -> StreamLimits {
+        env.storage().instance().get(&DataKey::StreamLimits).unwrap_or_default()
+    }
+
+    /// Longest `end_time - start_time` a new stream may have (admin only).
+    /// Existing streams are unaffected.
+    pub fn set_max_duration(env: Env, max_duration: u64) {
+        Self::require_admin(&env, "set_max_duration", max_duration as i128);
+        if max_duration == 0 {
+            panic_with_error!(&env, Error::InvalidConfiguration);
+        }
+
+        let old_max_duration = Self::get_max_duration(env.clone());
+        env.storage().instance().set(&DataKey::MaxDuration, &max_duration);
+        Self::extend_instance(&env);
+
+        env.events().publish(
+            ("MaxDurationChanged",),
+            MaxDurationChangedEvent { old_max_duration, new_max_duration: max_duration },
+        );
+    }
+
+    /// Point fee calculation at a shared fee registry contract exposing
+    /// `is_fee_exempt(address) -> bool` (admin only)
+    pub fn set_fee_registry(env: Env, registry: Address) {
+        Self::require_admin(&env, "set_fee_registry", 0);
+        Self::store_fee_registry(&env, Some(registry));
+    }
+
+    /// Stop consulting the fee registry (admin only)
+    pub fn remove_fee_registry(env: Env) {
+        Self::require_admin(&env, "remove_fee_registry", 0);
+        Self::store_fee_registry(&env, None);
+    }
+
+    pub fn get_fee_registry(env: Env) -> Option<Address> {
+        env.storage().instance().get(&ConfigKey::FeeRegistry)
+    }
+
+    fn store_fee_registry(env: &Env, new_registry: Option<Address>) {
+        let old_registry = Self::get_fee_registry(env.clone());
+        match &new_registry {
+            Some(registry) => env.storage().instance().set(&ConfigKey::FeeRegistry, registry),
+            None => env.storage().instance().remove(&ConfigKey::FeeRegistry),
+        }
+        Self::extend_instance(env);
+        env.events().publish(
+            ("FeeRegistryChanged",),
+            FeeRegistryChangedEvent { old_registry, new_registry },
+        );
+    }
+
+    /// Charge a flat `fee`, in the stream's token, on top of the initial
+    /// deposit of every new stream (fee manager). It accrues with the
+    /// withdrawal fees for `claim_fees`. Nothing is charged during a fee
+    /// holiday or to senders the fee registry exempts; 0 turns it off.
+    pub fn set_creation_fee(env: Env, caller: Address, fee: i128) {
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "creation_fee", fee);
+        if fee < 0 {
+            panic_with_error!(&env, Error::InvalidAmount);
+        }
+        if fee > 0 && Self::get_fee_collector(env.clone()).is_none() {
+            panic_with_error!(&env, Error::InvalidConfiguration);
+        }
+
+        let old_fee = Self::get_creation_fee(env.clone());
+        env.storage().instance().set(&ConfigKey::CreationFee, &fee);
+        Self::extend_instance(&env);
+        env.events().publish(("CreationFeeChanged",), CreationFeeChangedEvent { old_fee, new_fee: fee });
+    }
+
+    pub fn get_creation_fee(env: Env) -> i128 {
+        env.storage().instance().get_or(&ConfigKey::CreationFee, 0)
+    }
+
+    /// Creation fees ever charged in `token`, including claimed ones
+    pub fn get_creation_fees_collected(env: Env, token: Address) -> i128 {
+        env.storage().persistent().get_or(&TokenKey::CreationFeesCollected(token), 0)
+    }
+
+    /// Creation fee a new stream from `sender` pays: none during a fee
+    /// holiday or for senders the fee registry exempts
+    fn creation_fee_for(env: &Env, sender: &Address) -> i128 {
+        let fee = Self::get_creation_fee(env.clone());
+        if fee == 0 || Self::get_fee_holiday(env.clone()).is_some() || Self::is_fee_exempt(env, sender) {
+            return 0;
+        }
+        fee
+    }
+
+    /// Book a creation fee, already held by the contract, as claimable
+    fn collect_creation_fee(env: &Env, stream_id: u64, token: &Address, payer: &Address, fee: i128) {
+        Self::record_fee_paid(env, payer, fee);
+        let key = DataKey::AccruedFees(token.clone());
+        let accrued: i128 = env.storage().persistent().get_or(&key, 0);
+        env.storage().persistent().set(&key, &(accrued + fee));
+        Self::extend_persistent(env, &key);
+
+        let key = TokenKey::CreationFeesCollected(token.clone());
+        let collected: i128 = env.storage().persistent().get_or(&key, 0);
+        env.storage().persistent().set(&key, &(collected + fee));
+        Self::extend_persistent(env, &key);
+        env.events().publish(("CreationFeeCollected", stream_id), fee);
+    }
 
-            env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-            env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// Waive the protocol fee on every stream until `until` (fee manager).
+    /// The configured rate is left alone and applies again from `until`
+    /// on, without another transaction. Replaces any running holiday.
+    pub fn set_fee_holiday(env: Env, caller: Address, until: u64) {
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "fee_holiday", until as i128);
+        if until <= env.ledger().timestamp() {
+            panic_with_error!(&env, Error::InvalidTimeRange);
+        }
+        env.storage().instance().set(&ConfigKey::FeeHoliday, &until);
+        Self::extend_instance(&env);
+        env.events().publish(("FeeHolidaySet",), FeeHolidayEvent { until });
+    }
 
-            // Emit event
-            let event = DelegationRevokedEvent {
-                stream_id,
-                recipient: stream.recipient,
-            };
-            env.events().publish(("DelegationRevoked", stream_id), event);
+    /// End the fee holiday early (fee manager). A no-op without one.
+    pub fn clear_fee_holiday(env: Env, caller: Address) {
+        Self::require_role(&env, &caller, ROLE_FEE_MANAGER, "clear_holiday", 0);
+        if let Some(until) = Self::get_fee_holiday(env.clone()) {
+            env.storage().instance().remove(&ConfigKey::FeeHoliday);
+            env.events().publish(("FeeHolidayCleared",), FeeHolidayEvent { until });
         }
     }
 
-    /// Get the delegate for a stream
-    pub fn get_delegate(env: Env, stream_id: u64) -> Option<Address> {
-        // Ensure stream exists
-        Self::get_stream(env.clone(), stream_id);
-        env.storage().persistent().get(&(stream_id, Symbol::new(&env, "delegate")))
+    /// Expiry of the running fee holiday; `None` once it has passed
+    pub fn get_fee_holiday(env: Env) -> Option<u64> {
+        env.storage().instance()
+            .get(&ConfigKey::FeeHoliday)
+            .filter(|until: &u64| env.ledger().timestamp() < *until)
     }
 
-    /// Calculate the protocol fee for a given amount
-    fn calculate_protocol_fee(env: &Env, amount: i128) -> i128 {
-        let fee_rate: u32 = env.storage().instance().get(&Symbol::new(env, "general_protocol_fee_rate")).unwrap_or(0);
+    /// Longest duration a new stream may have, 10 years unless changed
+    pub fn get_max_duration(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::MaxDuration).unwrap_or(DEFAULT_MAX_DURATION)
+    }
 
-        if fee_rate == 0 || amount <= 0 {
-            return 0;
+    /// Change how far storage TTLs are extended (admin only). The threshold
+    /// must be at least a day's worth of ledgers and below `bump`, which
+    /// can't exceed the network's maximum entry TTL.
+    pub fn set_ttl_policy(env: Env, threshold: u32, bump: u32) {
+        Self::require_admin(&env, "set_ttl_policy", bump as i128);
+        if threshold < MIN_LEDGER_THRESHOLD || bump <= threshold || bump > env.storage().max_ttl() {
+            panic_with_error!(&env, Error::InvalidTtlPolicy);
         }
 
-        // fee = (amount * fee_rate) / 10000
-        // Split calculation to avoid overflow while preserving precision
-        let rate = fee_rate as i128;
-        let fee = (amount / 10000) * rate + ((amount % 10000) * rate) / 10000;
-        fee.max(0)
+        env.storage().instance().set(&DataKey::TtlPolicy, &TtlPolicy { threshold, bump });
+        Self::extend_instance(&env);
     }
 
-    /// Calculate withdrawable amount for a stream
-    pub fn withdrawable_amount(env: Env, stream_id: u64) -> i128 {
-        let stream: Stream = Self::get_stream(env.clone(), stream_id);
+    /// Current TTL policy, ~30/31 days unless the admin changed it
+    pub fn get_ttl_policy(env: Env) -> TtlPolicy {
+        env.storage().instance()
+            .get(&DataKey::TtlPolicy)
+            .unwrap_or(TtlPolicy { threshold: LEDGER_THRESHOLD, bump: LEDGER_BUMP })
+    }
 
-        // Paused streams have no withdrawable amount
-        if stream.status == StreamStatus::Paused {
-            return 0;
+    fn extend_instance(env: &Env) {
+        let policy = Self::get_ttl_policy(env.clone());
+        env.storage().instance().extend_ttl(policy.threshold, policy.bump);
+    }
+
+    fn extend_persistent<K: IntoVal<Env, soroban_sdk::Val>>(env: &Env, key: &K) {
+        let policy = Self::get_ttl_policy(env.clone());
+        env.storage().persistent().extend_ttl(key, policy.threshold, policy.bump);
+    }
+
+    /// Extend the TTL of up to 20 streams and everything stored alongside
+    /// them (metrics, delegation, pending changes, funding records,
+    /// milestones, status index entry). Anyone can call this to keep
+    /// long-running streams from being archived between interactions.
+    /// Unknown ids are skipped. Withdrawal log entries are extended when
+    /// read through `get_withdrawals`. Returns how many streams were bumped.
+    pub fn bump_stream_ttl(env: Env, stream_ids: Vec<u64>) -> u32 {
+        if stream_ids.len() > MAX_TTL_BATCH {
+            panic_with_error!(&env, Error::BatchTooLarge);
         }
+        Self::extend_instance(&env);
 
-        // Only active streams can have withdrawable amounts
-        if stream.status != StreamStatus::Active {
-            return 0;
+        let storage = env.storage().persistent();
+        let mut bumped = Vec::new(&env);
+        for stream_id in stream_ids.iter() {
+            let Some(stream) = Self::lookup_stream(&env, stream_id) else {
+                continue;
+            };
+            let keys = [
+                DataKey::Metrics(stream_id),
+                DataKey::Delegate(stream_id),
+                DataKey::DelegateCanRedirect(stream_id),
+                DataKey::CallerTipBps(stream_id),
+                DataKey::AutoForward(stream_id),
+                DataKey::SettlementAdapter(stream_id),
+                DataKey::PendingRecipient(stream_id),
+                DataKey::Metadata(stream_id),
+                DataKey::StreamTemplate(stream_id),
+                DataKey::Contributors(stream_id),
+                DataKey::FundingSource(stream_id),
+                DataKey::FeeSponsor(stream_id),
+                DataKey::FeeCarry(stream_id),
+                DataKey::Milestones(stream_id),
+                DataKey::Quarantine(stream_id),
+                DataKey::StatusPos(stream_id),
+            ];
+            for key in keys.iter() {
+                if storage.has(key) {
+                    Self::extend_persistent(&env, key);
+                }
+            }
+            if storage.has(&ActivityKey::Len(stream_id)) {
+                Self::extend_persistent(&env, &ActivityKey::Len(stream_id));
+            }
+            let stream_keys = [
+                StreamKey::Beneficiaries(stream_id),
+                StreamKey::MinWithdrawInterval(stream_id),
+                StreamKey::VestingBaseline(stream_id),
+                StreamKey::AllowFeeOnTransfer(stream_id),
+                StreamKey::TransferLoss(stream_id),
+                StreamKey::TimeBasis(stream_id),
+            ];
+            for key in stream_keys {
+                if storage.has(&key) {
+                    Self::extend_persistent(&env, &key);
+                }
+            }
+            if let Some(position) = storage.get::<_, u32>(&DataKey::StatusPos(stream_id)) {
+                let index_key = DataKey::StatusIndex(stream.status, position);
+                if storage.has(&index_key) {
+                    Self::extend_persistent(&env, &index_key);
+                }
+            }
+            bumped.push_back(stream_id);
         }
 
-        let current_time = env.ledger().timestamp();
+        let count = bumped.len();
+        env.events().publish(
+            ("StreamTtlBumped",),
+            StreamTtlBumpedEvent {
+                stream_ids: bumped,
+                live_until_ledger: env.ledger().sequence() + Self::get_ttl_policy(env.clone()).threshold,
+            },
+        );
+        count
+    }
 
-        if current_time <= stream.start_time {
-            return 0;
-        }
+    /// Upgrade the contract code (upgrader). Storage is left untouched and
+    /// the stored version is bumped so indexers can detect the upgrade.
+    pub fn upgrade(env: Env, caller: Address, new_wasm_hash: BytesN<32>) {
+        let version = Self::version(env.clone()) + 1;
+        Self::require_role(&env, &caller, ROLE_UPGRADER, "upgrade", version as i128);
 
-        // Calculate effective elapsed time (excluding paused duration)
-        let raw_elapsed = if current_time >= stream.end_time {
-            stream.end_time - stream.start_time
-        } else {
-            current_time - stream.start_time
-        };
+        env.storage().instance().set(&DataKey::Version, &version);
+        Self::extend_instance(&env);
 
-        // Subtract the total paused duration from elapsed time
-        let elapsed = raw_elapsed.saturating_sub(stream.total_paused_duration);
+        env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
 
-        let duration = (stream.end_time - stream.start_time).saturating_sub(stream.total_paused_duration);
-        if duration == 0 {
-            return 0;
+        env.events().publish(
+            ("ContractUpgraded",),
+            ContractUpgradedEvent { new_wasm_hash, version },
+        );
+    }
+
+    /// Get the contract version, bumped on every upgrade
+    pub fn version(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::Version).unwrap_or(CONTRACT_VERSION)
+    }
+
+    /// All instance-level configuration in one call
+    pub fn get_config(env: Env) -> ContractConfig {
+        ContractConfig {
+            config_version: CONFIG_VERSION,
+            admin: env.storage().instance().get(&DataKey::Admin),
+            fee_collector: Self::get_fee_collector(env.clone()),
+            protocol_fee_rate: Self::get_protocol_fee_rate(env.clone()),
+            paused: Self::is_paused(env.clone()),
+            stream_limits: Self::get_stream_limits(env.clone()),
+            version: Self::version(env.clone()),
+            recipient_rescue_delay: Self::get_recipient_rescue_delay(env.clone()),
+            ttl_policy: Self::get_ttl_policy(env.clone()),
+            max_duration: Self::get_max_duration(env.clone()),
+            fee_registry: Self::get_fee_registry(env.clone()),
+            fee_holiday: Self::get_fee_holiday(env.clone()),
+            creation_fee: Self::get_creation_fee(env),
         }
+    }
 
-        let vested = (stream.total_amount * elapsed as i128) / duration as i128;
+    /// Get the current protocol fee rate
+    pub fn get_protocol_fee_rate(env: Env) -> u32 {
+        env.storage().instance().get_or(&DataKey::FeeRate, 0)
+    }
 
-        vested - stream.withdrawn_amount
+    /// Get the current fee collector, if one is configured
+    pub fn get_fee_collector(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::FeeCollector)
     }
 
-    /// Withdraw from a stream
-    pub fn withdraw(env: Env, stream_id: u64, amount: i128) {
-        let mut stream: Stream = Self::get_stream(env.clone(), stream_id);
+    /// Protocol fees withheld from withdrawals in `token`, plus creation
+    /// fees, not yet claimed
+    pub fn get_accrued_fees(env: Env, token: Address) -> i128 {
+        env.storage().persistent().get_or(&DataKey::AccruedFees(token), 0)
+    }
 
-        Self::assert_is_recipient_or_delegate(&env, stream_id);
+    /// Pay out the fees accrued in `token`. Needs the fee collector's auth
+    /// and pays the collector; with no collector configured, the admin
+    /// claims instead. Returns the amount claimed.
+    pub fn claim_fees(env: Env, token: Address) -> i128 {
+        let to = Self::get_fee_collector(env.clone()).unwrap_or_else(|| Self::read_admin(&env));
+        to.require_auth();
 
-        let available = Self::withdrawable_amount(env.clone(), stream_id);
-        if amount > available || amount <= 0 {
-            panic_with_error!(&env, Error::InsufficientWithdrawable);
+        let amount = Self::get_accrued_fees(env.clone(), token.clone());
+        if amount <= 0 {
+            return 0;
         }
+        env.storage().persistent().remove(&DataKey::AccruedFees(token.clone()));
 
-        // Calculate protocol fee
-        let fee = Self::calculate_protocol_fee(&env, amount);
-        let net_amount = amount - fee;
+        Self::pay_out(&env, &token::Client::new(&env, &token), &to, amount, None);
+        env.events().publish(("FeesClaimed",), FeesClaimedEvent { token, to, amount });
+        amount
+    }
 
-        stream.withdrawn_amount += amount;
+    /// Opt an address into per-day fee tracking for tax reporting.
+    ///
+    /// Tracking costs one persistent entry per address per day on which a
+    /// fee was paid, so it is off unless the address enables it.
+    pub fn enable_fee_tracking(env: Env, address: Address) {
+        address.require_auth();
+        let key = DataKey::FeeTracking(address);
+        env.storage().persistent().set(&key, &true);
+        Self::extend_persistent(&env, &key);
+    }
 
-        // Check if stream is completed
-        if stream.withdrawn_amount >= stream.total_amount {
-            stream.status = StreamStatus::Completed;
-            
-            // Update protocol metrics - decrease active streams
-            let mut protocol_metrics: ProtocolMetrics = env.storage().instance()
-                .get(&Symbol::new(&env, "protocol_metrics"))
-                .unwrap();
-            protocol_metrics.total_active_streams = protocol_metrics.total_active_streams.saturating_sub(1);
-            env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &protocol_metrics);
+    /// Whether per-day fee tracking is enabled for an address
+    pub fn is_fee_tracking_enabled(env: Env, address: Address) -> bool {
+        env.storage().persistent()
+            .get(&DataKey::FeeTracking(address))
+            .unwrap_or(false)
+    }
+
+    /// Total fees paid by an address over the days covering `[from_ts, to_ts]`.
+    /// The range may span at most 366 days.
+    pub fn get_fees_paid(env: Env, address: Address, from_ts: u64, to_ts: u64) -> i128 {
+        if to_ts < from_ts {
+            panic_with_error!(&env, Error::InvalidTimeRange);
+        }
+        let from_day = from_ts / SECONDS_PER_DAY;
+        let to_day = to_ts / SECONDS_PER_DAY;
+        if to_day - from_day >= MAX_FEE_QUERY_DAYS {
+            panic_with_error!(&env, Error::FeeQueryRangeTooLarge);
         }
 
-        env.storage().persistent().set(&stream_id, &stream);
-        env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
+        let mut total: i128 = 0;
+        for day in from_day..=to_day {
+            let paid: i128 = env.storage().persistent()
+                .get(&DataKey::FeesByDay(address.clone(), day))
+                .unwrap_or(0);
+            total += paid;
+        }
+        total
+    }
 
-        // Update stream metrics
-        let mut metrics: StreamMetrics = env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+    /// Add a fee to the payer's counter for the current day, if they opted in
+    fn record_fee_paid(env: &Env, payer: &Address, fee: i128) {
+        if !Self::is_fee_tracking_enabled(env.clone(), payer.clone()) {
+            return;
+        }
+        let day = env.ledger().timestamp() / SECONDS_PER_DAY;
+        let key = DataKey::FeesByDay(payer.clone(), day);
+        let paid: i128 = env.storage().persistent().get_or(&key, 0);
+        env.storage().persistent().set(&key, &(paid + fee));
+        Self::extend_persistent(env, &key);
+    }
 
-        metrics.total_withdrawn += amount;
-        metrics.withdrawal_count += 1;
-        metrics.last_activity = env.ledger().timestamp();
+    /// Whether `token` is the Stellar Asset Contract for native XLM on this network
+    pub fn is_native(env: Env, token: Address) -> bool {
+        // XDR encoding of `Asset::Native` is just its 4-byte discriminant
+        let native_asset = Bytes::from_array(&env, &[0u8; 4]);
+        env.deployer().with_stellar_asset(native_asset).deployed_address() == token
+    }
+
+    /// Get metrics for streams denominated in `token`
+    pub fn get_token_metrics(env: Env, token: Address) -> TokenStreamMetrics {
+        env.storage().persistent()
+            .get(&DataKey::TokenMetrics(token))
+            .unwrap_or_default()
+    }
 
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+    fn update_token_metrics(env: &Env, token: &Address, update: impl FnOnce(&mut TokenStreamMetrics)) {
+        let key = DataKey::TokenMetrics(token.clone());
+        let mut metrics: TokenStreamMetrics = env.storage().persistent().get(&key).unwrap_or_default();
+        update(&mut metrics);
+        env.storage().persistent().set(&key, &metrics);
+        Self::extend_persistent(env, &key);
+    }
 
-        // Transfer net amount to recipient
-        let token_client = token::Client::new(&env, &stream.token);
-        token_client.transfer(&env.current_contract_address(), &stream.recipient, &net_amount);
+    /// Page through the ids of streams currently in `status` (at most 100
+    /// per call). Order is not stable across status changes.
+    pub fn get_streams_by_status(env: Env, status: StreamStatus, offset: u32, limit: u32) -> Vec<u64> {
+        let len: u32 = env.storage().persistent().get_or(&DataKey::StatusLen(status), 0);
+        let end = offset.saturating_add(limit.min(MAX_STATUS_PAGE)).min(len);
 
-        // Transfer fee to collector if fee > 0
-        if fee > 0 {
-            let fee_collector: Address = env.storage().instance().get(&Symbol::new(&env, "fee_collector")).unwrap();
-            token_client.transfer(&env.current_contract_address(), &fee_collector, &fee);
-            env.events().publish(("FeeCollected", stream_id), fee);
+        let mut ids = Vec::new(&env);
+        for position in offset..end {
+            let id: u64 = env.storage().persistent()
+                .get(&DataKey::StatusIndex(status, position))
+                .unwrap();
+            ids.push_back(id);
         }
+        ids
     }
 
-    /// Withdraw the maximum available amount from a stream
-    pub fn withdraw_max(env: Env, stream_id: u64) {
-        let available = Self::withdrawable_amount(env.clone(), stream_id);
-        if available <= 0 {
-            panic_with_error!(&env, Error::InsufficientWithdrawable);
+    /// Sum what `recipient` can withdraw now and what is still to vest,
+    /// over up to 50 of its streams starting at `cursor`
+    pub fn get_recipient_position(env: Env, recipient: Address, cursor: u32, limit: u32) -> RecipientPosition {
+        let storage = env.storage().persistent();
+        let len: u32 = storage.get(&DataKey::RecipientStreamCount(recipient.clone())).unwrap_or(0);
+        let end = cursor.saturating_add(limit.min(MAX_POSITION_PAGE)).min(len);
+        let mut position = RecipientPosition {
+            withdrawable_now: 0,
+            pending_unvested: 0,
+            active_stream_count: 0,
+            next_cursor: if end < len { Some(end) } else { None },
+        };
+        for i in cursor..end {
+            let stream_id: u64 = storage.get(&DataKey::RecipientStream(recipient.clone(), i)).unwrap();
+            let stream: Stream = match storage.get(&DataKey::Stream(stream_id)) {
+                Some(stream) => stream,
+                None => continue,
+            };
+            let vested_as_of = match (stream.status, stream.paused_at) {
+                (StreamStatus::Active, _) => Self::stream_clock(&env, stream_id),
+                (StreamStatus::Paused, Some(paused_at)) => paused_at,
+                _ => continue,
+            };
+            position.withdrawable_now += Self::compute_withdrawable(&env, &stream).max(0);
+            position.pending_unvested += stream.total_amount - Self::vested_amount(&env, &stream, vested_as_of);
+            position.active_stream_count += 1;
         }
-        Self::withdraw(env, stream_id, available);
+        position
     }
 
-    /// Pause a stream (sender only)
-    pub fn pause_stream(env: Env, stream_id: u64) {
-        let mut stream: Stream = Self::get_stream(env.clone(), stream_id);
+    /// Append a stream to a recipient's index
+    fn index_recipient_stream(env: &Env, recipient: &Address, stream_id: u64) {
+        let storage = env.storage().persistent();
+        let len_key = DataKey::RecipientStreamCount(recipient.clone());
+        let len: u32 = storage.get(&len_key).unwrap_or(0);
+        let idx_key = DataKey::RecipientStream(recipient.clone(), len);
+        storage.set(&idx_key, &stream_id);
+        storage.set(&len_key, &(len + 1));
+        Self::extend_persistent(env, &idx_key);
+        Self::extend_persistent(env, &len_key);
+    }
 
-        stream.sender.require_auth();
+    /// Drop a stream from a recipient's index, moving the last entry into
+    /// its slot. Searches from the newest entry, where recently created
+    /// streams are.
+    fn unindex_recipient_stream(env: &Env, recipient: &Address, stream_id: u64) {
+        let storage = env.storage().persistent();
+        let len_key = DataKey::RecipientStreamCount(recipient.clone());
+        let len: u32 = storage.get_or(&len_key, 0);
+        let Some(position) = (0..len).rev()
+            .find(|i| storage.get::<_, u64>(&DataKey::RecipientStream(recipient.clone(), *i)) == Some(stream_id))
+        else {
+            return;
+        };
 
-        if stream.status != StreamStatus::Active {
-            panic_with_error!(&env, Error::StreamNotActive);
+        let last_key = DataKey::RecipientStream(recipient.clone(), len - 1);
+        if position != len - 1 {
+            let moved_id: u64 = storage.get(&last_key).unwrap();
+            let idx_key = DataKey::RecipientStream(recipient.clone(), position);
+            storage.set(&idx_key, &moved_id);
+            Self::extend_persistent(env, &idx_key);
         }
+        storage.remove(&last_key);
+        storage.set(&len_key, &(len - 1));
+        Self::extend_persistent(env, &len_key);
+    }
 
-        let current_time = env.ledger().timestamp();
-        
-        stream.status = StreamStatus::Paused;
-        stream.paused_at = Some(current_time);
+    /// What a stream counts towards `total_committed`: its whole total while
+    /// it runs; once closed, what the recipient was paid plus approved
+    /// milestones a cancel left in escrow for them. `rebuild_protocol_metrics`
+    /// sums this, so the incremental updates must agree with it.
+    fn committed_amount(env: &Env, stream: &Stream) -> i128 {
+        match stream.status {
+            StreamStatus::Canceled => {
+                let owed = Self::milestone_total(env, stream.id).map_or(0, |approved| approved - stream.withdrawn_amount);
+                stream.withdrawn_amount + owed.max(0)
+            },
+            StreamStatus::Completed | StreamStatus::Rejected => stream.withdrawn_amount,
+            _ => stream.total_amount,
+        }
+    }
 
-        env.storage().persistent().set(&stream_id, &stream);
-        env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// Drop the part of a just-closed stream's total that is no longer
+    /// committed. Call after the final status is set.
+    fn release_commitment(env: &Env, stream: &Stream) {
+        let mut protocol_metrics = Self::load_protocol_metrics(env);
+        protocol_metrics.total_committed -= stream.total_amount - Self::committed_amount(env, stream);
+        Self::store_protocol_metrics(env, &protocol_metrics);
+    }
 
-        // Update stream metrics
-        let mut metrics: StreamMetrics = env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+    /// Change a stream's status through `transition_status`
+    fn set_status(env: &Env, stream: &mut Stream, to: StreamStatus) {
+        Self::transition_status(env, stream.id, &stream.token, Some(stream.status), to);
+        stream.status = to;
+    }
 
-        metrics.pause_count += 1;
-        metrics.last_activity = current_time;
+    /// Every status change goes through here (`from` is None for a new
+    /// stream), and it is the only place the protocol and per-token active
+    /// stream counts move: by +1 or -1 when the transition enters or
+    /// leaves Active, worked out from the pair alone.
+    fn transition_status(env: &Env, stream_id: u64, token: &Address, from: Option<StreamStatus>, to: StreamStatus) {
+        Self::move_status_index(env, stream_id, from, to);
 
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+        let was_active = from == Some(StreamStatus::Active);
+        let is_active = to == StreamStatus::Active;
+        if was_active == is_active {
+            return;
+        }
+        // Saturating only for counts that drifted before transitions were
+        // centralised; `rebuild_protocol_metrics` corrects those
+        let adjust = |count: u64| if is_active { count + 1 } else { count.saturating_sub(1) };
+        let mut protocol_metrics = Self::load_protocol_metrics(env);
+        protocol_metrics.total_active_streams = adjust(protocol_metrics.total_active_streams);
+        Self::store_protocol_metrics(env, &protocol_metrics);
+        Self::update_token_metrics(env, token, |m| m.active_streams = adjust(m.active_streams));
+    }
 
-        // Update protocol metrics - decrease active streams
-        let mut protocol_metrics: ProtocolMetrics = env.storage().instance()
-            .get(&Symbol::new(&env, "protocol_metrics"))
-            .unwrap();
-        protocol_metrics.total_active_streams = protocol_metrics.total_active_streams.saturating_sub(1);
-        env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &protocol_metrics);
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// Move a stream between per-status index sets. Each set is a dense
+    /// array of ids plus each id's position, so removal swaps the last id
+    /// into the gap instead of scanning.
+    fn move_status_index(env: &Env, stream_id: u64, from: Option<StreamStatus>, to: StreamStatus) {
+        let storage = env.storage().persistent();
+        let pos_key = DataKey::StatusPos(stream_id);
 
-        // Emit StreamPaused event
-        env.events().publish(
-            ("StreamPaused", stream_id),
-            StreamPausedEvent {
-                stream_id,
-                paused_at: current_time,
-            },
-        );
+        if let Some(from) = from {
+            let len_key = DataKey::StatusLen(from);
+            let last: u32 = storage.get::<_, u32>(&len_key).unwrap_or(0).saturating_sub(1);
+            let position: u32 = storage.get(&pos_key).unwrap_or(last);
+            let last_key = DataKey::StatusIndex(from, last);
+
+            if position != last {
+                let moved_id: u64 = storage.get(&last_key).unwrap();
+                storage.set(&DataKey::StatusIndex(from, position), &moved_id);
+                storage.set(&DataKey::StatusPos(moved_id), &position);
+                Self::extend_persistent(env, &DataKey::StatusIndex(from, position));
+                Self::extend_persistent(env, &DataKey::StatusPos(moved_id));
+            }
+            storage.remove(&last_key);
+            storage.set(&len_key, &last);
+            Self::extend_persistent(env, &len_key);
+        }
+
+        let len_key = DataKey::StatusLen(to);
+        let position: u32 = storage.get(&len_key).unwrap_or(0);
+        let idx_key = DataKey::StatusIndex(to, position);
+        storage.set(&idx_key, &stream_id);
+        storage.set(&pos_key, &position);
+        storage.set(&len_key, &(position + 1));
+        Self::extend_persistent(env, &idx_key);
+        Self::extend_persistent(env, &pos_key);
+        Self::extend_persistent(env, &len_key);
     }
 
-    /// Resume a paused stream (sender only)
-    pub fn resume_stream(env: Env, stream_id: u64) {
-        let mut stream: Stream = Self::get_stream(env.clone(), stream_id);
+    /// Tokens currently locked in streams denominated in `token`
+    pub fn get_tvl_by_token(env: Env, token: Address) -> i128 {
+        Self::get_token_metrics(env, token).escrowed_balance
+    }
 
-        stream.sender.require_auth();
+    /// Tokens currently locked in streams funded by `sender`, across tokens
+    pub fn get_tvl_by_sender(env: Env, sender: Address) -> i128 {
+        env.storage().persistent().get_or(&DataKey::SenderTvl(sender), 0)
+    }
 
-        if stream.status != StreamStatus::Paused {
-            panic_with_error!(&env, Error::StreamNotPaused);
+    fn adjust_sender_tvl(env: &Env, sender: &Address, delta: i128) {
+        if delta == 0 {
+            return;
         }
+        let key = DataKey::SenderTvl(sender.clone());
+        let tvl: i128 = env.storage().persistent().get_or(&key, 0);
+        env.storage().persistent().set(&key, &(tvl + delta));
+        Self::extend_persistent(env, &key);
+    }
 
-        let current_time = env.ledger().timestamp();
-        
-        // Calculate pause duration
-        let paused_duration = if let Some(paused_at) = stream.paused_at {
-            current_time.saturating_sub(paused_at)
-        } else {
-            0
-        };
-
-        // Update total paused duration
-        stream.total_paused_duration += paused_duration;
-        
-        // Extend end_time by the paused duration
-        stream.end_time += paused_duration;
+    /// Get stream-specific metrics
+    pub fn get_stream_metrics(env: Env, stream_id: u64) -> StreamMetrics {
+        // Ensure stream exists
+        let stream = Self::read_stream(&env, stream_id);
         
-        stream.status = StreamStatus::Active;
-        stream.paused_at = None;
+        // Return metrics or default if not found
+        Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount)
+    }
 
-        env.storage().persistent().set(&stream_id, &stream);
-        env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// SHA-256 of the stream's canonical state encoding, so auditors can
+    /// pin its exact state off-chain. Version 1 of the encoding is the
+    /// version byte followed by, in order: id, sender, recipient, token,
+    /// total_amount, balance, withdrawn_amount, start_time, end_time,
+    /// status, paused_at, total_paused_duration, cliff_time, cancelable,
+    /// notify_recipient, claim_deadline, max_pause_seconds, then the
+    /// metrics' total_withdrawn, withdrawal_count, pause_count,
+    /// total_delegations, forced_revocations and sponsored_fees.
+    ///
+    /// Integers are big-endian at their own width and addresses are their
+    /// XDR encoding. Status is one byte: Active 0, Paused 1, Canceled 2,
+    /// Completed 3, Quarantined 4, Rejected 5. Booleans are one byte, and
+    /// an option is a 0/1 presence byte followed by the value when present.
+    pub fn get_stream_state_hash(env: Env, stream_id: u64) -> BytesN<32> {
+        let stream = Self::read_stream(&env, stream_id);
+        let metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
 
-        // Update stream metrics
-        let mut metrics: StreamMetrics = env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+        let mut encoded = Bytes::new(&env);
+        encoded.push_back(STATE_HASH_VERSION);
+        encoded.extend_from_array(&stream.id.to_be_bytes());
+        for address in [&stream.sender, &stream.recipient, &stream.token] {
+            encoded.append(&address.clone().to_xdr(&env));
+        }
+        for amount in [stream.total_amount, stream.balance, stream.withdrawn_amount] {
+            encoded.extend_from_array(&amount.to_be_bytes());
+        }
+        encoded.extend_from_array(&stream.start_time.to_be_bytes());
+        encoded.extend_from_array(&stream.end_time.to_be_bytes());
+        encoded.push_back(match stream.status {
+            StreamStatus::Active => 0,
+            StreamStatus::Paused => 1,
+            StreamStatus::Canceled => 2,
+            StreamStatus::Completed => 3,
+            StreamStatus::Quarantined => 4,
+            StreamStatus::Rejected => 5,
+        });
+        Self::encode_optional_time(&mut encoded, stream.paused_at);
+        encoded.extend_from_array(&stream.total_paused_duration.to_be_bytes());
+        encoded.extend_from_array(&stream.cliff_time.to_be_bytes());
+        encoded.push_back(stream.cancelable as u8);
+        encoded.push_back(stream.notify_recipient as u8);
+        Self::encode_optional_time(&mut encoded, stream.claim_deadline);
+        Self::encode_optional_time(&mut encoded, stream.max_pause_seconds);
 
-        metrics.last_activity = current_time;
+        encoded.extend_from_array(&metrics.total_withdrawn.to_be_bytes());
+        for count in [metrics.withdrawal_count, metrics.pause_count, metrics.total_delegations, metrics.forced_revocations] {
+            encoded.extend_from_array(&count.to_be_bytes());
+        }
+        encoded.extend_from_array(&metrics.sponsored_fees.to_be_bytes());
 
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+        env.crypto().sha256(&encoded).into()
+    }
 
-        // Update protocol metrics - increase active streams
-        let mut protocol_metrics: ProtocolMetrics = env.storage().instance()
-            .get(&Symbol::new(&env, "protocol_metrics"))
-            .unwrap();
-        protocol_metrics.total_active_streams += 1;
-        env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &protocol_metrics);
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// Whether the stream's current state hashes to `expected`
+    pub fn verify_stream_state(env: Env, stream_id: u64, expected: BytesN<32>) -> bool {
+        Self::get_stream_state_hash(env, stream_id) == expected
+    }
 
-        // Emit StreamResumed event
-        env.events().publish(
-            ("StreamResumed", stream_id),
-            StreamResumedEvent {
-                stream_id,
-                resumed_at: current_time,
-                paused_duration,
+    fn encode_optional_time(encoded: &mut Bytes, value: Option<u64>) {
+        match value {
+            Some(value) => {
+                encoded.push_back(1);
+                encoded.extend_from_array(&value.to_be_bytes());
             },
-        );
+            None => encoded.push_back(0),
+        }
     }
 
-    /// Cancel a stream
-    pub fn cancel_stream(env: Env, stream_id: u64) {
-        let mut stream: Stream = Self::get_stream(env.clone(), stream_id);
-
-        stream.sender.require_auth();
+    /// Check a stream's bookkeeping invariants without changing anything.
+    /// Meant for debugging; an empty `violations` list means the stream's
+    /// amounts, pause state, metrics and the token's escrow counter agree.
+    pub fn verify_stream_integrity(env: Env, stream_id: u64) -> IntegrityReport {
+        let stream = Self::peek_stream(&env, stream_id)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotFound));
+        let mut violations = Vec::new(&env);
+        let mut check = |holds: bool, name: &str| {
+            if !holds {
+                violations.push_back(Symbol::new(&env, name));
+            }
+        };
 
-        if stream.status != StreamStatus::Active && stream.status != StreamStatus::Paused {
-            panic_with_error!(&env, Error::StreamCannotBeCanceled);
-        }
-        
-        let was_active = stream.status == StreamStatus::Active;
-        stream.status = StreamStatus::Canceled;
+        check(stream.withdrawn_amount <= stream.total_amount, "withdrawn_exceeds_total");
+        check(stream.balance >= 0, "negative_balance");
+        check(stream.withdrawn_amount <= stream.balance, "withdrawn_exceeds_balance");
 
-        env.storage().persistent().set(&stream_id, &stream);
-        env.storage().persistent().extend_ttl(&stream_id, LEDGER_THRESHOLD, LEDGER_BUMP);
+        // What the stream still holds must be covered by the token's escrow
+        let outstanding = match stream.status {
+            StreamStatus::Active | StreamStatus::Paused | StreamStatus::Quarantined => {
+                stream.balance - stream.withdrawn_amount
+            },
+            _ => 0,
+        };
+        let escrowed = Self::get_token_metrics(env.clone(), stream.token.clone()).escrowed_balance;
+        check(outstanding <= escrowed, "escrow_counter_short");
 
-        // Update stream metrics
-        let mut metrics: StreamMetrics = env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+        let frozen = matches!(stream.status, StreamStatus::Paused | StreamStatus::Quarantined);
+        check(stream.paused_at.is_some() == frozen, "pause_state_mismatch");
 
-        metrics.last_activity = env.ledger().timestamp();
+        let metrics = Self::load_stream_metrics(&env, stream_id, stream.withdrawn_amount);
+        check(metrics.total_withdrawn == stream.withdrawn_amount, "metrics_withdrawn_drift");
 
-        env.storage().persistent().set(&(stream_id, Symbol::new(&env, "metrics")), &metrics);
-        env.storage().persistent().extend_ttl(&(stream_id, Symbol::new(&env, "metrics")), LEDGER_THRESHOLD, LEDGER_BUMP);
+        IntegrityReport { ok: violations.is_empty(), violations }
+    }
 
-        // Update protocol metrics - decrease active streams if it was active
-        if was_active {
-            let mut protocol_metrics: ProtocolMetrics = env.storage().instance()
-                .get(&Symbol::new(&env, "protocol_metrics"))
-                .unwrap();
-            protocol_metrics.total_active_streams = protocol_metrics.total_active_streams.saturating_sub(1);
-            env.storage().instance().set(&Symbol::new(&env, "protocol_metrics"), &protocol_metrics);
-            env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
-        }
+    /// Page through a stream's activity log, oldest first (at most 50
+    /// entries per call)
+    pub fn get_activity(env: Env, stream_id: u64, offset: u32, limit: u32) -> Vec<ActivityEntry> {
+        let count: u32 = env.storage().persistent().get_or(&ActivityKey::Len(stream_id), 0);
+        let end = offset.saturating_add(limit.min(MAX_ACTIVITY_PAGE)).min(count);
 
-        // Refund remaining tokens to sender
-        let remaining = (stream.balance - stream.withdrawn_amount).max(0);
-        if remaining > 0 {
-            let token_client = token::Client::new(&env, &stream.token);
-            token_client.transfer(&env.current_contract_address(), &stream.sender, &remaining);
+        let mut entries = Vec::new(&env);
+        for seq in offset..end {
+            let key = ActivityKey::Entry(stream_id, seq);
+            if let Some(entry) = env.storage().persistent().get::<_, ActivityEntry>(&key) {
+                entries.push_back(entry);
+            }
         }
+        entries
     }
 
-    /// Set the protocol fee rate
-    pub fn set_protocol_fee_rate(env: Env, new_fee_rate: u32) {
-        let admin: Address = env.storage().instance().get(&Symbol::new(&env, "admin")).unwrap();
-        admin.require_auth();
+    /// Page through a stream's withdrawal log, oldest first (at most 50
+    /// records per call)
+    pub fn get_withdrawals(env: Env, stream_id: u64, offset: u32, limit: u32) -> Vec<WithdrawalRecord> {
+        let count = Self::get_stream_metrics(env.clone(), stream_id).withdrawal_count;
+        let end = offset.saturating_add(limit.min(MAX_WITHDRAWAL_PAGE)).min(count);
 
-        if new_fee_rate > MAX_FEE {
-            panic_with_error!(&env, Error::FeeTooHigh);
+        let mut records = Vec::new(&env);
+        for seq in offset..end {
+            let key = DataKey::Withdrawal(stream_id, seq);
+            if let Some(record) = env.storage().persistent().get::<_, WithdrawalRecord>(&key) {
+                Self::extend_persistent(&env, &key);
+                records.push_back(record);
+            }
         }
-
-        env.storage().instance().set(&Symbol::new(&env, "general_protocol_fee_rate"), &new_fee_rate);
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+        records
     }
 
-    /// Set the fee collector address
-    pub fn set_fee_collector(env: Env, new_fee_collector: Address) {
-        let admin: Address = env.storage().instance().get(&Symbol::new(&env, "admin")).unwrap();
-        admin.require_auth();
-
-        env.storage().instance().set(&Symbol::new(&env, "fee_collector"), &new_fee_collector);
-        env.storage().instance().extend_ttl(LEDGER_THRESHOLD, LEDGER_BUMP);
+    /// Get a stream together with its metrics, delegate, withdrawable amount
+    /// and the fee rate that would apply to a withdrawal right now
+    pub fn get_stream_summary(env: Env, stream_id: u64) -> StreamSummary {
+        let stream = Self::read_stream(&env, stream_id);
+        Self::build_stream_summary(&env, stream)
     }
 
-    /// Get the current protocol fee rate
-    pub fn get_protocol_fee_rate(env: Env) -> u32 {
-        env.storage().instance().get(&Symbol::new(&env, "general_protocol_fee_rate")).unwrap_or(0)
-    }
+    /// Batched `get_stream_summary`. Ids that don't resolve to a stream are
+    /// skipped so a single bad id doesn't fail the whole call.
+    pub fn get_stream_summaries(env: Env, ids: Vec<u64>) -> Vec<StreamSummary> {
+        if ids.len() > MAX_SUMMARY_BATCH {
+            panic_with_error!(&env, Error::BatchTooLarge);
+        }
 
-    /// Get the current fee collector
-    pub fn get_fee_collector(env: Env) -> Address {
-        env.storage().instance().get(&Symbol::new(&env, "fee_collector")).unwrap()
+        let mut summaries = Vec::new(&env);
+        for stream_id in ids.iter() {
+            if let Some(stream) = Self::lookup_stream(&env, stream_id) {
+                summaries.push_back(Self::build_stream_summary(&env, stream));
+            }
+        }
+        summaries
     }
 
-    /// Get stream-specific metrics
-    pub fn get_stream_metrics(env: Env, stream_id: u64) -> StreamMetrics {
-        // Ensure stream exists
-        Self::get_stream(env.clone(), stream_id);
-        
-        // Return metrics or default if not found
-        env.storage().persistent()
-            .get(&(stream_id, Symbol::new(&env, "metrics")))
-            .unwrap_or_else(|| Self::default_stream_metrics(&env))
+    fn build_stream_summary(env: &Env, stream: Stream) -> StreamSummary {
+        let stream_id = stream.id;
+        let metrics = Self::load_stream_metrics(env, stream_id, stream.withdrawn_amount);
+        let delegate: Option<Address> = env.storage().persistent().get(&DataKey::Delegate(stream_id));
+        let withdrawable = Self::compute_withdrawable(env, &stream);
+        let fee_rate = Self::effective_fee_rate(env, &stream);
+
+        StreamSummary {
+            stream,
+            metrics,
+            delegate,
+            withdrawable,
+            fee_rate,
+        }
     }
 
     /// Get protocol-wide metrics
     pub fn get_protocol_metrics(env: Env) -> ProtocolMetrics {
-        env.storage().instance()
-            .get(&Symbol::new(&env, "protocol_metrics"))
-            .unwrap_or(ProtocolMetrics {
+        Self::load_protocol_metrics(&env)
+    }
+
+    /// Read protocol metrics, converting the pre-fee-tracking layout
+    fn load_protocol_metrics(env: &Env) -> ProtocolMetrics {
+        if let Some(metrics) = env.storage().instance().get(&DataKey::ProtocolMetricsV2) {
+            return metrics;
+        }
+        let legacy: Option<LegacyProtocolMetrics> = env.storage().instance().get(&DataKey::ProtocolMetrics);
+        match legacy {
+            Some(legacy) => ProtocolMetrics {
+                total_active_streams: legacy.total_active_streams,
+                total_tokens_streamed: legacy.total_tokens_streamed,
+                total_streams_created: legacy.total_streams_created,
+                total_delegations: legacy.total_delegations,
+                total_committed: legacy.total_committed,
+                total_fees_collected: 0,
+            },
+            None => ProtocolMetrics {
                 total_active_streams: 0,
                 total_tokens_streamed: 0,
                 total_streams_created: 0,
                 total_delegations: 0,
-            })
+                total_committed: 0,
+                total_fees_collected: 0,
+            },
+        }
+    }
+
+    /// Recompute a stream's metrics from the stream and its withdrawal log
+    /// (admin only) and store them. Total withdrawn, withdrawal count, last
+    /// withdrawal time and current delegate are corrected; counters with no
+    /// other record are kept, or start at 0 if the entry was lost.
+    pub fn repair_metrics(env: Env, stream_id: u64) -> StreamMetrics {
+        Self::require_admin(&env, "repair_metrics", stream_id as i128);
+        let stream = Self::read_stream(&env, stream_id);
+
+        let base = env.storage().persistent()
+            .get(&DataKey::Metrics(stream_id))
+            .unwrap_or_else(|| Self::default_stream_metrics(&env));
+        let metrics = Self::derive_stream_metrics(&env, stream_id, stream.withdrawn_amount, base);
+        Self::store_stream_metrics(&env, stream_id, &metrics);
+        metrics
+    }
+
+    /// Rebuild the protocol metrics from the stored streams (admin only), at
+    /// most 50 streams per call. Start at id 1 and pass the returned id to
+    /// the next call until it returns `None`; the rebuilt metrics are then
+    /// stored. Starting at 1 again discards a rebuild in progress.
+    ///
+    /// Stream counts, delegations, funded tokens and commitments are
+    /// recomputed: a closed stream stays committed for what its recipient
+    /// was paid or is still owed, as when it closed. Fee revenue has no per-stream record and is
+    /// kept. Archived streams are skipped, so bump their TTL first.
+    pub fn rebuild_protocol_metrics(env: Env, start_id: u64, limit: u32) -> Option<u64> {
+        Self::require_admin(&env, "rebuild_protocol_metrics", start_id as i128);
+
+        let mut rebuild = if start_id == 1 {
+            MetricsRebuild {
+                next_id: 1,
+                metrics: ProtocolMetrics {
+                    total_active_streams: 0,
+                    total_tokens_streamed: 0,
+                    total_streams_created: 0,
+                    total_delegations: 0,
+                    total_committed: 0,
+                    total_fees_collected: Self::load_protocol_metrics(&env).total_fees_collected,
+                },
+            }
+        } else {
+            env.storage().instance()
+                .get::<_, MetricsRebuild>(&ConfigKey::MetricsRebuild)
+                .filter(|rebuild| rebuild.next_id == start_id)
+                .unwrap_or_else(|| panic_with_error!(&env, ErrorExt::RebuildOutOfOrder))
+        };
+
+        let stream_count = Self::get_stream_count(env.clone());
+        let end = start_id.saturating_add(limit.min(MAX_STREAM_PAGE) as u64).min(stream_count + 1);
+        for stream_id in start_id..end {
+            let Some(stream) = Self::lookup_stream(&env, stream_id) else {
+                continue;
+            };
+            let metrics = &mut rebuild.metrics;
+            metrics.total_tokens_streamed += stream.balance;
+            metrics.total_committed += Self::committed_amount(&env, &stream);
+            if stream.status == StreamStatus::Active {
+                metrics.total_active_streams += 1;
+            }
+            if let Some(stream_metrics) = env.storage().persistent().get::<_, StreamMetrics>(&DataKey::Metrics(stream_id)) {
+                metrics.total_delegations += stream_metrics.total_delegations as u64;
+            }
+        }
+
+        if end > stream_count {
+            // Every id up to the count was created, archived or not
+            rebuild.metrics.total_streams_created = stream_count;
+            env.storage().instance().remove(&ConfigKey::MetricsRebuild);
+            Self::store_protocol_metrics(&env, &rebuild.metrics);
+            return None;
+        }
+        rebuild.next_id = end;
+        env.storage().instance().set(&ConfigKey::MetricsRebuild, &rebuild);
+        Self::extend_instance(&env);
+        Some(end)
+    }
+
+    fn store_protocol_metrics(env: &Env, metrics: &ProtocolMetrics) {
+        env.storage().instance().remove(&DataKey::ProtocolMetrics);
+        env.storage().instance().set(&DataKey::ProtocolMetricsV2, metrics);
+        Self::extend_instance(env);
+    }
+
+    /// Protocol fees ever withheld in `token`, including claimed ones
+    pub fn get_fees_collected(env: Env, token: Address) -> i128 {
+        env.storage().persistent().get_or(&DataKey::FeesCollected(token), 0)
+    }
+}
+
+/// Stream constructors taking eight arguments besides `env`. The args
+/// helpers `#[contractimpl]` generates for them don't carry a function's
+/// attributes, so the lint is allowed for this block instead.
+mod constructors {
+    #![allow(clippy::too_many_arguments)]
+    use super::*;
+
+    #[contractimpl]
+    impl PaymentStreamContract {
+        /// Same as `create_stream`, but the initial amount is pulled from
+        /// `funding_source` while `authorizer` becomes the stream's sender and
+        /// controls it. Only `authorizer` must authorize this call; the funding
+        /// source only authorizes the token transfer, which lets a custom
+        /// account contract act as sender without also holding the funds.
+        /// Cancel refunds go to the funding source.
+        pub fn create_stream_for(
+            env: Env,
+            authorizer: Address,
+            funding_source: Address,
+            recipient: Address,
+            token: Address,
+            total_amount: i128,
+            initial_amount: i128,
+            start_time: u64,
+            end_time: u64,
+        ) -> u64 {
+            authorizer.require_auth();
+
+            Self::open_stream(
+                &env, authorizer, funding_source, recipient, token, total_amount, initial_amount, start_time, end_time,
+                start_time, StreamOptions::default(),
+            )
+        }
+
+        /// Same as `create_stream`, but lets the sender give up the right to
+        /// cancel or pause the stream
+        pub fn create_stream_v2(
+            env: Env,
+            sender: Address,
+            recipient: Address,
+            token: Address,
+            total_amount: i128,
+            initial_amount: i128,
+            start_time: u64,
+            end_time: u64,
+            cancelable: bool,
+        ) -> u64 {
+            sender.require_auth();
+
+            let options = StreamOptions { cancelable, ..StreamOptions::default() };
+            Self::open_stream(&env, sender.clone(), sender, recipient, token, total_amount, initial_amount, start_time, end_time, start_time, options)
+        }
+
+        /// Same as `create_stream`, with the optional behaviour in `options`.
+        /// With `notify_recipient` set, a contract recipient has its
+        /// `on_stream_payment(stream_id, amount, token)` called after each payout.
+        pub fn create_stream_with_options(
+            env: Env,
+            sender: Address,
+            recipient: Address,
+            token: Address,
+            total_amount: i128,
+            initial_amount: i128,
+            start_time: u64,
+            end_time: u64,
+            options: StreamOptions,
+        ) -> u64 {
+            sender.require_auth();
+
+            Self::open_stream(&env, sender.clone(), sender, recipient, token, total_amount, initial_amount, start_time, end_time, start_time, options)
+        }
+
+        /// Same as `create_stream`, but idempotent per `(sender, idempotency_key)`.
+        /// The first call creates the stream and records its id under the key;
+        /// later calls with the same key return that id without creating or
+        /// funding anything, whatever the other arguments are. Lets integrators
+        /// retry safely and look streams up by their own reference instead of
+        /// a bare id that also exists on other deployments.
+        pub fn create_stream_with_key(
+            env: Env,
+            sender: Address,
+            recipient: Address,
+            token: Address,
+            total_amount: i128,
+            initial_amount: i128,
+            start_time: u64,
+            end_time: u64,
+            idempotency_key: BytesN<32>,
+        ) -> u64 {
+            sender.require_auth();
+
+            let key = SenderKey::IdempotencyKey(sender.clone(), idempotency_key);
+            if let Some(existing) = env.storage().persistent().get::<_, u64>(&key) {
+                Self::extend_persistent(&env, &key);
+                return existing;
+            }
+
+            let stream_id = Self::open_stream(
+                &env, sender.clone(), sender, recipient, token, total_amount, initial_amount, start_time, end_time, start_time,
+                StreamOptions::default(),
+            );
+            env.storage().persistent().set(&key, &stream_id);
+            Self::extend_persistent(&env, &key);
+            stream_id
+        }
     }
 }
 
+#[cfg(test)]
+#[allow(clippy::module_inception)]
 mod test;
\ No newline at end of file
@@ -1,9 +1,55 @@
+use soroban_sdk::testutils::Address as _;
+use soroban_sdk::{token, Address, Env};
+use crate::{PaymentStreamContract, PaymentStreamContractClient, StreamOptions};
+
+fn setup_contract(env: &Env, fee_rate: u32) -> (PaymentStreamContractClient<'_>, Address, Address, Address, Address) {
+    let admin = Address::generate(env);
+    let fee_collector = Address::generate(env);
+
+    let sac = env.register_stellar_asset_contract_v2(admin.clone());
+    let token = sac.address();
+
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), fee_rate));
+    let client = PaymentStreamContractClient::new(env, &contract_id);
+
+    (client, contract_id, token, admin, fee_collector)
+}
+
+/// Mint `total` to a fresh sender and open a fully funded 0..100 stream to `recipient`
+fn open_funded_stream(
+    env: &Env,
+    client: &PaymentStreamContractClient<'_>,
+    token: &Address,
+    recipient: &Address,
+    total: i128,
+    options: &StreamOptions,
+) -> (u64, Address) {
+    let sender = Address::generate(env);
+    token::StellarAssetClient::new(env, token).mint(&sender, &total);
+    let stream_id = client.create_stream_with_options(&sender, recipient, token, &total, &total, &0, &100, options);
+    (stream_id, sender)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use soroban_sdk::testutils::{Address as _, Events, Ledger, MockAuth, MockAuthInvoke};
-    use soroban_sdk::{token, Address, Env, IntoVal};
-    use crate::{PaymentStreamContract, PaymentStreamContractClient, StreamStatus};
+    use soroban_sdk::testutils::{Events, Ledger, LedgerInfo, MockAuth, MockAuthInvoke};
+    use soroban_sdk::{Bytes, BytesN, IntoVal, Symbol};
+    use crate::{
+        ActivityEntry, AdminAction, Beneficiary, CallbackFailedEvent, ContractPausedEvent,
+        ContractUpgradedEvent, CreationFeeChangedEvent, DataKey, DelegationRevokedEvent, Error,
+        ErrorExt, ExcessSweptEvent, FeeChangeProposedEvent, FeeCollectorChangedEvent,
+        FeeHolidayEvent, FeeRegistryChangedEvent, FeesClaimedEvent, ForwardSkippedEvent,
+        FundingProgress, FundingStatus, IntegrityReport, LowBalanceWarningEvent,
+        MaxDurationChangedEvent, Milestone, MilestoneApprovedEvent, ProtocolFeeRateChangedEvent,
+        QuarantineReleasedEvent, QuarantineResolution, RecipientChangedEvent, RecipientPosition,
+        RoleChangedEvent, SettlementAdapter, SettlementFallbackEvent, SettlementSwappedEvent,
+        StaleFundsReclaimedEvent, StreamCurve, StreamForceResumedEvent, StreamFullyFundedEvent,
+        StreamLimits, StreamLimitsChangedEvent, StreamMetadataUpdatedEvent, StreamQuarantinedEvent,
+        StreamRejectedEvent, StreamRenewedEvent, StreamSplitEvent, StreamStatus, StreamTemplate,
+        StreamTtlBumpedEvent, TemplateRef, TimeBasis, TokenStreamMetrics, TransferShortfallEvent,
+        TtlPolicy, VestingBaseline, WithdrawalEvent, WithdrawalQuote, WithdrawalRoutedEvent,
+    };
 
 
     
@@ -20,11 +66,9 @@ mod test {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         // Mint tokens to sender
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
@@ -64,11 +108,9 @@ mod test {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -100,11 +142,9 @@ mod test {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -143,11 +183,9 @@ mod test {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -186,11 +224,9 @@ mod test {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -225,10 +261,9 @@ mod test {
 
         let admin = Address::generate(&env);
         let fee_collector = Address::generate(&env);
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
         client.get_stream(&999);
     }
 
@@ -245,19 +280,10 @@ mod test {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
         env.mock_auths(&[
-            MockAuth {
-                address: &admin,
-                invoke: &MockAuthInvoke {
-                    contract: &contract_id,
-                    fn_name: "initialize",
-                    args: (&admin, &fee_collector, &0u32).into_val(&env),
-                    sub_invokes: &[],
-                },
-            },
             MockAuth {
                 address: &admin,
                 invoke: &MockAuthInvoke {
@@ -283,9 +309,6 @@ mod test {
             },
         ]);
 
-        let fee_collector = Address::generate(&env);
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -317,11 +340,9 @@ fn test_pause_and_resume_stream() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
-    let client = PaymentStreamContractClient::new(&env, &contract_id);
-
     let fee_collector = Address::generate(&env);
-    client.initialize(&admin, &fee_collector, &0);
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
+    let client = PaymentStreamContractClient::new(&env, &contract_id);
 
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
@@ -364,11 +385,9 @@ fn test_pause_and_resume_stream() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -409,11 +428,9 @@ fn test_pause_and_resume_stream() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -445,11 +462,9 @@ fn test_pause_and_resume_stream() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -481,11 +496,9 @@ fn test_pause_and_resume_stream() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -523,11 +536,9 @@ fn test_pause_and_resume_stream() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -567,11 +578,9 @@ fn test_pause_and_resume_stream() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -604,11 +613,9 @@ fn test_set_delegate() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -647,11 +654,9 @@ fn test_delegate_withdraw() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -672,7 +677,7 @@ fn test_delegate_withdraw() {
 
         // Verify event was emitted (at least one event should exist)
         let events = env.events().all();
-        assert!(events.len() > 0);
+        assert!(!events.is_empty());
 }
 
 #[test]
@@ -689,11 +694,9 @@ fn test_revoke_delegate() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -739,11 +742,9 @@ fn test_set_self_delegate() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -776,11 +777,9 @@ fn test_overwrite_delegate() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -819,11 +818,9 @@ fn test_revoke_nonexistent_delegate() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -860,20 +857,11 @@ fn test_unauthorized_delegate_withdraw_after_revoke() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
     // Use specific mock_auths for setup operations
     env.mock_auths(&[
-        MockAuth {
-            address: &admin,
-            invoke: &MockAuthInvoke {
-                contract: &contract_id,
-                fn_name: "initialize",
-                args: (&admin, &fee_collector, &0u32).into_val(&env),
-                sub_invokes: &[],
-            },
-        },
         MockAuth {
             address: &admin,
             invoke: &MockAuthInvoke {
@@ -912,8 +900,6 @@ fn test_unauthorized_delegate_withdraw_after_revoke() {
         },
     ]);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -956,11 +942,9 @@ fn test_recipient_can_still_withdraw_after_delegate_set() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1004,11 +988,9 @@ fn test_pausing_stops_token_vesting() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1065,11 +1047,9 @@ fn test_resuming_continues_from_where_it_left_off() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1135,11 +1115,9 @@ fn test_withdrawable_amount_zero_for_paused_streams() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1189,11 +1167,9 @@ fn test_stream_paused_event_emitted() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1230,11 +1206,9 @@ fn test_stream_resumed_event_emitted() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1273,11 +1247,9 @@ fn test_stream_resumed_event_emitted() {
         let admin = Address::generate(&env);
         let fee_collector = Address::generate(&env);
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 100_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &100);
-
         // Verify protocol metrics are initialized
         let metrics = client.get_protocol_metrics();
         
@@ -1301,11 +1273,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1351,11 +1321,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1395,11 +1363,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1451,11 +1417,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1498,11 +1462,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1547,11 +1509,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1591,11 +1551,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &1000);
 
@@ -1634,11 +1592,9 @@ fn test_stream_resumed_event_emitted() {
         let sac = env.register_stellar_asset_contract_v2(admin.clone());
         let token = sac.address();
 
-        let contract_id = env.register(PaymentStreamContract, ());
+        let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
         let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-        client.initialize(&admin, &fee_collector, &0);
-
         let token_admin = token::StellarAssetClient::new(&env, &token);
         token_admin.mint(&sender, &6000);
 
@@ -1682,7 +1638,9 @@ fn test_stream_resumed_event_emitted() {
         
         assert_eq!(protocol_metrics.total_active_streams, 3);
         assert_eq!(protocol_metrics.total_tokens_streamed, 6000);
+        assert_eq!(protocol_metrics.total_committed, 6000);
         assert_eq!(protocol_metrics.total_streams_created, 3);
+        assert_eq!(client.get_token_metrics(&token).total_streamed, 6000);
     }
 
     #[test]
@@ -1698,11 +1656,9 @@ fn test_only_sender_can_pause() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1736,11 +1692,9 @@ fn test_only_sender_can_resume() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1778,11 +1732,9 @@ fn test_withdraw_after_pause_and_resume() {
     let sac = env.register_stellar_asset_contract_v2(admin.clone());
     let token = sac.address();
 
-    let contract_id = env.register(PaymentStreamContract, ());
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 0_u32));
     let client = PaymentStreamContractClient::new(&env, &contract_id);
 
-    client.initialize(&admin, &fee_collector, &0);
-
     let token_admin = token::StellarAssetClient::new(&env, &token);
     token_admin.mint(&sender, &1000);
 
@@ -1829,5 +1781,5682 @@ fn test_withdraw_after_pause_and_resume() {
     assert!(recipient_balance > 0);
     assert_eq!(recipient_balance, 600); // 100 + 500
 }
-    
+
+
+#[test]
+fn test_stream_summary_matches_individual_getters() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(40);
+    client.withdraw(&stream_id, &100);
+    client.set_delegate(&stream_id, &delegate);
+    env.ledger().set_timestamp(60);
+
+    let summary = client.get_stream_summary(&stream_id);
+    let stream = client.get_stream(&stream_id);
+    let metrics = client.get_stream_metrics(&stream_id);
+
+    assert_eq!(summary.stream.id, stream.id);
+    assert_eq!(summary.stream.withdrawn_amount, stream.withdrawn_amount);
+    assert_eq!(summary.stream.status, stream.status);
+    assert_eq!(summary.metrics.withdrawal_count, metrics.withdrawal_count);
+    assert_eq!(summary.metrics.total_withdrawn, metrics.total_withdrawn);
+    assert_eq!(summary.metrics.current_delegate, metrics.current_delegate);
+    assert_eq!(summary.delegate, client.get_delegate(&stream_id));
+    assert_eq!(summary.withdrawable, client.withdrawable_amount(&stream_id));
+    assert_eq!(summary.withdrawable, 500);
+    assert_eq!(summary.fee_rate, client.get_protocol_fee_rate());
+}
+
+#[test]
+fn test_stream_summaries_skip_missing_ids() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let first = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let second = client.create_stream(&sender, &recipient, &token, &1000, &500, &0, &200);
+
+    env.ledger().set_timestamp(50);
+
+    let ids = soroban_sdk::vec![&env, first, 999, second];
+    let summaries = client.get_stream_summaries(&ids);
+
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries.get(0).unwrap().stream.id, first);
+    assert_eq!(summaries.get(0).unwrap().withdrawable, 500);
+    assert_eq!(summaries.get(1).unwrap().stream.id, second);
+    assert_eq!(summaries.get(1).unwrap().withdrawable, 250);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #17)")]
+fn test_stream_summaries_batch_too_large() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+
+    let mut ids = soroban_sdk::Vec::new(&env);
+    for id in 0..26u64 {
+        ids.push_back(id);
+    }
+    client.get_stream_summaries(&ids);
+}
+
+#[test]
+fn test_initialize_emits_fee_config_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let fee_collector = Address::generate(&env);
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 150_u32));
+
+    let events = env.events().all();
+    assert_eq!(events.len(), 2);
+
+    let (emitter, topics, data) = events.get(0).unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("ProtocolFeeRateChanged",).into_val(&env));
+    let payload: ProtocolFeeRateChangedEvent = data.into_val(&env);
+    assert_eq!(payload, ProtocolFeeRateChangedEvent { old_rate: 0, new_rate: 150 });
+
+    let (_, topics, data) = events.get(1).unwrap();
+    assert_eq!(topics, ("FeeCollectorChanged",).into_val(&env));
+    let payload: FeeCollectorChangedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        FeeCollectorChangedEvent { old_collector: None, new_collector: Some(fee_collector), collector_is_admin: false }
+    );
+}
+
+#[test]
+fn test_set_protocol_fee_rate_emits_event() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+
+    client.set_protocol_fee_rate(&admin, &50);
+
+    let events = env.events().all();
+    let (emitter, topics, data) = events.last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("ProtocolFeeRateChanged",).into_val(&env));
+    let payload: ProtocolFeeRateChangedEvent = data.into_val(&env);
+    assert_eq!(payload, ProtocolFeeRateChangedEvent { old_rate: 100, new_rate: 50 });
+    assert_eq!(client.get_protocol_fee_rate(), 50);
+}
+
+#[test]
+fn test_set_fee_collector_emits_event() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, _token, admin, fee_collector) = setup_contract(&env, 100);
+    let new_collector = Address::generate(&env);
+
+    client.set_fee_collector(&admin, &Some(new_collector.clone()));
+
+    let events = env.events().all();
+    let (emitter, topics, data) = events.last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("FeeCollectorChanged",).into_val(&env));
+    let payload: FeeCollectorChangedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        FeeCollectorChangedEvent {
+            old_collector: Some(fee_collector),
+            new_collector: Some(new_collector.clone()),
+            collector_is_admin: false,
+        }
+    );
+    assert_eq!(client.get_fee_collector(), Some(new_collector));
+}
+
+#[test]
+fn test_get_fee_collector_not_initialized() {
+    let env = Env::default();
+
+    let contract_id = env.register(PaymentStreamContract, (Address::generate(&env), None::<Address>, 0_u32));
+    let client = PaymentStreamContractClient::new(&env, &contract_id);
+
+    assert_eq!(client.get_fee_collector(), None);
+}
+
+/// Smallest wasm module the host accepts as contract code: just the
+/// `contractenvmetav0` custom section declaring interface version 22.
+const UPGRADE_TARGET_WASM: [u8; 40] = [
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    0x00, 0x1e, 0x11,
+    b'c', b'o', b'n', b't', b'r', b'a', b'c', b't', b'e', b'n', b'v', b'm', b'e', b't', b'a', b'v', b'0',
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn test_upgrade_preserves_streams() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(client.version(), 1);
+
+    let new_wasm_hash = env.deployer().upload_contract_wasm(Bytes::from_array(&env, &UPGRADE_TARGET_WASM));
+    client.upgrade(&admin, &new_wasm_hash);
+
+    let events = env.events().all();
+    let (emitter, topics, data) = events.last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("ContractUpgraded",).into_val(&env));
+    let payload: ContractUpgradedEvent = data.into_val(&env);
+    assert_eq!(payload, ContractUpgradedEvent { new_wasm_hash, version: 2 });
+
+    // The new code exports nothing, so read storage directly to confirm the
+    // stream and version survived the code swap.
+    env.as_contract(&contract_id, || {
+        let stream: crate::Stream = env.storage().persistent().get(&crate::DataKey::Stream(stream_id)).unwrap();
+        assert_eq!(stream.sender, sender);
+        assert_eq!(stream.recipient, recipient);
+        assert_eq!(stream.balance, 1000);
+        let version: u32 = env.storage().instance().get(&crate::DataKey::Version).unwrap();
+        assert_eq!(version, 2);
+    });
+    assert_eq!(token::Client::new(&env, &token).balance(&contract_id), 1000);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_upgrade_requires_admin() {
+    let env = Env::default();
+
+    let (client, contract_id, _token, admin, _fee_collector) = {
+        env.mock_all_auths();
+        setup_contract(&env, 0)
+    };
+    let stranger = Address::generate(&env);
+    let new_wasm_hash = env.deployer().upload_contract_wasm(Bytes::from_array(&env, &UPGRADE_TARGET_WASM));
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &stranger,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "upgrade",
+                args: (admin.clone(), new_wasm_hash.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .upgrade(&admin, &new_wasm_hash);
+}
+
+#[test]
+fn test_fee_tracking_by_day() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &100_000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &100_000, &100_000, &0, &200_000);
+
+    // Fees paid before opting in are not tracked
+    env.ledger().set_timestamp(10_000);
+    client.withdraw(&stream_id, &1_000);
+    client.enable_fee_tracking(&recipient);
+    assert!(client.is_fee_tracking_enabled(&recipient));
+
+    // Day 0: two withdrawals of 1000 at 1% => 20 in fees
+    env.ledger().set_timestamp(20_000);
+    client.withdraw(&stream_id, &1_000);
+    client.withdraw(&stream_id, &1_000);
+
+    // Day 1: one withdrawal of 5000 => 50 in fees
+    env.ledger().set_timestamp(90_000);
+    client.withdraw(&stream_id, &5_000);
+
+    assert_eq!(client.get_fees_paid(&recipient, &0, &86_399), 20);
+    assert_eq!(client.get_fees_paid(&recipient, &86_400, &172_799), 50);
+    assert_eq!(client.get_fees_paid(&recipient, &0, &172_799), 70);
+    assert_eq!(client.get_fees_paid(&sender, &0, &172_799), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #18)")]
+fn test_fee_query_range_too_large() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let address = Address::generate(&env);
+
+    client.get_fees_paid(&address, &0, &(400 * 86_400));
+}
+
+/// Storage layout of Stellar Asset Contract balances, used to fund accounts
+/// with native XLM in tests since the native SAC has no admin to mint.
+#[soroban_sdk::contracttype]
+enum SacDataKey {
+    Balance(Address),
+}
+
+#[soroban_sdk::contracttype]
+struct SacBalance {
+    amount: i128,
+    authorized: bool,
+    clawback: bool,
+}
+
+fn register_native_token(env: &Env) -> Address {
+    env.deployer()
+        .with_stellar_asset(Bytes::from_array(env, &[0u8; 4]))
+        .deploy()
+}
+
+fn fund_native(env: &Env, native: &Address, holder: &Address, amount: i128) {
+    env.as_contract(native, || {
+        env.storage().persistent().set(
+            &SacDataKey::Balance(holder.clone()),
+            &SacBalance { amount, authorized: true, clawback: false },
+        );
+    });
+}
+
+#[test]
+fn test_native_xlm_stream_lifecycle() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, fee_collector) = setup_contract(&env, 100);
+    let native = register_native_token(&env);
+    assert!(client.is_native(&native));
+    assert!(!client.is_native(&token));
+
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    fund_native(&env, &native, &sender, 10_000);
+
+    let xlm = token::Client::new(&env, &native);
+    let stream_id = client.create_stream(&sender, &recipient, &native, &10_000, &4_000, &0, &100);
+    client.deposit(&stream_id, &6_000);
+    assert_eq!(xlm.balance(&contract_id), 10_000);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &5_000);
+    assert_eq!(xlm.balance(&recipient), 4_950);
+    assert_eq!(client.get_accrued_fees(&native), 50);
+
+    // 1 unit at 1% rounds the fee down to zero: nothing accrues
+    env.ledger().set_timestamp(51);
+    client.withdraw(&stream_id, &1);
+    assert_eq!(xlm.balance(&recipient), 4_951);
+    assert_eq!(client.get_accrued_fees(&native), 50);
+
+    // Cancel settles the other 99 vested units, and the carried fraction
+    // makes their fee 1
+    client.cancel_stream(&stream_id);
+    assert_eq!(xlm.balance(&recipient), 5_049);
+    assert_eq!(xlm.balance(&sender), 4_900);
+    assert_eq!(client.claim_fees(&native), 51);
+    assert_eq!(xlm.balance(&fee_collector), 51);
+    assert_eq!(xlm.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_single_address_plays_every_role() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let operator = Address::generate(&env);
+    let token = env.register_stellar_asset_contract_v2(operator.clone()).address();
+    let contract_id = env.register(PaymentStreamContract, (&operator, Some(Address::generate(&env)), 100_u32));
+    let client = PaymentStreamContractClient::new(&env, &contract_id);
+
+    // Initialize rejects admin == collector, but the collector can be
+    // reassigned to the admin afterwards and the event flags it
+    client.set_fee_collector(&operator, &Some(operator.clone()));
+    let (_, _, data) = env.events().all().last().unwrap();
+    let payload: FeeCollectorChangedEvent = data.into_val(&env);
+    assert!(payload.collector_is_admin);
+
+    token::StellarAssetClient::new(&env, &token).mint(&operator, &1000);
+    let token_client = token::Client::new(&env, &token);
+
+    let recipient = Address::generate(&env);
+    let stream_id = client.create_stream(&operator, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(token_client.balance(&operator), 0);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+    assert_eq!(token_client.balance(&recipient), 495);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 500);
+
+    // The operator gets the unvested half back plus the fee it collected
+    client.cancel_stream(&stream_id);
+    client.claim_fees(&token);
+    assert_eq!(token_client.balance(&operator), 505);
+    assert_eq!(token_client.balance(&contract_id), 0);
+
+    client.set_fee_collector(&operator, &Some(Address::generate(&env)));
+    let (_, _, data) = env.events().all().last().unwrap();
+    let payload: FeeCollectorChangedEvent = data.into_val(&env);
+    assert!(!payload.collector_is_admin);
+}
+
+fn payroll_template(token: &Address) -> StreamTemplate {
+    StreamTemplate {
+        token: token.clone(),
+        duration: 1000,
+        cliff: 250,
+        curve: StreamCurve::Linear,
+        cancelable: true,
+    }
+}
+
+#[test]
+fn test_streams_from_template_share_parameters() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let owner = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&owner, &3000);
+
+    let template_id = client.create_template(&owner, &payroll_template(&token));
+    assert_eq!(client.list_templates(&owner), soroban_sdk::vec![&env, template_id]);
+
+    let first = client.create_stream_from_template(&owner, &template_id, &alice, &1000, &100);
+    let second = client.create_stream_from_template(&owner, &template_id, &bob, &2000, &500);
+
+    let a = client.get_stream(&first);
+    let b = client.get_stream(&second);
+    assert_eq!((a.end_time, a.cliff_time), (1100, 350));
+    assert_eq!((b.end_time, b.cliff_time), (1500, 750));
+    assert_eq!(a.end_time - a.start_time, b.end_time - b.start_time);
+    assert_eq!(a.balance, 1000);
+    assert_eq!(b.balance, 2000);
+
+    // Nothing vests before the cliff, then vesting catches up linearly from start
+    env.ledger().set_timestamp(349);
+    assert_eq!(client.withdrawable_amount(&first), 0);
+    env.ledger().set_timestamp(350);
+    assert_eq!(client.withdrawable_amount(&first), 250);
+}
+
+#[test]
+fn test_template_edit_does_not_affect_existing_streams() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let owner = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&owner, &2000);
+
+    let template_id = client.create_template(&owner, &payroll_template(&token));
+    let old_stream = client.create_stream_from_template(&owner, &template_id, &recipient, &1000, &0);
+
+    let mut edited = payroll_template(&token);
+    edited.duration = 2000;
+    edited.cliff = 0;
+    client.update_template(&template_id, &edited);
+    assert_eq!(client.get_template(&template_id).version, 2);
+
+    let new_stream = client.create_stream_from_template(&owner, &template_id, &recipient, &1000, &0);
+    assert_eq!(client.get_stream(&old_stream).end_time, 1000);
+    assert_eq!(client.get_stream(&new_stream).end_time, 2000);
+    assert_eq!(client.get_stream_template(&old_stream), Some(TemplateRef { template_id, version: 1 }));
+    assert_eq!(client.get_stream_template(&new_stream), Some(TemplateRef { template_id, version: 2 }));
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_update_template_requires_owner() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let owner = Address::generate(&env);
+    let template_id = client.create_template(&owner, &payroll_template(&token));
+
+    env.set_auths(&[]);
+    client.update_template(&template_id, &payroll_template(&token));
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #19)")]
+fn test_create_stream_from_missing_template() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    client.create_stream_from_template(&sender, &7, &Address::generate(&env), &1000, &0);
+}
+
+fn create_non_cancelable_stream(env: &Env) -> (PaymentStreamContractClient<'_>, u64, Address, Address) {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, 0);
+    let recipient = Address::generate(env);
+    let options = StreamOptions { cancelable: false, ..StreamOptions::default() };
+    let (stream_id, _sender) = open_funded_stream(env, &client, &token, &recipient, 1000, &options);
+    (client, stream_id, token, recipient)
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #20)")]
+fn test_non_cancelable_stream_cannot_be_canceled() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, stream_id, _token, _recipient) = create_non_cancelable_stream(&env);
+    assert!(!client.get_stream(&stream_id).cancelable);
+    client.cancel_stream(&stream_id);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #20)")]
+fn test_non_cancelable_stream_cannot_be_paused() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, stream_id, _token, _recipient) = create_non_cancelable_stream(&env);
+    client.pause_stream(&stream_id);
+}
+
+#[test]
+fn test_non_cancelable_stream_withdrawals_proceed() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, stream_id, token, recipient) = create_non_cancelable_stream(&env);
+
+    env.ledger().set_timestamp(40);
+    client.withdraw(&stream_id, &400);
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&stream_id);
+
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 1000);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+}
+
+const THIRTY_DAYS: u64 = 30 * 86400;
+
+#[test]
+fn test_recipient_change_cooperative() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    let new_recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &delegate);
+
+    client.propose_recipient_change(&stream_id, &new_recipient);
+    assert_eq!(client.get_pending_recipient_change(&stream_id), Some(new_recipient.clone()));
+
+    client.approve_recipient_change(&stream_id, &recipient);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("RecipientChanged", stream_id).into_val(&env));
+    let payload: RecipientChangedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        RecipientChangedEvent {
+            stream_id,
+            old_recipient: recipient,
+            new_recipient: new_recipient.clone(),
+            approved_by_admin: false,
+        }
+    );
+
+    assert_eq!(client.get_stream(&stream_id).recipient, new_recipient);
+    assert_eq!(client.get_delegate(&stream_id), None);
+    assert_eq!(client.get_pending_recipient_change(&stream_id), None);
+
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&stream_id);
+    assert_eq!(token::Client::new(&env, &token).balance(&new_recipient), 1000);
+}
+
+#[test]
+fn test_recipient_change_admin_after_inactivity() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let new_recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.propose_recipient_change(&stream_id, &new_recipient);
+
+    env.ledger().set_timestamp(THIRTY_DAYS);
+    client.approve_recipient_change(&stream_id, &admin);
+
+    let (_, _, data) = env.events().all().last().unwrap();
+    let payload: RecipientChangedEvent = data.into_val(&env);
+    assert!(payload.approved_by_admin);
+    assert_eq!(client.get_stream(&stream_id).recipient, new_recipient);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #22)")]
+fn test_recipient_change_admin_rejected_before_window() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    // Recipient activity restarts the inactivity window
+    env.ledger().set_timestamp(THIRTY_DAYS - 10);
+    client.withdraw(&stream_id, &100);
+    client.propose_recipient_change(&stream_id, &Address::generate(&env));
+
+    env.ledger().set_timestamp(THIRTY_DAYS + 10);
+    client.approve_recipient_change(&stream_id, &admin);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #3)")]
+fn test_recipient_change_rejects_other_approvers() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.propose_recipient_change(&stream_id, &Address::generate(&env));
+
+    env.ledger().set_timestamp(THIRTY_DAYS * 2);
+    client.approve_recipient_change(&stream_id, &sender);
+}
+
+#[test]
+fn test_withdraw_to_routes_to_cold_wallet() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let cold_wallet = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(50);
+    client.withdraw_to(&stream_id, &500, &cold_wallet);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("WithdrawalRouted", stream_id).into_val(&env));
+    let payload: WithdrawalRoutedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        WithdrawalRoutedEvent { stream_id, destination: cold_wallet.clone(), amount: 500, net_amount: 495 }
+    );
+
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&cold_wallet), 495);
+    assert_eq!(token_client.balance(&recipient), 0);
+    assert_eq!(client.get_accrued_fees(&token), 5);
+    assert_eq!(token_client.balance(&fee_collector), 0);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 500);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #23)")]
+fn test_withdraw_to_delegate_redirect_blocked_by_default() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &delegate);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw_to(&stream_id, &500, &delegate);
+}
+
+#[test]
+fn test_withdraw_to_delegate_redirect_allowed_with_flag() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    let cold_wallet = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &delegate);
+
+    env.ledger().set_timestamp(50);
+    // Routing to the recipient is always fine for a delegate
+    client.withdraw_to(&stream_id, &200, &recipient);
+
+    client.set_delegate_can_redirect(&stream_id, &true);
+    assert!(client.get_delegate_can_redirect(&stream_id));
+    client.withdraw_to(&stream_id, &300, &cold_wallet);
+
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&recipient), 200);
+    assert_eq!(token_client.balance(&cold_wallet), 300);
+}
+
+#[test]
+fn test_token_metrics_split_by_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, usdc, admin, _fee_collector) = setup_contract(&env, 0);
+    let eurc = env.register_stellar_asset_contract_v2(admin).address();
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &usdc).mint(&sender, &3000);
+    token::StellarAssetClient::new(&env, &eurc).mint(&sender, &500);
+
+    let usdc_a = client.create_stream(&sender, &recipient, &usdc, &1000, &1000, &0, &100);
+    let usdc_b = client.create_stream(&sender, &recipient, &usdc, &2000, &2000, &0, &100);
+    let eurc_a = client.create_stream(&sender, &recipient, &eurc, &500, &500, &0, &100);
+
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&usdc_a);
+    client.withdraw(&eurc_a, &200);
+    client.cancel_stream(&usdc_b);
+
+    assert_eq!(
+        client.get_token_metrics(&usdc),
+        TokenStreamMetrics { total_streamed: 3000, active_streams: 0, total_withdrawn: 3000, escrowed_balance: 0 }
+    );
+    assert_eq!(
+        client.get_token_metrics(&eurc),
+        TokenStreamMetrics { total_streamed: 500, active_streams: 1, total_withdrawn: 200, escrowed_balance: 300 }
+    );
+
+    let global = client.get_protocol_metrics();
+    assert_eq!(global.total_streams_created, 3);
+    assert_eq!(global.total_active_streams, 1);
+    assert_eq!(global.total_tokens_streamed, 3500);
+}
+
+#[test]
+fn test_tokens_streamed_counts_funding_not_promises() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &0, &0, &100);
+    let metrics = client.get_protocol_metrics();
+    assert_eq!((metrics.total_tokens_streamed, metrics.total_committed), (0, 1000));
+
+    client.deposit(&stream_id, &300);
+    let metrics = client.get_protocol_metrics();
+    assert_eq!((metrics.total_tokens_streamed, metrics.total_committed), (300, 1000));
+
+    client.deposit(&stream_id, &200);
+    let metrics = client.get_protocol_metrics();
+    assert_eq!((metrics.total_tokens_streamed, metrics.total_committed), (500, 1000));
+
+    // Half vested at cancel: the other half of the commitment is released
+    env.ledger().set_timestamp(50);
+    client.cancel_stream(&stream_id);
+    let metrics = client.get_protocol_metrics();
+    assert_eq!((metrics.total_tokens_streamed, metrics.total_committed), (500, 500));
+}
+
+#[test]
+fn test_cancel_paused_stream_releases_unvested_as_of_pause() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(20);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(80);
+    client.cancel_stream(&stream_id);
+
+    assert_eq!(client.get_protocol_metrics().total_committed, 200);
+}
+
+/// Token stand-in whose `transfer` calls back into the stream contract. In
+/// its own module so its entrypoints don't clash with other stand-ins.
+mod reentrant_token {
+    use super::*;
+
+    #[soroban_sdk::contract]
+    pub struct ReentrantToken;
+
+    #[soroban_sdk::contractimpl]
+    impl ReentrantToken {
+        pub fn arm(env: Env, target: Address, stream_id: u64) {
+            env.storage().instance().set(&soroban_sdk::symbol_short!("target"), &(target, stream_id));
+        }
+
+        pub fn transfer(env: Env, _from: Address, _to: Address, amount: i128) {
+            let target: Option<(Address, u64)> = env.storage().instance().get(&soroban_sdk::symbol_short!("target"));
+            match target {
+                Some((target, stream_id)) => PaymentStreamContractClient::new(&env, &target).withdraw(&stream_id, &1),
+                // Before `arm` only the escrow deposit comes through
+                None => env.storage().instance().set(&soroban_sdk::symbol_short!("held"), &amount),
+            }
+        }
+
+        pub fn balance(env: Env, _id: Address) -> i128 {
+            env.storage().instance().get(&soroban_sdk::symbol_short!("held")).unwrap_or(0)
+        }
+    }
+}
+use reentrant_token::{ReentrantToken, ReentrantTokenClient};
+
+// The host itself refuses contract re-entry, so the call dies before our own
+// guard runs and surfaces as a rejected transfer;
+// `test_withdraw_rejected_while_stream_locked` covers the guard.
+#[test]
+#[should_panic(expected = "Error(Contract, #26)")]
+fn test_token_cannot_reenter_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let evil_token = env.register(ReentrantToken, ());
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+
+    let stream_id = client.create_stream(&sender, &recipient, &evil_token, &1000, &1000, &0, &100);
+    ReentrantTokenClient::new(&env, &evil_token).arm(&contract_id, &stream_id);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &10);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #24)")]
+fn test_withdraw_rejected_while_stream_locked() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    // Simulate a withdrawal still in flight on this stream
+    env.as_contract(&contract_id, || {
+        env.storage().temporary().set(&crate::DataKey::Lock(stream_id), &true);
+    });
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &10);
+}
+
+#[test]
+fn test_sweep_excess_leaves_escrow_untouched() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let token_admin = token::StellarAssetClient::new(&env, &token);
+    let token_client = token::Client::new(&env, &token);
+    token_admin.mint(&sender, &3000);
+
+    let first = client.create_stream(&sender, &recipient, &token, &2000, &1000, &0, &100);
+    let second = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.deposit(&first, &1000);
+
+    // Tokens sent straight to the contract, outside any stream
+    token_admin.mint(&contract_id, &77);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&first, &400);
+    client.cancel_stream(&second);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 1600);
+
+    assert_eq!(client.sweep_excess(&token, &treasury), 77);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("ExcessSwept",).into_val(&env));
+    let payload: ExcessSweptEvent = data.into_val(&env);
+    assert_eq!(payload, ExcessSweptEvent { token: token.clone(), to: treasury.clone(), amount: 77 });
+    assert_eq!(token_client.balance(&treasury), 77);
+    // Escrow plus the 4 + 5 in accrued fees stays put
+    assert_eq!(token_client.balance(&contract_id), 1609);
+
+    // Nothing left to sweep, and the stream still pays out in full
+    assert_eq!(client.sweep_excess(&token, &treasury), 0);
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&first);
+    client.claim_fees(&token);
+    // All of `first` plus the half of `second` that vested before the cancel
+    assert_eq!(token_client.balance(&recipient) + token_client.balance(&fee_collector), 2500);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_sweep_excess_requires_admin() {
+    let env = Env::default();
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    client.sweep_excess(&token, &Address::generate(&env));
+}
+
+#[test]
+fn test_withdraw_max_batch_across_tokens() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, usdc, admin, fee_collector) = setup_contract(&env, 100);
+    let eurc = env.register_stellar_asset_contract_v2(admin).address();
+    let recipient = Address::generate(&env);
+    let client_a = Address::generate(&env);
+    let client_b = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &usdc).mint(&client_a, &4000);
+    token::StellarAssetClient::new(&env, &eurc).mint(&client_b, &1000);
+
+    let usdc_1 = client.create_stream(&client_a, &recipient, &usdc, &1000, &1000, &0, &100);
+    let usdc_2 = client.create_stream(&client_a, &recipient, &usdc, &2000, &2000, &0, &200);
+    let eurc_1 = client.create_stream(&client_b, &recipient, &eurc, &1000, &1000, &0, &100);
+    let not_started = client.create_stream(&client_a, &recipient, &usdc, &1000, &1000, &500, &600);
+
+    env.ledger().set_timestamp(100);
+    let nets = client.withdraw_max_batch(&soroban_sdk::vec![&env, usdc_1, usdc_2, eurc_1, not_started]);
+    assert_eq!(nets, soroban_sdk::vec![&env, 990, 990, 990, 0]);
+
+    // One transfer per (token, payee); fees accrue in the contract
+    let transfers = env.events().all().iter()
+        .filter(|(emitter, _, _)| *emitter == usdc || *emitter == eurc)
+        .count();
+    assert_eq!(transfers, 2);
+
+    let usdc_client = token::Client::new(&env, &usdc);
+    let eurc_client = token::Client::new(&env, &eurc);
+    assert_eq!(usdc_client.balance(&recipient), 1980);
+    assert_eq!(client.get_accrued_fees(&usdc), 20);
+    assert_eq!(eurc_client.balance(&recipient), 990);
+    assert_eq!(client.get_accrued_fees(&eurc), 10);
+    assert_eq!(usdc_client.balance(&fee_collector), 0);
+    assert_eq!(usdc_client.balance(&contract_id), 2020);
+    assert_eq!(client.get_stream(&usdc_1).status, StreamStatus::Completed);
+    assert_eq!(client.get_stream(&usdc_2).withdrawn_amount, 1000);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_withdraw_max_batch_fails_on_foreign_stream() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+
+    let mine = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let theirs = client.create_stream(&sender, &stranger, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(100);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &recipient,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "withdraw_max_batch",
+                args: (soroban_sdk::vec![&env, mine, theirs],).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .withdraw_max_batch(&soroban_sdk::vec![&env, mine, theirs]);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #17)")]
+fn test_withdraw_max_batch_too_large() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let mut ids = soroban_sdk::Vec::new(&env);
+    for id in 1..=21u64 {
+        ids.push_back(id);
+    }
+    client.withdraw_max_batch(&ids);
+}
+
+#[test]
+fn test_stream_metadata_set_and_overwrite() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(client.get_stream_metadata(&stream_id), None);
+
+    let label = Symbol::new(&env, "April_retainer_Acme");
+    let invoice = BytesN::from_array(&env, &[7; 32]);
+    client.set_stream_metadata(&stream_id, &label, &invoice);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("StreamMetadataUpdated", stream_id).into_val(&env));
+    let payload: StreamMetadataUpdatedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        StreamMetadataUpdatedEvent { stream_id, label: label.clone(), external_ref: invoice.clone() }
+    );
+
+    let metadata = client.get_stream_metadata(&stream_id).unwrap();
+    assert_eq!((metadata.label, metadata.external_ref), (label, invoice));
+
+    env.ledger().set_timestamp(10);
+    let new_label = Symbol::new(&env, "May_retainer_Acme");
+    client.set_stream_metadata(&stream_id, &new_label, &BytesN::from_array(&env, &[8; 32]));
+    let metadata = client.get_stream_metadata(&stream_id).unwrap();
+    assert_eq!(metadata.label, new_label);
+    assert_eq!(metadata.updated_at, 10);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #7)")]
+fn test_stream_metadata_rejected_after_completion() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&stream_id);
+
+    client.set_stream_metadata(&stream_id, &Symbol::new(&env, "late"), &BytesN::from_array(&env, &[0; 32]));
+}
+
+#[test]
+fn test_tvl_counters_track_contract_balance() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let token_client = token::Client::new(&env, &token);
+    let senders = [Address::generate(&env), Address::generate(&env)];
+    let recipient = Address::generate(&env);
+    for sender in senders.iter() {
+        token::StellarAssetClient::new(&env, &token).mint(sender, &1_000_000);
+    }
+
+    let mut streams: [(u64, usize); 12] = [(0, 0); 12];
+    let mut stream_count = 0;
+    let mut seed: u64 = 0x5eed;
+    let mut now = 0;
+
+    for _ in 0..60 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let roll = seed >> 33;
+        let sender_idx = (roll % 2) as usize;
+
+        match roll % 4 {
+            0 if stream_count < streams.len() => {
+                let total = 1000 + (roll % 5000) as i128;
+                let initial = total / (1 + (roll % 3) as i128);
+                let id = client.create_stream(&senders[sender_idx], &recipient, &token, &total, &initial, &now, &(now + 100));
+                streams[stream_count] = (id, sender_idx);
+                stream_count += 1;
+            }
+            1 if stream_count > 0 => {
+                let (id, _) = streams[(roll as usize) % stream_count];
+                let stream = client.get_stream(&id);
+                let room = stream.total_amount - stream.balance;
+                if room > 0 && stream.status != StreamStatus::Canceled && stream.status != StreamStatus::Completed {
+                    client.deposit(&id, &(1 + (roll as i128) % room));
+                }
+            }
+            2 if stream_count > 0 => {
+                let (id, _) = streams[(roll as usize) % stream_count];
+                let stream = client.get_stream(&id);
+                let funded = stream.balance - stream.withdrawn_amount;
+                let amount = client.withdrawable_amount(&id).min(funded);
+                if amount > 0 {
+                    client.withdraw(&id, &amount);
+                }
+            }
+            3 if stream_count > 0 => {
+                let (id, _) = streams[(roll as usize) % stream_count];
+                let status = client.get_stream(&id).status;
+                if status == StreamStatus::Active || status == StreamStatus::Paused {
+                    client.cancel_stream(&id);
+                }
+            }
+            _ => {}
+        }
+
+        now += 7;
+        env.ledger().set_timestamp(now);
+
+        // Accrued protocol fees sit in the contract but aren't locked value
+        let contract_balance = token_client.balance(&contract_id) - client.get_accrued_fees(&token);
+        assert_eq!(client.get_tvl_by_token(&token), contract_balance);
+        assert_eq!(
+            client.get_tvl_by_sender(&senders[0]) + client.get_tvl_by_sender(&senders[1]),
+            contract_balance
+        );
+    }
+
+    assert!(stream_count >= 4);
+    assert!(client.get_token_metrics(&token).total_withdrawn > 0);
+
+    // Per-sender split matches the streams each sender still has escrowed
+    for (idx, sender) in senders.iter().enumerate() {
+        let mut expected = 0;
+        for (id, owner) in streams.iter().take(stream_count) {
+            let stream = client.get_stream(id);
+            if *owner == idx && stream.status != StreamStatus::Canceled {
+                expected += stream.balance - stream.withdrawn_amount;
+            }
+        }
+        assert_eq!(client.get_tvl_by_sender(sender), expected);
+    }
+}
+
+fn status_membership(client: &PaymentStreamContractClient<'_>, stream_id: u64) -> soroban_sdk::Vec<StreamStatus> {
+    let mut found = soroban_sdk::Vec::new(&client.env);
+    for status in [StreamStatus::Active, StreamStatus::Paused, StreamStatus::Canceled, StreamStatus::Completed] {
+        if client.get_streams_by_status(&status, &0, &100).contains(stream_id) {
+            found.push_back(status);
+        }
+    }
+    found
+}
+
+#[test]
+fn test_status_index_follows_transitions() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &3000);
+
+    let first = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let third = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(status_membership(&client, stream_id), soroban_sdk::vec![&env, StreamStatus::Active]);
+
+    client.pause_stream(&stream_id);
+    assert_eq!(status_membership(&client, stream_id), soroban_sdk::vec![&env, StreamStatus::Paused]);
+    // Swap-remove kept the other active streams reachable
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Active, &0, &100).len(), 2);
+    assert!(client.get_streams_by_status(&StreamStatus::Active, &0, &100).contains(first));
+    assert!(client.get_streams_by_status(&StreamStatus::Active, &0, &100).contains(third));
+
+    client.resume_stream(&stream_id);
+    assert_eq!(status_membership(&client, stream_id), soroban_sdk::vec![&env, StreamStatus::Active]);
+
+    env.ledger().set_timestamp(200);
+    client.withdraw_max(&stream_id);
+    assert_eq!(status_membership(&client, stream_id), soroban_sdk::vec![&env, StreamStatus::Completed]);
+
+    client.cancel_stream(&first);
+    assert_eq!(status_membership(&client, first), soroban_sdk::vec![&env, StreamStatus::Canceled]);
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Active, &0, &100), soroban_sdk::vec![&env, third]);
+}
+
+#[test]
+fn test_streams_by_status_pagination() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &5000);
+
+    for _ in 0..5 {
+        client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    }
+
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Active, &0, &2), soroban_sdk::vec![&env, 1, 2]);
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Active, &4, &10), soroban_sdk::vec![&env, 5]);
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Active, &9, &10).len(), 0);
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Paused, &0, &10).len(), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #25)")]
+fn test_initialize_rejects_admin_as_fee_collector() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    env.register(PaymentStreamContract, (&admin, Some(admin.clone()), 0_u32));
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #25)")]
+fn test_initialize_rejects_fee_without_collector() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.register(PaymentStreamContract, (Address::generate(&env), None::<Address>, 100_u32));
+}
+
+#[test]
+fn test_initialize_without_collector_and_zero_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let client = PaymentStreamContractClient::new(&env, &env.register(PaymentStreamContract, (Address::generate(&env), None::<Address>, 0_u32)));
+    assert_eq!(client.get_fee_collector(), None);
+    assert_eq!(client.get_protocol_fee_rate(), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #25)")]
+fn test_fee_rate_cannot_be_raised_without_collector() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let admin = Address::generate(&env);
+    let client = PaymentStreamContractClient::new(&env, &env.register(PaymentStreamContract, (&admin, None::<Address>, 0_u32)));
+    client.set_protocol_fee_rate(&admin, &50);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #25)")]
+fn test_collector_cannot_be_cleared_while_fee_nonzero() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+    client.set_fee_collector(&admin, &None);
+}
+
+#[test]
+fn test_collector_can_be_cleared_once_fee_is_zero() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    client.set_protocol_fee_rate(&admin, &0);
+    client.set_fee_collector(&admin, &None);
+    assert_eq!(client.get_fee_collector(), None);
+
+    // Withdrawals work without a collector when no fee is owed
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&stream_id);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 1000);
+}
+
+#[test]
+fn test_projected_withdrawable_matches_and_is_monotonic() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(30);
+    client.withdraw(&stream_id, &100);
+
+    let now = env.ledger().timestamp();
+    let projection = client.projected_withdrawable(&stream_id, &soroban_sdk::vec![&env, now]);
+    assert_eq!(projection.get(0).unwrap(), client.withdrawable_amount(&stream_id));
+
+    let timestamps = soroban_sdk::vec![&env, 0, 10, 30, 45, 60, 99, 100, 500];
+    let projection = client.projected_withdrawable(&stream_id, &timestamps);
+    assert_eq!(projection, soroban_sdk::vec![&env, 0, 0, 200, 350, 500, 890, 900, 900]);
+    for i in 1..projection.len() {
+        assert!(projection.get(i).unwrap() >= projection.get(i - 1).unwrap());
+    }
+
+    // Paused: frozen at the pause time
+    env.ledger().set_timestamp(50);
+    client.pause_stream(&stream_id);
+    let projection = client.projected_withdrawable(&stream_id, &soroban_sdk::vec![&env, 40, 50, 80]);
+    assert_eq!(projection, soroban_sdk::vec![&env, 300, 400, 400]);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #17)")]
+fn test_projected_withdrawable_caps_points() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+
+    let mut timestamps = soroban_sdk::Vec::new(&env);
+    for t in 0..51u64 {
+        timestamps.push_back(t);
+    }
+    client.projected_withdrawable(&stream_id, &timestamps);
+}
+
+fn withdrawal_event(env: &Env, stream_id: u64) -> WithdrawalEvent {
+    let topics: soroban_sdk::Vec<soroban_sdk::Val> = ("Withdrawal", stream_id).into_val(env);
+    let (_, _, data) = env.events().all().iter()
+        .find(|(_, event_topics, _)| *event_topics == topics)
+        .expect("no Withdrawal event");
+    data.into_val(env)
+}
+
+#[test]
+fn test_withdrawal_event_for_recipient_and_delegate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &200);
+    assert_eq!(
+        withdrawal_event(&env, stream_id),
+        WithdrawalEvent {
+            stream_id,
+            caller_is_delegate: false,
+            gross_amount: 200,
+            fee: 2,
+            tip: 0,
+            net_amount: 198,
+            recipient: recipient.clone(),
+            sponsor: None,
+        }
+    );
+
+    client.set_delegate(&stream_id, &delegate);
+    client.withdraw(&stream_id, &300);
+    assert_eq!(
+        withdrawal_event(&env, stream_id),
+        WithdrawalEvent {
+            stream_id,
+            caller_is_delegate: true,
+            gross_amount: 300,
+            fee: 3,
+            tip: 0,
+            net_amount: 297,
+            recipient,
+            sponsor: None,
+        }
+    );
+}
+
+#[test]
+fn test_withdrawal_event_without_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+
+    let event = withdrawal_event(&env, stream_id);
+    assert_eq!((event.gross_amount, event.fee, event.net_amount), (500, 0, 500));
+    assert!(!event.caller_is_delegate);
+}
+
+/// Stream on a revocable asset whose issuer has frozen the recipient
+fn setup_frozen_recipient(env: &Env) -> (PaymentStreamContractClient<'_>, Address, u64, Address) {
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(env, 100);
+    let sac = env.register_stellar_asset_contract_v2(admin);
+    sac.issuer().set_flag(soroban_sdk::testutils::IssuerFlags::RevocableFlag);
+    let token = sac.address();
+    let recipient = Address::generate(env);
+
+    let (stream_id, _sender) = open_funded_stream(env, &client, &token, &recipient, 1000, &StreamOptions::default());
+    token::StellarAssetClient::new(env, &token).set_authorized(&recipient, &false);
+    env.ledger().set_timestamp(50);
+    (client, token, stream_id, recipient)
+}
+
+#[test]
+fn test_withdraw_to_frozen_recipient_rejected_cleanly() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _token, stream_id, _recipient) = setup_frozen_recipient(&env);
+
+    let result = client.try_withdraw(&stream_id, &100);
+    assert_eq!(result, Err(Ok(Error::TokenTransferRejected.into())));
+    let result = client.try_withdraw_max_batch(&soroban_sdk::vec![&env, stream_id]);
+    assert_eq!(result, Err(Ok(Error::TokenTransferRejected.into())));
+
+    // Nothing was recorded
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 0);
+    assert_eq!(client.get_stream_metrics(&stream_id).withdrawal_count, 0);
+    assert_eq!(client.withdrawable_amount(&stream_id), 500);
+}
+
+#[test]
+fn test_rescue_to_alternate_pays_alternate_address() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, recipient) = setup_frozen_recipient(&env);
+    let alt = Address::generate(&env);
+
+    assert_eq!(client.rescue_to_alternate(&stream_id, &alt), 495);
+    assert_eq!(env.auths()[0].0, recipient);
+
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&alt), 495);
+    assert_eq!(token_client.balance(&recipient), 0);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 500);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #10)")]
+fn test_rescue_to_alternate_requires_withdrawable() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _token, stream_id, _recipient) = setup_frozen_recipient(&env);
+
+    client.rescue_to_alternate(&stream_id, &Address::generate(&env));
+    client.rescue_to_alternate(&stream_id, &Address::generate(&env));
+}
+
+/// Contract recipient that records `on_stream_payment` calls, or panics
+/// when told to
+#[soroban_sdk::contract]
+struct PaymentReceiver;
+
+#[soroban_sdk::contractimpl]
+impl PaymentReceiver {
+    pub fn set_broken(env: Env, broken: bool) {
+        env.storage().instance().set(&soroban_sdk::symbol_short!("broken"), &broken);
+    }
+
+    pub fn on_stream_payment(env: Env, stream_id: u64, amount: i128, token: Address) {
+        if env.storage().instance().get(&soroban_sdk::symbol_short!("broken")).unwrap_or(false) {
+            panic!("receiver is broken");
+        }
+        env.storage().instance().set(&soroban_sdk::symbol_short!("last"), &(stream_id, amount, token));
+    }
+
+    pub fn last_payment(env: Env) -> Option<(u64, i128, Address)> {
+        env.storage().instance().get(&soroban_sdk::symbol_short!("last"))
+    }
+}
+
+fn setup_notified_stream(env: &Env) -> (PaymentStreamContractClient<'_>, Address, u64, Address) {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, 100);
+    let receiver = env.register(PaymentReceiver, ());
+    let options = StreamOptions { notify_recipient: true, ..StreamOptions::default() };
+    let (stream_id, _sender) = open_funded_stream(env, &client, &token, &receiver, 1000, &options);
+    env.ledger().set_timestamp(50);
+    (client, token, stream_id, receiver)
+}
+
+#[test]
+fn test_contract_recipient_notified_on_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, receiver) = setup_notified_stream(&env);
+
+    client.withdraw(&stream_id, &200);
+
+    let receiver_client = PaymentReceiverClient::new(&env, &receiver);
+    assert_eq!(receiver_client.last_payment(), Some((stream_id, 198, token.clone())));
+    assert_eq!(token::Client::new(&env, &token).balance(&receiver), 198);
+
+    client.withdraw_max_batch(&soroban_sdk::vec![&env, stream_id]);
+    assert_eq!(receiver_client.last_payment(), Some((stream_id, 297, token)));
+}
+
+#[test]
+fn test_broken_receiver_does_not_block_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, receiver) = setup_notified_stream(&env);
+    let receiver_client = PaymentReceiverClient::new(&env, &receiver);
+    receiver_client.set_broken(&true);
+
+    client.withdraw(&stream_id, &200);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("CallbackFailed", stream_id).into_val(&env));
+    let payload: CallbackFailedEvent = data.into_val(&env);
+    assert_eq!(payload, CallbackFailedEvent { stream_id, recipient: receiver.clone(), amount: 198 });
+
+    assert_eq!(receiver_client.last_payment(), None);
+    assert_eq!(token::Client::new(&env, &token).balance(&receiver), 198);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 200);
+}
+
+#[test]
+fn test_recipient_not_notified_without_flag() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let receiver = env.register(PaymentReceiver, ());
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &receiver, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &200);
+
+    assert_eq!(PaymentReceiverClient::new(&env, &receiver).last_payment(), None);
+}
+
+/// Move a stream and its metrics and delegate back to the pre-`DataKey`
+/// layout, as left behind by an older deployment
+fn move_to_legacy_keys(env: &Env, contract_id: &Address, stream_id: u64) {
+    env.as_contract(contract_id, || {
+        let storage = env.storage().persistent();
+        let stream: crate::Stream = storage.get(&crate::DataKey::Stream(stream_id)).unwrap();
+        let metrics: crate::StreamMetrics = storage.get(&crate::DataKey::Metrics(stream_id)).unwrap();
+        let delegate: Address = storage.get(&crate::DataKey::Delegate(stream_id)).unwrap();
+        storage.remove(&crate::DataKey::Stream(stream_id));
+        storage.remove(&crate::DataKey::Metrics(stream_id));
+        storage.remove(&crate::DataKey::Delegate(stream_id));
+        storage.set(&stream_id, &stream);
+        storage.set(&(stream_id, Symbol::new(env, "metrics")), &metrics);
+        storage.set(&(stream_id, Symbol::new(env, "delegate")), &delegate);
+    });
+}
+
+#[test]
+fn test_get_stream_migrates_legacy_keys() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &delegate);
+    move_to_legacy_keys(&env, &contract_id, stream_id);
+
+    // Read-only views see the legacy record without rewriting it
+    env.ledger().set_timestamp(50);
+    assert_eq!(client.projected_withdrawable(&stream_id, &soroban_sdk::vec![&env, 50]), soroban_sdk::vec![&env, 500]);
+
+    let stream = client.get_stream(&stream_id);
+    assert_eq!((stream.sender, stream.recipient), (sender, recipient));
+
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().persistent();
+        assert!(storage.has(&crate::DataKey::Stream(stream_id)));
+        assert!(storage.has(&crate::DataKey::Metrics(stream_id)));
+        assert!(!storage.has(&stream_id));
+        assert!(!storage.has(&(stream_id, Symbol::new(&env, "metrics"))));
+        assert!(!storage.has(&(stream_id, Symbol::new(&env, "delegate"))));
+    });
+    assert_eq!(client.get_delegate(&stream_id), Some(delegate));
+    assert_eq!(client.get_stream_metrics(&stream_id).total_delegations, 1);
+
+    // The migrated stream keeps working
+    client.withdraw(&stream_id, &500);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 500);
+}
+
+#[test]
+fn test_withdraw_on_legacy_stream_without_prior_read() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &Address::generate(&env));
+    move_to_legacy_keys(&env, &contract_id, stream_id);
+
+    env.ledger().set_timestamp(100);
+    client.withdraw_max(&stream_id);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 1000);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+}
+
+#[test]
+fn test_fees_accrue_per_token_and_claim() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, usdc, admin, fee_collector) = setup_contract(&env, 100);
+    let eurc = env.register_stellar_asset_contract_v2(admin).address();
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &usdc).mint(&sender, &1000);
+    token::StellarAssetClient::new(&env, &eurc).mint(&sender, &2000);
+
+    let usdc_stream = client.create_stream(&sender, &recipient, &usdc, &1000, &1000, &0, &100);
+    let eurc_stream = client.create_stream(&sender, &recipient, &eurc, &2000, &2000, &0, &100);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&usdc_stream, &200);
+    client.withdraw(&usdc_stream, &300);
+    client.withdraw(&eurc_stream, &1000);
+
+    // Only the net amount left the contract on each withdrawal
+    let usdc_client = token::Client::new(&env, &usdc);
+    assert_eq!(usdc_client.balance(&recipient), 495);
+    assert_eq!(usdc_client.balance(&fee_collector), 0);
+    assert_eq!(client.get_accrued_fees(&usdc), 5);
+    assert_eq!(client.get_accrued_fees(&eurc), 10);
+
+    assert_eq!(client.claim_fees(&usdc), 5);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("FeesClaimed",).into_val(&env));
+    let payload: FeesClaimedEvent = data.into_val(&env);
+    assert_eq!(payload, FeesClaimedEvent { token: usdc.clone(), to: fee_collector.clone(), amount: 5 });
+
+    assert_eq!(usdc_client.balance(&fee_collector), 5);
+    assert_eq!(usdc_client.balance(&contract_id), 500);
+    assert_eq!(client.get_accrued_fees(&usdc), 0);
+    assert_eq!(client.get_accrued_fees(&eurc), 10);
+    assert_eq!(client.claim_fees(&usdc), 0);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_claim_fees_requires_fee_collector() {
+    let env = Env::default();
+    let (client, _contract_id, token, _admin, _fee_collector) = {
+        env.mock_all_auths();
+        setup_contract(&env, 100)
+    };
+    env.set_auths(&[]);
+    client.claim_fees(&token);
+}
+
+#[test]
+fn test_sponsor_deposit_refunded_proportionally_on_cancel() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let sponsor = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let token_admin = token::StellarAssetClient::new(&env, &token);
+    token_admin.mint(&sender, &400);
+    token_admin.mint(&sponsor, &600);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &400, &0, &100);
+    assert_eq!(client.get_stream_contributors(&stream_id), soroban_sdk::vec![&env, (sender.clone(), 400)]);
+
+    client.deposit_from(&stream_id, &sponsor, &600);
+    assert_eq!(env.auths()[0].0, sponsor);
+    assert_eq!(
+        client.get_stream_contributors(&stream_id),
+        soroban_sdk::vec![&env, (sender.clone(), 400), (sponsor.clone(), 600)]
+    );
+    assert_eq!(client.get_stream(&stream_id).balance, 1000);
+
+    env.ledger().set_timestamp(30);
+    client.withdraw(&stream_id, &300);
+    client.cancel_stream(&stream_id);
+
+    // 700 left: 40% back to the sender, 60% to the sponsor
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&recipient), 300);
+    assert_eq!(token_client.balance(&sender), 280);
+    assert_eq!(token_client.balance(&sponsor), 420);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_contributor_refund_rounding_goes_to_sender() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let sponsor_a = Address::generate(&env);
+    let sponsor_b = Address::generate(&env);
+    let token_admin = token::StellarAssetClient::new(&env, &token);
+    token_admin.mint(&sender, &200);
+    token_admin.mint(&sponsor_a, &200);
+    token_admin.mint(&sponsor_b, &200);
+
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &100, &0, &1000);
+    client.deposit_from(&stream_id, &sponsor_a, &100);
+    client.deposit(&stream_id, &100);
+    client.deposit_from(&stream_id, &sponsor_b, &100);
+    client.deposit_from(&stream_id, &sponsor_a, &100);
+    assert_eq!(
+        client.get_stream_contributors(&stream_id),
+        soroban_sdk::vec![&env, (sender.clone(), 200), (sponsor_a.clone(), 200), (sponsor_b.clone(), 100)]
+    );
+
+    // 499 left after one unit vests and is withdrawn: 199.6 / 199.6 / 99.8
+    env.ledger().set_timestamp(1);
+    client.withdraw(&stream_id, &1);
+    client.cancel_stream(&stream_id);
+
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(token_client.balance(&sponsor_a), 199);
+    assert_eq!(token_client.balance(&sponsor_b), 100 + 99);
+    assert_eq!(token_client.balance(&sender), 201);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_recipient_position_aggregates_streams() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &5000);
+
+    let active = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let paused = client.create_stream(&sender, &recipient, &token, &2000, &2000, &0, &200);
+    let canceled = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.create_stream(&sender, &recipient, &token, &500, &500, &100, &200);
+    client.create_stream(&sender, &Address::generate(&env), &token, &500, &500, &0, &100);
+
+    env.ledger().set_timestamp(20);
+    client.withdraw(&active, &100);
+    client.cancel_stream(&canceled);
+    env.ledger().set_timestamp(40);
+    client.pause_stream(&paused);
+    env.ledger().set_timestamp(50);
+
+    // Active: 400 withdrawable, 500 unvested. Paused: frozen at 400 vested
+    // of 2000. Not started: all 500 unvested. Canceled: ignored.
+    assert_eq!(
+        client.get_recipient_position(&recipient, &0, &10),
+        RecipientPosition { withdrawable_now: 400, pending_unvested: 2600, active_stream_count: 3, next_cursor: None }
+    );
+
+    let first = client.get_recipient_position(&recipient, &0, &2);
+    assert_eq!(first.next_cursor, Some(2));
+    assert_eq!((first.withdrawable_now, first.pending_unvested, first.active_stream_count), (400, 2100, 2));
+    let rest = client.get_recipient_position(&recipient, &2, &2);
+    assert_eq!(rest.next_cursor, None);
+    assert_eq!((rest.withdrawable_now, rest.pending_unvested, rest.active_stream_count), (0, 500, 1));
+}
+
+#[test]
+fn test_recipient_position_follows_recipient_change() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let old_recipient = Address::generate(&env);
+    let new_recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &old_recipient, &token, &1000, &1000, &0, &100);
+    client.propose_recipient_change(&stream_id, &new_recipient);
+    client.approve_recipient_change(&stream_id, &old_recipient);
+
+    // The stream moves from one index to the other
+    let indexed = |recipient: &Address| -> u32 {
+        env.as_contract(&contract_id, || {
+            env.storage().persistent().get(&DataKey::RecipientStreamCount(recipient.clone())).unwrap_or(0)
+        })
+    };
+    assert_eq!((indexed(&old_recipient), indexed(&new_recipient)), (0, 1));
+
+    env.ledger().set_timestamp(10);
+    let old_position = client.get_recipient_position(&old_recipient, &0, &10);
+    assert_eq!(old_position.active_stream_count, 0);
+    let new_position = client.get_recipient_position(&new_recipient, &0, &10);
+    assert_eq!((new_position.withdrawable_now, new_position.pending_unvested), (100, 900));
+}
+
+
+#[test]
+fn test_direct_fee_increase_requires_timelock() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+
+    assert_eq!(
+        client.try_set_protocol_fee_rate(&admin, &250),
+        Err(Ok(Error::FeeIncreaseRequiresTimelock.into()))
+    );
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+}
+
+#[test]
+fn test_fee_proposal_applies_after_timelock() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(1_000);
+
+    let (client, contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+
+    client.propose_protocol_fee_rate(&admin, &250);
+
+    let activates_at = 1_000 + 3 * 86_400;
+    let events = env.events().all();
+    let (emitter, topics, data) = events.last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("FeeChangeProposed",).into_val(&env));
+    let payload: FeeChangeProposedEvent = data.into_val(&env);
+    assert_eq!(payload, FeeChangeProposedEvent { new_rate: 250, activates_at });
+    assert_eq!(client.get_pending_fee_change(), Some((250, activates_at)));
+
+    env.ledger().set_timestamp(activates_at - 1);
+    assert_eq!(
+        client.try_apply_protocol_fee_rate(),
+        Err(Ok(Error::FeeTimelockNotElapsed.into()))
+    );
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+
+    env.ledger().set_timestamp(activates_at);
+    client.apply_protocol_fee_rate();
+
+    let events = env.events().all();
+    let (_, topics, data) = events.last().unwrap();
+    assert_eq!(topics, ("FeeChangeApplied",).into_val(&env));
+    let payload: ProtocolFeeRateChangedEvent = data.into_val(&env);
+    assert_eq!(payload, ProtocolFeeRateChangedEvent { old_rate: 100, new_rate: 250 });
+    assert_eq!(client.get_protocol_fee_rate(), 250);
+    assert_eq!(client.get_pending_fee_change(), None);
+}
+
+#[test]
+fn test_cancel_fee_proposal() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+
+    client.propose_protocol_fee_rate(&admin, &300);
+    client.cancel_fee_proposal(&admin);
+
+    let events = env.events().all();
+    let (_, topics, _) = events.last().unwrap();
+    assert_eq!(topics, ("FeeChangeCanceled",).into_val(&env));
+    assert_eq!(client.get_pending_fee_change(), None);
+
+    env.ledger().set_timestamp(env.ledger().timestamp() + 4 * 86_400);
+    assert_eq!(client.try_apply_protocol_fee_rate(), Err(Ok(Error::NoPendingFeeChange.into())));
+    assert_eq!(client.try_cancel_fee_proposal(&admin), Err(Ok(Error::NoPendingFeeChange.into())));
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+}
+
+#[test]
+fn test_fee_proposal_validates_rate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+
+    assert_eq!(client.try_propose_protocol_fee_rate(&admin, &501), Err(Ok(Error::FeeTooHigh.into())));
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_propose_fee_rate_requires_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+
+    env.set_auths(&[]);
+    client.propose_protocol_fee_rate(&admin, &250);
+}
+
+fn setup_milestone_stream(env: &Env) -> (PaymentStreamContractClient<'_>, Address, u64, Address, Address) {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, 0);
+    let recipient = Address::generate(env);
+    let milestone = Milestone { amount: 200, approved: false };
+    let options = StreamOptions {
+        milestones: Some(soroban_sdk::vec![env, milestone.clone(), milestone.clone(), milestone]),
+        ..StreamOptions::default()
+    };
+    let (stream_id, sender) = open_funded_stream(env, &client, &token, &recipient, 600, &options);
+    (client, token, stream_id, sender, recipient)
+}
+
+#[test]
+fn test_milestone_stream_ignores_time_vesting() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _token, stream_id, _sender, _recipient) = setup_milestone_stream(&env);
+
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.withdrawable_amount(&stream_id), 0);
+
+    client.approve_milestone(&stream_id, &1);
+
+    let events = env.events().all();
+    let (_, topics, data) = events.last().unwrap();
+    assert_eq!(topics, ("MilestoneApproved", stream_id).into_val(&env));
+    let payload: MilestoneApprovedEvent = data.into_val(&env);
+    assert_eq!(payload, MilestoneApprovedEvent { stream_id, index: 1, amount: 200 });
+
+    assert_eq!(client.withdrawable_amount(&stream_id), 200);
+    assert_eq!(client.projected_withdrawable(&stream_id, &soroban_sdk::vec![&env, 0, 500]), soroban_sdk::vec![&env, 200, 200]);
+}
+
+#[test]
+fn test_milestone_approve_withdraw_cancel() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, sender, recipient) = setup_milestone_stream(&env);
+    let token_client = token::Client::new(&env, &token);
+
+    client.approve_milestone(&stream_id, &0);
+    client.approve_milestone(&stream_id, &2);
+    assert_eq!(client.withdrawable_amount(&stream_id), 400);
+
+    client.withdraw(&stream_id, &300);
+    assert_eq!(token_client.balance(&recipient), 300);
+
+    // Only the unapproved milestone goes back to the sender
+    client.cancel_stream(&stream_id);
+    assert_eq!(token_client.balance(&sender), 200);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Canceled);
+
+    // The approved remainder stays claimable after cancel
+    assert_eq!(client.withdrawable_amount(&stream_id), 100);
+    client.withdraw_max(&stream_id);
+    assert_eq!(token_client.balance(&recipient), 400);
+    assert_eq!(token_client.balance(&client.address), 0);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Canceled);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 0);
+}
+
+#[test]
+fn test_milestone_errors() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, sender, recipient) = setup_milestone_stream(&env);
+
+    client.approve_milestone(&stream_id, &0);
+    assert_eq!(client.try_approve_milestone(&stream_id, &0), Err(Ok(Error::MilestoneAlreadyApproved.into())));
+    assert_eq!(client.try_approve_milestone(&stream_id, &3), Err(Ok(Error::MilestoneNotFound.into())));
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &100);
+    let plain = client.create_stream(&sender, &recipient, &token, &100, &100, &0, &100);
+    assert_eq!(client.try_approve_milestone(&plain, &0), Err(Ok(Error::MilestoneNotFound.into())));
+    assert_eq!(client.get_milestones(&plain), None);
+
+    // Milestone amounts must add up to the stream total
+    let options = StreamOptions {
+        milestones: Some(soroban_sdk::vec![&env, Milestone { amount: 50, approved: false }]),
+        ..StreamOptions::default()
+    };
+    assert_eq!(
+        client.try_create_stream_with_options(&sender, &recipient, &token, &100, &0, &0, &100, &options),
+        Err(Ok(Error::InvalidAmount.into()))
+    );
+
+    // An empty list has nothing to release
+    let options = StreamOptions { milestones: Some(soroban_sdk::vec![&env]), ..StreamOptions::default() };
+    assert_eq!(
+        client.try_create_stream_with_options(&sender, &recipient, &token, &100, &0, &0, &100, &options),
+        Err(Ok(Error::InvalidAmount.into()))
+    );
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_approve_milestone_requires_sender() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _token, stream_id, _sender, _recipient) = setup_milestone_stream(&env);
+
+    env.set_auths(&[]);
+    client.approve_milestone(&stream_id, &0);
+}
+
+#[test]
+fn test_find_stream_returns_none_for_missing_stream() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    let found = client.find_stream(&stream_id).unwrap();
+    assert_eq!(found.id, stream_id);
+    assert_eq!(found.recipient, client.get_stream(&stream_id).recipient);
+    assert!(client.find_stream(&99).is_none());
+
+    // The panicking getters keep their error codes
+    assert!(matches!(client.try_get_stream(&99), Err(Ok(e)) if e == Error::StreamNotFound.into()));
+    assert!(matches!(client.try_get_stream_metrics(&99), Err(Ok(e)) if e == Error::StreamNotFound.into()));
+    assert_eq!(client.try_withdrawable_amount(&99), Err(Ok(Error::StreamNotFound.into())));
+
+    assert_eq!(client.get_fee_collector(), Some(fee_collector));
+    client.set_protocol_fee_rate(&admin, &0);
+    client.set_fee_collector(&admin, &None);
+    assert_eq!(client.get_fee_collector(), None);
+}
+
+#[test]
+fn test_sender_revokes_compromised_delegate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &delegate);
+
+    client.revoke_delegate_by_sender(&stream_id);
+    assert_eq!(env.auths()[0].0, sender);
+
+    let events = env.events().all();
+    let (_, topics, data) = events.last().unwrap();
+    assert_eq!(topics, ("DelegationRevoked", stream_id).into_val(&env));
+    let payload: DelegationRevokedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        DelegationRevokedEvent { stream_id, recipient: recipient.clone(), revoked_by: sender.clone() }
+    );
+    assert_eq!(client.get_delegate(&stream_id), None);
+    assert_eq!(client.get_stream_metrics(&stream_id).forced_revocations, 1);
+
+    // The old delegate's signature no longer authorizes withdrawals
+    env.ledger().set_timestamp(50);
+    env.mock_auths(&[MockAuth {
+        address: &delegate,
+        invoke: &MockAuthInvoke {
+            contract: &contract_id,
+            fn_name: "withdraw",
+            args: (stream_id, 100i128).into_val(&env),
+            sub_invokes: &[],
+        },
+    }]);
+    assert!(client.try_withdraw(&stream_id, &100).is_err());
+
+    // The recipient can delegate again
+    env.mock_all_auths();
+    let new_delegate = Address::generate(&env);
+    client.set_delegate(&stream_id, &new_delegate);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(env.auths()[0].0, new_delegate);
+    assert_eq!(client.get_stream_metrics(&stream_id).forced_revocations, 1);
+}
+
+#[test]
+#[should_panic(expected = "Unauthorized")]
+fn test_revoke_delegate_by_sender_requires_sender() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &Address::generate(&env));
+
+    env.set_auths(&[]);
+    client.revoke_delegate_by_sender(&stream_id);
+}
+
+fn setup_deadline_stream(env: &Env) -> (PaymentStreamContractClient<'_>, Address, u64, Address, Address) {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, 0);
+    let recipient = Address::generate(env);
+    let options = StreamOptions { claim_deadline: Some(200), ..StreamOptions::default() };
+    let (stream_id, sender) = open_funded_stream(env, &client, &token, &recipient, 1000, &options);
+    (client, token, stream_id, sender, recipient)
+}
+
+#[test]
+fn test_claim_deadline_blocks_late_withdrawals() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, _sender, recipient) = setup_deadline_stream(&env);
+
+    env.ledger().set_timestamp(200);
+    client.withdraw(&stream_id, &300);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 300);
+
+    env.ledger().set_timestamp(201);
+    assert_eq!(client.withdrawable_amount(&stream_id), 0);
+    assert_eq!(client.try_withdraw(&stream_id, &100), Err(Ok(Error::ClaimWindowExpired.into())));
+}
+
+#[test]
+fn test_reclaim_stale_returns_remaining_escrow() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, sender, _recipient) = setup_deadline_stream(&env);
+    let token_client = token::Client::new(&env, &token);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &400);
+
+    env.ledger().set_timestamp(200);
+    assert_eq!(client.try_reclaim_stale(&stream_id), Err(Ok(Error::ClaimWindowOpen.into())));
+
+    env.ledger().set_timestamp(201);
+    assert_eq!(client.reclaim_stale(&stream_id), 600);
+
+    let events = env.events().all();
+    let (_, topics, data) = events.last().unwrap();
+    assert_eq!(topics, ("StaleFundsReclaimed", stream_id).into_val(&env));
+    let payload: StaleFundsReclaimedEvent = data.into_val(&env);
+    assert_eq!(payload, StaleFundsReclaimedEvent { stream_id, sender: sender.clone(), amount: 600 });
+
+    assert_eq!(token_client.balance(&sender), 600);
+    assert_eq!(token_client.balance(&client.address), 0);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 0);
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 0);
+    assert_eq!(client.try_reclaim_stale(&stream_id), Err(Ok(Error::StreamNotActive.into())));
+}
+
+#[test]
+fn test_stream_without_deadline_cannot_be_reclaimed() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(10_000);
+    assert_eq!(client.try_reclaim_stale(&stream_id), Err(Ok(Error::ClaimWindowOpen.into())));
+    client.withdraw(&stream_id, &1000);
+
+    // A deadline before the stream ends is rejected
+    let options = StreamOptions { claim_deadline: Some(10_050), ..StreamOptions::default() };
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    assert_eq!(
+        client.try_create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &10_000, &10_100, &options),
+        Err(Ok(Error::InvalidTimeRange.into()))
+    );
+}
+
+#[test]
+fn test_fee_revenue_counters_match_collector_balance() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, fee_collector) = setup_contract(&env, 250);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let first = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let second = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&first, &400);
+    client.withdraw(&second, &333);
+    env.ledger().set_timestamp(100);
+    client.withdraw_max_batch(&soroban_sdk::vec![&env, first, second]);
+    client.claim_fees(&token);
+
+    let collector_balance = token::Client::new(&env, &token).balance(&fee_collector);
+    // The fraction dropped on the 333 withdrawal is carried into the final one
+    assert_eq!(collector_balance, 10 + 8 + 15 + 17);
+    assert_eq!(client.get_fees_collected(&token), collector_balance);
+    assert_eq!(client.get_protocol_metrics().total_fees_collected, collector_balance);
+    // Claiming empties the accrual but not the lifetime counters
+    assert_eq!(client.get_accrued_fees(&token), 0);
+}
+
+#[test]
+fn test_legacy_protocol_metrics_are_migrated() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    // Storage as written before fee revenue was tracked
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().instance();
+        storage.remove(&crate::DataKey::ProtocolMetricsV2);
+        storage.set(
+            &crate::DataKey::ProtocolMetrics,
+            &crate::LegacyProtocolMetrics {
+                total_active_streams: 4,
+                total_tokens_streamed: 5000,
+                total_streams_created: 7,
+                total_delegations: 2,
+                total_committed: 6000,
+            },
+        );
+    });
+    assert_eq!(client.get_protocol_metrics().total_streams_created, 7);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(100);
+    client.withdraw(&stream_id, &1000);
+
+    let metrics = client.get_protocol_metrics();
+    assert_eq!(metrics.total_streams_created, 8);
+    assert_eq!(metrics.total_active_streams, 4);
+    assert_eq!(metrics.total_fees_collected, 10);
+    env.as_contract(&contract_id, || {
+        assert!(!env.storage().instance().has(&crate::DataKey::ProtocolMetrics));
+    });
+}
+
+#[test]
+fn test_quote_withdrawal_matches_execution() {
+    for fee_rate in [0u32, 1, 100, 250, 500] {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, fee_rate);
+        let sender = Address::generate(&env);
+        let recipient = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token).mint(&sender, &1_000_000);
+        let stream_id = client.create_stream(&sender, &recipient, &token, &1_000_000, &1_000_000, &0, &100);
+
+        env.ledger().set_timestamp(37);
+        let quote = client.quote_withdrawal(&stream_id, &123_457);
+        assert!(quote.valid);
+        assert_eq!(quote.gross, 123_457);
+        assert_eq!(quote.net + quote.fee, quote.gross);
+        assert_eq!(quote.withdrawable, 370_000);
+
+        client.withdraw(&stream_id, &123_457);
+        assert_eq!(token::Client::new(&env, &token).balance(&recipient), quote.net);
+        assert_eq!(client.get_accrued_fees(&token), quote.fee);
+    }
+}
+
+#[test]
+fn test_quote_withdrawal_flags_invalid_amounts() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(50);
+    assert_eq!(
+        client.quote_withdrawal(&stream_id, &600),
+        WithdrawalQuote { gross: 600, fee: 6, net: 594, withdrawable: 500, valid: false }
+    );
+    assert!(!client.quote_withdrawal(&stream_id, &0).valid);
+    assert!(!client.quote_withdrawal(&stream_id, &-5).valid);
+    assert_eq!(client.try_withdraw(&stream_id, &600), Err(Ok(Error::InsufficientWithdrawable.into())));
+}
+
+/// Once a stream has ended and been drained, nothing may be left behind
+fn assert_no_remaining_dust(env: &Env, client: &PaymentStreamContractClient, token: &Address, stream_id: u64) {
+    let stream = client.get_stream(&stream_id);
+    assert_eq!(stream.withdrawn_amount, stream.total_amount);
+    assert_eq!(stream.status, StreamStatus::Completed);
+    assert_eq!(client.withdrawable_amount(&stream_id), 0);
+    assert_eq!(token::Client::new(env, token).balance(&client.address), 0);
+}
+
+#[test]
+fn test_uneven_stream_drains_exactly_at_end() {
+    for (total, duration) in [(1000i128, 7u64), (333, 10), (1, 3), (999_999_999, 86_399)] {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+        let sender = Address::generate(&env);
+        let recipient = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token).mint(&sender, &total);
+        let stream_id = client.create_stream(&sender, &recipient, &token, &total, &total, &0, &duration);
+
+        for t in [1, duration / 3, duration - 1] {
+            env.ledger().set_timestamp(t);
+            if client.withdrawable_amount(&stream_id) > 0 {
+                client.withdraw_max(&stream_id);
+            }
+        }
+
+        env.ledger().set_timestamp(duration);
+        assert_eq!(client.withdrawable_amount(&stream_id), total - client.get_stream(&stream_id).withdrawn_amount);
+        client.withdraw_max(&stream_id);
+        assert_no_remaining_dust(&env, &client, &token, stream_id);
+        assert_eq!(token::Client::new(&env, &token).balance(&recipient), total);
+    }
+}
+
+#[test]
+fn test_uneven_paused_stream_drains_exactly_at_extended_end() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &333);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &333, &333, &0, &10);
+
+    env.ledger().set_timestamp(3);
+    client.withdraw_max(&stream_id);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(7);
+    client.resume_stream(&stream_id);
+    env.ledger().set_timestamp(13);
+    client.withdraw_max(&stream_id);
+
+    env.ledger().set_timestamp(14);
+    client.withdraw_max(&stream_id);
+    assert_no_remaining_dust(&env, &client, &token, stream_id);
+}
+
+#[test]
+fn test_delegate_withdrawal_pays_caller_tip() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let token_client = token::Client::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let keeper = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    client.set_caller_tip_bps(&stream_id, &100);
+    assert_eq!(client.get_caller_tip_bps(&stream_id), 100);
+
+    // No delegate: the recipient withdraws and pays no tip
+    env.ledger().set_timestamp(20);
+    client.withdraw(&stream_id, &200);
+    assert_eq!(withdrawal_event(&env, stream_id).tip, 0);
+    assert_eq!(token_client.balance(&recipient), 198);
+
+    client.set_delegate(&stream_id, &keeper);
+    env.ledger().set_timestamp(70);
+    client.withdraw(&stream_id, &500);
+
+    // gross 500, fee 5, tip 1% of the 495 net rounded down
+    assert_eq!(
+        withdrawal_event(&env, stream_id),
+        WithdrawalEvent {
+            stream_id,
+            caller_is_delegate: true,
+            gross_amount: 500,
+            fee: 5,
+            tip: 4,
+            net_amount: 491,
+            recipient: recipient.clone(),
+            sponsor: None,
+        }
+    );
+    assert_eq!(token_client.balance(&keeper), 4);
+    assert_eq!(token_client.balance(&recipient), 198 + 491);
+
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.withdraw_max_batch(&soroban_sdk::vec![&env, stream_id]), soroban_sdk::vec![&env, 295]);
+    assert_eq!(token_client.balance(&keeper), 4 + 2);
+    assert_eq!(token_client.balance(&recipient), 198 + 491 + 295);
+}
+
+#[test]
+fn test_caller_tip_above_cap_rejected() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    assert_eq!(client.try_set_caller_tip_bps(&stream_id, &101), Err(Ok(Error::TipTooHigh.into())));
+    assert_eq!(client.get_caller_tip_bps(&stream_id), 0);
+}
+
+#[test]
+fn test_fee_manager_sets_rate_but_cannot_pause() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let manager = Address::generate(&env);
+    let fee_manager = Symbol::new(&env, "fee_manager");
+
+    client.grant_role(&fee_manager, &manager);
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("RoleGranted",).into_val(&env));
+    let payload: RoleChangedEvent = data.into_val(&env);
+    assert_eq!(payload, RoleChangedEvent { role: fee_manager.clone(), account: manager.clone() });
+    assert!(client.has_role(&fee_manager, &manager));
+    assert!(!client.has_role(&Symbol::new(&env, "pauser"), &manager));
+
+    client.set_protocol_fee_rate(&manager, &50);
+    assert_eq!(client.get_protocol_fee_rate(), 50);
+
+    assert_eq!(client.try_set_paused(&manager, &true), Err(Ok(Error::Unauthorized.into())));
+    assert!(!client.is_paused());
+}
+
+#[test]
+fn test_revoked_role_loses_access() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let manager = Address::generate(&env);
+    let fee_manager = Symbol::new(&env, "fee_manager");
+
+    client.grant_role(&fee_manager, &manager);
+    client.set_fee_collector(&manager, &Some(Address::generate(&env)));
+
+    client.revoke_role(&fee_manager, &manager);
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("RoleRevoked",).into_val(&env));
+    let payload: RoleChangedEvent = data.into_val(&env);
+    assert_eq!(payload, RoleChangedEvent { role: fee_manager.clone(), account: manager.clone() });
+    assert!(!client.has_role(&fee_manager, &manager));
+
+    assert_eq!(client.try_set_protocol_fee_rate(&manager, &50), Err(Ok(Error::Unauthorized.into())));
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+}
+
+#[test]
+fn test_admin_holds_every_role() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 0);
+
+    for role in ["pauser", "fee_manager", "upgrader"] {
+        assert!(client.has_role(&Symbol::new(&env, role), &admin));
+    }
+    assert_eq!(
+        client.try_grant_role(&Symbol::new(&env, "minter"), &Address::generate(&env)),
+        Err(Ok(Error::InvalidRole.into()))
+    );
+}
+
+#[test]
+fn test_contract_pause_blocks_streaming() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let pauser = Address::generate(&env);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &500, &0, &100);
+
+    client.grant_role(&Symbol::new(&env, "pauser"), &pauser);
+    client.set_paused(&pauser, &true);
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("ContractPaused",).into_val(&env));
+    let payload: ContractPausedEvent = data.into_val(&env);
+    assert_eq!(payload, ContractPausedEvent { paused: true, by: pauser.clone() });
+    assert!(client.is_paused());
+
+    env.ledger().set_timestamp(40);
+    assert_eq!(client.try_withdraw(&stream_id, &100), Err(Ok(Error::ContractPaused.into())));
+    assert_eq!(client.try_deposit(&stream_id, &100), Err(Ok(Error::ContractPaused.into())));
+    assert_eq!(
+        client.try_create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100),
+        Err(Ok(Error::ContractPaused.into()))
+    );
+    assert!(!client.quote_withdrawal(&stream_id, &100).valid);
+
+    client.set_paused(&pauser, &false);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 100);
+}
+
+#[test]
+fn test_split_stream_preserves_vesting() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let partner = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(50);
+    let child_id = client.split_stream(&stream_id, &4000, &partner);
+
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(topics, ("StreamSplit", stream_id).into_val(&env));
+    let payload: StreamSplitEvent = data.into_val(&env);
+    assert_eq!(payload, StreamSplitEvent { parent: stream_id, child: child_id, split_bps: 4000 });
+
+    let parent = client.get_stream(&stream_id);
+    let child = client.get_stream(&child_id);
+    assert_eq!((parent.total_amount, parent.balance), (600, 600));
+    assert_eq!((child.total_amount, child.balance), (400, 400));
+    assert_eq!(child.sender, sender);
+    assert_eq!(child.recipient, partner);
+    assert_eq!((child.start_time, child.end_time), (0, 100));
+    assert_eq!(client.withdrawable_amount(&stream_id), 300);
+    assert_eq!(client.withdrawable_amount(&child_id), 200);
+
+    env.ledger().set_timestamp(75);
+    assert_eq!(client.withdrawable_amount(&stream_id), 450);
+    assert_eq!(client.withdrawable_amount(&child_id), 300);
+
+    // Both halves are indexed and counted
+    assert_eq!(client.get_streams_by_status(&StreamStatus::Active, &0, &10), soroban_sdk::vec![&env, stream_id, child_id]);
+    assert_eq!(client.get_recipient_position(&partner, &0, &10).withdrawable_now, 300);
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 2);
+    assert_eq!(client.get_token_metrics(&token).active_streams, 2);
+    assert_eq!(client.get_tvl_by_token(&token), 1000);
+}
+
+#[test]
+fn test_split_stream_keeps_withdrawals_on_parent() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let partner = Address::generate(&env);
+    let token_client = token::Client::new(&env, &token);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &300);
+    let child_id = client.split_stream(&stream_id, &4000, &partner);
+
+    // 40% of the 700 still in escrow moves to the child
+    let parent = client.get_stream(&stream_id);
+    assert_eq!((parent.total_amount, parent.withdrawn_amount), (720, 300));
+    assert_eq!(client.get_stream(&child_id).total_amount, 280);
+    assert_eq!(client.withdrawable_amount(&stream_id) + client.withdrawable_amount(&child_id), 200);
+
+    env.ledger().set_timestamp(75);
+    assert_eq!(client.withdrawable_amount(&stream_id), 240);
+    assert_eq!(client.withdrawable_amount(&child_id), 210);
+
+    env.ledger().set_timestamp(100);
+    client.withdraw(&stream_id, &420);
+    client.withdraw(&child_id, &280);
+    assert_eq!(token_client.balance(&recipient), 720);
+    assert_eq!(token_client.balance(&partner), 280);
+    assert_eq!(client.get_stream(&child_id).status, StreamStatus::Completed);
+    assert_eq!(token_client.balance(&contract_id), 0);
+}
+
+#[test]
+fn test_split_stream_rejects_invalid_splits() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let partner = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    assert_eq!(client.try_split_stream(&stream_id, &0, &partner), Err(Ok(Error::InvalidSplit.into())));
+    assert_eq!(client.try_split_stream(&stream_id, &10_000, &partner), Err(Ok(Error::InvalidSplit.into())));
+
+    client.pause_stream(&stream_id);
+    assert_eq!(client.try_split_stream(&stream_id, &4000, &partner), Err(Ok(Error::StreamNotActive.into())));
+}
+
+fn set_limits(env: &Env, client: &PaymentStreamContractClient, admin: &Address, amount: i128, duration: u64, withdrawal: i128) {
+    let limits = StreamLimits { min_stream_amount: amount, min_duration: duration, min_withdrawal_amount: withdrawal };
+    client.set_stream_limits(admin, &limits);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("StreamLimitsChanged",).into_val(env));
+    let payload: StreamLimitsChangedEvent = data.into_val(env);
+    assert_eq!(payload.new_limits, limits);
+}
+
+#[test]
+fn test_stream_limits_default_to_zero() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    assert_eq!(client.get_stream_limits(), StreamLimits::default());
+
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1);
+    client.create_stream(&sender, &Address::generate(&env), &token, &1, &1, &0, &1);
+
+    set_limits(&env, &client, &admin, 100, 10, 5);
+    let (_, _, data) = env.events().all().last().unwrap();
+    let payload: StreamLimitsChangedEvent = data.into_val(&env);
+    assert_eq!(payload.old_limits, StreamLimits::default());
+    assert_eq!(client.get_stream_limits().min_duration, 10);
+}
+
+#[test]
+fn test_create_stream_minimums() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &10_000);
+    set_limits(&env, &client, &admin, 100, 50, 0);
+
+    assert_eq!(
+        client.try_create_stream(&sender, &recipient, &token, &99, &99, &0, &100),
+        Err(Ok(Error::AmountBelowMinimum.into()))
+    );
+    assert_eq!(
+        client.try_create_stream(&sender, &recipient, &token, &100, &100, &0, &49),
+        Err(Ok(Error::DurationBelowMinimum.into()))
+    );
+    client.create_stream(&sender, &recipient, &token, &100, &100, &0, &50);
+    client.create_stream(&sender, &recipient, &token, &101, &101, &0, &51);
+}
+
+#[test]
+fn test_withdrawal_minimum() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    set_limits(&env, &client, &admin, 0, 0, 100);
+
+    env.ledger().set_timestamp(50);
+    assert_eq!(client.try_withdraw(&stream_id, &99), Err(Ok(Error::AmountBelowMinimum.into())));
+    assert!(!client.quote_withdrawal(&stream_id, &99).valid);
+    client.withdraw(&stream_id, &100);
+    client.withdraw(&stream_id, &101);
+    client.withdraw(&stream_id, &299);
+
+    // Batches skip streams whose available amount is below the minimum
+    env.ledger().set_timestamp(55);
+    assert_eq!(client.withdraw_max_batch(&soroban_sdk::vec![&env, stream_id]), soroban_sdk::vec![&env, 0]);
+
+    // The last withdrawal may fall below the minimum so the stream can finish
+    env.ledger().set_timestamp(100);
+    client.withdraw(&stream_id, &451);
+    client.withdraw(&stream_id, &49);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 1000);
+}
+
+#[test]
+fn test_stream_limits_require_fee_manager() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let stranger = Address::generate(&env);
+    assert_eq!(
+        client.try_set_stream_limits(&stranger, &StreamLimits::default()),
+        Err(Ok(Error::Unauthorized.into()))
+    );
+
+    client.grant_role(&Symbol::new(&env, "fee_manager"), &stranger);
+    let limits = StreamLimits { min_stream_amount: -1, min_duration: 0, min_withdrawal_amount: 0 };
+    assert_eq!(client.try_set_stream_limits(&stranger, &limits), Err(Ok(Error::InvalidAmount.into())));
+    set_limits(&env, &client, &stranger, 10, 0, 0);
+}
+
+/// Stream A pays `recipient`, who in turn streams to someone else from
+/// stream B (funded 0 of 500). Returns (A, B).
+fn setup_forwarding_streams(env: &Env, client: &PaymentStreamContractClient, token_a: &Address, token_b: &Address, recipient: &Address) -> (u64, u64) {
+    let sender = Address::generate(env);
+    token::StellarAssetClient::new(env, token_a).mint(&sender, &1000);
+    let stream_a = client.create_stream(&sender, recipient, token_a, &1000, &1000, &0, &100);
+    let stream_b = client.create_stream(recipient, &Address::generate(env), token_b, &500, &0, &0, &200);
+    (stream_a, stream_b)
+}
+
+#[test]
+fn test_auto_forward_funds_recipient_stream() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let recipient = Address::generate(&env);
+    let token_client = token::Client::new(&env, &token);
+    let (stream_a, stream_b) = setup_forwarding_streams(&env, &client, &token, &token, &recipient);
+
+    client.set_auto_forward(&stream_a, &Some(stream_b));
+    assert_eq!(client.get_auto_forward(&stream_a), Some(stream_b));
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_a, &400);
+
+    // 1% fee withheld, the rest booked as the recipient's deposit into B
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("StreamDeposit", stream_b).into_val(&env));
+    let payload: crate::StreamDepositEvent = data.into_val(&env);
+    assert_eq!(payload.amount, 396);
+    assert_eq!(client.get_stream(&stream_b).balance, 396);
+    assert_eq!(client.get_stream(&stream_a).withdrawn_amount, 400);
+    assert_eq!(token_client.balance(&recipient), 0);
+    assert_eq!(token_client.balance(&contract_id), 1000);
+    assert_eq!(client.get_tvl_by_sender(&recipient), 396);
+
+    // B can't take another 594 on top of 396, so this one is paid out
+    env.ledger().set_timestamp(100);
+    client.withdraw(&stream_a, &600);
+    let events = env.events().all();
+    let skipped = events.iter().find(|(_, topics, _)| *topics == ("ForwardSkipped", stream_a).into_val(&env)).unwrap();
+    let payload: ForwardSkippedEvent = skipped.2.into_val(&env);
+    assert_eq!(payload, ForwardSkippedEvent { stream_id: stream_a, target_stream_id: stream_b, amount: 594 });
+    assert_eq!(client.get_stream(&stream_b).balance, 396);
+    assert_eq!(token_client.balance(&recipient), 594);
+}
+
+#[test]
+fn test_auto_forward_token_mismatch_falls_back() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let other_token = env.register_stellar_asset_contract_v2(admin).address();
+    let recipient = Address::generate(&env);
+    let (stream_a, stream_b) = setup_forwarding_streams(&env, &client, &token, &other_token, &recipient);
+
+    client.set_auto_forward(&stream_a, &Some(stream_b));
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_a, &300);
+
+    let events = env.events().all();
+    let skipped = events.iter().find(|(_, topics, _)| *topics == ("ForwardSkipped", stream_a).into_val(&env)).unwrap();
+    let payload: ForwardSkippedEvent = skipped.2.into_val(&env);
+    assert_eq!(payload, ForwardSkippedEvent { stream_id: stream_a, target_stream_id: stream_b, amount: 300 });
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 300);
+    assert_eq!(client.get_stream(&stream_b).balance, 0);
+}
+
+#[test]
+fn test_auto_forward_can_be_cleared() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let recipient = Address::generate(&env);
+    let (stream_a, stream_b) = setup_forwarding_streams(&env, &client, &token, &token, &recipient);
+
+    // Only streams the recipient sends can be targets
+    let stranger_stream = client.create_stream(&Address::generate(&env), &recipient, &token, &1000, &0, &0, &100);
+    assert_eq!(
+        client.try_set_auto_forward(&stream_a, &Some(stranger_stream)),
+        Err(Ok(Error::InvalidConfiguration.into()))
+    );
+
+    client.set_auto_forward(&stream_a, &Some(stream_b));
+    client.set_auto_forward(&stream_a, &None);
+    assert_eq!(client.get_auto_forward(&stream_a), None);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_a, &300);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 300);
+    assert_eq!(client.get_stream(&stream_b).balance, 0);
+}
+
+fn low_balance_warning(env: &Env, stream_id: u64) -> Option<LowBalanceWarningEvent> {
+    let topics: soroban_sdk::Vec<soroban_sdk::Val> = ("LowBalanceWarning", stream_id).into_val(env);
+    env.events().all().iter()
+        .find(|(_, event_topics, _)| *event_topics == topics)
+        .map(|(_, _, data)| data.into_val(env))
+}
+
+#[test]
+fn test_underfunded_stream_warns_and_reports_runout() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &400, &0, &100);
+
+    assert_eq!(client.get_funding_status(&stream_id), FundingStatus { funded_through: 40, shortfall: 600 });
+
+    env.ledger().set_timestamp(20);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(
+        low_balance_warning(&env, stream_id),
+        Some(LowBalanceWarningEvent { stream_id, balance: 300, projected_runout_time: 40 })
+    );
+
+    client.deposit(&stream_id, &200);
+    assert_eq!(
+        low_balance_warning(&env, stream_id),
+        Some(LowBalanceWarningEvent { stream_id, balance: 500, projected_runout_time: 60 })
+    );
+    assert_eq!(client.get_funding_status(&stream_id), FundingStatus { funded_through: 60, shortfall: 400 });
+
+    // Fully funded streams stay quiet
+    client.deposit(&stream_id, &400);
+    assert_eq!(low_balance_warning(&env, stream_id), None);
+    assert_eq!(client.get_funding_status(&stream_id), FundingStatus { funded_through: 100, shortfall: 0 });
+}
+
+#[test]
+fn test_underfunded_stream_cannot_withdraw_past_its_balance() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &400, &0, &100);
+    client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+
+    // 800 has vested but only 400 was ever deposited
+    env.ledger().set_timestamp(80);
+    assert_eq!(client.withdrawable_amount(&stream_id), 400);
+    assert_eq!(client.try_withdraw(&stream_id, &800), Err(Ok(Error::InsufficientWithdrawable.into())));
+
+    client.withdraw(&stream_id, &400);
+    assert_eq!(client.withdrawable_amount(&stream_id), 0);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 400);
+    // The fully funded stream's escrow is untouched
+    assert_eq!(token::Client::new(&env, &token).balance(&contract_id), 1000);
+}
+
+#[soroban_sdk::contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum TestAccountError {
+    ContractNotAllowed = 1,
+}
+
+/// Custom account contract that authorizes calls into one contract only and
+/// counts how often the host asked it to
+#[soroban_sdk::contract]
+pub struct TestAccount;
+
+#[soroban_sdk::contractimpl]
+impl TestAccount {
+    pub fn __constructor(env: Env, allowed: Address) {
+        env.storage().instance().set(&soroban_sdk::symbol_short!("allowed"), &allowed);
+    }
+
+    pub fn checks(env: Env) -> u32 {
+        env.storage().instance().get(&soroban_sdk::symbol_short!("checks")).unwrap_or(0)
+    }
+}
+
+use soroban_sdk::auth::CustomAccountInterface;
+
+#[soroban_sdk::contractimpl]
+impl CustomAccountInterface for TestAccount {
+    type Signature = ();
+    type Error = TestAccountError;
+
+    #[allow(non_snake_case)]
+    fn __check_auth(
+        env: Env,
+        _signature_payload: soroban_sdk::crypto::Hash<32>,
+        _signatures: (),
+        auth_contexts: soroban_sdk::Vec<soroban_sdk::auth::Context>,
+    ) -> Result<(), TestAccountError> {
+        let allowed: Address = env.storage().instance().get(&soroban_sdk::symbol_short!("allowed")).unwrap();
+        for context in auth_contexts.iter() {
+            match context {
+                soroban_sdk::auth::Context::Contract(call) if call.contract == allowed => {},
+                _ => return Err(TestAccountError::ContractNotAllowed),
+            }
+        }
+        let checks = Self::checks(env.clone());
+        env.storage().instance().set(&soroban_sdk::symbol_short!("checks"), &(checks + 1));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_create_stream_for_custom_account_authorizer() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token_client = token::Client::new(&env, &token);
+    // The authorizer may only call the stream contract, the treasury only the token
+    let authorizer = env.register(TestAccount, (contract_id.clone(),));
+    let treasury = env.register(TestAccount, (token.clone(),));
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&treasury, &1000);
+
+    let create = MockAuth {
+        address: &authorizer,
+        invoke: &MockAuthInvoke {
+            contract: &contract_id,
+            fn_name: "create_stream_for",
+            args: (&authorizer, &treasury, &recipient, &token, 1000_i128, 1000_i128, 0_u64, 100_u64).into_val(&env),
+            sub_invokes: &[],
+        },
+    };
+    let transfer = MockAuth {
+        address: &treasury,
+        invoke: &MockAuthInvoke {
+            contract: &token,
+            fn_name: "transfer",
+            args: (&treasury, &contract_id, 1000_i128).into_val(&env),
+            sub_invokes: &[],
+        },
+    };
+    env.set_auths(&[(&create).into(), (&transfer).into()]);
+
+    let stream_id = client.create_stream_for(&authorizer, &treasury, &recipient, &token, &1000, &1000, &0, &100);
+
+    assert_eq!(TestAccountClient::new(&env, &authorizer).checks(), 1);
+    assert_eq!(TestAccountClient::new(&env, &treasury).checks(), 1);
+    assert_eq!(client.get_stream(&stream_id).sender, authorizer);
+    assert_eq!(client.get_funding_source(&stream_id), treasury);
+    assert_eq!(token_client.balance(&treasury), 0);
+    assert_eq!(token_client.balance(&contract_id), 1000);
+
+    // The authorizer controls the stream; refunds go back to the treasury
+    env.mock_all_auths();
+    env.ledger().set_timestamp(30);
+    client.cancel_stream(&stream_id);
+    assert_eq!(token_client.balance(&recipient), 300);
+    assert_eq!(token_client.balance(&treasury), 700);
+    assert_eq!(token_client.balance(&authorizer), 0);
+}
+
+#[test]
+fn test_funding_source_defaults_to_sender() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+
+    assert_eq!(client.get_funding_source(&stream_id), sender);
+    assert_eq!(client.get_stream_contributors(&stream_id), soroban_sdk::vec![&env, (sender, 1000)]);
+}
+
+#[test]
+fn test_withdrawal_log_records_each_withdrawal_in_order() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    for (timestamp, amount) in [(10_u64, 100_i128), (30, 200), (60, 300)] {
+        env.ledger().set_timestamp(timestamp);
+        client.withdraw(&stream_id, &amount);
+    }
+
+    let records = client.get_withdrawals(&stream_id, &0, &10);
+    assert_eq!(records.len(), 3);
+    for (seq, (timestamp, amount)) in [(10_u64, 100_i128), (30, 200), (60, 300)].into_iter().enumerate() {
+        let record = records.get(seq as u32).unwrap();
+        assert_eq!(record.seq, seq as u32);
+        assert_eq!(record.amount, amount);
+        assert_eq!(record.fee, amount / 100);
+        assert_eq!(record.timestamp, timestamp);
+        assert_eq!(record.caller, recipient);
+    }
+
+    // Pages are clamped to the log length
+    let page = client.get_withdrawals(&stream_id, &2, &10);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap().amount, 300);
+    assert!(client.get_withdrawals(&stream_id, &3, &10).is_empty());
+}
+
+#[test]
+fn test_create_stream_rejects_invalid_recipients() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    for recipient in [&sender, &contract_id, &token] {
+        let result = client.try_create_stream(&sender, recipient, &token, &1000, &1000, &0, &100);
+        assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+    }
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 1000);
+}
+
+#[test]
+fn test_recipient_changes_reject_invalid_recipients() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+
+    for recipient in [&sender, &contract_id, &token] {
+        let result = client.try_propose_recipient_change(&stream_id, recipient);
+        assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+        let result = client.try_split_stream(&stream_id, &5000, recipient);
+        assert_eq!(result, Err(Ok(Error::InvalidRecipient.into())));
+    }
+}
+
+fn budgeted_stream(env: &Env, max_pause_seconds: Option<u64>) -> (PaymentStreamContractClient<'_>, u64) {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, 0);
+    let sender = Address::generate(env);
+    token::StellarAssetClient::new(env, &token).mint(&sender, &1000);
+    let options = StreamOptions { max_pause_seconds, ..StreamOptions::default() };
+    let stream_id = client.create_stream_with_options(
+        &sender, &Address::generate(env), &token, &1000, &1000, &0, &100, &options,
+    );
+    (client, stream_id)
+}
+
+#[test]
+fn test_pause_budget_exhausted_across_two_pauses() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, stream_id) = budgeted_stream(&env, Some(100));
+
+    env.ledger().set_timestamp(10);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(70);
+    client.resume_stream(&stream_id);
+
+    // 40 seconds left, so another pause is still allowed
+    env.ledger().set_timestamp(80);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(130);
+    client.resume_stream(&stream_id);
+    assert_eq!(client.get_stream(&stream_id).total_paused_duration, 110);
+
+    env.ledger().set_timestamp(140);
+    assert_eq!(client.try_pause_stream(&stream_id), Err(Ok(Error::PauseBudgetExceeded.into())));
+}
+
+#[test]
+fn test_force_resume_after_pause_outlasts_budget() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, stream_id) = budgeted_stream(&env, Some(100));
+
+    env.ledger().set_timestamp(10);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(50);
+    client.resume_stream(&stream_id);
+
+    // 60 seconds of budget left for this pause
+    env.ledger().set_timestamp(60);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(120);
+    assert_eq!(client.try_force_resume(&stream_id), Err(Ok(Error::PauseWithinBudget.into())));
+
+    env.ledger().set_timestamp(121);
+    env.set_auths(&[]);
+    client.force_resume(&stream_id);
+
+    let events = env.events().all();
+    let resumed = events.iter().find(|(_, topics, _)| *topics == ("StreamForceResumed", stream_id).into_val(&env)).unwrap();
+    let event: StreamForceResumedEvent = resumed.2.into_val(&env);
+    assert_eq!(
+        event,
+        StreamForceResumedEvent { stream_id, resumed_at: 121, paused_duration: 61, max_pause_seconds: 100 },
+    );
+
+    let stream = client.get_stream(&stream_id);
+    assert_eq!(stream.status, StreamStatus::Active);
+    assert_eq!(stream.total_paused_duration, 101);
+    assert_eq!(stream.end_time, 201);
+}
+
+#[test]
+fn test_force_resume_requires_a_budget() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, stream_id) = budgeted_stream(&env, None);
+
+    assert_eq!(client.try_force_resume(&stream_id), Err(Ok(Error::StreamNotPaused.into())));
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(1_000_000);
+    assert_eq!(client.try_force_resume(&stream_id), Err(Ok(Error::PauseWithinBudget.into())));
+}
+
+#[test]
+fn test_get_streams_pages_and_skips_missing_ids() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &7000);
+    assert_eq!(client.get_stream_count(), 0);
+    for _ in 0..7 {
+        client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+    }
+    assert_eq!(client.get_stream_count(), 7);
+
+    env.as_contract(&contract_id, || env.storage().persistent().remove(&DataKey::Stream(3)));
+
+    let ids = |streams: soroban_sdk::Vec<crate::Stream>| {
+        let mut ids = soroban_sdk::Vec::new(&env);
+        streams.iter().for_each(|stream| ids.push_back(stream.id));
+        ids
+    };
+    assert_eq!(ids(client.get_streams(&1, &4)), soroban_sdk::vec![&env, 1, 2, 4]);
+    assert_eq!(ids(client.get_streams(&5, &4)), soroban_sdk::vec![&env, 5, 6, 7]);
+    assert!(client.get_streams(&8, &4).is_empty());
+}
+
+#[test]
+fn test_get_streams_does_not_bump_ttl() {
+    use soroban_sdk::testutils::storage::Persistent as _;
+
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+
+    // Age the entry past the bump threshold
+    env.ledger().set_sequence_number(env.ledger().sequence() + 20_000);
+    let ttl = || env.as_contract(&contract_id, || env.storage().persistent().get_ttl(&DataKey::Stream(stream_id)));
+    let aged = ttl();
+
+    assert_eq!(client.get_streams(&1, &10).len(), 1);
+    assert_eq!(ttl(), aged);
+
+    client.get_stream(&stream_id);
+    assert!(ttl() > aged);
+}
+
+fn sponsored_stream(env: &Env, sponsor_balance: i128) -> (PaymentStreamContractClient<'_>, Address, u64, Address, Address) {
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(env, 100);
+    let sender = Address::generate(env);
+    let recipient = Address::generate(env);
+    let sponsor = Address::generate(env);
+    let token_admin = token::StellarAssetClient::new(env, &token);
+    token_admin.mint(&sender, &1000);
+    token_admin.mint(&sponsor, &sponsor_balance);
+    token::Client::new(env, &token).approve(&sponsor, &contract_id, &100, &(env.ledger().sequence() + 1000));
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_fee_sponsor(&stream_id, &sponsor);
+    (client, token, stream_id, recipient, sponsor)
+}
+
+#[test]
+fn test_fee_sponsor_pays_withdrawal_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, recipient, sponsor) = sponsored_stream(&env, 100);
+    let token_client = token::Client::new(&env, &token);
+    assert_eq!(client.get_fee_sponsor(&stream_id), Some(sponsor.clone()));
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+    let event = withdrawal_event(&env, stream_id);
+    assert_eq!((event.fee, event.net_amount, event.sponsor), (5, 500, Some(sponsor.clone())));
+
+    assert_eq!(token_client.balance(&recipient), 500);
+    assert_eq!(token_client.balance(&sponsor), 95);
+    assert_eq!(client.get_accrued_fees(&token), 5);
+    assert_eq!(client.get_stream_metrics(&stream_id).sponsored_fees, 5);
+}
+
+#[test]
+fn test_fee_sponsor_without_balance_falls_back_to_deduction() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, recipient, sponsor) = sponsored_stream(&env, 0);
+    let token_client = token::Client::new(&env, &token);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+    let event = withdrawal_event(&env, stream_id);
+    assert_eq!((event.fee, event.net_amount, event.sponsor), (5, 495, None));
+
+    assert_eq!(token_client.balance(&recipient), 495);
+    assert_eq!(token_client.balance(&sponsor), 0);
+    assert_eq!(client.get_accrued_fees(&token), 5);
+    assert_eq!(client.get_stream_metrics(&stream_id).sponsored_fees, 0);
+}
+
+#[test]
+fn test_constructor_configures_contract_at_deploy() {
+    let env = Env::default();
+
+    let admin = Address::generate(&env);
+    let fee_collector = Address::generate(&env);
+    let contract_id = env.register(PaymentStreamContract, (&admin, Some(fee_collector.clone()), 100_u32));
+    let client = PaymentStreamContractClient::new(&env, &contract_id);
+
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+    assert_eq!(client.get_fee_collector(), Some(fee_collector));
+    assert!(client.has_role(&Symbol::new(&env, "pauser"), &admin));
+    assert_eq!(client.get_stream_count(), 0);
+}
+
+#[test]
+fn test_initialize_after_constructor_cannot_take_over() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 0);
+    let stranger = Address::generate(&env);
+
+    let result = client.try_initialize(&stranger, &None, &0);
+    assert_eq!(result, Err(Ok(Error::AlreadyInitialized.into())));
+    assert!(!client.has_role(&Symbol::new(&env, "pauser"), &stranger));
+    assert!(client.has_role(&Symbol::new(&env, "pauser"), &admin));
+}
+
+#[test]
+fn test_create_stream_detailed_returns_stored_stream() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let created = client.create_stream_detailed(&sender, &recipient, &token, &1000, &400, &10, &110);
+
+    assert_eq!(created.id, 1);
+    assert_eq!(created.stream, client.get_stream(&created.id));
+    assert_eq!(created.stream.balance, 400);
+    assert_eq!(created.effective_fee_rate, client.get_protocol_fee_rate());
+    assert_eq!(created.effective_fee_rate, client.get_stream_summary(&created.id).fee_rate);
+}
+
+#[test]
+fn test_create_stream_detailed_fee_rate_matches_withdrawal() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 250);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let created = client.create_stream_detailed(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(created.effective_fee_rate, 250);
+
+    env.ledger().set_timestamp(100);
+    let quote = client.quote_withdrawal(&created.id, &1000);
+    client.withdraw(&created.id, &1000);
+
+    assert_eq!(quote.fee, 1000 * created.effective_fee_rate as i128 / 10000);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), quote.net);
+}
+
+#[test]
+fn test_admin_actions_logged_newest_first() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, _fee_collector) = setup_contract(&env, 100);
+    let pauser = Address::generate(&env);
+
+    env.ledger().set_timestamp(10);
+    client.set_protocol_fee_rate(&admin, &50);
+    env.ledger().set_timestamp(20);
+    client.grant_role(&Symbol::new(&env, "pauser"), &pauser);
+    env.ledger().set_timestamp(30);
+    client.set_paused(&pauser, &true);
+
+    let actions = client.get_admin_actions(&0, &10);
+    assert_eq!(actions.len(), 3);
+    assert_eq!(
+        actions.get(0).unwrap(),
+        AdminAction { action: Symbol::new(&env, "set_paused"), actor: pauser, timestamp: 30, detail: 1 }
+    );
+    assert_eq!(
+        actions.get(1).unwrap(),
+        AdminAction { action: Symbol::new(&env, "grant_role"), actor: admin.clone(), timestamp: 20, detail: 0 }
+    );
+    assert_eq!(
+        actions.get(2).unwrap(),
+        AdminAction { action: Symbol::new(&env, "set_fee_rate"), actor: admin, timestamp: 10, detail: 50 }
+    );
+
+    // Paging walks back from the newest entry
+    let older = client.get_admin_actions(&1, &1);
+    assert_eq!(older.len(), 1);
+    assert_eq!(older.get(0).unwrap().action, Symbol::new(&env, "grant_role"));
+    assert_eq!(client.get_admin_actions(&3, &10).len(), 0);
+}
+
+#[test]
+fn test_failed_admin_call_is_not_logged() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let stranger = Address::generate(&env);
+
+    let result = client.try_set_paused(&stranger, &true);
+    assert_eq!(result, Err(Ok(Error::Unauthorized.into())));
+    assert_eq!(client.get_admin_actions(&0, &10).len(), 0);
+}
+
+/// Yield adapter stand-in: holds whatever the stream contract parks with it
+/// and sends it back on `withdraw`, unless told to fail
+#[soroban_sdk::contract]
+pub struct MockYieldAdapter;
+
+#[soroban_sdk::contractimpl]
+impl MockYieldAdapter {
+    pub fn init(env: Env, owner: Address, token: Address) {
+        env.storage().instance().set(&soroban_sdk::symbol_short!("owner"), &owner);
+        env.storage().instance().set(&soroban_sdk::symbol_short!("token"), &token);
+    }
+
+    pub fn deposit(env: Env, amount: i128) {
+        let owner: Address = env.storage().instance().get(&soroban_sdk::symbol_short!("owner")).unwrap();
+        owner.require_auth();
+        env.storage().instance().set(&soroban_sdk::symbol_short!("balance"), &(Self::balance(env.clone()) + amount));
+    }
+
+    pub fn withdraw(env: Env, amount: i128) {
+        let owner: Address = env.storage().instance().get(&soroban_sdk::symbol_short!("owner")).unwrap();
+        owner.require_auth();
+        let failing: bool = env.storage().instance().get(&soroban_sdk::symbol_short!("failing")).unwrap_or(false);
+        if failing || amount > Self::balance(env.clone()) {
+            panic!("adapter unavailable");
+        }
+        let token: Address = env.storage().instance().get(&soroban_sdk::symbol_short!("token")).unwrap();
+        token::Client::new(&env, &token).transfer(&env.current_contract_address(), &owner, &amount);
+        env.storage().instance().set(&soroban_sdk::symbol_short!("balance"), &(Self::balance(env.clone()) - amount));
+    }
+
+    pub fn balance(env: Env) -> i128 {
+        env.storage().instance().get(&soroban_sdk::symbol_short!("balance")).unwrap_or(0)
+    }
+
+    pub fn set_failing(env: Env, failing: bool) {
+        env.storage().instance().set(&soroban_sdk::symbol_short!("failing"), &failing);
+    }
+}
+
+#[test]
+fn test_yield_adapter_lifecycle() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, fee_collector) = setup_contract(&env, 100);
+    let token_client = token::Client::new(&env, &token);
+    let adapter_id = env.register(MockYieldAdapter, ());
+    let adapter = MockYieldAdapterClient::new(&env, &adapter_id);
+    adapter.init(&contract_id, &token);
+    client.set_yield_adapter(&token, &adapter_id);
+    assert_eq!(client.get_yield_adapter(&token), Some(adapter_id.clone()));
+
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    // Escrow is parked with the adapter as it comes in
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &600, &0, &100);
+    client.deposit(&stream_id, &400);
+    assert_eq!(adapter.balance(), 1000);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    assert_eq!(client.get_yield_deposited(&token), 1000);
+
+    // Payouts pull back only what they need; the fee stays parked
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+    assert_eq!(token_client.balance(&recipient), 495);
+    assert_eq!(client.get_yield_deposited(&token), 505);
+
+    client.cancel_stream(&stream_id);
+    assert_eq!(token_client.balance(&sender), 500);
+    assert_eq!(client.get_yield_deposited(&token), 5);
+
+    assert_eq!(client.claim_fees(&token), 5);
+    assert_eq!(token_client.balance(&fee_collector), 5);
+    assert_eq!(client.get_yield_deposited(&token), 0);
+    assert_eq!(adapter.balance(), 0);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 0);
+}
+
+#[test]
+fn test_yield_adapter_failure_leaves_stream_untouched() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let adapter_id = env.register(MockYieldAdapter, ());
+    let adapter = MockYieldAdapterClient::new(&env, &adapter_id);
+    adapter.init(&contract_id, &token);
+    client.set_yield_adapter(&token, &adapter_id);
+
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    adapter.set_failing(&true);
+    env.ledger().set_timestamp(50);
+    let result = client.try_withdraw(&stream_id, &500);
+    assert_eq!(result, Err(Ok(Error::YieldAdapterError.into())));
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 0);
+    assert_eq!(client.get_yield_deposited(&token), 1000);
+
+    // Once the adapter recovers, removing it brings the escrow home
+    adapter.set_failing(&false);
+    client.remove_yield_adapter(&token);
+    assert_eq!(client.get_yield_adapter(&token), None);
+    assert_eq!(token::Client::new(&env, &token).balance(&contract_id), 1000);
+    client.withdraw(&stream_id, &500);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 500);
+}
+
+#[test]
+fn test_verify_stream_integrity_passes_through_lifecycle() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(client.verify_stream_integrity(&stream_id), IntegrityReport { ok: true, violations: soroban_sdk::vec![&env] });
+
+    env.ledger().set_timestamp(30);
+    client.withdraw(&stream_id, &300);
+    client.pause_stream(&stream_id);
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+
+    // Canceling a paused stream clears its pause marker
+    client.cancel_stream(&stream_id);
+    assert_eq!(client.get_stream(&stream_id).paused_at, None);
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+}
+
+#[test]
+fn test_verify_stream_integrity_stays_clean_after_underfunded_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &400);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &400, &0, &100);
+
+    // Vesting has run past the deposit; withdraw everything allowed
+    env.ledger().set_timestamp(80);
+    client.withdraw(&stream_id, &client.withdrawable_amount(&stream_id));
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 400);
+    assert_eq!(client.verify_stream_integrity(&stream_id), IntegrityReport { ok: true, violations: soroban_sdk::vec![&env] });
+}
+
+#[test]
+fn test_verify_stream_integrity_flags_drifted_state() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &600, &0, &100);
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &200);
+
+    // Corrupt the stored state behind the contract's back
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().persistent();
+        let mut stream: crate::Stream = storage.get(&DataKey::Stream(stream_id)).unwrap();
+        stream.withdrawn_amount = 700;
+        stream.paused_at = Some(40);
+        storage.set(&DataKey::Stream(stream_id), &stream);
+
+        let mut metrics: crate::StreamMetrics = storage.get(&DataKey::Metrics(stream_id)).unwrap();
+        metrics.total_withdrawn = 150;
+        storage.set(&DataKey::Metrics(stream_id), &metrics);
+    });
+
+    let report = client.verify_stream_integrity(&stream_id);
+    assert!(!report.ok);
+    assert_eq!(
+        report.violations,
+        soroban_sdk::vec![
+            &env,
+            Symbol::new(&env, "withdrawn_exceeds_balance"),
+            Symbol::new(&env, "pause_state_mismatch"),
+            Symbol::new(&env, "metrics_withdrawn_drift"),
+        ]
+    );
+
+    // An escrow counter below what the stream still holds is flagged too
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().persistent();
+        let mut stream: crate::Stream = storage.get(&DataKey::Stream(stream_id)).unwrap();
+        stream.withdrawn_amount = 200;
+        stream.paused_at = None;
+        storage.set(&DataKey::Stream(stream_id), &stream);
+        let mut metrics: crate::StreamMetrics = storage.get(&DataKey::Metrics(stream_id)).unwrap();
+        metrics.total_withdrawn = 200;
+        storage.set(&DataKey::Metrics(stream_id), &metrics);
+
+        let mut token_metrics: TokenStreamMetrics = storage.get(&DataKey::TokenMetrics(token.clone())).unwrap();
+        token_metrics.escrowed_balance = 100;
+        storage.set(&DataKey::TokenMetrics(token.clone()), &token_metrics);
+    });
+    let report = client.verify_stream_integrity(&stream_id);
+    assert_eq!(report.violations, soroban_sdk::vec![&env, Symbol::new(&env, "escrow_counter_short")]);
+}
+
+#[test]
+fn test_bump_stream_ttl_extends_related_entries() {
+    use soroban_sdk::testutils::storage::Persistent as _;
+
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+    client.set_delegate(&stream_id, &Address::generate(&env));
+    client.set_stream_metadata(&stream_id, &Symbol::new(&env, "payroll"), &BytesN::from_array(&env, &[7; 32]));
+
+    let keys = [
+        DataKey::Stream(stream_id),
+        DataKey::Metrics(stream_id),
+        DataKey::Delegate(stream_id),
+        DataKey::Metadata(stream_id),
+        DataKey::StatusPos(stream_id),
+        DataKey::StatusIndex(StreamStatus::Active, 0),
+    ];
+    let ttl = |key: &DataKey| env.as_contract(&contract_id, || env.storage().persistent().get_ttl(key));
+
+    // Age every entry past the bump threshold
+    env.ledger().set_sequence_number(env.ledger().sequence() + 20_000);
+    let bump = client.get_ttl_policy().bump;
+    for key in keys.iter() {
+        assert!(ttl(key) < bump - 10_000);
+    }
+
+    // Unknown ids are skipped
+    assert_eq!(client.bump_stream_ttl(&soroban_sdk::vec![&env, stream_id, 99]), 1);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("StreamTtlBumped",).into_val(&env));
+    let event: StreamTtlBumpedEvent = data.into_val(&env);
+    assert_eq!(event.stream_ids, soroban_sdk::vec![&env, stream_id]);
+    for key in keys.iter() {
+        assert_eq!(ttl(key), bump);
+    }
+
+    let mut too_many = soroban_sdk::Vec::new(&env);
+    for id in 0..21_u64 {
+        too_many.push_back(id);
+    }
+    assert_eq!(client.try_bump_stream_ttl(&too_many), Err(Ok(Error::BatchTooLarge.into())));
+}
+
+#[test]
+fn test_ttl_policy_bounds_and_use() {
+    use soroban_sdk::testutils::storage::Persistent as _;
+
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    assert_eq!(client.get_ttl_policy(), TtlPolicy { threshold: 518400, bump: 535680 });
+
+    // Threshold too short, bump not above threshold, bump past the network max
+    for (threshold, bump) in [(1_000, 100_000), (200_000, 200_000), (200_000, u32::MAX)] {
+        assert_eq!(client.try_set_ttl_policy(&threshold, &bump), Err(Ok(Error::InvalidTtlPolicy.into())));
+    }
+
+    client.set_ttl_policy(&100_000, &1_000_000);
+    assert_eq!(client.get_ttl_policy(), TtlPolicy { threshold: 100_000, bump: 1_000_000 });
+
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+    let ttl = env.as_contract(&contract_id, || env.storage().persistent().get_ttl(&DataKey::Stream(stream_id)));
+    assert_eq!(ttl, 1_000_000);
+}
+
+fn collected_fees(env: &Env, fee_rate: u32, withdrawals: &[i128]) -> i128 {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, fee_rate);
+    let sender = Address::generate(env);
+    let recipient = Address::generate(env);
+    let total: i128 = withdrawals.iter().sum();
+    token::StellarAssetClient::new(env, &token).mint(&sender, &total);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &total, &total, &0, &100);
+    env.ledger().set_timestamp(100);
+    for amount in withdrawals {
+        client.withdraw(&stream_id, amount);
+    }
+    client.get_accrued_fees(&token)
+}
+
+#[test]
+fn test_tiny_withdrawals_pay_same_fee_as_one_large() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    // 100 withdrawals of 3 units each round down to a zero fee without the carry
+    let tiny = [3i128; 100];
+    let split = collected_fees(&env, 250, &tiny);
+    let single = collected_fees(&env, 250, &[300]);
+    assert_eq!(single, 7);
+    assert!((split - single).abs() <= 1);
+}
+
+#[test]
+fn test_fee_carry_converges_across_rates_and_amounts() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let rates = [1u32, 33, 100, 250, 499, 500];
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    for rate in rates {
+        for round in 0..4 {
+            let mut withdrawals = [0i128; 24];
+            for amount in withdrawals.iter_mut() {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                *amount = 1 + (seed % (1 + 50 * round as u64)) as i128;
+            }
+            let total: i128 = withdrawals.iter().sum();
+            let split = collected_fees(&env, rate, &withdrawals);
+            let single = collected_fees(&env, rate, &[total]);
+            assert_eq!(single, total * rate as i128 / 10000);
+            assert!((split - single).abs() <= 1, "rate {} total {}: {} vs {}", rate, total, split, single);
+        }
+    }
+}
+
+#[test]
+fn test_quote_includes_fee_carry() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 250);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(100);
+
+    client.withdraw(&stream_id, &30);
+    // 30 * 2.5% = 0.75 is carried, so the next 10 units owe 0.75 + 0.25 = 1
+    let quote = client.quote_withdrawal(&stream_id, &10);
+    assert_eq!(quote.fee, 1);
+    client.withdraw(&stream_id, &10);
+    assert_eq!(client.get_accrued_fees(&token), 1);
+}
+
+#[test]
+fn test_get_config_matches_getters() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, admin, fee_collector) = setup_contract(&env, 100);
+    client.set_paused(&admin, &true);
+    client.set_protocol_fee_rate(&admin, &50);
+    client.set_stream_limits(&admin, &StreamLimits { min_stream_amount: 10, min_duration: 60, min_withdrawal_amount: 5 });
+
+    let config = client.get_config();
+    assert_eq!(config.config_version, 5);
+    assert_eq!(config.admin, Some(admin));
+    assert_eq!(config.fee_collector, Some(fee_collector));
+    assert_eq!(config.protocol_fee_rate, client.get_protocol_fee_rate());
+    assert_eq!(config.protocol_fee_rate, 50);
+    assert_eq!(config.paused, client.is_paused());
+    assert!(config.paused);
+    assert_eq!(config.stream_limits, client.get_stream_limits());
+    assert_eq!(config.version, client.version());
+    assert_eq!(config.recipient_rescue_delay, client.get_recipient_rescue_delay());
+    assert_eq!(config.ttl_policy, client.get_ttl_policy());
+    assert_eq!(config.max_duration, client.get_max_duration());
+    assert_eq!(config.fee_registry, client.get_fee_registry());
+    assert_eq!(config.fee_holiday, client.get_fee_holiday());
+    assert_eq!(config.creation_fee, client.get_creation_fee());
+}
+
+/// Cancel a 1000-over-100s stream at `elapsed`, optionally withdrawing
+/// everything vested first. Returns the recipient, sender and fee
+/// collector balances.
+fn cancel_outcome(env: &Env, fee_rate: u32, elapsed: u64, withdraw_first: bool) -> (i128, i128, i128) {
+    let (client, contract_id, token, _admin, fee_collector) = setup_contract(env, fee_rate);
+    let sender = Address::generate(env);
+    let recipient = Address::generate(env);
+    token::StellarAssetClient::new(env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(elapsed);
+    if withdraw_first {
+        client.withdraw_max(&stream_id);
+    }
+    client.cancel_stream(&stream_id);
+    client.claim_fees(&token);
+
+    let token_client = token::Client::new(env, &token);
+    assert_eq!(token_client.balance(&contract_id), 0);
+    (token_client.balance(&recipient), token_client.balance(&sender), token_client.balance(&fee_collector))
+}
+
+#[test]
+fn test_cancel_settles_vested_like_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    for (fee_rate, elapsed) in [(250, 37), (100, 50), (500, 99), (0, 10)] {
+        let direct = cancel_outcome(&env, fee_rate, elapsed, false);
+        let withdrawn = cancel_outcome(&env, fee_rate, elapsed, true);
+        assert_eq!(direct, withdrawn);
+    }
+
+    // 2.5% of the 370 vested goes to the fee collector, the rest is refunded
+    assert_eq!(cancel_outcome(&env, 250, 37, false), (361, 630, 9));
+}
+
+#[test]
+fn test_cancel_after_end_pays_recipient_and_stays_canceled() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(150);
+    client.cancel_stream(&stream_id);
+
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Canceled);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 990);
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 0);
+    let metrics = client.get_stream_metrics(&stream_id);
+    assert_eq!((metrics.total_withdrawn, metrics.withdrawal_count), (1000, 1));
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 0);
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+}
+
+/// Swaps `token_in` pulled from `payer` into `token_out` at `rate_bps`,
+/// paid from its own `token_out` balance. Rejects swaps below `min_out`
+/// unless `lenient` is set.
+#[soroban_sdk::contract]
+pub struct MockSwapAdapter;
+
+#[soroban_sdk::contractimpl]
+impl MockSwapAdapter {
+    pub fn configure(env: Env, payer: Address, token_out: Address, rate_bps: u32) {
+        env.storage().instance().set(&soroban_sdk::symbol_short!("payer"), &payer);
+        env.storage().instance().set(&soroban_sdk::symbol_short!("token_out"), &token_out);
+        env.storage().instance().set(&soroban_sdk::symbol_short!("rate"), &rate_bps);
+    }
+
+    pub fn set_rate(env: Env, rate_bps: u32, lenient: bool) {
+        env.storage().instance().set(&soroban_sdk::symbol_short!("rate"), &rate_bps);
+        env.storage().instance().set(&soroban_sdk::symbol_short!("lenient"), &lenient);
+    }
+
+    pub fn swap(env: Env, token_in: Address, amount: i128, recipient: Address, min_out: i128) -> i128 {
+        let payer: Address = env.storage().instance().get(&soroban_sdk::symbol_short!("payer")).unwrap();
+        let token_out: Address = env.storage().instance().get(&soroban_sdk::symbol_short!("token_out")).unwrap();
+        let rate: u32 = env.storage().instance().get(&soroban_sdk::symbol_short!("rate")).unwrap();
+        let lenient: bool = env.storage().instance().get(&soroban_sdk::symbol_short!("lenient")).unwrap_or(false);
+
+        token::Client::new(&env, &token_in).transfer(&payer, &env.current_contract_address(), &amount);
+        let amount_out = amount * rate as i128 / 10000;
+        if amount_out < min_out && !lenient {
+            panic!("slippage");
+        }
+        token::Client::new(&env, &token_out).transfer(&env.current_contract_address(), &recipient, &amount_out);
+        amount_out
+    }
+}
+
+#[test]
+fn test_settlement_adapter_swaps_withdrawals() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    let eurc = env.register_stellar_asset_contract_v2(admin).address();
+    let adapter_id = env.register(MockSwapAdapter, ());
+    MockSwapAdapterClient::new(&env, &adapter_id).configure(&contract_id, &eurc, &9000);
+    token::StellarAssetClient::new(&env, &eurc).mint(&adapter_id, &10_000);
+
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    client.set_settlement_adapter(&stream_id, &adapter_id, &8500);
+    assert_eq!(
+        client.get_settlement_adapter(&stream_id),
+        Some(SettlementAdapter { adapter: adapter_id.clone(), min_out_bps: 8500 })
+    );
+    assert_eq!(
+        client.try_set_settlement_adapter(&stream_id, &adapter_id, &10_001),
+        Err(Ok(Error::InvalidConfiguration.into()))
+    );
+
+    // 495 net after the 1% fee, swapped at 0.9
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("SettlementSwapped", stream_id).into_val(&env));
+    let payload: SettlementSwappedEvent = data.into_val(&env);
+    assert_eq!(payload.amount_out, 445);
+    assert_eq!(token::Client::new(&env, &eurc).balance(&recipient), 445);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 0);
+    assert_eq!(token::Client::new(&env, &token).balance(&adapter_id), 495);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 500);
+}
+
+#[test]
+fn test_settlement_adapter_falls_back_on_slippage_and_clear() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    let eurc = env.register_stellar_asset_contract_v2(admin).address();
+    let adapter_id = env.register(MockSwapAdapter, ());
+    let adapter = MockSwapAdapterClient::new(&env, &adapter_id);
+    adapter.configure(&contract_id, &eurc, &8000);
+    token::StellarAssetClient::new(&env, &eurc).mint(&adapter_id, &10_000);
+
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_settlement_adapter(&stream_id, &adapter_id, &8500);
+    let token_client = token::Client::new(&env, &token);
+
+    // 0.8 is below the 85% minimum: the adapter refuses and the
+    // withdrawal is paid out in the stream's token
+    env.ledger().set_timestamp(20);
+    client.withdraw(&stream_id, &200);
+    let (_, _, data) = env.events().all().iter()
+        .find(|(_, topics, _)| *topics == ("SettlementFallback", stream_id).into_val(&env))
+        .unwrap();
+    let payload: SettlementFallbackEvent = data.into_val(&env);
+    assert_eq!(payload, SettlementFallbackEvent { stream_id, adapter: adapter_id.clone(), amount: 198 });
+    assert_eq!(token_client.balance(&recipient), 198);
+    assert_eq!(token_client.balance(&adapter_id), 0);
+
+    // An adapter that swaps below the minimum anyway reverts the withdrawal
+    adapter.set_rate(&8000, &true);
+    env.ledger().set_timestamp(40);
+    assert_eq!(client.try_withdraw(&stream_id, &200), Err(Ok(Error::SlippageExceeded.into())));
+    assert_eq!(token_client.balance(&recipient), 198);
+
+    // Cleared: paid out directly without calling the adapter
+    client.clear_settlement_adapter(&stream_id);
+    assert_eq!(client.get_settlement_adapter(&stream_id), None);
+    client.withdraw(&stream_id, &200);
+    assert_eq!(token_client.balance(&recipient), 396);
+    assert_eq!(token::Client::new(&env, &eurc).balance(&recipient), 0);
+}
+
+#[test]
+fn test_create_stream_rejects_stale_start_unless_backdated() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &3000);
+    env.ledger().set_timestamp(100_000);
+
+    // Up to an hour in the past is tolerated for clock skew
+    client.create_stream(&sender, &recipient, &token, &1000, &1000, &(100_000 - 3600), &200_000);
+    assert_eq!(
+        client.try_create_stream(&sender, &recipient, &token, &1000, &1000, &0, &200_000),
+        Err(Ok(Error::StartTimeInPast.into()))
+    );
+
+    let options = StreamOptions { backdated: true, ..StreamOptions::default() };
+    let stream_id = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &0, &200_000, &options);
+    assert_eq!(client.withdrawable_amount(&stream_id), 500);
+}
+
+#[test]
+fn test_max_duration_is_enforced_and_configurable() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+
+    let ten_years = 10 * 365 * 86400;
+    assert_eq!(client.get_max_duration(), ten_years);
+    client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &ten_years);
+    assert_eq!(
+        client.try_create_stream(&sender, &recipient, &token, &1000, &1000, &0, &(ten_years + 1)),
+        Err(Ok(Error::DurationTooLong.into()))
+    );
+
+    client.set_max_duration(&86400);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("MaxDurationChanged",).into_val(&env));
+    let payload: MaxDurationChangedEvent = data.into_val(&env);
+    assert_eq!(payload, MaxDurationChangedEvent { old_max_duration: ten_years, new_max_duration: 86400 });
+    assert_eq!(client.get_admin_actions(&0, &1).get(0).unwrap().actor, admin);
+
+    assert_eq!(
+        client.try_create_stream(&sender, &recipient, &token, &1000, &1000, &0, &86401),
+        Err(Ok(Error::DurationTooLong.into()))
+    );
+    client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &86400);
+    assert_eq!(client.try_set_max_duration(&0), Err(Ok(Error::InvalidConfiguration.into())));
+}
+
+#[test]
+fn test_activity_feed_records_stream_lifecycle() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &600, &0, &100);
+    env.ledger().set_timestamp(10);
+    client.deposit(&stream_id, &400);
+    env.ledger().set_timestamp(20);
+    client.pause_stream(&stream_id);
+    env.ledger().set_timestamp(30);
+    client.resume_stream(&stream_id);
+    env.ledger().set_timestamp(40);
+    client.withdraw(&stream_id, &300);
+    client.cancel_stream(&stream_id);
+
+    let entries = client.get_activity(&stream_id, &0, &100);
+    assert_eq!(entries.len(), 6);
+    let expected = [
+        ("created", 1000, &sender, 0),
+        ("deposit", 400, &sender, 10),
+        ("pause", 0, &sender, 20),
+        ("resume", 0, &sender, 30),
+        ("withdraw", 300, &recipient, 40),
+        ("cancel", 700, &sender, 40),
+    ];
+    for (seq, (kind, amount, actor, timestamp)) in expected.into_iter().enumerate() {
+        assert_eq!(
+            entries.get(seq as u32).unwrap(),
+            ActivityEntry { seq: seq as u32, kind: Symbol::new(&env, kind), amount, actor: actor.clone(), timestamp }
+        );
+    }
+
+    // Paging
+    let page = client.get_activity(&stream_id, &4, &10);
+    assert_eq!(page.len(), 2);
+    assert_eq!(page.get(0).unwrap().seq, 4);
+    assert_eq!(client.get_activity(&stream_id, &6, &10).len(), 0);
+}
+
+#[test]
+fn test_activity_feed_logs_delegation_changes() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    client.set_delegate(&stream_id, &delegate);
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &100);
+    client.revoke_delegate_by_sender(&stream_id);
+
+    let mut kinds = soroban_sdk::Vec::new(&env);
+    for entry in client.get_activity(&stream_id, &0, &50).iter() {
+        kinds.push_back(entry.kind);
+    }
+    assert_eq!(
+        kinds,
+        soroban_sdk::vec![
+            &env,
+            Symbol::new(&env, "created"),
+            Symbol::new(&env, "delegate"),
+            Symbol::new(&env, "withdraw"),
+            Symbol::new(&env, "undelegate"),
+        ]
+    );
+    // Withdrawals log the delegate that made them, revocations who revoked
+    assert_eq!(client.get_activity(&stream_id, &2, &1).get(0).unwrap().actor, delegate);
+    assert_eq!(client.get_activity(&stream_id, &3, &1).get(0).unwrap().actor, sender);
+}
+
+/// A 1000-token stream over [0, 100) with 200 withdrawn at t=30 and
+/// quarantined at t=40. Returns (client, token, sender, recipient, stream_id).
+fn quarantined_stream(env: &Env, fee_rate: u32) -> (PaymentStreamContractClient<'_>, Address, Address, Address, u64) {
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(env, fee_rate);
+    let sender = Address::generate(env);
+    let recipient = Address::generate(env);
+    token::StellarAssetClient::new(env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(30);
+    client.withdraw(&stream_id, &200);
+    env.ledger().set_timestamp(40);
+    client.quarantine_stream(&stream_id, &Symbol::new(env, "sanctions"));
+    (client, token, sender, recipient, stream_id)
+}
+
+#[test]
+fn test_quarantine_blocks_fund_movements() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(0);
+
+    let (client, token, sender, recipient, stream_id) = quarantined_stream(&env, 0);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("StreamQuarantined", stream_id).into_val(&env));
+    let payload: StreamQuarantinedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        StreamQuarantinedEvent {
+            stream_id,
+            reason: Symbol::new(&env, "sanctions"),
+            previous_status: StreamStatus::Active,
+        }
+    );
+    let action = client.get_admin_actions(&0, &1).get(0).unwrap();
+    assert_eq!((action.action, action.detail), (Symbol::new(&env, "quarantine"), stream_id as i128));
+
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Quarantined);
+    assert_eq!(client.get_quarantine(&stream_id).unwrap().quarantined_at, 40);
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 0);
+
+    env.ledger().set_timestamp(60);
+    let quarantined = Err(Ok(Error::StreamQuarantined.into()));
+    assert_eq!(client.withdrawable_amount(&stream_id), 0);
+    assert_eq!(client.try_withdraw(&stream_id, &100), quarantined);
+    assert_eq!(client.try_deposit(&stream_id, &10), quarantined);
+    assert_eq!(client.try_cancel_stream(&stream_id), quarantined);
+    assert_eq!(client.try_pause_stream(&stream_id), quarantined);
+    assert_eq!(client.try_propose_recipient_change(&stream_id, &Address::generate(&env)), quarantined);
+    assert_eq!(
+        client.try_split_stream(&stream_id, &5000, &Address::generate(&env)),
+        Err(Ok(Error::StreamQuarantined.into()))
+    );
+    assert_eq!(client.try_quarantine_stream(&stream_id, &Symbol::new(&env, "again")), quarantined);
+
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 200);
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 0);
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+}
+
+#[test]
+fn test_release_quarantine_resolutions() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    // Back to the sender: the 800 left in escrow is refunded
+    let (client, token, sender, recipient, stream_id) = quarantined_stream(&env, 100);
+    env.ledger().set_timestamp(60);
+    assert_eq!(client.release_quarantine(&stream_id, &QuarantineResolution::ReturnToSender), 800);
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 800);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 198);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Canceled);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 0);
+    assert!(client.get_quarantine(&stream_id).is_none());
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+
+    // To the recipient: the 800 is paid out less the 1% fee
+    env.ledger().set_timestamp(0);
+    let (client, token, sender, recipient, stream_id) = quarantined_stream(&env, 100);
+    env.ledger().set_timestamp(60);
+    assert_eq!(client.release_quarantine(&stream_id, &QuarantineResolution::ReleaseToRecipient), 800);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("QuarantineReleased", stream_id).into_val(&env));
+    let payload: QuarantineReleasedEvent = data.into_val(&env);
+    assert_eq!(
+        payload,
+        QuarantineReleasedEvent {
+            stream_id,
+            reason: Symbol::new(&env, "sanctions"),
+            resolution: QuarantineResolution::ReleaseToRecipient,
+            amount: 800,
+        }
+    );
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 198 + 792);
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 0);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 0);
+    let action = client.get_admin_actions(&0, &1).get(0).unwrap();
+    assert_eq!((action.action, action.detail), (Symbol::new(&env, "release_quarantine"), 1));
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+
+    // Resume: the 20 seconds in quarantine count as a pause
+    env.ledger().set_timestamp(0);
+    let (client, token, sender, recipient, stream_id) = quarantined_stream(&env, 100);
+    env.ledger().set_timestamp(60);
+    assert_eq!(client.release_quarantine(&stream_id, &QuarantineResolution::Resume), 0);
+    let stream = client.get_stream(&stream_id);
+    assert_eq!((stream.status, stream.end_time, stream.paused_at), (StreamStatus::Active, 120, None));
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 1);
+    assert_eq!(client.withdrawable_amount(&stream_id), 200);
+    client.withdraw(&stream_id, &200);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 396);
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 0);
+    assert_eq!(
+        client.try_release_quarantine(&stream_id, &QuarantineResolution::Resume),
+        Err(Ok(Error::StreamNotQuarantined.into()))
+    );
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+}
+
+#[test]
+fn test_fee_registry_exempts_stream_parties() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    let registry = env.register(fee_registry::FeeRegistryContract, (&admin,));
+    let registry_client = fee_registry::FeeRegistryContractClient::new(&env, &registry);
+    let token_client = token::Client::new(&env, &token);
+
+    let exempt_sender = Address::generate(&env);
+    let exempt_recipient = Address::generate(&env);
+    let sender = Address::generate(&env);
+    let mut streams = soroban_sdk::Vec::new(&env);
+    let mut recipients = soroban_sdk::Vec::new(&env);
+    for (from, to) in [
+        (exempt_sender.clone(), Address::generate(&env)),
+        (sender.clone(), exempt_recipient.clone()),
+        (sender.clone(), Address::generate(&env)),
+    ] {
+        token::StellarAssetClient::new(&env, &token).mint(&from, &1000);
+        streams.push_back(client.create_stream(&from, &to, &token, &1000, &1000, &0, &100));
+        recipients.push_back(to);
+    }
+
+    registry_client.add_exempt(&exempt_sender);
+    registry_client.add_exempt(&exempt_recipient);
+    client.set_fee_registry(&registry);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("FeeRegistryChanged",).into_val(&env));
+    let payload: FeeRegistryChangedEvent = data.into_val(&env);
+    assert_eq!(payload, FeeRegistryChangedEvent { old_registry: None, new_registry: Some(registry.clone()) });
+    assert_eq!(client.get_admin_actions(&0, &1).get(0).unwrap().action, Symbol::new(&env, "set_fee_registry"));
+
+    env.ledger().set_timestamp(50);
+    for stream_id in streams.iter() {
+        client.withdraw(&stream_id, &500);
+    }
+    for (to, expected) in recipients.iter().zip([500, 500, 495]) {
+        assert_eq!(token_client.balance(&to), expected);
+    }
+    assert_eq!(client.get_stream_summary(&streams.get(1).unwrap()).fee_rate, 0);
+
+    // Lifting the exemption restores the fee on the next withdrawal
+    registry_client.remove_exempt(&exempt_recipient);
+    env.ledger().set_timestamp(100);
+    client.withdraw(&streams.get(1).unwrap(), &500);
+    assert_eq!(token_client.balance(&exempt_recipient), 995);
+
+    // A registry that can't answer exempts nobody
+    client.set_fee_registry(&token);
+    client.withdraw(&streams.get(0).unwrap(), &500);
+    assert_eq!(token_client.balance(&recipients.get(0).unwrap()), 995);
+
+    client.remove_fee_registry();
+    assert_eq!(client.get_fee_registry(), None);
+}
+
+/// `funded_bps` from the `StreamDeposit` event of the last call, and
+/// whether that call also emitted `StreamFullyFunded`
+fn last_deposit_events(env: &Env, stream_id: u64) -> (u32, Option<StreamFullyFundedEvent>) {
+    let mut funded_bps = None;
+    let mut fully_funded = None;
+    for (_, topics, data) in env.events().all().iter() {
+        if topics == ("StreamDeposit", stream_id).into_val(env) {
+            let payload: crate::StreamDepositEvent = data.into_val(env);
+            funded_bps = Some(payload.funded_bps);
+        } else if topics == ("StreamFullyFunded", stream_id).into_val(env) {
+            fully_funded = Some(data.into_val(env));
+        }
+    }
+    (funded_bps.unwrap(), fully_funded)
+}
+
+#[test]
+fn test_funding_progress_tracks_partial_deposits() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &300, &0, &100);
+    assert_eq!(client.get_funding_progress(&stream_id), FundingProgress { funded: 300, total: 1000, funded_bps: 3000 });
+
+    client.deposit(&stream_id, &333);
+    assert_eq!(last_deposit_events(&env, stream_id), (6330, None));
+    client.deposit(&stream_id, &366);
+    assert_eq!(last_deposit_events(&env, stream_id), (9990, None));
+
+    // Withdrawals leave the funded figure alone
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &400);
+    assert_eq!(client.get_funding_progress(&stream_id), FundingProgress { funded: 999, total: 1000, funded_bps: 9990 });
+}
+
+#[test]
+fn test_funding_progress_exact_completion_emits_once() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &999, &0, &100);
+
+    client.deposit(&stream_id, &1);
+    assert_eq!(
+        last_deposit_events(&env, stream_id),
+        (10_000, Some(StreamFullyFundedEvent { stream_id, total_amount: 1000 }))
+    );
+    assert_eq!(client.get_funding_progress(&stream_id).funded_bps, 10_000);
+    assert_eq!(client.try_deposit(&stream_id, &1), Err(Ok(Error::DepositExceedsTotal.into())));
+
+    // Created fully funded: no deposit, no event, but complete progress
+    let funded_id = client.create_stream(&sender, &recipient, &token, &500, &500, &0, &100);
+    assert_eq!(client.get_funding_progress(&funded_id), FundingProgress { funded: 500, total: 500, funded_bps: 10_000 });
+}
+
+#[test]
+fn test_shared_stream_pays_each_beneficiary_its_share() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let token_client = token::Client::new(&env, &token);
+    let sender = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &10_000);
+
+    let beneficiaries = soroban_sdk::vec![&env, (alice.clone(), 7000_u32), (bob.clone(), 3000_u32)];
+    let stream_id = client.create_shared_stream(&sender, &token, &10_000, &10_000, &0, &100, &beneficiaries);
+    assert_eq!(token_client.balance(&contract_id), 10_000);
+
+    // Alice claims 70% of the 4000 vested at t=40, less the 1% fee
+    env.ledger().set_timestamp(40);
+    assert_eq!(client.withdraw_share(&stream_id, &alice), 2772);
+    // Bob claims 30% of 7000 at t=70; Alice's earlier claim doesn't affect his
+    env.ledger().set_timestamp(70);
+    assert_eq!(client.withdraw_share(&stream_id, &bob), 2079);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 4900);
+
+    // The recipient-level paths are closed to shared streams
+    assert_eq!(client.try_withdraw(&stream_id, &100), Err(Ok(Error::SharedStream.into())));
+    assert_eq!(client.try_set_delegate(&stream_id, &bob), Err(Ok(Error::SharedStream.into())));
+    assert_eq!(client.try_withdraw_share(&stream_id, &sender), Err(Ok(Error::Unauthorized.into())));
+
+    // Cancel at t=90: 9000 vested, each settled up to its share, 1000 refunded
+    env.ledger().set_timestamp(90);
+    client.cancel_stream(&stream_id);
+    assert_eq!(token_client.balance(&alice), 2772 + 3465);
+    assert_eq!(token_client.balance(&bob), 2079 + 594);
+    assert_eq!(token_client.balance(&sender), 1000);
+    assert_eq!(client.get_accrued_fees(&token), 90);
+    assert_eq!(token_client.balance(&contract_id), 90);
+    assert_eq!(
+        client.get_beneficiaries(&stream_id).unwrap(),
+        soroban_sdk::vec![
+            &env,
+            Beneficiary { address: alice, share_bps: 7000, withdrawn: 6300 },
+            Beneficiary { address: bob, share_bps: 3000, withdrawn: 2700 },
+        ]
+    );
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Canceled);
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+}
+
+#[test]
+fn test_shared_stream_shares_validated_and_dust_to_last() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token_client = token::Client::new(&env, &token);
+    let sender = Address::generate(&env);
+    let (a, b, c) = (Address::generate(&env), Address::generate(&env), Address::generate(&env));
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    let invalid = Err(Ok(Error::InvalidShares.into()));
+    let short = soroban_sdk::vec![&env, (a.clone(), 5000_u32), (b.clone(), 4999_u32)];
+    assert_eq!(client.try_create_shared_stream(&sender, &token, &1000, &1000, &0, &100, &short), invalid);
+    let duplicate = soroban_sdk::vec![&env, (a.clone(), 5000_u32), (a.clone(), 5000_u32)];
+    assert_eq!(client.try_create_shared_stream(&sender, &token, &1000, &1000, &0, &100, &duplicate), invalid);
+    let mut crowd = soroban_sdk::Vec::new(&env);
+    for _ in 0..11 {
+        crowd.push_back((Address::generate(&env), 1000_u32));
+    }
+    assert_eq!(client.try_create_shared_stream(&sender, &token, &1000, &1000, &0, &100, &crowd), invalid);
+
+    let thirds = soroban_sdk::vec![&env, (a.clone(), 3333_u32), (b.clone(), 3333_u32), (c.clone(), 3334_u32)];
+    let stream_id = client.create_shared_stream(&sender, &token, &1000, &1000, &0, &100, &thirds);
+
+    // Part way through everyone's share rounds down
+    env.ledger().set_timestamp(10);
+    assert_eq!(client.withdraw_share(&stream_id, &c), 33);
+    // Once fully vested the last beneficiary picks up the rounding dust and
+    // the stream completes when all three have claimed
+    env.ledger().set_timestamp(100);
+    for beneficiary in [&a, &b, &c] {
+        client.withdraw_share(&stream_id, beneficiary);
+    }
+    assert_eq!((token_client.balance(&a), token_client.balance(&b), token_client.balance(&c)), (333, 333, 334));
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+    assert_eq!(
+        client.try_withdraw_share(&stream_id, &a),
+        Err(Ok(Error::InsufficientWithdrawable.into()))
+    );
+    assert!(client.verify_stream_integrity(&stream_id).ok);
+}
+
+#[test]
+fn test_min_withdraw_interval_rejects_rapid_withdrawals() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_min_withdraw_interval(&stream_id, &30);
+    assert_eq!(client.get_min_withdraw_interval(&stream_id), 30);
+    // No withdrawal yet, so no wait
+    assert_eq!(client.get_next_withdrawal_time(&stream_id), 0);
+
+    env.ledger().set_timestamp(20);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(client.get_stream_metrics(&stream_id).last_withdrawal_time, 20);
+    assert_eq!(client.get_next_withdrawal_time(&stream_id), 50);
+
+    env.ledger().set_timestamp(21);
+    assert_eq!(client.try_withdraw(&stream_id, &10), Err(Ok(ErrorExt::WithdrawTooSoon.into())));
+    assert!(!client.quote_withdrawal(&stream_id, &10).valid);
+    // Other activity doesn't restart the clock
+    env.ledger().set_timestamp(40);
+    client.set_delegate(&stream_id, &Address::generate(&env));
+    assert_eq!(client.get_stream_metrics(&stream_id).last_activity, 40);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 200);
+    assert_eq!(client.get_next_withdrawal_time(&stream_id), 80);
+}
+
+#[test]
+fn test_min_withdraw_interval_lifted_after_stream_end() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_min_withdraw_interval(&stream_id, &3600);
+
+    env.ledger().set_timestamp(90);
+    client.withdraw(&stream_id, &500);
+    assert_eq!(client.try_withdraw(&stream_id, &100), Err(Ok(ErrorExt::WithdrawTooSoon.into())));
+
+    // From the end time on, withdrawals go through back to back
+    env.ledger().set_timestamp(100);
+    assert_eq!(client.get_next_withdrawal_time(&stream_id), 0);
+    client.withdraw(&stream_id, &250);
+    client.withdraw(&stream_id, &250);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 1000);
+
+    // Clearing the interval removes the limit mid-stream too
+    let second = client.create_stream(&sender, &recipient, &token, &1000, &0, &100, &200);
+    client.set_min_withdraw_interval(&second, &3600);
+    client.set_min_withdraw_interval(&second, &0);
+    assert_eq!(client.get_min_withdraw_interval(&second), 0);
+}
+
+/// A stream whose addresses are all fixed, so its state hash is too
+fn state_hash_fixture(env: &Env) -> (PaymentStreamContractClient<'_>, Address, u64) {
+    let admin = Address::from_str(env, "CAAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQC526");
+    let sender = Address::from_str(env, "CABAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAEAQCAIBAFNSZ");
+    let recipient = Address::from_str(env, "CABQGAYDAMBQGAYDAMBQGAYDAMBQGAYDAMBQGAYDAMBQGAYDAMBQGCK3");
+    let token = env.register_stellar_asset_contract_v2(admin.clone()).address();
+    let contract_id = env.register(PaymentStreamContract, (&admin, None::<Address>, 0_u32));
+    let client = PaymentStreamContractClient::new(env, &contract_id);
+
+    token::StellarAssetClient::new(env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &600, &0, &100);
+    (client, token, stream_id)
+}
+
+#[test]
+fn test_stream_state_hash_matches_documented_encoding() {
+    use soroban_sdk::xdr::ToXdr;
+
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id) = state_hash_fixture(&env);
+    let stream = client.get_stream(&stream_id);
+
+    // Version 1 encoding, built field by field as documented
+    let mut encoded = Bytes::new(&env);
+    encoded.push_back(1);
+    encoded.extend_from_array(&stream_id.to_be_bytes());
+    for address in [&stream.sender, &stream.recipient, &token] {
+        encoded.append(&address.clone().to_xdr(&env));
+    }
+    for amount in [1000_i128, 600, 0] {
+        encoded.extend_from_array(&amount.to_be_bytes());
+    }
+    for time in [0_u64, 100] {
+        encoded.extend_from_array(&time.to_be_bytes());
+    }
+    encoded.extend_from_array(&[0, 0]); // Active, no paused_at
+    for time in [0_u64, 0] {
+        encoded.extend_from_array(&time.to_be_bytes());
+    }
+    encoded.extend_from_array(&[1, 0, 0, 0]); // cancelable, no notify, no deadline, no pause cap
+    encoded.extend_from_array(&0_i128.to_be_bytes());
+    encoded.extend_from_array(&[0; 16]); // four u32 counters
+    encoded.extend_from_array(&0_i128.to_be_bytes());
+    let expected: BytesN<32> = env.crypto().sha256(&encoded).into();
+
+    let hash = client.get_stream_state_hash(&stream_id);
+    assert_eq!(hash, expected);
+    assert_eq!(
+        hash,
+        BytesN::from_array(
+            &env,
+            &[
+                226, 84, 194, 138, 205, 34, 55, 67, 25, 109, 155, 199, 17, 58, 61, 26, 100, 236, 191, 161, 240, 33,
+                123, 248, 88, 131, 168, 7, 164, 134, 211, 162,
+            ]
+        )
+    );
+    assert!(client.verify_stream_state(&stream_id, &hash));
+    assert!(!client.verify_stream_state(&stream_id, &BytesN::from_array(&env, &[0; 32])));
+}
+
+#[test]
+fn test_stream_state_hash_changes_with_state() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, _token, stream_id) = state_hash_fixture(&env);
+
+    let before = client.get_stream_state_hash(&stream_id);
+    assert_eq!(client.get_stream_state_hash(&stream_id), before);
+
+    client.deposit(&stream_id, &1);
+    let after = client.get_stream_state_hash(&stream_id);
+    assert_ne!(after, before);
+    assert!(!client.verify_stream_state(&stream_id, &before));
+    assert!(client.verify_stream_state(&stream_id, &after));
+
+    // Time passing alone doesn't change the state
+    env.ledger().set_timestamp(50);
+    assert_eq!(client.get_stream_state_hash(&stream_id), after);
+    client.withdraw(&stream_id, &100);
+    assert_ne!(client.get_stream_state_hash(&stream_id), after);
+}
+
+#[test]
+fn test_fee_holiday_expires_on_its_own() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let token_client = token::Client::new(&env, &token);
+
+    client.set_fee_holiday(&admin, &50);
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!((emitter, topics), (contract_id.clone(), ("FeeHolidaySet",).into_val(&env)));
+    let payload: FeeHolidayEvent = data.into_val(&env);
+    assert_eq!(payload, FeeHolidayEvent { until: 50 });
+    assert_eq!(client.get_fee_holiday(), Some(50));
+
+    env.ledger().set_timestamp(49);
+    client.withdraw(&stream_id, &400);
+    assert_eq!(token_client.balance(&recipient), 400);
+    assert_eq!(client.get_accrued_fees(&token), 0);
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+
+    // One second past the holiday the configured 1% applies again
+    env.ledger().set_timestamp(51);
+    assert_eq!(client.get_fee_holiday(), None);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(token_client.balance(&recipient), 499);
+    assert_eq!(client.get_accrued_fees(&token), 1);
+    assert_eq!(client.get_protocol_fee_rate(), 100);
+
+    let result = client.try_set_fee_holiday(&admin, &51);
+    assert_eq!(result, Err(Ok(Error::InvalidTimeRange.into())));
+    let outsider = Address::generate(&env);
+    let result = client.try_set_fee_holiday(&outsider, &1000);
+    assert_eq!(result, Err(Ok(Error::Unauthorized.into())));
+}
+
+#[test]
+fn test_fee_holiday_can_be_cleared_early() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    client.set_fee_holiday(&admin, &1_000);
+    env.ledger().set_timestamp(50);
+    client.clear_fee_holiday(&admin);
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!((emitter, topics), (contract_id, ("FeeHolidayCleared",).into_val(&env)));
+    let payload: FeeHolidayEvent = data.into_val(&env);
+    assert_eq!(payload, FeeHolidayEvent { until: 1_000 });
+    assert_eq!(client.get_fee_holiday(), None);
+    assert_eq!(client.get_config().fee_holiday, None);
+
+    client.withdraw(&stream_id, &500);
+    assert_eq!(client.get_accrued_fees(&token), 5);
+}
+
+#[test]
+fn test_renew_stream_preserves_vesting_at_renewal() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(40);
+    client.withdraw(&stream_id, &100);
+    assert_eq!(client.withdrawable_amount(&stream_id), 300);
+
+    client.renew_stream(&stream_id, &1000, &200);
+
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("StreamRenewed", stream_id).into_val(&env));
+    let event: StreamRenewedEvent = data.into_val(&env);
+    assert_eq!(
+        event,
+        StreamRenewedEvent { stream_id, additional_amount: 1000, old_end_time: 100, new_end_time: 200, vested_at_renewal: 400 }
+    );
+
+    // No jump or clawback at the renewal instant
+    assert_eq!(client.withdrawable_amount(&stream_id), 300);
+    let stream = client.get_stream(&stream_id);
+    assert_eq!((stream.total_amount, stream.balance, stream.end_time), (2000, 2000, 200));
+    assert_eq!(token::Client::new(&env, &token).balance(&sender), 0);
+    assert_eq!(token::Client::new(&env, &token).balance(&contract_id), 1900);
+    assert_eq!(
+        client.get_vesting_baseline(&stream_id),
+        Some(VestingBaseline { time: 40, vested: 400, paused_duration: 0 })
+    );
+
+    // The remaining 1600 vest over the remaining 160 seconds
+    env.ledger().set_timestamp(41);
+    assert_eq!(client.withdrawable_amount(&stream_id), 310);
+    env.ledger().set_timestamp(120);
+    assert_eq!(client.withdrawable_amount(&stream_id), 1100);
+    env.ledger().set_timestamp(200);
+    client.withdraw(&stream_id, &1900);
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Completed);
+}
+
+#[test]
+fn test_renew_matured_stream() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1500);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(150);
+    let result = client.try_renew_stream(&stream_id, &500, &150);
+    assert_eq!(result, Err(Ok(Error::InvalidTimeRange.into())));
+
+    client.renew_stream(&stream_id, &500, &250);
+    assert_eq!(client.withdrawable_amount(&stream_id), 1000);
+    env.ledger().set_timestamp(200);
+    assert_eq!(client.withdrawable_amount(&stream_id), 1250);
+
+    // Renewed streams keep their schedule to themselves
+    let result = client.try_split_stream(&stream_id, &5000, &Address::generate(&env));
+    assert_eq!(result, Err(Ok(Error::InvalidSplit.into())));
+}
+
+#[test]
+fn test_renew_closed_stream_fails() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &5000);
+
+    let completed = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let canceled = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(50);
+    client.cancel_stream(&canceled);
+    env.ledger().set_timestamp(100);
+    client.withdraw(&completed, &1000);
+
+    for stream_id in [completed, canceled] {
+        let result = client.try_renew_stream(&stream_id, &500, &300);
+        assert_eq!(result, Err(Ok(Error::StreamNotActive.into())));
+    }
+}
+
+#[test]
+fn test_renew_checks_claim_deadline() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let (client, token, stream_id, sender, recipient) = setup_deadline_stream(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+
+    // Renewing would reopen the stale funds the sender may now reclaim
+    env.ledger().set_timestamp(201);
+    assert_eq!(client.try_renew_stream(&stream_id, &500, &300), Err(Ok(Error::ClaimWindowExpired.into())));
+    assert_eq!(client.get_stream(&stream_id).claim_deadline, Some(200));
+
+    // Moving a far-off deadline out overflows cleanly
+    let options = StreamOptions { claim_deadline: Some(u64::MAX), ..StreamOptions::default() };
+    let far = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &0, &300, &options);
+    assert_eq!(client.try_renew_stream(&far, &500, &400), Err(Ok(Error::ArithmeticOverflow.into())));
+}
+
+/// Explicit authorization trees per entrypoint. `mock_all_auths` accepts
+/// any signer for any call, so these suites pin down who must sign what,
+/// including the token transfers a signer authorizes through the call.
+/// Transfers the contract makes from its own escrow (withdrawal payouts,
+/// fees, refunds) need no signature and must not appear in a tree.
+mod auth {
+    extern crate std;
+
+    use super::*;
+    use soroban_sdk::testutils::{AuthorizedFunction, AuthorizedInvocation};
+    use soroban_sdk::xdr::{ScErrorCode, ScErrorType};
+    use soroban_sdk::{InvokeError, Val, Vec};
+
+    /// A call one signer authorizes, plus the token transfer out of the
+    /// signer's balance it makes, if any
+    struct AuthTree {
+        signer: Address,
+        contract: Address,
+        fn_name: &'static str,
+        args: Vec<Val>,
+        transfer: Option<(Address, Vec<Val>)>,
+    }
+
+    impl AuthTree {
+        fn signed_by(mut self, signer: &Address) -> Self {
+            self.signer = signer.clone();
+            self
+        }
+
+        fn without_transfer(mut self) -> Self {
+            self.transfer = None;
+            self
+        }
+
+        /// Make this the only authorization the next calls can use
+        fn mock(&self, env: &Env) {
+            let transfer = self.transfer.as_ref().map(|(token, args)| MockAuthInvoke {
+                contract: token,
+                fn_name: "transfer",
+                args: args.clone(),
+                sub_invokes: &[],
+            });
+            env.mock_auths(&[MockAuth {
+                address: &self.signer,
+                invoke: &MockAuthInvoke {
+                    contract: &self.contract,
+                    fn_name: self.fn_name,
+                    args: self.args.clone(),
+                    sub_invokes: transfer.as_slice(),
+                },
+            }]);
+        }
+
+        /// Assert the last call required exactly this tree
+        fn assert_used(&self, env: &Env) {
+            let sub_invocations = match &self.transfer {
+                Some((token, args)) => std::vec![AuthorizedInvocation {
+                    function: AuthorizedFunction::Contract((token.clone(), Symbol::new(env, "transfer"), args.clone())),
+                    sub_invocations: std::vec![],
+                }],
+                None => std::vec![],
+            };
+            let root = AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    self.contract.clone(),
+                    Symbol::new(env, self.fn_name),
+                    self.args.clone(),
+                )),
+                sub_invocations,
+            };
+            assert_eq!(env.auths(), std::vec![(self.signer.clone(), root)]);
+        }
+    }
+
+    /// The host's auth failure, as opposed to a contract error
+    fn assert_rejected<T: core::fmt::Debug>(result: Result<T, Result<soroban_sdk::Error, InvokeError>>) {
+        let invalid_action = soroban_sdk::Error::from_type_and_code(ScErrorType::Context, ScErrorCode::InvalidAction);
+        assert_eq!(result.err(), Some(Ok(invalid_action)));
+    }
+
+    /// Contract with a 1% fee and the parties to a stream. Setup runs under
+    /// `mock_all_auths`; each call under test installs its own tree.
+    struct Fixture {
+        env: Env,
+        client: PaymentStreamContractClient<'static>,
+        contract_id: Address,
+        token: Address,
+        sender: Address,
+        recipient: Address,
+        delegate: Address,
+        outsider: Address,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let env = Env::default();
+            env.mock_all_auths();
+            let (_client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+            let client = PaymentStreamContractClient::new(&env, &contract_id);
+            let sender = Address::generate(&env);
+            token::StellarAssetClient::new(&env, &token).mint(&sender, &10_000);
+            Fixture {
+                client,
+                contract_id,
+                token,
+                sender,
+                recipient: Address::generate(&env),
+                delegate: Address::generate(&env),
+                outsider: Address::generate(&env),
+                env,
+            }
+        }
+
+        /// A stream of 1000 over [0, 100] funded with `initial`, created
+        /// without checking auth
+        fn open_stream(&self, initial: i128) -> u64 {
+            self.env.mock_all_auths();
+            self.client.create_stream(&self.sender, &self.recipient, &self.token, &1000, &initial, &0, &100)
+        }
+
+        fn tree(&self, signer: &Address, fn_name: &'static str, args: Vec<Val>) -> AuthTree {
+            AuthTree { signer: signer.clone(), contract: self.contract_id.clone(), fn_name, args, transfer: None }
+        }
+
+        fn create_stream(&self, initial: i128) -> AuthTree {
+            let args = (&self.sender, &self.recipient, &self.token, 1000_i128, initial, 0_u64, 100_u64).into_val(&self.env);
+            AuthTree {
+                transfer: Some((self.token.clone(), (&self.sender, &self.contract_id, initial).into_val(&self.env))),
+                ..self.tree(&self.sender, "create_stream", args)
+            }
+        }
+
+        fn deposit(&self, stream_id: u64, amount: i128) -> AuthTree {
+            AuthTree {
+                transfer: Some((self.token.clone(), (&self.sender, &self.contract_id, amount).into_val(&self.env))),
+                ..self.tree(&self.sender, "deposit", (stream_id, amount).into_val(&self.env))
+            }
+        }
+
+        fn withdraw(&self, stream_id: u64, amount: i128) -> AuthTree {
+            self.tree(&self.recipient, "withdraw", (stream_id, amount).into_val(&self.env))
+        }
+
+        fn withdraw_as(&self, caller: &Address, stream_id: u64, amount: i128) -> AuthTree {
+            self.tree(caller, "withdraw_as", (caller, stream_id, amount).into_val(&self.env))
+        }
+
+        fn pause(&self, stream_id: u64) -> AuthTree {
+            self.tree(&self.sender, "pause_stream", (stream_id,).into_val(&self.env))
+        }
+
+        fn resume(&self, stream_id: u64) -> AuthTree {
+            self.tree(&self.sender, "resume_stream", (stream_id,).into_val(&self.env))
+        }
+
+        fn cancel(&self, stream_id: u64) -> AuthTree {
+            self.tree(&self.sender, "cancel_stream", (stream_id,).into_val(&self.env))
+        }
+
+        fn set_delegate(&self, stream_id: u64) -> AuthTree {
+            self.tree(&self.recipient, "set_delegate", (stream_id, &self.delegate).into_val(&self.env))
+        }
+
+        fn revoke_delegate(&self, stream_id: u64) -> AuthTree {
+            self.tree(&self.recipient, "revoke_delegate", (stream_id,).into_val(&self.env))
+        }
+    }
+
+    #[test]
+    fn test_create_stream_auth() {
+        let f = Fixture::new();
+        let call = || f.client.try_create_stream(&f.sender, &f.recipient, &f.token, &1000, &600, &0, &100);
+
+        f.create_stream(600).signed_by(&f.recipient).mock(&f.env);
+        assert_rejected(call());
+        // The sender must also authorize the escrow transfer
+        f.create_stream(600).without_transfer().mock(&f.env);
+        assert_rejected(call());
+
+        let tree = f.create_stream(600);
+        tree.mock(&f.env);
+        assert!(call().is_ok());
+        tree.assert_used(&f.env);
+    }
+
+    #[test]
+    fn test_deposit_auth() {
+        let f = Fixture::new();
+        let stream_id = f.open_stream(0);
+        let call = || f.client.try_deposit(&stream_id, &500);
+
+        f.deposit(stream_id, 500).signed_by(&f.outsider).mock(&f.env);
+        assert_rejected(call());
+        f.deposit(stream_id, 500).without_transfer().mock(&f.env);
+        assert_rejected(call());
+
+        let tree = f.deposit(stream_id, 500);
+        tree.mock(&f.env);
+        assert!(call().is_ok());
+        tree.assert_used(&f.env);
+        assert_eq!(f.client.get_stream(&stream_id).balance, 500);
+    }
+
+    #[test]
+    fn test_withdraw_auth() {
+        let f = Fixture::new();
+        let stream_id = f.open_stream(1000);
+        f.env.ledger().set_timestamp(50);
+        let call = || f.client.try_withdraw(&stream_id, &500);
+
+        f.withdraw(stream_id, 500).signed_by(&f.sender).mock(&f.env);
+        assert_rejected(call());
+
+        // The fee comes out of escrow, so the recipient signs nothing else
+        let tree = f.withdraw(stream_id, 500);
+        tree.mock(&f.env);
+        assert!(call().is_ok());
+        tree.assert_used(&f.env);
+        assert_eq!(token::Client::new(&f.env, &f.token).balance(&f.recipient), 495);
+        assert_eq!(f.client.get_accrued_fees(&f.token), 5);
+    }
+
+    #[test]
+    fn test_withdraw_with_delegate_auth() {
+        let f = Fixture::new();
+        let stream_id = f.open_stream(1000);
+        f.env.mock_all_auths();
+        f.client.set_delegate(&stream_id, &f.delegate);
+        f.env.ledger().set_timestamp(50);
+
+        // Without an explicit caller the delegate has to sign
+        f.withdraw(stream_id, 100).mock(&f.env);
+        assert_rejected(f.client.try_withdraw(&stream_id, &100));
+        let tree = f.withdraw(stream_id, 100).signed_by(&f.delegate);
+        tree.mock(&f.env);
+        assert!(f.client.try_withdraw(&stream_id, &100).is_ok());
+        tree.assert_used(&f.env);
+
+        // `withdraw_as` lets the recipient keep withdrawing directly
+        for caller in [&f.recipient, &f.delegate] {
+            let tree = f.withdraw_as(caller, stream_id, 100);
+            tree.mock(&f.env);
+            assert!(f.client.try_withdraw_as(caller, &stream_id, &100).is_ok());
+            tree.assert_used(&f.env);
+        }
+        assert_eq!(f.client.get_stream(&stream_id).withdrawn_amount, 300);
+
+        // Naming the recipient doesn't let the delegate sign for it
+        f.withdraw_as(&f.recipient, stream_id, 100).signed_by(&f.delegate).mock(&f.env);
+        assert_rejected(f.client.try_withdraw_as(&f.recipient, &stream_id, &100));
+        f.withdraw_as(&f.outsider, stream_id, 100).mock(&f.env);
+        let result = f.client.try_withdraw_as(&f.outsider, &stream_id, &100);
+        assert_eq!(result, Err(Ok(Error::Unauthorized.into())));
+    }
+
+    #[test]
+    fn test_pause_and_resume_auth() {
+        let f = Fixture::new();
+        let stream_id = f.open_stream(1000);
+
+        f.pause(stream_id).signed_by(&f.recipient).mock(&f.env);
+        assert_rejected(f.client.try_pause_stream(&stream_id));
+        let tree = f.pause(stream_id);
+        tree.mock(&f.env);
+        assert!(f.client.try_pause_stream(&stream_id).is_ok());
+        tree.assert_used(&f.env);
+
+        f.resume(stream_id).signed_by(&f.recipient).mock(&f.env);
+        assert_rejected(f.client.try_resume_stream(&stream_id));
+        let tree = f.resume(stream_id);
+        tree.mock(&f.env);
+        assert!(f.client.try_resume_stream(&stream_id).is_ok());
+        tree.assert_used(&f.env);
+    }
+
+    #[test]
+    fn test_cancel_auth() {
+        let f = Fixture::new();
+        let stream_id = f.open_stream(1000);
+        f.env.ledger().set_timestamp(50);
+
+        f.cancel(stream_id).signed_by(&f.recipient).mock(&f.env);
+        assert_rejected(f.client.try_cancel_stream(&stream_id));
+
+        // The refund and the recipient's settlement come out of escrow
+        let tree = f.cancel(stream_id);
+        tree.mock(&f.env);
+        assert!(f.client.try_cancel_stream(&stream_id).is_ok());
+        tree.assert_used(&f.env);
+        assert_eq!(token::Client::new(&f.env, &f.token).balance(&f.sender), 10_000 - 500);
+    }
+
+    #[test]
+    fn test_delegate_management_auth() {
+        let f = Fixture::new();
+        let stream_id = f.open_stream(1000);
+
+        f.set_delegate(stream_id).signed_by(&f.sender).mock(&f.env);
+        assert_rejected(f.client.try_set_delegate(&stream_id, &f.delegate));
+        let tree = f.set_delegate(stream_id);
+        tree.mock(&f.env);
+        assert!(f.client.try_set_delegate(&stream_id, &f.delegate).is_ok());
+        tree.assert_used(&f.env);
+
+        // The delegate can't remove itself through the recipient's call
+        f.revoke_delegate(stream_id).signed_by(&f.delegate).mock(&f.env);
+        assert_rejected(f.client.try_revoke_delegate(&stream_id));
+        let tree = f.revoke_delegate(stream_id);
+        tree.mock(&f.env);
+        assert!(f.client.try_revoke_delegate(&stream_id).is_ok());
+        tree.assert_used(&f.env);
+        assert_eq!(f.client.get_delegate(&stream_id), None);
+    }
+}
+
+#[test]
+fn test_creation_fee_charged_on_top_of_initial_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, fee_collector) = setup_contract(&env, 0);
+    let token_client = token::Client::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1050);
+
+    // Off by default
+    client.create_stream(&sender, &recipient, &token, &500, &500, &0, &100);
+    assert_eq!(token_client.balance(&sender), 550);
+
+    client.set_creation_fee(&admin, &25);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("CreationFeeChanged",).into_val(&env));
+    let event: CreationFeeChangedEvent = data.into_val(&env);
+    assert_eq!(event, CreationFeeChangedEvent { old_fee: 0, new_fee: 25 });
+
+    let stream_id = client.create_stream(&sender, &recipient, &token, &500, &500, &0, &100);
+    assert_eq!(token_client.balance(&sender), 25);
+    assert_eq!(client.get_stream(&stream_id).balance, 500);
+
+    // The fee is additive, so it's charged even with nothing deposited
+    client.create_stream(&sender, &recipient, &token, &500, &0, &0, &100);
+    assert_eq!(token_client.balance(&sender), 0);
+
+    assert_eq!(client.get_accrued_fees(&token), 50);
+    assert_eq!(client.get_creation_fees_collected(&token), 50);
+    assert_eq!(client.get_fees_collected(&token), 0);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 1000);
+    assert_eq!(client.claim_fees(&token), 50);
+    assert_eq!(token_client.balance(&fee_collector), 50);
+    assert_eq!(token_client.balance(&contract_id), 1000);
+
+    let result = client.try_set_creation_fee(&admin, &-1);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount.into())));
+    let result = client.try_set_fee_collector(&admin, &None);
+    assert_eq!(result, Err(Ok(Error::InvalidConfiguration.into())));
+}
+
+#[test]
+fn test_creation_fee_skipped_for_exempt_sender() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, admin, _fee_collector) = setup_contract(&env, 0);
+    let registry = env.register(fee_registry::FeeRegistryContract, (&admin,));
+    client.set_fee_registry(&registry);
+    client.set_creation_fee(&admin, &25);
+
+    let exempt_sender = Address::generate(&env);
+    fee_registry::FeeRegistryContractClient::new(&env, &registry).add_exempt(&exempt_sender);
+    token::StellarAssetClient::new(&env, &token).mint(&exempt_sender, &1000);
+    client.create_stream(&exempt_sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+
+    assert_eq!(token::Client::new(&env, &token).balance(&exempt_sender), 0);
+    assert_eq!(client.get_accrued_fees(&token), 0);
+    assert_eq!(client.get_creation_fees_collected(&token), 0);
+}
+
+#[test]
+fn test_create_stream_with_key_is_idempotent() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token_client = token::Client::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &3000);
+
+    let key = BytesN::from_array(&env, &[1; 32]);
+    let other_key = BytesN::from_array(&env, &[2; 32]);
+    assert_eq!(client.get_stream_by_key(&sender, &key), None);
+
+    let stream_id = client.create_stream_with_key(&sender, &recipient, &token, &1000, &1000, &0, &100, &key);
+    assert_eq!(client.get_stream_by_key(&sender, &key), Some(stream_id));
+
+    // A retry returns the same stream and pulls nothing
+    let retried = client.create_stream_with_key(&sender, &recipient, &token, &1000, &1000, &0, &100, &key);
+    assert_eq!(retried, stream_id);
+    assert_eq!(token_client.balance(&sender), 2000);
+    assert_eq!(token_client.balance(&contract_id), 1000);
+
+    let second = client.create_stream_with_key(&sender, &recipient, &token, &1000, &1000, &0, &100, &other_key);
+    assert_ne!(second, stream_id);
+    assert_eq!(client.get_stream_by_key(&sender, &other_key), Some(second));
+    assert_eq!(token_client.balance(&sender), 1000);
+
+    // Keys are namespaced per sender
+    let other_sender = Address::generate(&env);
+    assert_eq!(client.get_stream_by_key(&other_sender, &key), None);
+    token::StellarAssetClient::new(&env, &token).mint(&other_sender, &1000);
+    let third = client.create_stream_with_key(&other_sender, &recipient, &token, &1000, &1000, &0, &100, &key);
+    assert_ne!(third, stream_id);
+    assert_eq!(client.get_stream(&third).sender, other_sender);
+}
+
+#[test]
+fn test_missing_stream_metrics_are_rebuilt_and_repaired() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    let delegate = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &200);
+    client.set_delegate(&stream_id, &delegate);
+    client.pause_stream(&stream_id);
+    client.resume_stream(&stream_id);
+    env.ledger().set_timestamp(60);
+    client.withdraw(&stream_id, &100);
+
+    let remove_metrics = || env.as_contract(&contract_id, || {
+        env.storage().persistent().remove(&DataKey::Metrics(stream_id));
+        env.storage().instance().remove(&DataKey::ProtocolMetricsV2);
+    });
+
+    // Reads fall back to what the stream and the withdrawal log record
+    remove_metrics();
+    let metrics = client.get_stream_metrics(&stream_id);
+    assert_eq!(metrics.total_withdrawn, 300);
+    assert_eq!(metrics.withdrawal_count, 2);
+    assert_eq!(metrics.last_withdrawal_time, 60);
+    assert_eq!(metrics.current_delegate, Some(delegate.clone()));
+
+    // Entrypoints keep working, and the next withdrawal extends the log
+    // instead of overwriting its first record
+    client.pause_stream(&stream_id);
+    client.resume_stream(&stream_id);
+    env.ledger().set_timestamp(70);
+    client.withdraw(&stream_id, &100);
+    let log = client.get_withdrawals(&stream_id, &0, &10);
+    assert_eq!(log.len(), 3);
+    assert_eq!(log.get(0).unwrap().amount, 200);
+    assert_eq!(log.get(2).unwrap().seq, 2);
+
+    remove_metrics();
+    let repaired = client.repair_metrics(&stream_id);
+    assert_eq!(repaired.total_withdrawn, 400);
+    assert_eq!(repaired.withdrawal_count, 3);
+    assert_eq!(repaired.last_withdrawal_time, 70);
+    assert_eq!(repaired.current_delegate, Some(delegate));
+    // Counters with no other record restart from 0
+    assert_eq!(repaired.pause_count, 0);
+    assert_eq!(client.get_stream_metrics(&stream_id).withdrawal_count, 3);
+}
+
+#[test]
+fn test_rebuild_protocol_metrics_in_chunks() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &10_000);
+
+    let active = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &400, &0, &100);
+    client.deposit(&active, &100);
+    client.set_delegate(&active, &Address::generate(&env));
+    let paused = client.create_stream(&sender, &Address::generate(&env), &token, &2000, &2000, &0, &100);
+    client.pause_stream(&paused);
+    let canceled = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &1000, &0, &100);
+    env.ledger().set_timestamp(30);
+    client.cancel_stream(&canceled);
+    let completed = client.create_stream(&sender, &Address::generate(&env), &token, &500, &500, &0, &20);
+    client.withdraw(&completed, &500);
+    client.create_stream(&sender, &Address::generate(&env), &token, &800, &0, &30, &130);
+
+    let expected = client.get_protocol_metrics();
+    assert_eq!(expected.total_streams_created, 5);
+    env.as_contract(&contract_id, || env.storage().instance().remove(&DataKey::ProtocolMetricsV2));
+    assert_eq!(client.get_protocol_metrics().total_streams_created, 0);
+
+    assert_eq!(client.rebuild_protocol_metrics(&1, &2), Some(3));
+    assert_eq!(client.try_rebuild_protocol_metrics(&4, &2), Err(Ok(ErrorExt::RebuildOutOfOrder.into())));
+    assert_eq!(client.rebuild_protocol_metrics(&3, &2), Some(5));
+    // Nothing is written until the last chunk
+    assert_eq!(client.get_protocol_metrics().total_streams_created, 0);
+    assert_eq!(client.rebuild_protocol_metrics(&5, &2), None);
+
+    let rebuilt = client.get_protocol_metrics();
+    assert_eq!(rebuilt.total_streams_created, expected.total_streams_created);
+    assert_eq!(rebuilt.total_active_streams, expected.total_active_streams);
+    assert_eq!(rebuilt.total_tokens_streamed, expected.total_tokens_streamed);
+    assert_eq!(rebuilt.total_delegations, expected.total_delegations);
+    assert_eq!(rebuilt.total_committed, expected.total_committed);
+    assert_eq!(client.try_rebuild_protocol_metrics(&3, &2), Err(Ok(ErrorExt::RebuildOutOfOrder.into())));
+}
+
+/// SEP-41 subset whose transfers take a `fee_bps` fee, out of the amount
+/// received or, with `charge_payer`, on top of it from the payer
+mod fee_token {
+    use super::*;
+
+    #[soroban_sdk::contract]
+    pub struct FeeOnTransferToken;
+
+    #[soroban_sdk::contractimpl]
+    impl FeeOnTransferToken {
+        pub fn set_fee(env: Env, fee_bps: i128, charge_payer: bool) {
+            env.storage().instance().set(&soroban_sdk::symbol_short!("fee"), &(fee_bps, charge_payer));
+        }
+
+        pub fn mint(env: Env, to: Address, amount: i128) {
+            env.storage().persistent().set(&to, &(Self::balance(env.clone(), to.clone()) + amount));
+        }
+
+        pub fn balance(env: Env, id: Address) -> i128 {
+            env.storage().persistent().get(&id).unwrap_or(0)
+        }
+
+        pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+            from.require_auth();
+            let (fee_bps, charge_payer): (i128, bool) =
+                env.storage().instance().get(&soroban_sdk::symbol_short!("fee")).unwrap_or((0, false));
+            let fee = amount * fee_bps / 10_000;
+            let (debit, credit) = if charge_payer { (amount + fee, amount) } else { (amount, amount - fee) };
+            let from_balance = Self::balance(env.clone(), from.clone());
+            if from_balance < debit {
+                panic!("insufficient balance");
+            }
+            env.storage().persistent().set(&from, &(from_balance - debit));
+            env.storage().persistent().set(&to, &(Self::balance(env.clone(), to.clone()) + credit));
+        }
+    }
+}
+use fee_token::{FeeOnTransferToken, FeeOnTransferTokenClient};
+
+#[test]
+fn test_fee_on_transfer_token_rejected_by_default() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token = env.register(FeeOnTransferToken, ());
+    let token_client = FeeOnTransferTokenClient::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token_client.mint(&sender, &2000);
+
+    // 1% kept out of what the contract receives
+    token_client.set_fee(&100, &false);
+    let result = client.try_create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    assert_eq!(result, Err(Ok(ErrorExt::NonStandardTokenBehavior.into())));
+
+    // 1% charged on top of what the contract sends
+    token_client.set_fee(&0, &false);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    token_client.set_fee(&100, &true);
+    env.ledger().set_timestamp(50);
+    assert_eq!(client.try_withdraw(&stream_id, &500), Err(Ok(ErrorExt::NonStandardTokenBehavior.into())));
+    assert_eq!(client.try_cancel_stream(&stream_id), Err(Ok(ErrorExt::NonStandardTokenBehavior.into())));
+    assert_eq!(client.get_stream(&stream_id).withdrawn_amount, 0);
+}
+
+#[test]
+fn test_fee_on_transfer_token_absorbed_when_allowed() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, _token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token = env.register(FeeOnTransferToken, ());
+    let token_client = FeeOnTransferTokenClient::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token_client.mint(&sender, &1000);
+    token_client.set_fee(&100, &false);
+
+    let options = StreamOptions { allow_fee_on_transfer: true, ..StreamOptions::default() };
+    let stream_id = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &0, &100, &options);
+
+    // The stream is booked with what actually arrived
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(topics, ("TransferShortfall", stream_id).into_val(&env));
+    let event: TransferShortfallEvent = data.into_val(&env);
+    assert_eq!(event, TransferShortfallEvent { stream_id, expected: 1000, actual: 990, absorbed: 10 });
+    assert!(client.allows_fee_on_transfer(&stream_id));
+    assert_eq!(client.get_stream(&stream_id).balance, 990);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 990);
+
+    // A payer-side fee on the way out comes off the stream as well
+    token_client.set_fee(&100, &true);
+    env.ledger().set_timestamp(50);
+    client.withdraw(&stream_id, &500);
+    assert_eq!(token_client.balance(&recipient), 500);
+
+    let stream = client.get_stream(&stream_id);
+    assert_eq!(stream.balance, 985);
+    assert_eq!(client.get_transfer_loss(&stream_id), 15);
+    assert_eq!(token_client.balance(&contract_id), stream.balance - stream.withdrawn_amount);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 485);
+}
+
+#[test]
+fn test_recipient_rejects_stream_before_withdrawing() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token_client = token::Client::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &3000);
+    let unwanted = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let kept = client.create_stream(&sender, &recipient, &token, &2000, &2000, &0, &100);
+
+    // Vesting is irrelevant: the whole escrow goes back
+    env.ledger().set_timestamp(60);
+    assert_eq!(client.reject_stream(&unwanted), 1000);
+
+    let events = env.events().all();
+    let (_, topics, data) = events.last().unwrap();
+    assert_eq!(topics, ("StreamRejected", unwanted).into_val(&env));
+    let payload: StreamRejectedEvent = data.into_val(&env);
+    assert_eq!(payload, StreamRejectedEvent { stream_id: unwanted, recipient: recipient.clone(), refunded: 1000 });
+
+    assert_eq!(token_client.balance(&sender), 1000);
+    assert_eq!(token_client.balance(&recipient), 0);
+    assert_eq!(client.get_stream(&unwanted).status, StreamStatus::Rejected);
+    assert_eq!(client.get_token_metrics(&token).escrowed_balance, 2000);
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 1);
+
+    let position = client.get_recipient_position(&recipient, &0, &10);
+    assert_eq!(position.active_stream_count, 1);
+    assert_eq!(position.withdrawable_now, 1200);
+    assert_eq!(position.next_cursor, None);
+
+    assert_eq!(client.try_reject_stream(&unwanted), Err(Ok(Error::StreamNotActive.into())));
+    assert_eq!(client.try_withdraw(&unwanted, &1), Err(Ok(Error::InsufficientWithdrawable.into())));
+    assert_eq!(client.try_deposit(&unwanted, &10), Err(Ok(Error::StreamNotActive.into())));
+    assert_eq!(client.get_stream(&kept).status, StreamStatus::Active);
+}
+
+#[test]
+fn test_reject_stream_fails_after_withdrawal() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    let stream_id = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+
+    env.ledger().set_timestamp(10);
+    client.withdraw(&stream_id, &50);
+    assert_eq!(client.try_reject_stream(&stream_id), Err(Ok(ErrorExt::AlreadyAccepted.into())));
+    assert_eq!(client.get_stream(&stream_id).status, StreamStatus::Active);
+}
+
+fn set_ledger(env: &Env, sequence_number: u32, timestamp: u64) {
+    env.ledger().set(LedgerInfo { sequence_number, timestamp, ..env.ledger().get() });
+}
+
+#[test]
+fn test_ledger_sequence_stream_vests_by_sequence() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    set_ledger(&env, 100, 100);
+
+    let options = StreamOptions { time_basis: TimeBasis::LedgerSequence, ..StreamOptions::default() };
+    let by_ledger = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &100, &200, &options);
+    let by_time = client.create_stream(&sender, &recipient, &token, &1000, &1000, &100, &200);
+    assert_eq!(client.get_time_basis(&by_ledger), TimeBasis::LedgerSequence);
+    assert_eq!(client.get_time_basis(&by_time), TimeBasis::Timestamp);
+
+    // Only the sequence moves: the ledger-based stream vests, the other doesn't
+    set_ledger(&env, 150, 100);
+    assert_eq!(client.withdrawable_amount(&by_ledger), 500);
+    assert_eq!(client.withdrawable_amount(&by_time), 0);
+
+    // Only the clock moves
+    set_ledger(&env, 150, 1_000_000);
+    assert_eq!(client.withdrawable_amount(&by_ledger), 500);
+    assert_eq!(client.withdrawable_amount(&by_time), 1000);
+    client.withdraw(&by_ledger, &500);
+
+    // Pauses are measured in ledgers too, however much time passes
+    set_ledger(&env, 160, 1_000_000);
+    client.pause_stream(&by_ledger);
+    assert_eq!(client.get_stream(&by_ledger).paused_at, Some(160));
+    set_ledger(&env, 180, 9_000_000);
+    assert_eq!(client.withdrawable_amount(&by_ledger), 0);
+    client.resume_stream(&by_ledger);
+    let stream = client.get_stream(&by_ledger);
+    assert_eq!(stream.total_paused_duration, 20);
+    assert_eq!(stream.end_time, 220);
+    assert_eq!(client.withdrawable_amount(&by_ledger), 100);
+
+    set_ledger(&env, 220, 9_000_000);
+    assert_eq!(client.withdrawable_amount(&by_ledger), 500);
+}
+
+#[test]
+fn test_ledger_sequence_stream_limits() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    set_ledger(&env, 10_000, 5_000_000);
+    let options = StreamOptions { time_basis: TimeBasis::LedgerSequence, ..StreamOptions::default() };
+
+    // The start is checked against the sequence, not the timestamp
+    assert_eq!(
+        client.try_create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &9_000, &20_000, &options),
+        Err(Ok(Error::StartTimeInPast.into()))
+    );
+
+    // Durations are held to the limits at five seconds per ledger
+    client.set_max_duration(&86_400);
+    assert_eq!(
+        client.try_create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &10_000, &30_000, &options),
+        Err(Ok(Error::DurationTooLong.into()))
+    );
+    let options = StreamOptions { claim_deadline: Some(27_380), ..options };
+    let stream_id = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &10_000, &27_280, &options);
+    assert_eq!(client.try_renew_stream(&stream_id, &1, &30_000), Err(Ok(Error::DurationTooLong.into())));
+
+    // The claim deadline is a ledger number as well
+    set_ledger(&env, 27_380, 90_000_000);
+    assert_eq!(client.withdrawable_amount(&stream_id), 1000);
+    set_ledger(&env, 27_381, 5_000_000);
+    assert_eq!(client.withdrawable_amount(&stream_id), 0);
+    assert_eq!(client.reclaim_stale(&stream_id), 1000);
+}
+
+fn assert_active_counts(client: &PaymentStreamContractClient<'_>, token: &Address, stream_ids: &soroban_sdk::Vec<u64>) {
+    let active = stream_ids.iter()
+        .filter(|id| client.get_stream(id).status == StreamStatus::Active)
+        .count() as u64;
+    assert_eq!(client.get_protocol_metrics().total_active_streams, active);
+    assert_eq!(client.get_token_metrics(token).active_streams, active);
+}
+
+#[test]
+fn test_active_count_follows_every_transition() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &10_000);
+    let mut ids = soroban_sdk::Vec::new(&env);
+    for _ in 0..6 {
+        let recipient = Address::generate(&env);
+        ids.push_back(client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100));
+    }
+    let [a, b, c, d, e, f] = [0, 1, 2, 3, 4, 5].map(|i| ids.get(i).unwrap());
+    assert_active_counts(&client, &token, &ids);
+
+    // Rejected calls leave the counts alone
+    client.pause_stream(&a);
+    assert!(client.try_pause_stream(&a).is_err());
+    assert!(client.try_resume_stream(&b).is_err());
+    assert_active_counts(&client, &token, &ids);
+
+    // Quarantine from both Paused and Active, then put both back
+    env.ledger().set_timestamp(20);
+    client.quarantine_stream(&a, &Symbol::new(&env, "review"));
+    client.quarantine_stream(&b, &Symbol::new(&env, "review"));
+    assert_active_counts(&client, &token, &ids);
+    client.release_quarantine(&a, &QuarantineResolution::Resume);
+    client.release_quarantine(&b, &QuarantineResolution::Resume);
+    assert_eq!(client.get_stream(&a).status, StreamStatus::Paused);
+    assert_eq!(client.get_stream(&b).status, StreamStatus::Active);
+    assert_active_counts(&client, &token, &ids);
+
+    let child = client.split_stream(&c, &5000, &Address::generate(&env));
+    ids.push_back(child);
+    assert_active_counts(&client, &token, &ids);
+
+    // Pause, fail to withdraw the rest while paused, resume, complete
+    env.ledger().set_timestamp(200);
+    client.pause_stream(&d);
+    assert!(client.try_withdraw_max(&d).is_err());
+    client.resume_stream(&d);
+    client.withdraw_max(&d);
+    client.withdraw_max(&c);
+    client.withdraw_max(&child);
+    assert_eq!(client.get_stream(&d).status, StreamStatus::Completed);
+    assert!(client.try_cancel_stream(&c).is_err());
+    assert!(client.try_pause_stream(&child).is_err());
+    assert_active_counts(&client, &token, &ids);
+
+    client.reject_stream(&e);
+    client.cancel_stream(&a);
+    client.pause_stream(&b);
+    client.cancel_stream(&b);
+    client.quarantine_stream(&f, &Symbol::new(&env, "review"));
+    client.release_quarantine(&f, &QuarantineResolution::ReturnToSender);
+    assert_active_counts(&client, &token, &ids);
+    assert_eq!(client.get_protocol_metrics().total_active_streams, 0);
+
+    // A full rebuild arrives at the same count
+    assert_eq!(client.rebuild_protocol_metrics(&1, &50), None);
+    assert_active_counts(&client, &token, &ids);
+}
+
+#[test]
+fn test_quarantine_of_ledger_sequence_stream_pauses_in_ledgers() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, _contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &1000);
+    set_ledger(&env, 100, 100);
+    let options = StreamOptions { time_basis: TimeBasis::LedgerSequence, ..StreamOptions::default() };
+    let stream_id = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &100, &200, &options);
+
+    // The clock runs far ahead of the sequence while the stream is frozen
+    set_ledger(&env, 150, 10_000);
+    client.quarantine_stream(&stream_id, &Symbol::new(&env, "review"));
+    assert_eq!(client.get_stream(&stream_id).paused_at, Some(150));
+    set_ledger(&env, 170, 9_000_000);
+    client.release_quarantine(&stream_id, &QuarantineResolution::Resume);
+
+    let stream = client.get_stream(&stream_id);
+    assert_eq!(stream.status, StreamStatus::Active);
+    assert_eq!(stream.total_paused_duration, 20);
+    assert_eq!(stream.end_time, 220);
+    assert_eq!(client.withdrawable_amount(&stream_id), 500);
+}
+
+#[test]
+fn test_withdraw_max_batch_honours_auto_forward() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 100);
+    let recipient = Address::generate(&env);
+    let token_client = token::Client::new(&env, &token);
+    let (stream_a, stream_b) = setup_forwarding_streams(&env, &client, &token, &token, &recipient);
+    client.set_auto_forward(&stream_a, &Some(stream_b));
+
+    env.ledger().set_timestamp(40);
+    let nets = client.withdraw_max_batch(&soroban_sdk::vec![&env, stream_a]);
+    assert_eq!(nets, soroban_sdk::vec![&env, 396]);
+    assert_eq!(client.get_stream(&stream_b).balance, 396);
+    assert_eq!(token_client.balance(&recipient), 0);
+    assert_eq!(token_client.balance(&contract_id), 1000);
+}
+
+#[test]
+fn test_withdraw_max_batch_pays_fee_on_transfer_streams_individually() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, sac, _admin, _fee_collector) = setup_contract(&env, 0);
+    let token = env.register(FeeOnTransferToken, ());
+    let token_client = FeeOnTransferTokenClient::new(&env, &token);
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token_client.mint(&sender, &1000);
+    token::StellarAssetClient::new(&env, &sac).mint(&sender, &1000);
+
+    let options = StreamOptions { allow_fee_on_transfer: true, ..StreamOptions::default() };
+    let taxed = client.create_stream_with_options(&sender, &recipient, &token, &1000, &1000, &0, &100, &options);
+    let plain = client.create_stream(&sender, &recipient, &sac, &1000, &1000, &0, &100);
+
+    // 1% charged on top of what the contract sends
+    token_client.set_fee(&100, &true);
+    env.ledger().set_timestamp(50);
+    let nets = client.withdraw_max_batch(&soroban_sdk::vec![&env, taxed, plain]);
+    assert_eq!(nets, soroban_sdk::vec![&env, 500, 500]);
+    assert_eq!(token_client.balance(&recipient), 500);
+    assert_eq!(token::Client::new(&env, &sac).balance(&recipient), 500);
+
+    let stream = client.get_stream(&taxed);
+    assert_eq!(client.get_transfer_loss(&taxed), 5);
+    assert_eq!(token_client.balance(&contract_id), stream.balance - stream.withdrawn_amount);
+}
+
+#[test]
+fn test_withdraw_max_batch_swaps_adapter_streams() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, admin, _fee_collector) = setup_contract(&env, 100);
+    let eurc = env.register_stellar_asset_contract_v2(admin).address();
+    let adapter_id = env.register(MockSwapAdapter, ());
+    MockSwapAdapterClient::new(&env, &adapter_id).configure(&contract_id, &eurc, &9000);
+    token::StellarAssetClient::new(&env, &eurc).mint(&adapter_id, &10_000);
+
+    let sender = Address::generate(&env);
+    let recipient = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &2000);
+    let swapped = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    let plain = client.create_stream(&sender, &recipient, &token, &1000, &1000, &0, &100);
+    client.set_settlement_adapter(&swapped, &adapter_id, &8500);
+
+    // 495 net on each; only the adapter stream's share is swapped at 0.9
+    env.ledger().set_timestamp(50);
+    let nets = client.withdraw_max_batch(&soroban_sdk::vec![&env, swapped, plain]);
+    assert_eq!(nets, soroban_sdk::vec![&env, 495, 495]);
+    assert_eq!(token::Client::new(&env, &eurc).balance(&recipient), 445);
+    assert_eq!(token::Client::new(&env, &token).balance(&recipient), 495);
+    assert_eq!(token::Client::new(&env, &token).balance(&adapter_id), 495);
+}
+
+#[test]
+fn test_rebuild_matches_committed_after_underfunded_cancel() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let (client, contract_id, token, _admin, _fee_collector) = setup_contract(&env, 0);
+    let sender = Address::generate(&env);
+    token::StellarAssetClient::new(&env, &token).mint(&sender, &10_000);
+
+    // Half vested but only 200 funded: the recipient gets 200
+    let underfunded = client.create_stream(&sender, &Address::generate(&env), &token, &1000, &200, &0, &100);
+    // One approved milestone stays owed to the recipient after the cancel
+    let milestone = Milestone { amount: 300, approved: false };
+    let options = StreamOptions {
+        milestones: Some(soroban_sdk::vec![&env, milestone.clone(), milestone]),
+        ..StreamOptions::default()
+    };
+    let milestones = client.create_stream_with_options(&sender, &Address::generate(&env), &token, &600, &600, &0, &100, &options);
+    client.approve_milestone(&milestones, &0);
+
+    env.ledger().set_timestamp(50);
+    client.cancel_stream(&underfunded);
+    client.cancel_stream(&milestones);
+    let expected = client.get_protocol_metrics();
+    assert_eq!(expected.total_committed, 200 + 300);
+
+    env.as_contract(&contract_id, || env.storage().instance().remove(&DataKey::ProtocolMetricsV2));
+    assert_eq!(client.rebuild_protocol_metrics(&1, &50), None);
+    assert_eq!(client.get_protocol_metrics().total_committed, expected.total_committed);
+
+    // Paying out the owed milestone keeps the commitment where it was
+    client.withdraw(&milestones, &300);
+    assert_eq!(client.get_protocol_metrics().total_committed, 500);
+}
 }
@@ -30,24 +30,36 @@ cd ..
 # Install Stellar CLI
 cargo install --locked stellar-cli@25.0.0
 
+# Get deployer address
+DEPLOYER_ADDRESS=$(stellar keys address "$STELLAR_SECRET_KEY" 2>/dev/null || echo "unknown")
+
+echo "Deployer address is: $DEPLOYER_ADDRESS"
+
 # Deploy contract
 for CONTRACT in "${CONTRACTS[@]}"; do
   WASM_PATH="contracts/target/wasm32-unknown-unknown/release/${CONTRACT}.wasm"
 
+# Constructor arguments, so the contract is configured in the deploy transaction
+case "$CONTRACT" in
+  payment_stream)
+    CONSTRUCTOR_ARGS=(--admin "$DEPLOYER_ADDRESS" --general_fee_rate 0)
+    ;;
+  distributor)
+    CONSTRUCTOR_ARGS=(--admin "$DEPLOYER_ADDRESS" --protocol_fee_percent 0 --fee_address "$DEPLOYER_ADDRESS")
+    ;;
+esac
+
 CONTRACT_ID=$(stellar contract deploy \
   --wasm "$WASM_PATH" \
   --source "$STELLAR_SECRET_KEY" \
   --network "$NETWORK" \
-  --network-passphrase "$NETWORK_PARAPHRASE")
+  --network-passphrase "$NETWORK_PARAPHRASE" \
+  -- \
+  "${CONSTRUCTOR_ARGS[@]}")
   
 
 echo "Deployed contract ID: $CONTRACT_ID"
 
-# Get deployer address
-DEPLOYER_ADDRESS=$(stellar keys address "$STELLAR_SECRET_KEY" 2>/dev/null || echo "unknown")
-
-echo "Deployer address is: $DEPLOYER_ADDRESS"
-
 # Save deployment address
 jq --arg name "$CONTRACT" --arg id "$CONTRACT_ID" \
   '. + {($name): $id}' \